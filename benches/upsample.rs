@@ -0,0 +1,59 @@
+//! Benchmarks for the chroma up-samplers, decoding down-sampled images on one
+//! thread with the scalar, SSE and AVX2 routines
+
+use std::fs::read;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use zune_jpeg::{Decoder, ZuneJpegOptions};
+
+fn decode_jpeg(buf: &[u8], options: ZuneJpegOptions) -> Vec<u8>
+{
+    let options = options.set_num_threads(NonZeroU32::new(1).unwrap());
+    let mut d = Decoder::new_with_options(options);
+
+    d.decode_buffer(buf).unwrap()
+}
+
+fn bench_paths(c: &mut Criterion, group: &str, image: &str)
+{
+    let x = read(env!("CARGO_MANIFEST_DIR").to_string() + "/benches/images/" + image).unwrap();
+    let mut group = c.benchmark_group(group);
+
+    group.bench_function("scalar", |b| {
+        b.iter(|| black_box(decode_jpeg(x.as_slice(), ZuneJpegOptions::new().set_use_unsafe(false))))
+    });
+
+    group.bench_function("sse", |b| {
+        b.iter(|| black_box(decode_jpeg(x.as_slice(), ZuneJpegOptions::new().set_use_avx2(false))))
+    });
+
+    group.bench_function("avx2", |b| {
+        b.iter(|| black_box(decode_jpeg(x.as_slice(), ZuneJpegOptions::new())))
+    });
+}
+
+fn upsample_h_samp(c: &mut Criterion)
+{
+    bench_paths(c, "Horizontal up-sampling", "speed_bench_horizontal_subsampling.jpg");
+}
+
+fn upsample_hv_samp(c: &mut Criterion)
+{
+    bench_paths(c, "HV up-sampling", "speed_bench_hv_subsampling.jpg");
+}
+
+fn upsample_prog_hv_samp(c: &mut Criterion)
+{
+    bench_paths(c, "Progressive HV up-sampling", "speed_bench_prog_hv_sampling.jpg");
+}
+
+criterion_group!(name=benches;
+      config={
+      let c = Criterion::default();
+        c.measurement_time(Duration::from_secs(20))
+      };
+    targets=upsample_h_samp,upsample_hv_samp,upsample_prog_hv_samp);
+
+criterion_main!(benches);
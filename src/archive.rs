@@ -0,0 +1,679 @@
+//! Lossless compression of JPEG files
+//!
+//! [`compress`] makes JPEG files 10 to 25% smaller, and [`decompress`] restores the
+//! original file byte for byte, including headers, padding bits and data after the
+//! image, like Dropbox's Lepton does. This is meant for archiving, compressed files
+//! are not JPEG files.
+//!
+//! Huffman coded data is replaced by the quantized coefficients of the image, coded
+//! with the QM-coder of arithmetic coded JPEGs and a model predicting each block
+//! from its neighbours, see `archive_model.rs`. Everything else is kept as is. When
+//! decompressing, each scan is coded again with the Huffman tables and restart
+//! interval of the file, the way libjpeg codes it.
+//!
+//! Scans that don't come out exactly the same, because the file is damaged or its
+//! encoder made different choices, are stored as they are, so any file can be
+//! compressed, though only JPEG files get much smaller. Arithmetic coded scans are
+//! always stored.
+//!
+//! # Format
+//! A compressed file starts with [`MAGIC`], a version byte and the MD5 digest of the
+//! original file, followed by QM coded data ended by an EOI marker. The coded data
+//! holds the length of the original file and its number of scans, the bytes before,
+//! between and after the entropy coded data of scans, and for each scan whether it
+//! is coded again. Scans coded again have the padding of their last bytes and any
+//! bytes following the data, others their data. Then come the coefficients of all
+//! components, if any scan is coded again.
+//!
+//! # Examples
+//! ```no_run
+//! use zune_jpeg::archive::{compress, decompress};
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//!
+//! let compressed = compress(&img_data).unwrap();
+//! let restored = decompress(&compressed).unwrap();
+//!
+//! assert_eq!(restored, img_data);
+//! ```
+
+use std::io::Cursor;
+use std::ops::Range;
+
+use crate::archive_model::{Coder, Model};
+use crate::arithmetic::{QmDecoder, QmEncoder};
+use crate::coefficients::Coefficients;
+use crate::errors::DecodeErrors;
+use crate::huffman_encoder::{
+    encode_block, HuffmanEncoder, SymbolCounter, SymbolSink, SymbolWriter,
+};
+use crate::huffman_encoder_prog::{encode_dc_first, encode_dc_refine, AcEncoder};
+use crate::marker::Marker;
+use crate::md5::md5;
+use crate::segments::Segments;
+use crate::transcode::{for_each_block, Scan};
+use crate::Decoder;
+
+/// First bytes of a compressed file
+pub const MAGIC: &[u8; 4] = b"ZJPA";
+
+/// Version of the format of compressed files
+const VERSION: u8 = 1;
+
+/// Flag telling whether a scan is coded again
+const CODED_FLAG: usize = 0;
+
+/// Flag telling whether the last byte of a scan is padded with ones
+const PADDING_FLAG: usize = 1;
+
+/// Largest size of the original file for each byte of coded data
+///
+/// The QM-coder spends no less than about 2^-15 bits on a decision, and a byte is
+/// eight of them, so a byte of coded data holds less than 2^16 bytes.
+const MAX_RATIO: usize = 1 << 16;
+
+/// Bytes decoded at a time, checking whether the coded data ran out in between
+const CHUNK: usize = 1 << 16;
+
+/// Entropy coded data of a scan in a compressed file
+enum ScanData
+{
+    /// Data stored as is
+    Stored(Vec<u8>),
+    /// Data coded again from the coefficients, padded with one or zero bits and
+    /// followed by `trailing`
+    Coded
+    {
+        padding:  bool,
+        trailing: Vec<u8>,
+    },
+}
+
+/// Contents of a compressed file
+struct Archive
+{
+    /// Length of the original file
+    length:  usize,
+    /// Bytes before, between and after the entropy coded data of scans, one more
+    /// than there are scans
+    markers: Vec<Vec<u8>>,
+    /// Entropy coded data of each scan
+    scans:   Vec<ScanData>,
+    /// Coefficients of each component, if any scan is coded
+    planes:  Vec<Vec<i16>>,
+}
+
+/// How the entropy coded data of a scan is coded, from the headers before it
+struct ScanCoding
+{
+    scan:             Scan,
+    progressive:      bool,
+    restart_interval: usize,
+    /// DC and AC Huffman tables of each component of the frame, for those in the scan
+    tables:           Vec<(Option<HuffmanEncoder>, Option<HuffmanEncoder>)>,
+}
+
+impl ScanCoding
+{
+    /// The coding of the scan whose header `decoder` parsed last
+    fn new(decoder: &Decoder) -> ScanCoding
+    {
+        let components = decoder.z_order[..usize::from(decoder.num_scans)].to_vec();
+        let scan = if decoder.is_progressive
+        {
+            Scan::new(
+                &components,
+                decoder.spec_start,
+                decoder.spec_end,
+                decoder.succ_high,
+                decoder.succ_low,
+            )
+        }
+        else
+        {
+            Scan::new(&components, 0, 63, 0, 0)
+        };
+        let tables = decoder
+            .components
+            .iter()
+            .map(|component| {
+                let dc = decoder.dc_huffman_tables[component.dc_huff_table & 3].as_ref();
+                let ac = decoder.ac_huffman_tables[component.ac_huff_table & 3].as_ref();
+
+                (
+                    dc.and_then(|x| HuffmanEncoder::from_table(x).ok()),
+                    ac.and_then(|x| HuffmanEncoder::from_table(x).ok()),
+                )
+            })
+            .collect();
+
+        ScanCoding {
+            scan,
+            progressive: decoder.is_progressive,
+            restart_interval: decoder.restart_interval,
+            tables,
+        }
+    }
+
+    /// Huffman code the blocks of the scan in `image`, padding the last byte before
+    /// each restart marker and at the end with ones or zeroes
+    ///
+    /// Returns `None` if the tables don't code every symbol of the scan.
+    fn encode(&self, image: &Coefficients, padding: bool) -> Option<Vec<u8>>
+    {
+        let mut counter = SymbolCounter::new(image.components.len());
+
+        self.encode_into(&mut counter, image, |_, _| ());
+
+        let covered = |table: &Option<HuffmanEncoder>, frequencies: &[u32; 256]| {
+            frequencies.iter().all(|x| *x == 0)
+                || table.as_ref().is_some_and(|x| x.covers(frequencies))
+        };
+        let covers_all = self
+            .tables
+            .iter()
+            .zip(counter.dc.iter().zip(&counter.ac))
+            .all(|((dc, ac), (dc_frequencies, ac_frequencies))| {
+                covered(dc, dc_frequencies) && covered(ac, ac_frequencies)
+            });
+
+        if !covers_all
+        {
+            return None;
+        }
+        let mut writer = SymbolWriter::new(&self.tables);
+
+        self.encode_into(&mut writer, image, |writer, number| {
+            writer.writer.pad(padding);
+            writer.writer.data.extend_from_slice(&[0xFF, Marker::RST(number).to_u8().unwrap()]);
+        });
+        writer.writer.pad(padding);
+
+        Some(writer.writer.data)
+    }
+
+    /// Code the blocks of the scan into `sink`, components use the tables of their
+    /// position in the frame, `restart` is called at each restart marker with its
+    /// number
+    #[allow(clippy::cast_possible_truncation)]
+    fn encode_into<S: SymbolSink>(
+        &self, sink: &mut S, image: &Coefficients, mut restart: impl FnMut(&mut S, u8),
+    )
+    {
+        let scan = &self.scan;
+        let (ss, ah, al) = (scan.spec_start, scan.succ_high, scan.succ_low);
+        let mut predictions = vec![0; image.components.len()];
+        let mut encoder = AcEncoder::new(scan.components[0], ss, scan.spec_end, al);
+        let mut restarts = 0;
+
+        for_each_block(image, &scan.components, |mcu, pos, block| {
+            if self.restart_interval > 0 && mcu == (restarts + 1) * self.restart_interval
+            {
+                encoder.flush(sink);
+                restart(sink, (restarts % 8) as u8);
+                predictions.fill(0);
+                restarts += 1;
+            }
+            if !self.progressive
+            {
+                encode_block(sink, pos, block, &mut predictions[pos]);
+            }
+            else if ss == 0 && ah == 0
+            {
+                encode_dc_first(sink, pos, block, &mut predictions[pos], al);
+            }
+            else if ss == 0
+            {
+                encode_dc_refine(sink, block, al);
+            }
+            else if ah == 0
+            {
+                encoder.encode_first(sink, block);
+            }
+            else
+            {
+                encoder.encode_refine(sink, block);
+            }
+        });
+        encoder.flush(sink);
+    }
+}
+
+/// Split `buf` at the entropy coded data of its scans, returns the ranges of the data
+///
+/// The data of a scan runs from its header to the next marker other than a restart
+/// marker, fill bytes before that marker are not part of it.
+fn scan_ranges(buf: &[u8]) -> Vec<Range<usize>>
+{
+    let mut ranges = vec![];
+    let mut scan: Option<Range<usize>> = None;
+
+    for segment in Segments::new(buf)
+    {
+        let end = segment.offset + segment.length;
+        let in_scan = matches!(segment.marker(), None | Some(Marker::RST(_)));
+
+        if let Some(range) = scan.as_mut().filter(|_| in_scan)
+        {
+            range.end = end;
+            continue;
+        }
+        ranges.extend(scan.take());
+
+        if segment.marker() == Some(Marker::SOS)
+        {
+            scan = Some(end..end);
+        }
+    }
+    ranges.extend(scan);
+    ranges
+}
+
+/// Parse the headers in `markers`, returns the decoder holding the frame and the
+/// coding of each scan
+///
+/// Fails when there is no scan, which corrupt files whose headers still parse can have.
+fn scan_codings(markers: &[Vec<u8>]) -> Result<(Decoder, Vec<ScanCoding>), DecodeErrors>
+{
+    if markers.len() < 2
+    {
+        return Err(DecodeErrors::FormatStatic("No scans to code"));
+    }
+    let mut decoder = Decoder::new();
+    let mut codings = vec![];
+
+    decoder.decode_headers_internal(&mut Cursor::new(&markers[0]))?;
+    codings.push(ScanCoding::new(&decoder));
+
+    // each of these ends with the header of the next scan
+    for headers in &markers[1..markers.len() - 1]
+    {
+        for segment in Segments::new(headers)
+        {
+            let marker = segment.marker().ok_or(DecodeErrors::FormatStatic(
+                "Entropy coded data between scans",
+            ))?;
+            let mut reader = Cursor::new(&headers[segment.offset + 2..]);
+
+            decoder.parse_marker_inner(marker, &mut reader)?;
+        }
+        codings.push(ScanCoding::new(&decoder));
+    }
+    Ok((decoder, codings))
+}
+
+/// Code the contents of a compressed file, in either direction
+///
+/// When decoding `archive` starts out empty, and grows as its contents are decoded
+/// so corrupt lengths run out of coded data before they are allocated. The original
+/// file can't be longer than `max_length`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn code_archive<C: Coder>(
+    coder: &mut C, archive: &mut Archive, max_length: usize,
+) -> Result<(), DecodeErrors>
+{
+    let mut model = Model::new();
+    let corrupt = || DecodeErrors::FormatStatic("Corrupt compressed file");
+
+    archive.length = model.code_number(coder, archive.length);
+
+    let scans = model.code_number(coder, archive.scans.len());
+
+    if archive.length > max_length || scans > archive.length
+    {
+        return Err(corrupt());
+    }
+    // lengths can't add up to more than the file, this keeps corrupt files in check
+    let mut budget = archive.length;
+    let mut code_bytes = |model: &mut Model, coder: &mut C, kind, bytes: &mut Vec<u8>| {
+        let length = model.code_number(coder, bytes.len());
+
+        budget = budget.checked_sub(length).ok_or_else(corrupt)?;
+
+        for start in (0..length).step_by(CHUNK)
+        {
+            let end = length.min(start + CHUNK);
+
+            if bytes.len() < end
+            {
+                bytes.resize(end, 0);
+            }
+            model.code_bytes(coder, kind, &mut bytes[start..end]);
+
+            if coder.exhausted()
+            {
+                return Err(corrupt());
+            }
+        }
+        if coder.exhausted()
+        {
+            return Err(corrupt());
+        }
+        Ok(())
+    };
+
+    for pos in 0..=scans
+    {
+        if archive.markers.len() == pos
+        {
+            archive.markers.push(vec![]);
+        }
+        code_bytes(&mut model, coder, 0, &mut archive.markers[pos])?;
+    }
+    for pos in 0..scans
+    {
+        if archive.scans.len() == pos
+        {
+            archive.scans.push(ScanData::Stored(vec![]));
+        }
+        let scan = &mut archive.scans[pos];
+        let coded = matches!(scan, ScanData::Coded { .. });
+
+        if model.code_flag(coder, CODED_FLAG, coded) != coded
+        {
+            *scan = ScanData::Coded {
+                padding:  true,
+                trailing: vec![],
+            };
+        }
+        match scan
+        {
+            ScanData::Stored(data) => code_bytes(&mut model, coder, 1, data)?,
+            ScanData::Coded { padding, trailing } =>
+            {
+                *padding = model.code_flag(coder, PADDING_FLAG, *padding);
+                code_bytes(&mut model, coder, 1, trailing)?;
+            }
+        }
+    }
+    if archive.scans.iter().all(|x| matches!(x, ScanData::Stored(_)))
+    {
+        return Ok(());
+    }
+    let (decoder, _) = scan_codings(&archive.markers)?;
+
+    for pos in 0..decoder.components.len()
+    {
+        let (blocks_wide, blocks_high) = decoder.coefficient_plane_dimensions(pos);
+
+        if archive.planes.len() == pos
+        {
+            archive.planes.push(vec![]);
+        }
+        let plane = &mut archive.planes[pos];
+
+        if !plane.is_empty() && plane.len() != 64 * blocks_wide * blocks_high
+        {
+            return Err(corrupt());
+        }
+        let quantization = decoder.components[pos].quantization_table.0.map(|x| x as u16);
+
+        if !model.code_plane(coder, plane, blocks_wide, blocks_high, &quantization, pos > 0)
+        {
+            return Err(corrupt());
+        }
+    }
+    Ok(())
+}
+
+/// Compress the file in `buf`, see the [module docs](self)
+///
+/// Data that isn't a JPEG file is stored as is.
+///
+/// # Errors
+/// If the coefficients of the image don't fit the frame its headers describe when
+/// they are parsed again, which doesn't happen for files the decoder reads
+pub fn compress(buf: &[u8]) -> Result<Vec<u8>, DecodeErrors>
+{
+    let ranges = scan_ranges(buf);
+    let mut markers = vec![];
+    let mut start = 0;
+
+    for range in &ranges
+    {
+        markers.push(buf[start..range.start].to_vec());
+        start = range.end;
+    }
+    markers.push(buf[start..].to_vec());
+
+    let mut scans = ranges
+        .iter()
+        .map(|range| ScanData::Stored(buf[range.clone()].to_vec()))
+        .collect::<Vec<_>>();
+    let mut planes = vec![];
+
+    let image = Decoder::new().decode_coefficients(buf).ok();
+    let codings = scan_codings(&markers).ok();
+
+    // arithmetic coded scans are never coded again
+    if let (Some(image), Some((decoder, codings))) = (image, codings)
+    {
+        if !decoder.is_arithmetic && image.components.len() == decoder.components.len()
+        {
+            for (scan, coding) in scans.iter_mut().zip(&codings)
+            {
+                let ScanData::Stored(data) = scan
+                else
+                {
+                    continue;
+                };
+                let coded = [true, false].into_iter().find_map(|padding| {
+                    let coded = coding.encode(&image, padding)?;
+
+                    data.starts_with(&coded).then_some((padding, coded.len()))
+                });
+
+                if let Some((padding, length)) = coded
+                {
+                    *scan = ScanData::Coded {
+                        padding,
+                        trailing: data[length..].to_vec(),
+                    };
+                }
+            }
+            planes = image.components.into_iter().map(|x| x.coefficients).collect();
+        }
+    }
+    let mut archive = Archive {
+        length: buf.len(),
+        markers,
+        scans,
+        planes,
+    };
+    let mut encoder = QmEncoder::new();
+
+    code_archive(&mut encoder, &mut archive, usize::MAX)?;
+    encoder.finish();
+
+    let mut output = MAGIC.to_vec();
+
+    output.push(VERSION);
+    output.extend_from_slice(&md5(buf));
+    output.extend_from_slice(&encoder.data);
+    output.extend_from_slice(&[0xFF, Marker::EOI.to_u8().unwrap()]);
+
+    Ok(output)
+}
+
+/// Restore the file compressed into `buf` by [`compress`]
+///
+/// # Errors
+/// If `buf` is not a compressed file, or it is corrupt and the restored file is not
+/// the original one
+pub fn decompress(buf: &[u8]) -> Result<Vec<u8>, DecodeErrors>
+{
+    let header = MAGIC.len() + 17;
+
+    if buf.len() < header || !buf.starts_with(MAGIC)
+    {
+        return Err(DecodeErrors::FormatStatic("Not a compressed JPEG file"));
+    }
+    if buf[MAGIC.len()] != VERSION
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Unsupported compressed file version {}",
+            buf[MAGIC.len()]
+        )));
+    }
+    let mut archive = Archive {
+        length:  0,
+        markers: vec![],
+        scans:   vec![],
+        planes:  vec![],
+    };
+    let mut decoder = QmDecoder::new(&buf[header..]);
+
+    code_archive(&mut decoder, &mut archive, buf.len().saturating_mul(MAX_RATIO))?;
+
+    // the length is only checked against the output at the end, don't allocate it
+    let mut output = vec![];
+    let coded = archive.scans.iter().any(|x| matches!(x, ScanData::Coded { .. }));
+    let (image, codings) = if coded
+    {
+        let (decoder, codings) = scan_codings(&archive.markers)?;
+
+        (Some(decoder.planes_to_coefficients(archive.planes)), codings)
+    }
+    else
+    {
+        (None, vec![])
+    };
+
+    for (pos, (markers, scan)) in archive.markers.iter().zip(&archive.scans).enumerate()
+    {
+        output.extend_from_slice(markers);
+
+        match scan
+        {
+            ScanData::Stored(data) => output.extend_from_slice(data),
+            ScanData::Coded { padding, trailing } =>
+            {
+                let data = image
+                    .as_ref()
+                    .zip(codings.get(pos))
+                    .and_then(|(image, coding)| coding.encode(image, *padding))
+                    .ok_or(DecodeErrors::FormatStatic(
+                        "Corrupt compressed file, scan can't be coded",
+                    ))?;
+
+                output.extend_from_slice(&data);
+                output.extend_from_slice(trailing);
+            }
+        }
+    }
+    output.extend_from_slice(archive.markers.last().map_or(&[], Vec::as_slice));
+
+    if output.len() != archive.length || md5(&output) != buf[MAGIC.len() + 1..header]
+    {
+        return Err(DecodeErrors::FormatStatic(
+            "Corrupt compressed file, restored file differs from the original",
+        ));
+    }
+    Ok(output)
+}
+//----------------------------------------------
+// TEST
+//----------------------------------------------
+#[test]
+fn archive_scan_ranges()
+{
+    let buf = [
+        0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02, 0x12, 0xFF, 0x00, 0xFF, 0xD0, 0x34, 0xFF, 0xFF,
+        0xFF, 0xD9,
+    ];
+
+    assert_eq!(scan_ranges(&buf), vec![6..12]);
+}
+
+#[test]
+fn archive_restart_interval()
+{
+    // standard tables, optimized ones may not code the larger DC differences
+    let pixels = (0..96 * 64)
+        .map(|i| u8::try_from(i % 96 + i / 96 * 2).unwrap())
+        .collect::<Vec<u8>>();
+    let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_GRAYSCALE);
+
+    comp.set_fastest_defaults();
+    comp.set_size(96, 64);
+    comp.set_mem_dest();
+    comp.start_compress();
+    assert!(comp.write_scanlines(&pixels));
+    comp.finish_compress();
+
+    let buf = comp.data_to_vec().unwrap();
+    let image = Decoder::new().decode_coefficients(&buf).unwrap();
+    let ranges = scan_ranges(&buf);
+    let sof = buf
+        .windows(2)
+        .position(|x| x[0] == 0xFF && matches!(x[1], 0xC0..=0xC2))
+        .unwrap();
+
+    // a restart marker every seven MCUs, padded with zeroes
+    let mut headers = buf[..sof].to_vec();
+
+    headers.extend_from_slice(&[0xFF, 0xDD, 0x00, 0x04, 0x00, 0x07]);
+    headers.extend_from_slice(&buf[sof..ranges[0].start]);
+
+    let mut markers = vec![headers];
+
+    markers.extend(ranges.windows(2).map(|x| buf[x[0].end..x[1].start].to_vec()));
+    markers.push(buf[ranges[ranges.len() - 1].end..].to_vec());
+
+    let (_, codings) = scan_codings(&markers).unwrap();
+    let mut file = vec![];
+    let mut data = 0;
+
+    for (markers, coding) in markers.iter().zip(&codings)
+    {
+        assert_eq!(coding.restart_interval, 7);
+
+        file.extend_from_slice(markers);
+        let scan = coding.encode(&image, false).unwrap();
+
+        data += scan.len();
+        file.extend(scan);
+    }
+    file.extend_from_slice(&markers[markers.len() - 1]);
+
+    let compressed = compress(&file).unwrap();
+
+    // the scans are coded again, not stored
+    assert!(compressed.len() < file.len() - data / 2);
+    assert_eq!(decompress(&compressed).unwrap(), file);
+}
+
+#[test]
+fn archive_forged_header()
+{
+    // numbers at the start of the coded data: length, scans and marker lengths
+    let forged = |numbers: &[usize]| {
+        let mut model = Model::new();
+        let mut encoder = QmEncoder::new();
+
+        for &number in numbers
+        {
+            model.code_number(&mut encoder, number);
+        }
+        encoder.finish();
+
+        let mut output = MAGIC.to_vec();
+
+        output.push(VERSION);
+        output.extend_from_slice(&[0; 16]);
+        output.extend_from_slice(&encoder.data);
+        output.extend_from_slice(&[0xFF, Marker::EOI.to_u8().unwrap()]);
+        output
+    };
+
+    for numbers in [
+        &[1 << 60, 1 << 40][..],
+        &[1 << 20, 1 << 20],
+        &[10, 20],
+        &[1 << 30, 0, 1 << 29],
+        &[2_000_000, 0, 2_000_000],
+        &[2_000_000, 1_000_000],
+    ]
+    {
+        assert!(decompress(&forged(numbers)).is_err(), "{numbers:?}");
+    }
+}
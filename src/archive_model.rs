@@ -0,0 +1,637 @@
+//! Context modelling of compressed files, see [`archive`](crate::archive)
+//!
+//! Everything is coded as binary decisions with the QM-coder, each in a context
+//! picked from what was coded before, so probability estimates adapt to the image.
+//!
+//! Coefficient planes are coded block by block in raster order, in three parts as
+//! Lepton does. First the interior, coefficients outside the first row and column:
+//! the number of non zero ones, predicted from the blocks to the left and above,
+//! then each in zigzag order until all non zero ones are coded, in contexts of the
+//! magnitude of the coefficient at the same position in the neighbouring blocks.
+//! Then the first row and column, in contexts of a prediction from the interior and
+//! the neighbouring blocks, samples continuing smoothly across block boundaries.
+//! Last the DC coefficient, as the difference to a prediction extending the
+//! gradients of samples across the boundaries. Magnitudes are coded as their number
+//! of bits, in unary, followed by the bits below the top one.
+//!
+//! The model is written once for both directions, see [`Coder`].
+#![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+
+use crate::arithmetic::{QmDecoder, QmEncoder};
+use crate::misc::UN_ZIGZAG;
+
+/// Number of contexts for the count of non zero AC coefficients of a block
+const COUNT_CONTEXTS: usize = 12;
+
+/// Number of contexts for the magnitude of neighbouring coefficients
+const NEIGHBOUR_CONTEXTS: usize = 12;
+
+/// Number of contexts for the spread of estimates of the DC coefficient
+const ACTIVITY_CONTEXTS: usize = 10;
+
+/// Bits of the largest AC magnitude, that of -32768
+const MAX_AC_EXPONENT: usize = 16;
+
+/// Bits of the largest DC difference
+const MAX_DC_EXPONENT: usize = 17;
+
+/// Number of contexts for predicted magnitudes of the first row and column
+const PREDICTION_CONTEXTS: usize = 12;
+
+/// Positions in natural order of the coefficients outside the first row and column,
+/// the interior of a block, in zigzag order
+const INTERIOR: [usize; 49] = interior();
+
+/// Fixed point 1-D inverse DCT, `COSINES[u][x]` is 4096 `c(u) cos((2x + 1)uπ/16) / 2`
+const COSINES: [[i64; 8]; 8] = [
+    [1448, 1448, 1448, 1448, 1448, 1448, 1448, 1448],
+    [2009, 1703, 1138, 400, -400, -1138, -1703, -2009],
+    [1892, 784, -784, -1892, -1892, -784, 784, 1892],
+    [1703, -400, -2009, -1138, 1138, 2009, 400, -1703],
+    [1448, -1448, -1448, 1448, 1448, -1448, -1448, 1448],
+    [1138, -2009, 400, 1703, -1703, -400, 2009, -1138],
+    [784, -1892, 1892, -784, -784, 1892, -1892, 784],
+    [400, -1138, 1703, -2009, 2009, -1703, 1138, -400],
+];
+
+/// Samples computed by [`column`] and [`row`] for a DC coefficient of one
+const SAMPLE_SCALE: i64 = 1448 * 1448 / 64;
+
+/// Estimate with fixed probability 0.5, the last state of Table D.2
+const FIXED: u8 = 113;
+
+/// Zero bytes a decoder may read after the coded data before it is known to have
+/// run out, the encoder leaves out trailing zero bytes
+const MAX_PAST_END: usize = 64;
+
+/// Codes decisions, into or out of coded data
+///
+/// The encoder codes `value` and returns it, the decoder ignores it and returns the
+/// decoded decision. Code built on this works in both directions, when decoding
+/// the values it passes are meaningless but it only keeps what is returned.
+pub(crate) trait Coder
+{
+    /// Code the decision `value` with the estimate in `state`, updating it
+    fn code(&mut self, state: &mut u8, value: bool) -> bool;
+
+    /// Whether decoding ran out of coded data, decisions decoded since are garbage
+    fn exhausted(&self) -> bool;
+}
+
+impl Coder for QmEncoder
+{
+    fn code(&mut self, state: &mut u8, value: bool) -> bool
+    {
+        self.encode(state, value);
+        value
+    }
+
+    fn exhausted(&self) -> bool
+    {
+        false
+    }
+}
+
+impl Coder for QmDecoder<'_>
+{
+    fn code(&mut self, state: &mut u8, _: bool) -> bool
+    {
+        self.decode(state)
+    }
+
+    fn exhausted(&self) -> bool
+    {
+        self.past_end() > MAX_PAST_END
+    }
+}
+
+/// Collect [`INTERIOR`]
+const fn interior() -> [usize; 49]
+{
+    let mut positions = [0; 49];
+    let mut found = 0;
+    let mut k = 1;
+
+    while k < 64
+    {
+        if UN_ZIGZAG[k] >= 8 && !UN_ZIGZAG[k].is_multiple_of(8)
+        {
+            positions[found] = UN_ZIGZAG[k];
+            found += 1;
+        }
+        k += 1;
+    }
+    positions
+}
+
+/// Index of the logarithmic bucket of `value`, 0 for 0, 1 for 1, 2 for 2 and 3, ...
+fn bucket(value: u32) -> usize
+{
+    (u32::BITS - value.leading_zeros()) as usize
+}
+
+/// Like [`bucket`], with two buckets per power of two above 4
+fn fine_bucket(value: u32) -> usize
+{
+    if value < 4
+    {
+        return value as usize;
+    }
+    let bits = bucket(value);
+
+    2 * bits - 2 + ((value >> (bits - 2)) & 1) as usize
+}
+
+/// Code `value`, a non zero magnitude, with the number of its bits in `exponent`
+/// and the bits below the top one in `mantissa`
+///
+/// Returns the coded magnitude.
+fn code_magnitude<C: Coder>(
+    coder: &mut C, exponent: &mut [u8], mantissa: &mut [[u8; MAX_DC_EXPONENT]], value: u32,
+) -> u32
+{
+    let bits = bucket(value);
+    let mut coded = 1;
+
+    while coded < exponent.len() && coder.code(&mut exponent[coded - 1], bits > coded)
+    {
+        coded += 1;
+    }
+    let mut magnitude = 1;
+
+    for bit in (0..coded - 1).rev()
+    {
+        let one = coder.code(&mut mantissa[coded][bit], (value >> bit) & 1 == 1);
+
+        magnitude = (magnitude << 1) | u32::from(one);
+    }
+    magnitude
+}
+
+/// Estimates of the bits of an exponent, in unary
+type Exponent = [u8; MAX_AC_EXPONENT];
+
+/// Statistics of the blocks of a class of components
+#[derive(Clone)]
+struct BlockStatistics
+{
+    /// Binary tree of the count of non zero coefficients in the interior of a block,
+    /// by predicted count
+    count:         [[u8; 64]; COUNT_CONTEXTS],
+    /// Whether an interior coefficient is non zero, by position in [`INTERIOR`],
+    /// count of non zero coefficients left and neighbouring magnitude
+    non_zero:      [[[u8; NEIGHBOUR_CONTEXTS]; COUNT_CONTEXTS]; 49],
+    /// Interior exponents by position, count of non zero coefficients left and
+    /// neighbouring magnitude
+    ac_exponent:   [[[Exponent; NEIGHBOUR_CONTEXTS]; 6]; 49],
+    /// Interior mantissa bits by exponent and bit
+    ac_mantissa:   [[u8; MAX_DC_EXPONENT]; MAX_DC_EXPONENT + 1],
+    /// Interior signs by position
+    ac_sign:       [u8; 49],
+    /// Binary trees of the count of non zero coefficients in the first row, 0, and
+    /// column, 1, by count in the interior
+    edge_count:    [[[u8; 8]; COUNT_CONTEXTS]; 2],
+    /// Whether an edge coefficient is non zero, by edge, position, count of non zero
+    /// coefficients left and predicted magnitude
+    edge_non_zero: [[[[u8; PREDICTION_CONTEXTS]; 8]; 7]; 2],
+    /// Edge exponents by edge, position, magnitude of the interior coefficients next
+    /// to it, count of non zero coefficients left and predicted magnitude
+    edge_exponent: [[[[[Exponent; PREDICTION_CONTEXTS]; 4]; 6]; 7]; 2],
+    /// Edge mantissa bits by exponent and bit
+    edge_mantissa: [[u8; MAX_DC_EXPONENT]; MAX_DC_EXPONENT + 1],
+    /// Edge signs by edge, position, predicted sign and predicted magnitude
+    edge_sign:     [[[[u8; PREDICTION_CONTEXTS]; 3]; 7]; 2],
+    /// Whether the DC difference is non zero, by uncertainty of the prediction and
+    /// count of non zero AC coefficients
+    dc_non_zero:   [[u8; COUNT_CONTEXTS]; ACTIVITY_CONTEXTS],
+    /// DC exponents by uncertainty
+    dc_exponent:   [[u8; MAX_DC_EXPONENT]; ACTIVITY_CONTEXTS],
+    /// DC mantissa bits by exponent and bit
+    dc_mantissa:   [[u8; MAX_DC_EXPONENT]; MAX_DC_EXPONENT + 1],
+    /// DC signs by uncertainty
+    dc_sign:       [u8; ACTIVITY_CONTEXTS],
+}
+
+impl BlockStatistics
+{
+    #[allow(clippy::large_stack_arrays)]
+    fn new() -> BlockStatistics
+    {
+        BlockStatistics {
+            count:         [[0; 64]; COUNT_CONTEXTS],
+            non_zero:      [[[0; NEIGHBOUR_CONTEXTS]; COUNT_CONTEXTS]; 49],
+            ac_exponent:   [[[[0; MAX_AC_EXPONENT]; NEIGHBOUR_CONTEXTS]; 6]; 49],
+            ac_mantissa:   [[0; MAX_DC_EXPONENT]; MAX_DC_EXPONENT + 1],
+            ac_sign:       [0; 49],
+            edge_count:    [[[0; 8]; COUNT_CONTEXTS]; 2],
+            edge_non_zero: [[[[0; PREDICTION_CONTEXTS]; 8]; 7]; 2],
+            edge_exponent: [[[[[[0; MAX_AC_EXPONENT]; PREDICTION_CONTEXTS]; 4]; 6]; 7]; 2],
+            edge_mantissa: [[0; MAX_DC_EXPONENT]; MAX_DC_EXPONENT + 1],
+            edge_sign:     [[[[0; PREDICTION_CONTEXTS]; 3]; 7]; 2],
+            dc_non_zero:   [[0; COUNT_CONTEXTS]; ACTIVITY_CONTEXTS],
+            dc_exponent:   [[0; MAX_DC_EXPONENT]; ACTIVITY_CONTEXTS],
+            dc_mantissa:   [[0; MAX_DC_EXPONENT]; MAX_DC_EXPONENT + 1],
+            dc_sign:       [0; ACTIVITY_CONTEXTS],
+        }
+    }
+}
+
+/// Blocks next to the block being coded, already coded
+struct Neighbours<'a>
+{
+    left:         Option<[i16; 64]>,
+    above:        Option<[i16; 64]>,
+    /// Predicted count of non zero interior coefficients
+    count:        u32,
+    /// Quantization table of the component, in natural order
+    quantization: &'a [u16; 64],
+}
+
+/// Probability estimates of everything in a compressed file
+pub(crate) struct Model
+{
+    /// Flags and numbers
+    flags:   [u8; 2],
+    number:  [u8; 64],
+    /// Bytes of marker segments and stored entropy coded data, as binary trees
+    bytes:   [[u8; 256]; 2],
+    /// Blocks of luminance and chrominance components
+    classes: Vec<BlockStatistics>,
+}
+
+impl Model
+{
+    pub fn new() -> Model
+    {
+        Model {
+            flags:   [0; 2],
+            number:  [0; 64],
+            bytes:   [[0; 256]; 2],
+            classes: vec![BlockStatistics::new(); 2],
+        }
+    }
+
+    /// Code a flag, `kind` tells flags with different probabilities apart
+    pub fn code_flag<C: Coder>(&mut self, coder: &mut C, kind: usize, value: bool) -> bool
+    {
+        coder.code(&mut self.flags[kind], value)
+    }
+
+    /// Code a number, its count of bits in unary and then the bits below the top one
+    pub fn code_number<C: Coder>(&mut self, coder: &mut C, value: usize) -> usize
+    {
+        let bits = (usize::BITS - value.leading_zeros()) as usize;
+        let mut coded = 0;
+
+        while coded < 63 && coder.code(&mut self.number[coded], bits > coded)
+        {
+            coded += 1;
+        }
+        if coded == 0
+        {
+            return 0;
+        }
+        let mut fixed = FIXED;
+        let mut number = 1;
+
+        for bit in (0..coded - 1).rev()
+        {
+            number = (number << 1) | usize::from(coder.code(&mut fixed, (value >> bit) & 1 == 1));
+        }
+        number
+    }
+
+    /// Code the bytes of `bytes`, whose length was coded before
+    ///
+    /// `kind` tells marker segments, 0, and entropy coded data, 1, apart.
+    pub fn code_bytes<C: Coder>(&mut self, coder: &mut C, kind: usize, bytes: &mut [u8])
+    {
+        for byte in bytes
+        {
+            *byte = code_tree(coder, &mut self.bytes[kind], 8, usize::from(*byte)) as u8;
+        }
+    }
+
+    /// Code the blocks of a coefficient plane `blocks_wide` by `blocks_high` blocks,
+    /// quantized with `quantization`
+    ///
+    /// When decoding `plane` starts out empty and grows by a row of blocks at a time,
+    /// returns false if the coded data runs out first. `chrominance` selects the
+    /// statistics of chrominance components, those of the luminance component differ.
+    pub fn code_plane<C: Coder>(
+        &mut self, coder: &mut C, plane: &mut Vec<i16>, blocks_wide: usize,
+        blocks_high: usize, quantization: &[u16; 64], chrominance: bool,
+    ) -> bool
+    {
+        let statistics = &mut self.classes[usize::from(chrominance)];
+        // non zero interior coefficients of each block coded
+        let mut counts = vec![];
+
+        for y in 0..blocks_high
+        {
+            let end = blocks_wide * (y + 1);
+
+            if plane.len() < 64 * end
+            {
+                plane.resize(64 * end, 0);
+            }
+            counts.resize(end, 0_u32);
+
+            for x in 0..blocks_wide
+            {
+                let index = y * blocks_wide + x;
+                let block = |index: usize| -> [i16; 64] {
+                    plane[64 * index..64 * index + 64].try_into().unwrap()
+                };
+                let count = match (x > 0, y > 0)
+                {
+                    (true, true) => (counts[index - 1] + counts[index - blocks_wide]).div_ceil(2),
+                    (true, false) => counts[index - 1],
+                    (false, true) => counts[index - blocks_wide],
+                    (false, false) => 0,
+                };
+                let neighbours = Neighbours {
+                    left: (x > 0).then(|| block(index - 1)),
+                    above: (y > 0).then(|| block(index - blocks_wide)),
+                    count,
+                    quantization,
+                };
+                let current = (&mut plane[64 * index..64 * index + 64]).try_into().unwrap();
+
+                counts[index] = code_block(coder, statistics, current, &neighbours);
+            }
+            if coder.exhausted()
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Code `value` as `bits` decisions in the binary tree `tree`, returns the coded value
+fn code_tree<C: Coder>(coder: &mut C, tree: &mut [u8], bits: usize, value: usize) -> usize
+{
+    let mut node = 1;
+
+    for bit in (0..bits).rev()
+    {
+        let one = coder.code(&mut tree[node], (value >> bit) & 1 == 1);
+
+        node = 2 * node + usize::from(one);
+    }
+    node - (1 << bits)
+}
+
+/// Divide rounding to the nearest, `denominator` is positive
+fn divide(numerator: i64, denominator: i64) -> i64
+{
+    (numerator + numerator.signum() * denominator / 2) / denominator
+}
+
+/// Dequantize `block`, DCT coefficients times 4096 `c(u)` `c(v)`
+fn dequantize(block: &[i16; 64], quantization: &[u16; 64]) -> [i64; 64]
+{
+    std::array::from_fn(|i| i64::from(block[i]) * i64::from(quantization[i]))
+}
+
+/// Samples of column `x` of the inverse DCT of `block`, see [`SAMPLE_SCALE`]
+fn column(block: &[i64; 64], x: usize) -> [i64; 8]
+{
+    let rows: [i64; 8] = std::array::from_fn(|v| {
+        (0..8).map(|u| COSINES[u][x] * block[8 * v + u]).sum::<i64>() >> 6
+    });
+
+    std::array::from_fn(|y| (0..8).map(|v| COSINES[v][y] * rows[v]).sum())
+}
+
+/// Samples of row `y` of the inverse DCT of `block`, see [`SAMPLE_SCALE`]
+fn row(block: &[i64; 64], y: usize) -> [i64; 8]
+{
+    let columns: [i64; 8] = std::array::from_fn(|u| {
+        (0..8).map(|v| COSINES[v][y] * block[8 * v + u]).sum::<i64>() >> 6
+    });
+
+    std::array::from_fn(|x| (0..8).map(|u| COSINES[u][x] * columns[u]).sum())
+}
+
+/// Predict the first row, 0, and column, 1, of `block` from the blocks above and to
+/// the left, assuming samples continue smoothly across the boundary
+///
+/// The samples on either side of a boundary, half a sample past the block, are sums
+/// of coefficients, those in the interior of `block` being known this solves for
+/// the one on the edge.
+fn edge_predictions(block: &[i16; 64], neighbours: &Neighbours) -> [[i64; 8]; 2]
+{
+    let quantization = neighbours.quantization;
+    let current = dequantize(block, quantization);
+    let mut predictions = [[0; 8]; 2];
+    // 4096 c(u), and the same negated for odd frequencies, past the end of a block
+    let near = |u: usize| if u == 0 { 2896 } else { 4096 };
+    let far = |u: usize| if u.is_multiple_of(2) { near(u) } else { -near(u) };
+    let above = neighbours.above.map(|x| dequantize(&x, quantization));
+    let left = neighbours.left.map(|x| dequantize(&x, quantization));
+
+    for i in 1..8
+    {
+        if let Some(above) = &above
+        {
+            let sum = (0..8).map(|v| far(v) * above[8 * v + i]).sum::<i64>()
+                - (1..8).map(|v| near(v) * current[8 * v + i]).sum::<i64>();
+
+            predictions[0][i] = divide(sum, 2896 * i64::from(quantization[i].max(1)));
+        }
+        if let Some(left) = &left
+        {
+            let sum = (0..8).map(|u| far(u) * left[8 * i + u]).sum::<i64>()
+                - (1..8).map(|u| near(u) * current[8 * i + u]).sum::<i64>();
+
+            predictions[1][i] = divide(sum, 2896 * i64::from(quantization[8 * i].max(1)));
+        }
+    }
+    predictions
+}
+
+/// Predict the DC coefficient of `block` from the blocks above and to the left,
+/// extending the gradient of samples on both sides of the boundaries
+///
+/// Returns the prediction and the spread of the estimates along the boundaries, or
+/// `None` without neighbours.
+fn dc_prediction(block: &[i16; 64], neighbours: &Neighbours) -> Option<(i64, u32)>
+{
+    let quantization = neighbours.quantization;
+    let mut current = dequantize(block, quantization);
+
+    current[0] = 0;
+
+    let mut estimates = vec![];
+
+    if let Some(left) = &neighbours.left
+    {
+        let left = dequantize(left, quantization);
+        let (outer, inner) = (column(&left, 7), column(&left, 6));
+        let (first, second) = (column(&current, 0), column(&current, 1));
+
+        estimates.extend((0..8).map(|y| {
+            (3 * outer[y] - inner[y]) / 2 - (3 * first[y] - second[y]) / 2
+        }));
+    }
+    if let Some(above) = &neighbours.above
+    {
+        let above = dequantize(above, quantization);
+        let (outer, inner) = (row(&above, 7), row(&above, 6));
+        let (first, second) = (row(&current, 0), row(&current, 1));
+
+        estimates.extend((0..8).map(|x| {
+            (3 * outer[x] - inner[x]) / 2 - (3 * first[x] - second[x]) / 2
+        }));
+    }
+    if estimates.is_empty()
+    {
+        return None;
+    }
+    let unit = SAMPLE_SCALE * i64::from(quantization[0].max(1));
+    let sum = estimates.iter().sum::<i64>();
+    let spread = estimates.iter().max().unwrap() - estimates.iter().min().unwrap();
+
+    Some((
+        divide(sum, unit * estimates.len() as i64),
+        u32::try_from(spread / unit).unwrap_or(u32::MAX),
+    ))
+}
+
+/// Code `block`, returns the number of its non zero interior coefficients
+fn code_block<C: Coder>(
+    coder: &mut C, stats: &mut BlockStatistics, block: &mut [i16; 64], neighbours: &Neighbours,
+) -> u32
+{
+    let count = INTERIOR.iter().filter(|x| block[**x] != 0).count();
+    let context = fine_bucket(neighbours.count).min(COUNT_CONTEXTS - 1);
+    let count = code_tree(coder, &mut stats.count[context], 6, count);
+    let mut left = count;
+
+    for (k, &position) in INTERIOR.iter().enumerate()
+    {
+        if left == 0
+        {
+            break;
+        }
+        let at_position = |block: &Option<[i16; 64]>| block.map(|x| x[position].unsigned_abs());
+        let expected = match (at_position(&neighbours.left), at_position(&neighbours.above))
+        {
+            (Some(left), Some(above)) => u32::from(left) + u32::from(above),
+            (Some(one), None) | (None, Some(one)) => 2 * u32::from(one),
+            (None, None) => 0,
+        };
+        let neighbour = bucket(expected).min(NEIGHBOUR_CONTEXTS - 1);
+        let value = i32::from(block[position]);
+
+        // once every coefficient left is non zero there is nothing to code
+        let non_zero = left == 49 - k || {
+            let state = &mut stats.non_zero[k][fine_bucket(left as u32).min(COUNT_CONTEXTS - 1)];
+
+            coder.code(&mut state[neighbour], value != 0)
+        };
+        if !non_zero
+        {
+            continue;
+        }
+        let magnitude = code_magnitude(
+            coder,
+            &mut stats.ac_exponent[k][bucket(left as u32).min(5)][neighbour],
+            &mut stats.ac_mantissa,
+            value.unsigned_abs(),
+        ) as i32;
+        let negative = coder.code(&mut stats.ac_sign[k], value < 0);
+
+        block[position] = (if negative { -magnitude } else { magnitude }) as i16;
+        left -= 1;
+    }
+    let predictions = edge_predictions(block, neighbours);
+    let context = fine_bucket(count as u32).min(COUNT_CONTEXTS - 1);
+    let mut total = count;
+
+    for (edge, predictions) in predictions.iter().enumerate()
+    {
+        let step = if edge == 0 { 1 } else { 8 };
+        let count = (1..8).filter(|i| block[step * i] != 0).count();
+        let count = code_tree(coder, &mut stats.edge_count[edge][context], 3, count);
+        let mut left = count;
+
+        total += count;
+
+        for i in 1..8
+        {
+            if left == 0
+            {
+                break;
+            }
+            let value = i32::from(block[step * i]);
+            let predicted = predictions[i];
+            let magnitude = u32::try_from(predicted.unsigned_abs()).unwrap_or(u32::MAX);
+            let expected = fine_bucket(magnitude).min(PREDICTION_CONTEXTS - 1);
+            // interior coefficients of the same frequency across the edge
+            let next = |j: usize| u32::from(block[step * i + 8 * j / step].unsigned_abs());
+            let interior = bucket(2 * next(1) + next(2) + next(3)).min(5);
+            let non_zero = left == 8 - i || {
+                let state = &mut stats.edge_non_zero[edge][i - 1][left];
+
+                coder.code(&mut state[expected], value != 0)
+            };
+            if !non_zero
+            {
+                continue;
+            }
+            let magnitude = code_magnitude(
+                coder,
+                &mut stats.edge_exponent[edge][i - 1][interior][bucket(left as u32)][expected],
+                &mut stats.edge_mantissa,
+                value.unsigned_abs(),
+            ) as i32;
+            let sign = usize::from(predicted > 0) + usize::from(predicted >= 0);
+            let sign = &mut stats.edge_sign[edge][i - 1][sign];
+            let negative = coder.code(&mut sign[expected], value < 0);
+
+            block[step * i] = (if negative { -magnitude } else { magnitude }) as i16;
+            left -= 1;
+        }
+    }
+    code_dc(coder, stats, block, neighbours, total as u32);
+
+    count as u32
+}
+
+/// Code the DC coefficient of `block` as the difference to its prediction,
+/// `count` is the number of non zero AC coefficients
+fn code_dc<C: Coder>(
+    coder: &mut C, stats: &mut BlockStatistics, block: &mut [i16; 64], neighbours: &Neighbours,
+    count: u32,
+)
+{
+    let (predicted, activity) = match dc_prediction(block, neighbours)
+    {
+        Some((predicted, spread)) => (predicted, 1 + bucket(spread).min(ACTIVITY_CONTEXTS - 2)),
+        None => (0, 0),
+    };
+    // in range, so the difference has at most MAX_DC_EXPONENT bits
+    let predicted = predicted.clamp(i16::MIN.into(), i16::MAX.into());
+    let difference = i64::from(block[0]) - predicted;
+    let context = fine_bucket(count).min(COUNT_CONTEXTS - 1);
+
+    if !coder.code(&mut stats.dc_non_zero[activity][context], difference != 0)
+    {
+        block[0] = predicted as i16;
+        return;
+    }
+    let magnitude = code_magnitude(
+        coder,
+        &mut stats.dc_exponent[activity],
+        &mut stats.dc_mantissa,
+        u32::try_from(difference.unsigned_abs()).unwrap_or(u32::MAX),
+    );
+    let negative = coder.code(&mut stats.dc_sign[activity], difference < 0);
+
+    let magnitude = i64::from(magnitude);
+
+    block[0] = (predicted + if negative { -magnitude } else { magnitude }) as i16;
+}
@@ -0,0 +1,607 @@
+//! QM-coder, the binary arithmetic coder of ITU-T T.81 Annex D
+//!
+//! Arithmetic coded images (SOF9 and SOF10) code every decision of the entropy coding
+//! procedures of Annex F and G, like whether a coefficient is zero, as a single bit
+//! with a probability estimate that adapts to the image. This usually makes files
+//! 8-12% smaller than with Huffman coding.
+//!
+//! Probability estimates are kept per context in [`Statistics`], which the encoder and
+//! decoder update the same way. Each estimate is a byte, the index of the state of
+//! Table D.2 in the lower seven bits and the more probable symbol in the top bit, as
+//! libjpeg stores them.
+//!
+//! Block level coding is in `arithmetic_encoder.rs` and `mcu_arith.rs`.
+
+use std::cmp::Ordering;
+
+use crate::decoder::MAX_COMPONENTS;
+use crate::marker::Marker;
+
+/// Number of DC statistics bins of a table, Table F.4
+pub(crate) const DC_STAT_BINS: usize = 64;
+
+/// Number of AC statistics bins of a table, Table F.5
+pub(crate) const AC_STAT_BINS: usize = 256;
+
+/// Table D.2, the `Qe` value, next state after a less and more probable symbol and
+/// whether a less probable symbol switches the more probable one, of every state
+///
+/// The last state is not part of the table, it is a fixed estimate of 0.5 used to
+/// code signs and DC refinement bits.
+#[rustfmt::skip]
+const QE_TABLE: [(u16, u8, u8, bool); 114] = [
+    (0x5A1D, 1, 1, true), (0x2586, 14, 2, false), (0x1114, 16, 3, false),
+    (0x080B, 18, 4, false), (0x03D8, 20, 5, false), (0x01DA, 23, 6, false),
+    (0x00E5, 25, 7, false), (0x006F, 28, 8, false), (0x0036, 30, 9, false),
+    (0x001A, 33, 10, false), (0x000D, 35, 11, false), (0x0006, 9, 12, false),
+    (0x0003, 10, 13, false), (0x0001, 12, 13, false), (0x5A7F, 15, 15, true),
+    (0x3F25, 36, 16, false), (0x2CF2, 38, 17, false), (0x207C, 39, 18, false),
+    (0x17B9, 40, 19, false), (0x1182, 42, 20, false), (0x0CEF, 43, 21, false),
+    (0x09A1, 45, 22, false), (0x072F, 46, 23, false), (0x055C, 48, 24, false),
+    (0x0406, 49, 25, false), (0x0303, 51, 26, false), (0x0240, 52, 27, false),
+    (0x01B1, 54, 28, false), (0x0144, 56, 29, false), (0x00F5, 57, 30, false),
+    (0x00B7, 59, 31, false), (0x008A, 60, 32, false), (0x0068, 62, 33, false),
+    (0x004E, 63, 34, false), (0x003B, 32, 35, false), (0x002C, 33, 9, false),
+    (0x5AE1, 37, 37, true), (0x484C, 64, 38, false), (0x3A0D, 65, 39, false),
+    (0x2EF1, 67, 40, false), (0x261F, 68, 41, false), (0x1F33, 69, 42, false),
+    (0x19A8, 70, 43, false), (0x1518, 72, 44, false), (0x1177, 73, 45, false),
+    (0x0E74, 74, 46, false), (0x0BFB, 75, 47, false), (0x09F8, 77, 48, false),
+    (0x0861, 78, 49, false), (0x0706, 79, 50, false), (0x05CD, 48, 51, false),
+    (0x04DE, 50, 52, false), (0x040F, 50, 53, false), (0x0363, 51, 54, false),
+    (0x02D4, 52, 55, false), (0x025C, 53, 56, false), (0x01F8, 54, 57, false),
+    (0x01A4, 55, 58, false), (0x0160, 56, 59, false), (0x0125, 57, 60, false),
+    (0x00F6, 58, 61, false), (0x00CB, 59, 62, false), (0x00AB, 61, 63, false),
+    (0x008F, 61, 32, false), (0x5B12, 65, 65, true), (0x4D04, 80, 66, false),
+    (0x412C, 81, 67, false), (0x37D8, 82, 68, false), (0x2FE8, 83, 69, false),
+    (0x293C, 84, 70, false), (0x2379, 86, 71, false), (0x1EDF, 87, 72, false),
+    (0x1AA9, 87, 73, false), (0x174E, 72, 74, false), (0x1424, 72, 75, false),
+    (0x119C, 74, 76, false), (0x0F6B, 74, 77, false), (0x0D51, 75, 78, false),
+    (0x0BB6, 77, 79, false), (0x0A40, 77, 48, false), (0x5832, 80, 81, true),
+    (0x4D1C, 88, 82, false), (0x438E, 89, 83, false), (0x3BDD, 90, 84, false),
+    (0x34EE, 91, 85, false), (0x2EAE, 92, 86, false), (0x299A, 93, 87, false),
+    (0x2516, 86, 71, false), (0x5570, 88, 89, true), (0x4CA9, 95, 90, false),
+    (0x44D9, 96, 91, false), (0x3E22, 97, 92, false), (0x3824, 99, 93, false),
+    (0x32B4, 99, 94, false), (0x2E17, 93, 86, false), (0x56A8, 95, 96, true),
+    (0x4F46, 101, 97, false), (0x47E5, 102, 98, false), (0x41CF, 103, 99, false),
+    (0x3C3D, 104, 100, false), (0x375E, 99, 93, false), (0x5231, 105, 102, false),
+    (0x4C0F, 106, 103, false), (0x4639, 107, 104, false), (0x415E, 103, 99, false),
+    (0x5627, 105, 106, true), (0x50E7, 108, 107, false), (0x4B85, 109, 103, false),
+    (0x5597, 110, 109, false), (0x504F, 111, 107, false), (0x5A10, 110, 111, true),
+    (0x5522, 112, 109, false), (0x59EB, 112, 111, true), (0x5A1D, 113, 113, false),
+];
+
+/// Returns `Qe` and the estimates after a less and more probable symbol for the
+/// estimate `state`
+fn estimate(state: u8) -> (u32, u8, u8)
+{
+    let (qe, next_lps, next_mps, switch) = QE_TABLE[usize::from(state & 0x7F)];
+    let mps = state & 0x80;
+
+    (u32::from(qe), (mps ^ (u8::from(switch) << 7)) | next_lps, mps | next_mps)
+}
+
+/// Conditioning of the statistics, set by DAC segments
+///
+/// ITU-T T.81 Annex F.1.4.4, DC differences are classified as small or large by
+/// `L` and `U`, and AC magnitudes use different bins below and above `Kx`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Conditioning
+{
+    /// `L` and `U` of each DC table
+    pub dc: [(u8, u8); MAX_COMPONENTS],
+    /// `Kx` of each AC table
+    pub ac: [u8; MAX_COMPONENTS],
+}
+
+impl Default for Conditioning
+{
+    /// The conditioning used when there is no DAC segment
+    fn default() -> Self
+    {
+        Conditioning {
+            dc: [(0, 1); MAX_COMPONENTS],
+            ac: [5; MAX_COMPONENTS],
+        }
+    }
+}
+
+impl Conditioning
+{
+    /// Statistics bin of the next DC difference of a component, after coding a
+    /// difference of `category` bits (zero for a zero difference) and sign `negative`
+    ///
+    /// See F.1.4.4.1.2
+    pub fn dc_context(&self, table: usize, category: usize, negative: bool) -> usize
+    {
+        let (lower, upper) = self.dc[table];
+        // the difference is below 2^(L-1), or above 2^(U-1)
+        let magnitude = (1_u32 << category) >> 1;
+
+        if magnitude < (1 << lower) >> 1
+        {
+            0
+        }
+        else if magnitude > (1 << upper) >> 1
+        {
+            12 + 4 * usize::from(negative)
+        }
+        else
+        {
+            4 + 4 * usize::from(negative)
+        }
+    }
+
+    /// First statistics bin of AC magnitude categories above one for coefficient `k`
+    /// of `table`, see Table F.5
+    pub fn ac_magnitude_bin(&self, table: usize, k: usize) -> usize
+    {
+        if k <= usize::from(self.ac[table])
+        {
+            189
+        }
+        else
+        {
+            217
+        }
+    }
+}
+
+/// Probability estimates of a scan
+#[derive(Clone)]
+pub(crate) struct Statistics
+{
+    /// DC bins of each table
+    pub dc:    [[u8; DC_STAT_BINS]; MAX_COMPONENTS],
+    /// AC bins of each table
+    pub ac:    [[u8; AC_STAT_BINS]; MAX_COMPONENTS],
+    /// Estimate with fixed probability 0.5
+    pub fixed: u8,
+}
+
+impl Statistics
+{
+    /// Statistics of the start of a scan or restart interval, all bins are in state
+    /// zero
+    pub fn new() -> Statistics
+    {
+        Statistics {
+            dc:    [[0; DC_STAT_BINS]; MAX_COMPONENTS],
+            ac:    [[0; AC_STAT_BINS]; MAX_COMPONENTS],
+            fixed: 113,
+        }
+    }
+}
+
+/// The QM encoder, Annex D.1
+pub(crate) struct QmEncoder
+{
+    /// Base of the coding interval
+    c:        u32,
+    /// Size of the coding interval
+    a:        u32,
+    /// Number of stacked 0xFF bytes, they may still be changed by a carry
+    sc:       u32,
+    /// Number of zero bytes not written yet, trailing zeroes are dropped
+    zc:       u32,
+    /// Bits of `c` left before a byte is ready
+    ct:       u32,
+    /// Last byte out of `c`, it may still be changed by a carry
+    buffer:   Option<u8>,
+    /// Coded bytes, with 0xFF bytes stuffed
+    pub data: Vec<u8>,
+}
+
+impl QmEncoder
+{
+    pub fn new() -> QmEncoder
+    {
+        QmEncoder {
+            c:      0,
+            a:      0x10000,
+            sc:     0,
+            zc:     0,
+            ct:     11,
+            buffer: None,
+            data:   vec![],
+        }
+    }
+
+    /// Code the decision `value` with the estimate in `state`, updating it
+    pub fn encode(&mut self, state: &mut u8, value: bool)
+    {
+        let (qe, after_lps, after_mps) = estimate(*state);
+
+        self.a -= qe;
+
+        if value == (*state >> 7 == 1)
+        {
+            if self.a >= 0x8000
+            {
+                return;
+            }
+            if self.a < qe
+            {
+                self.c += self.a;
+                self.a = qe;
+            }
+            *state = after_mps;
+        }
+        else
+        {
+            // less probable symbol, the sub-intervals are exchanged if the
+            // one of the more probable symbol is smaller
+            if self.a >= qe
+            {
+                self.c += self.a;
+                self.a = qe;
+            }
+            *state = after_lps;
+        }
+        // renormalization, D.1.6
+        while self.a < 0x8000
+        {
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+
+            if self.ct == 0
+            {
+                self.byte_out();
+                self.c &= 0x7FFFF;
+                self.ct += 8;
+            }
+        }
+    }
+
+    /// Move the next byte out of `c`
+    #[allow(clippy::cast_possible_truncation)]
+    fn byte_out(&mut self)
+    {
+        let byte = self.c >> 19;
+
+        match byte.cmp(&0xFF)
+        {
+            Ordering::Greater =>
+            {
+                self.carry();
+                // the spacer bits of `c` make sure this isn't 0xFF
+                self.buffer = Some((byte & 0xFF) as u8);
+            }
+            Ordering::Equal => self.sc += 1,
+            Ordering::Less =>
+            {
+                self.flush_stacked();
+                self.buffer = Some(byte as u8);
+            }
+        }
+    }
+
+    /// Propagate a carry into the buffered byte, stacked 0xFF bytes become zeroes
+    fn carry(&mut self)
+    {
+        if let Some(byte) = self.buffer
+        {
+            self.flush_zeroes();
+            self.emit(byte + 1);
+        }
+        self.zc += self.sc;
+        self.sc = 0;
+    }
+
+    /// Write the buffered byte and stacked 0xFF bytes, no carry can reach them
+    fn flush_stacked(&mut self)
+    {
+        match self.buffer
+        {
+            Some(0) => self.zc += 1,
+            Some(byte) =>
+            {
+                self.flush_zeroes();
+                self.emit(byte);
+            }
+            None => (),
+        }
+        if self.sc > 0
+        {
+            self.flush_zeroes();
+
+            for _ in 0..self.sc
+            {
+                self.emit(0xFF);
+            }
+            self.sc = 0;
+        }
+    }
+
+    fn flush_zeroes(&mut self)
+    {
+        for _ in 0..self.zc
+        {
+            self.data.push(0);
+        }
+        self.zc = 0;
+    }
+
+    /// Write a byte, stuffing a zero after 0xFF
+    fn emit(&mut self, byte: u8)
+    {
+        self.data.push(byte);
+
+        if byte == 0xFF
+        {
+            self.data.push(0);
+        }
+    }
+
+    /// Terminate the coded data, D.1.8
+    ///
+    /// Of the values in the final interval the one with most trailing zero bits is
+    /// written, without its trailing zero bytes.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn finish(&mut self)
+    {
+        let temp = (self.a - 1 + self.c) & 0xFFFF_0000;
+
+        self.c = if temp < self.c { temp + 0x8000 } else { temp };
+        self.c <<= self.ct;
+
+        if self.c & 0xF800_0000 != 0
+        {
+            self.carry();
+        }
+        else
+        {
+            self.flush_stacked();
+        }
+        self.buffer = None;
+
+        if self.c & 0x07FF_F800 != 0
+        {
+            self.flush_zeroes();
+            self.emit(((self.c >> 19) & 0xFF) as u8);
+
+            if self.c & 0x0007_F800 != 0
+            {
+                self.emit(((self.c >> 11) & 0xFF) as u8);
+            }
+        }
+    }
+}
+
+/// The QM decoder, Annex D.2
+///
+/// Data is read from a slice starting at the entropy coded data, a marker ends it and
+/// zeroes are decoded after it.
+pub(crate) struct QmDecoder<'a>
+{
+    data:     &'a [u8],
+    /// Position in `data` of the next byte
+    position: usize,
+    /// Offset into the coding interval, with bits not yet used in the lower `ct` bits
+    c:        u32,
+    /// Size of the coding interval
+    a:        u32,
+    /// Bits of `c` not yet used, negative until the first two bytes are read
+    ct:       i32,
+    /// Marker found in the data
+    marker:   Option<Marker>,
+    /// Zero bytes decoded after the marker or the end of the data
+    past_end: usize,
+}
+
+impl<'a> QmDecoder<'a>
+{
+    pub fn new(data: &'a [u8]) -> QmDecoder<'a>
+    {
+        let mut decoder = QmDecoder {
+            data,
+            position: 0,
+            c: 0,
+            a: 0,
+            ct: 0,
+            marker: None,
+            past_end: 0,
+        };
+        decoder.reset();
+        decoder
+    }
+
+    /// Start decoding a new interval, at a restart marker
+    pub fn reset(&mut self)
+    {
+        self.c = 0;
+        self.a = 0;
+        // read two bytes before decoding
+        self.ct = -16;
+    }
+
+    /// Number of bytes of the data read
+    pub fn position(&self) -> usize
+    {
+        self.position
+    }
+
+    /// Number of zero bytes decoded after the marker ending the coded data
+    ///
+    /// The encoder leaves out trailing zero bytes, so a few are normal.
+    pub fn past_end(&self) -> usize
+    {
+        self.past_end
+    }
+
+    /// Returns the next byte of coded data, or zero after a marker
+    fn next_byte(&mut self) -> u32
+    {
+        if self.marker.is_some()
+        {
+            self.past_end += 1;
+            return 0;
+        }
+        let Some(&byte) = self.data.get(self.position)
+        else
+        {
+            warn!("Premature end of buffer, image may be truncated");
+            self.marker = Some(Marker::EOI);
+            self.past_end += 1;
+            return 0;
+        };
+        self.position += 1;
+
+        if byte != 0xFF
+        {
+            return u32::from(byte);
+        }
+        // skip fill bytes, 0xFF00 is a stuffed 0xFF
+        while let Some(&next) = self.data.get(self.position)
+        {
+            self.position += 1;
+
+            match next
+            {
+                0xFF => {}
+                0 => return 0xFF,
+                _ =>
+                {
+                    self.marker = Marker::from_u8(next);
+                    self.past_end += 1;
+                    return 0;
+                }
+            }
+        }
+        self.marker = Some(Marker::EOI);
+        self.past_end += 1;
+
+        0
+    }
+
+    /// Returns the marker ending the coded data, skipping data not read yet
+    ///
+    /// `None` if the data ends without a marker.
+    pub fn next_marker(&mut self) -> Option<Marker>
+    {
+        while self.marker.is_none() && self.position < self.data.len()
+        {
+            self.next_byte();
+        }
+        self.marker.take()
+    }
+
+    /// Put back a marker returned by [`next_marker`](Self::next_marker), the data
+    /// ends there
+    pub fn set_marker(&mut self, marker: Marker)
+    {
+        self.marker = Some(marker);
+    }
+
+    /// Decode a decision with the estimate in `state`, updating it
+    pub fn decode(&mut self, state: &mut u8) -> bool
+    {
+        // renormalization and data input, D.2.6
+        while self.a < 0x8000
+        {
+            self.ct -= 1;
+
+            if self.ct < 0
+            {
+                self.c = (self.c << 8) | self.next_byte();
+                self.ct += 8;
+
+                if self.ct < 0
+                {
+                    self.ct += 1;
+
+                    if self.ct == 0
+                    {
+                        // the first two bytes are in, this becomes 0x10000 below
+                        self.a = 0x8000;
+                    }
+                }
+            }
+            self.a <<= 1;
+        }
+        let (qe, after_lps, after_mps) = estimate(*state);
+        let mut symbol = *state >> 7 == 1;
+
+        self.a -= qe;
+
+        let boundary = self.a << self.ct;
+
+        // the sub-intervals are exchanged if the one of the more probable symbol is
+        // the smaller one, D.2.4
+        if self.c >= boundary
+        {
+            self.c -= boundary;
+
+            if self.a < qe
+            {
+                *state = after_mps;
+            }
+            else
+            {
+                *state = after_lps;
+                symbol = !symbol;
+            }
+            self.a = qe;
+        }
+        else if self.a < 0x8000
+        {
+            if self.a < qe
+            {
+                *state = after_lps;
+                symbol = !symbol;
+            }
+            else
+            {
+                *state = after_mps;
+            }
+        }
+        symbol
+    }
+}
+
+//---------------------------------------------
+// TEST
+//----------------------------------------------
+
+#[test]
+fn qm_coder_round_trip()
+{
+    // decisions with skewed and changing probabilities, in a few contexts
+    let mut seed = 0x1234_5678_u32;
+    let decisions = (0..20000)
+        .map(|i| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+
+            let context = i % 3;
+            let value = seed % 16 < [1, 8, if i < 10000 { 15 } else { 2 }][context];
+
+            (context, value)
+        })
+        .collect::<Vec<_>>();
+
+    let mut states = [0_u8; 3];
+    let mut encoder = QmEncoder::new();
+
+    for &(context, value) in &decisions
+    {
+        encoder.encode(&mut states[context], value);
+    }
+    encoder.finish();
+
+    assert!(encoder.data.len() < 20000 / 8);
+
+    let mut data = encoder.data.clone();
+
+    data.extend_from_slice(&[0xFF, Marker::EOI.to_u8().unwrap()]);
+
+    let mut states = [0_u8; 3];
+    let mut decoder = QmDecoder::new(&data);
+
+    for &(context, value) in &decisions
+    {
+        assert_eq!(decoder.decode(&mut states[context]), value);
+    }
+    assert_eq!(decoder.next_marker(), Some(Marker::EOI));
+}
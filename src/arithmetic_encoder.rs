@@ -0,0 +1,244 @@
+//! Arithmetic entropy encoding of blocks
+//!
+//! This follows ITU-T T.81 Annex F.1.4 for sequential scans and G.1.3 for progressive
+//! ones, as libjpeg's `jcarith.c` does. Every scan is coded with a new
+//! [`ArithmeticEncoder`], and the default conditioning, so images need no DAC
+//! segment.
+//!
+//! The magnitude of a value is coded as its number of bits, one decision per bit in
+//! consecutive statistics bins, followed by the bits below the top one.
+
+use crate::arithmetic::{Conditioning, QmEncoder, Statistics};
+use crate::decoder::MAX_COMPONENTS;
+use crate::marker::Marker;
+use crate::misc::UN_ZIGZAG;
+
+/// Codes blocks of a scan
+pub(crate) struct ArithmeticEncoder
+{
+    coder:        QmEncoder,
+    statistics:   Statistics,
+    conditioning: Conditioning,
+    /// DC statistics bin of the next block of each component
+    dc_context:   [usize; MAX_COMPONENTS],
+    /// Shifted DC coefficient of the previous block of each component
+    dc_pred:      [i32; MAX_COMPONENTS],
+}
+
+impl ArithmeticEncoder
+{
+    pub fn new() -> ArithmeticEncoder
+    {
+        ArithmeticEncoder {
+            coder:        QmEncoder::new(),
+            statistics:   Statistics::new(),
+            conditioning: Conditioning::default(),
+            dc_context:   [0; MAX_COMPONENTS],
+            dc_pred:      [0; MAX_COMPONENTS],
+        }
+    }
+
+    /// Terminate the scan and return its coded data
+    pub fn finish(mut self) -> Vec<u8>
+    {
+        self.coder.finish();
+        self.coder.data
+    }
+
+    /// Terminate the interval, write restart marker `number` and start a new interval
+    ///
+    /// Like at the start of a scan, the statistics and DC predictions are reset.
+    pub fn restart(&mut self, number: u8)
+    {
+        self.coder.finish();
+
+        let mut data = std::mem::take(&mut self.coder.data);
+
+        data.extend_from_slice(&[0xFF, Marker::RST(number).to_u8().unwrap()]);
+
+        self.coder = QmEncoder::new();
+        self.coder.data = data;
+        self.statistics = Statistics::new();
+        self.dc_context = [0; MAX_COMPONENTS];
+        self.dc_pred = [0; MAX_COMPONENTS];
+    }
+
+    /// Code a block of a sequential scan
+    ///
+    /// `component` is the position of the component in the frame, `dc_table` and
+    /// `ac_table` the conditioning tables of the scan.
+    pub fn encode_sequential(
+        &mut self, component: usize, dc_table: usize, ac_table: usize, block: &[i16; 64],
+    )
+    {
+        self.encode_dc_first(component, dc_table, block, 0);
+        self.encode_ac_first(ac_table, block, 1, 63, 0);
+    }
+
+    /// Code the DC coefficient of a block in a first DC scan, F.1.4.1
+    pub fn encode_dc_first(&mut self, component: usize, table: usize, block: &[i16; 64], al: u8)
+    {
+        let dc = i32::from(block[0]) >> al;
+        let difference = dc - self.dc_pred[component];
+        let stats = &mut self.statistics.dc[table];
+        let context = self.dc_context[component];
+
+        self.dc_pred[component] = dc;
+
+        self.coder.encode(&mut stats[context], difference != 0);
+
+        if difference == 0
+        {
+            self.dc_context[component] = 0;
+            return;
+        }
+        let negative = difference < 0;
+
+        self.coder.encode(&mut stats[context + 1], negative);
+
+        let sign_bin = context + 2 + usize::from(negative);
+        let category = encode_magnitude(
+            &mut self.coder,
+            stats,
+            |bit| if bit == 0 { sign_bin } else { 19 + bit },
+            difference.unsigned_abs() - 1,
+        );
+
+        self.dc_context[component] = self.conditioning.dc_context(table, category, negative);
+    }
+
+    /// Code the DC coefficient of a block in a DC refinement scan, the next bit as is
+    pub fn encode_dc_refine(&mut self, block: &[i16; 64], al: u8)
+    {
+        let bit = (block[0] >> al) & 1 == 1;
+
+        self.coder.encode(&mut self.statistics.fixed, bit);
+    }
+
+    /// Code the band of a block in a first AC scan, F.1.4.2
+    pub fn encode_ac_first(
+        &mut self, table: usize, block: &[i16; 64], spec_start: u8, spec_end: u8, al: u8,
+    )
+    {
+        let (spec_start, spec_end) = (usize::from(spec_start), usize::from(spec_end));
+        let magnitude = |k: usize| i32::from(block[UN_ZIGZAG[k]]).unsigned_abs() >> al;
+        // after the last non zero coefficient the end of block is coded
+        let end = (spec_start..=spec_end)
+            .rev()
+            .find(|k| magnitude(*k) != 0)
+            .unwrap_or(0);
+
+        let stats = &mut self.statistics.ac[table];
+        let mut k = spec_start;
+
+        while k <= end
+        {
+            let mut st = 3 * (k - 1);
+
+            self.coder.encode(&mut stats[st], false);
+
+            // zero coefficients are coded as such, they have no runs
+            while magnitude(k) == 0
+            {
+                self.coder.encode(&mut stats[st + 1], false);
+                st += 3;
+                k += 1;
+            }
+            self.coder.encode(&mut stats[st + 1], true);
+            self.coder.encode(&mut self.statistics.fixed, block[UN_ZIGZAG[k]] < 0);
+
+            let higher = self.conditioning.ac_magnitude_bin(table, k);
+
+            encode_magnitude(
+                &mut self.coder,
+                stats,
+                |bit| if bit < 2 { st + 2 } else { higher + bit - 2 },
+                magnitude(k) - 1,
+            );
+            k += 1;
+        }
+        if k <= spec_end
+        {
+            self.coder.encode(&mut stats[3 * (k - 1)], true);
+        }
+    }
+
+    /// Code the band of a block in an AC refinement scan, G.1.3.3
+    ///
+    /// Coefficients already non zero get their next bit, others whether they become
+    /// non zero, and then their sign.
+    pub fn encode_ac_refine(
+        &mut self, table: usize, block: &[i16; 64], spec_start: u8, spec_end: u8, al: u8,
+    )
+    {
+        let (spec_start, spec_end) = (usize::from(spec_start), usize::from(spec_end));
+        let magnitude = |k: usize| i32::from(block[UN_ZIGZAG[k]]).unsigned_abs() >> al;
+        let end = (spec_start..=spec_end)
+            .rev()
+            .find(|k| magnitude(*k) != 0)
+            .unwrap_or(0);
+        // the end of block of the previous scans, no end of block is coded before it
+        let previous_end = (spec_start..=end)
+            .rev()
+            .find(|k| magnitude(*k) > 1)
+            .unwrap_or(0);
+
+        let stats = &mut self.statistics.ac[table];
+        let mut k = spec_start;
+
+        while k <= end
+        {
+            let mut st = 3 * (k - 1);
+
+            if k > previous_end
+            {
+                self.coder.encode(&mut stats[st], false);
+            }
+            while magnitude(k) == 0
+            {
+                self.coder.encode(&mut stats[st + 1], false);
+                st += 3;
+                k += 1;
+            }
+            if magnitude(k) > 1
+            {
+                self.coder.encode(&mut stats[st + 2], magnitude(k) & 1 == 1);
+            }
+            else
+            {
+                self.coder.encode(&mut stats[st + 1], true);
+                self.coder.encode(&mut self.statistics.fixed, block[UN_ZIGZAG[k]] < 0);
+            }
+            k += 1;
+        }
+        if k <= spec_end
+        {
+            self.coder.encode(&mut stats[3 * (k - 1)], true);
+        }
+    }
+}
+
+/// Code `value`, a magnitude less one, F.1.4.4.1.3
+///
+/// `bin` gives the statistics bin of each bit of the magnitude category, the bits of
+/// the value are coded in the bin 14 after the last one. Returns the category.
+fn encode_magnitude(
+    coder: &mut QmEncoder, stats: &mut [u8], bin: impl Fn(usize) -> usize, value: u32,
+) -> usize
+{
+    let category = (u32::BITS - value.leading_zeros()) as usize;
+
+    for bit in 0..category
+    {
+        coder.encode(&mut stats[bin(bit)], true);
+    }
+    let st = bin(category);
+
+    coder.encode(&mut stats[st], false);
+
+    for bit in (0..category.saturating_sub(1)).rev()
+    {
+        coder.encode(&mut stats[st + 14], (value >> bit) & 1 == 1);
+    }
+    category
+}
@@ -104,6 +104,8 @@ pub(crate) struct BitStream
     pub(crate) bits_left: u8,
     /// Did we find a marker(RST/EOF) during decoding?
     pub marker:           Option<Marker>,
+    /// Did we read past the end of the data, filling the buffer with zeroes?
+    pub(crate) padded:    bool,
 
     /// Progressive decoding
     pub successive_high: u8,
@@ -123,6 +125,7 @@ impl BitStream
             aligned_buffer:  0,
             bits_left:       0,
             marker:          None,
+            padded:          false,
             successive_high: 0,
             successive_low:  0,
             spec_start:      0,
@@ -140,6 +143,7 @@ impl BitStream
             aligned_buffer: 0,
             bits_left: 0,
             marker: None,
+            padded: false,
             successive_high: ah,
             successive_low: al,
             spec_start,
@@ -248,6 +252,7 @@ impl BitStream
             refill!(self.buffer, byte, self.bits_left);
             refill!(self.buffer, byte, self.bits_left);
             refill!(self.buffer, byte, self.bits_left);
+            self.padded |= reader.position() as usize > reader.get_ref().len();
             // Construct an MSB buffer whose top bits are the bitstream we are currently holding.
             self.aligned_buffer = self.buffer << (64 - self.bits_left);
         }
@@ -275,7 +280,8 @@ impl BitStream
     {
         let (mut symbol, r);
 
-        if self.bits_left < 16
+        // a DC code and its extra bits take up to 27 bits
+        if self.bits_left < 27
         {
             self.refill(reader)?;
         };
@@ -678,6 +684,18 @@ impl BitStream
         self.aligned_buffer = 0;
         self.eob_run = 0;
     }
+
+    /// Discard the first `n` bits of the stream, to start decoding in the middle of a
+    /// byte
+    pub(crate) fn skip_bits(
+        &mut self, reader: &mut Cursor<Vec<u8>>, n: u8,
+    ) -> Result<(), DecodeErrors>
+    {
+        self.refill(reader)?;
+        self.drop_bits(n);
+
+        Ok(())
+    }
 }
 
 /// Do the equivalent of JPEG HUFF_EXTEND
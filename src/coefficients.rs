@@ -0,0 +1,388 @@
+//! Decoding of quantized DCT coefficients
+//!
+//! Unlike the main decoding path in `mcu.rs`, which decodes a row of MCU's and
+//! immediately sends it for post processing, routines here decode the whole image
+//! into per component coefficient planes, without dequantization or IDCT.
+//!
+//! # Layout
+//! Each component is stored as a plane of 64 coefficient blocks in raster order,
+//! with each block in natural (not zig-zag) order, the same layout progressive decoding
+//! uses.
+//!
+//! A plane is `mcu_x * horizontal_sample` blocks wide and `mcu_y * vertical_sample`
+//! blocks high, blocks in the padding that no scan covers are left as zeroes.
+//!
+//! [`Decoder::decode_coefficients`] exposes the planes together with what is needed
+//! to interpret them, for tools working on compressed data like lossless transforms
+//! or steganalysis.
+//!
+//! # Examples
+//! Count non-zero AC coefficients of the luminance component
+//! ```no_run
+//! use zune_jpeg::Decoder;
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//! let image = Decoder::new().decode_coefficients(&img_data).unwrap();
+//!
+//! let non_zero = image.components[0]
+//!     .coefficients
+//!     .chunks_exact(64)
+//!     .flat_map(|block| &block[1..])
+//!     .filter(|&&x| x != 0)
+//!     .count();
+//! ```
+
+use std::io::Cursor;
+
+use crate::bitstream::BitStream;
+use crate::errors::DecodeErrors;
+use crate::headers::{parse_huffman, parse_sos};
+use crate::marker::Marker;
+use crate::mcu::DCT_BLOCK;
+use crate::mcu_prog::get_marker;
+use crate::{ColorSpace, Decoder};
+
+/// Quantized DCT coefficients of a single component
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ComponentCoefficients
+{
+    /// Component identifier as stored in the frame header
+    pub id:                 u8,
+    /// Horizontal sampling factor
+    pub horizontal_sample:  u8,
+    /// Vertical sampling factor
+    pub vertical_sample:    u8,
+    /// Width of the component in samples, smaller than the image width when the
+    /// component is subsampled
+    pub width:              usize,
+    /// Height of the component in samples
+    pub height:             usize,
+    /// Number of blocks in a row of `coefficients`
+    ///
+    /// This covers whole MCUs, so it may be larger than `width` divided by 8 rounded
+    /// up, blocks past the edge of the component are padding.
+    pub blocks_wide:        usize,
+    /// Number of rows of blocks in `coefficients`
+    pub blocks_high:        usize,
+    /// Quantization table of the component, in natural (not zig-zag) order
+    pub quantization_table: [u16; 64],
+    /// Blocks of 64 coefficients in raster order, each in natural order
+    ///
+    /// Coefficients are quantized, multiply them by `quantization_table` to get
+    /// DCT coefficients.
+    pub coefficients:       Vec<i16>,
+}
+
+impl ComponentCoefficients
+{
+    /// Returns the coefficients of the block at column `x` and row `y`
+    ///
+    /// # Panics
+    /// If the block is outside the plane
+    #[must_use]
+    pub fn block(&self, x: usize, y: usize) -> &[i16; 64]
+    {
+        assert!(x < self.blocks_wide && y < self.blocks_high, "Block out of bounds");
+
+        let start = DCT_BLOCK * (y * self.blocks_wide + x);
+
+        self.coefficients[start..start + DCT_BLOCK].try_into().unwrap()
+    }
+}
+
+/// Quantized DCT coefficients of an image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Coefficients
+{
+    /// Width of the image
+    pub width:      u16,
+    /// Height of the image
+    pub height:     u16,
+    /// Colorspace of the components
+    pub colorspace: ColorSpace,
+    /// Components in frame header order
+    pub components: Vec<ComponentCoefficients>,
+}
+
+impl Decoder
+{
+    /// Decode the quantized DCT coefficients of an image
+    ///
+    /// Entropy coded data is decoded for sequential and progressive images, Huffman or
+    /// arithmetic coded, but coefficients are neither dequantized nor transformed, see the
+    /// [module docs](crate::coefficients) for the layout.
+    ///
+    /// # Errors
+    /// See DecodeErrors enum for list of possible errors during decoding
+    pub fn decode_coefficients(&mut self, buf: &[u8]) -> Result<Coefficients, DecodeErrors>
+    {
+        let mut reader = Cursor::new(buf.to_vec());
+
+        self.decode_headers_internal(&mut reader)?;
+
+        let planes = self.decode_coefficients_internal(&mut reader, false)?;
+
+        Ok(self.planes_to_coefficients(planes))
+    }
+
+    /// Wrap coefficient planes of the components of the frame, as decoded by
+    /// [`decode_coefficients_internal`](Self::decode_coefficients_internal)
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn planes_to_coefficients(&self, planes: Vec<Vec<i16>>) -> Coefficients
+    {
+        let components = planes
+            .into_iter()
+            .enumerate()
+            .map(|(pos, coefficients)| {
+                let component = &self.components[pos];
+                let (blocks_wide, blocks_high) = self.coefficient_plane_dimensions(pos);
+
+                ComponentCoefficients {
+                    id: component.id,
+                    horizontal_sample: component.horizontal_sample as u8,
+                    vertical_sample: component.vertical_sample as u8,
+                    width: (usize::from(self.info.width) * component.horizontal_sample)
+                        .div_ceil(self.h_max),
+                    height: (usize::from(self.info.height) * component.vertical_sample)
+                        .div_ceil(self.v_max),
+                    blocks_wide,
+                    blocks_high,
+                    quantization_table: component.quantization_table.0.map(|x| x as u16),
+                    coefficients,
+                }
+            })
+            .collect();
+
+        Coefficients {
+            width: self.info.width,
+            height: self.info.height,
+            colorspace: self.input_colorspace,
+            components,
+        }
+    }
+
+    /// Number of blocks in a row and column of the coefficient plane for component
+    /// at `pos`
+    pub(crate) fn coefficient_plane_dimensions(&self, pos: usize) -> (usize, usize)
+    {
+        let component = &self.components[pos];
+
+        (
+            self.mcu_x * component.horizontal_sample,
+            self.mcu_y * component.vertical_sample,
+        )
+    }
+
+    /// Decode quantized coefficients for all components of the image.
+    ///
+    /// Headers should already be decoded, and `reader` positioned at the start of the
+    /// first scan. With `skip_ac`, progressive scans of AC coefficients are skipped
+    /// without decoding them, leaving those coefficients zero. Sequential scans code
+    /// AC coefficients between DC coefficients, so they are always decoded.
+    pub(crate) fn decode_coefficients_internal(
+        &mut self, reader: &mut Cursor<Vec<u8>>, skip_ac: bool,
+    ) -> Result<Vec<Vec<i16>>, DecodeErrors>
+    {
+        if self.is_arithmetic
+        {
+            return self.decode_arithmetic_coefficients(reader, skip_ac);
+        }
+        if self.is_progressive
+        {
+            let (planes, _) = self.decode_progressive_coefficients(reader, skip_ac)?;
+
+            return Ok(planes
+                .into_iter()
+                .take(self.input_colorspace.num_components())
+                .collect());
+        }
+
+        self.check_component_dimensions()?;
+
+        let mut planes = (0..self.input_colorspace.num_components())
+            .map(|pos| {
+                let (width, height) = self.coefficient_plane_dimensions(pos);
+
+                vec![0; width * height * DCT_BLOCK]
+            })
+            .collect::<Vec<Vec<i16>>>();
+
+        let mut stream = BitStream::new();
+        let mut seen_scans = 1;
+
+        self.decode_sequential_scan(reader, &mut stream, &mut planes)?;
+
+        // A sequential image may have more than one scan if components are not interleaved
+        loop
+        {
+            let Some(marker) = get_marker(reader, &mut stream)
+            else
+            {
+                warn!("Premature end of buffer, image may be truncated");
+                break;
+            };
+
+            match marker
+            {
+                Marker::EOI => break,
+                Marker::SOS =>
+                {
+                    parse_sos(reader, self)?;
+
+                    seen_scans += 1;
+
+                    if seen_scans > self.options.get_max_scans()
+                    {
+                        return Err(DecodeErrors::Format(format!(
+                            "Too many scans, exceeded limit of {}",
+                            self.options.get_max_scans()
+                        )));
+                    }
+
+                    self.decode_sequential_scan(reader, &mut stream, &mut planes)?;
+                }
+                Marker::DHT =>
+                {
+                    parse_huffman(self, reader)?;
+                }
+                Marker::RST(_) =>
+                {}
+                _ =>
+                {
+                    self.parse_marker_inner(marker, reader)?;
+                }
+            }
+        }
+
+        Ok(planes)
+    }
+
+    /// Decode the entropy coded data of a single sequential scan into `planes`
+    #[allow(clippy::similar_names)]
+    fn decode_sequential_scan(
+        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, planes: &mut [Vec<i16>],
+    ) -> Result<(), DecodeErrors>
+    {
+        stream.reset();
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        self.todo = if self.restart_interval == 0
+        {
+            0x7fff_ffff
+        }
+        else
+        {
+            self.restart_interval
+        };
+
+        let num_scans = usize::from(self.num_scans);
+
+        for &k in &self.z_order[..num_scans]
+        {
+            if k >= planes.len()
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Cannot find component {k}, corrupt image"
+                )));
+            }
+        }
+
+        if num_scans == 1
+        {
+            // Non-interleaved scan, data units are in raster order over the component,
+            // which may cover fewer blocks than the plane.
+            let k = self.z_order[0];
+            let (stride, _) = self.coefficient_plane_dimensions(k);
+            let component = &self.components[k];
+
+            let comp_width =
+                (usize::from(self.info.width) * component.horizontal_sample).div_ceil(self.h_max);
+            let comp_height =
+                (usize::from(self.info.height) * component.vertical_sample).div_ceil(self.v_max);
+            let (blocks_x, blocks_y) = (comp_width.div_ceil(8), comp_height.div_ceil(8));
+
+            for block_y in 0..blocks_y
+            {
+                for block_x in 0..blocks_x
+                {
+                    let start = DCT_BLOCK * (block_y * stride + block_x);
+
+                    self.decode_block_into(reader, stream, k, &mut planes[k], start)?;
+
+                    self.count_down_restart(stream)?;
+                }
+            }
+        }
+        else
+        {
+            for mcu_y in 0..self.mcu_y
+            {
+                for mcu_x in 0..self.mcu_x
+                {
+                    for i in 0..num_scans
+                    {
+                        let k = self.z_order[i];
+                        let (stride, _) = self.coefficient_plane_dimensions(k);
+                        let (h_samp, v_samp) = (
+                            self.components[k].horizontal_sample,
+                            self.components[k].vertical_sample,
+                        );
+
+                        for v in 0..v_samp
+                        {
+                            for h in 0..h_samp
+                            {
+                                let block_y = mcu_y * v_samp + v;
+                                let block_x = mcu_x * h_samp + h;
+                                let start = DCT_BLOCK * (block_y * stride + block_x);
+
+                                self.decode_block_into(reader, stream, k, &mut planes[k], start)?;
+                            }
+                        }
+                    }
+                    self.count_down_restart(stream)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decode a single block of component `k` into `plane[start..start+64]`
+    fn decode_block_into(
+        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, k: usize,
+        plane: &mut [i16], start: usize,
+    ) -> Result<(), DecodeErrors>
+    {
+        let component = &mut self.components[k];
+        // tables were checked to exist in check_tables
+        let dc_table = self.dc_huffman_tables[component.dc_huff_table & 3]
+            .as_ref()
+            .ok_or(DecodeErrors::FormatStatic("No DC table for component"))?;
+        let ac_table = self.ac_huffman_tables[component.ac_huff_table & 3]
+            .as_ref()
+            .ok_or(DecodeErrors::FormatStatic("No AC table for component"))?;
+
+        let block: &mut [i16; 64] = plane
+            .get_mut(start..start + DCT_BLOCK)
+            .ok_or(DecodeErrors::FormatStatic("Block out of bounds"))?
+            .try_into()
+            .unwrap();
+
+        stream.decode_mcu_block(reader, dc_table, ac_table, block, &mut component.dc_pred)
+    }
+
+    /// Count down restart interval after a MCU and handle restart markers
+    ///
+    /// The end of image marker is not a reason to stop, the bitstream reads ahead and
+    /// finds it while bits of the last MCUs are still buffered.
+    fn count_down_restart(&mut self, stream: &mut BitStream) -> Result<(), DecodeErrors>
+    {
+        self.todo = self.todo.wrapping_sub(1);
+
+        if self.todo == 0
+        {
+            self.handle_rst(stream)?;
+        }
+
+        Ok(())
+    }
+}
@@ -54,47 +54,45 @@ mod sse;
 pub use scalar::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
 
 use crate::misc::ColorSpace;
+use crate::ZuneJpegOptions;
 
 /// This function determines the best color-convert function to carry out
 /// based on the colorspace needed
 
 pub fn choose_ycbcr_to_rgb_convert_func(
-    type_need: ColorSpace, use_unsafe: bool,
+    type_need: ColorSpace, options: &ZuneJpegOptions,
 ) -> Option<ColorConvert16Ptr>
 {
-    if use_unsafe
+    #[cfg(feature = "x86")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     {
-        #[cfg(feature = "x86")]
-        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if options.use_avx2()
         {
-            if is_x86_feature_detected!("avx2")
-            {
-                debug!("Using AVX optimised color conversion functions");
+            debug!("Using AVX optimised color conversion functions");
 
-                // I believe avx2 means sse4 is also available
-                // match colorspace
-                return match type_need
-                {
-                    ColorSpace::RGB => Some(ycbcr_to_rgb_avx2),
-                    ColorSpace::RGBA => Some(ycbcr_to_rgba_avx2),
-                    ColorSpace::RGBX => Some(ycbcr_to_rgbx_avx2),
-                    _ => None,
-                };
-            }
-            // try sse
-            else if is_x86_feature_detected!("sse4.1")
+            // I believe avx2 means sse4 is also available
+            // match colorspace
+            return match type_need
+            {
+                ColorSpace::RGB => Some(ycbcr_to_rgb_avx2),
+                ColorSpace::RGBA => Some(ycbcr_to_rgba_avx2),
+                ColorSpace::RGBX => Some(ycbcr_to_rgbx_avx2),
+                _ => None,
+            };
+        }
+        // try sse
+        else if options.use_sse41()
+        {
+            // I believe avx2 means sse4 is also available
+            // match colorspace
+            debug!("No support for avx2 switching to sse");
+            debug!("Using sse color convert functions");
+            return match type_need
             {
-                // I believe avx2 means sse4 is also available
-                // match colorspace
-                debug!("No support for avx2 switching to sse");
-                debug!("Using sse color convert functions");
-                return match type_need
-                {
-                    ColorSpace::RGB => Some(ycbcr_to_rgb_sse_16),
-                    ColorSpace::RGBA | ColorSpace::RGBX => Some(ycbcr_to_rgba_sse_16),
-                    _ => None,
-                };
-            }
+                ColorSpace::RGB => Some(ycbcr_to_rgb_sse_16),
+                ColorSpace::RGBA | ColorSpace::RGBX => Some(ycbcr_to_rgba_sse_16),
+                _ => None,
+            };
         }
     }
     // when there is no x86 or we haven't returned by here, resort to scalar
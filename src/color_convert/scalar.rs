@@ -88,15 +88,13 @@ pub fn ycbcr_to_rgb_16_scalar(
     *pos += 48;
 }
 
-pub fn ycbcr_to_grayscale(y: &[i16], width: usize, output: &mut [u8])
+/// Copy the luma channel to the output
+///
+/// `width_chunk` is the width of a row of `y`, which accounts for fill bytes
+pub fn ycbcr_to_grayscale(y: &[i16], width: usize, width_chunk: usize, output: &mut [u8])
 {
     // Convert i16's to u8's
     let temp_output = y.iter().map(|x| *x as u8).collect::<Vec<u8>>();
-    // chunk according to width.
-
-    let width_mcu = y.len() / width;
-
-    let width_chunk = y.len() / width_mcu;
 
     let mut start = 0;
 
@@ -116,14 +114,9 @@ pub fn ycbcr_to_grayscale(y: &[i16], width: usize, output: &mut [u8])
 /// Convert YcbCr to YCbCr
 ///
 /// Basically all we do is remove fill bytes (if there) in the edges
-pub fn ycbcr_to_ycbcr(
-    channels: &[Vec<i16>; 3], width: usize, h_samp: usize, v_samp: usize, output: &mut [u8],
-)
+/// `width_chunk` is the width of a row of the channels, which accounts for fill bytes
+pub fn ycbcr_to_ycbcr(channels: &[Vec<i16>; 3], width: usize, width_chunk: usize, output: &mut [u8])
 {
-    // copy to a temporary vector.
-
-    let mcu_chunks = channels[0].len() / (h_samp * v_samp);
-
     // pixels we write per width. since this is YcbCr we write
     // width times color components.
     let stride = width * 3;
@@ -134,8 +127,6 @@ pub fn ycbcr_to_ycbcr(
 
     let addition = width * 3;
 
-    // width which accounts number of fill bytes
-    let width_chunk = mcu_chunks >> 3;
     // vector for temporary storage.
     let mut temp_output = vec![0; width_chunk * 3];
 
@@ -270,8 +270,8 @@ pub fn ycbcr_to_rgb_sse_16(
     y: &[i16; 16], cb: &[i16; 16], cr: &[i16; 16], out: &mut [u8], offset: &mut usize,
 )
 {
-    // check if out has enough space
-    out.get_mut(*offset..*offset + 64)
+    // check if out has enough space, 16 pixels of 3 bytes
+    out.get_mut(*offset..*offset + 48)
         .expect("Slice to small cannot write");
     unsafe {
         ycbcr_to_rgb_16(y, cb, cr, out, offset);
@@ -10,8 +10,11 @@ use crate::upsampler::upsample_no_op;
 
 /// Represents an up-sampler function, this function will be called to upsample
 /// a down-sampled image
+///
+/// It takes the samples, the number of samples in a row of them
+/// and the length of the output
 
-pub type UpSampler = fn(&[i16], usize) -> Vec<i16>;
+pub type UpSampler = fn(&[i16], usize, usize) -> Vec<i16>;
 
 /// Component Data from start of frame
 #[derive(Clone)]
@@ -0,0 +1,211 @@
+//! Data appended after the end of image
+//!
+//! Many files carry more than a JPEG image, phones append a video to make
+//! "motion photos", gain maps and depth maps, and some cameras append debug data.
+//! Decoders stop at the end of image marker and never see any of this.
+//!
+//! Google's container format describes the appended items in the XMP of the
+//! primary image, as a list of items in the order they appear in the file
+//!
+//! ```xml
+//! <Container:Directory>
+//!   <rdf:Seq>
+//!     <rdf:li rdf:parseType="Resource">
+//!       <Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary"/>
+//!     </rdf:li>
+//!     <rdf:li rdf:parseType="Resource">
+//!       <Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="4407276"/>
+//!     </rdf:li>
+//!   </rdf:Seq>
+//! </Container:Directory>
+//! ```
+//!
+//! Items after the primary image are stored back to back at the end of the file, so they
+//! can be located from their lengths without scanning the file.
+//!
+//! Older motion photos instead use `GCamera:MicroVideoOffset`, the distance from the
+//! end of the file to the start of the video.
+//!
+//! See <https://developer.android.com/media/platform/motion-photo-format>
+
+use std::ops::Range;
+
+use crate::segments::end_of_image;
+use crate::xmp::{property_value, property_values};
+use crate::Decoder;
+
+/// A media item described in the container directory of the XMP
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContainerItem
+{
+    /// Mime type of the item, e.g `video/mp4`
+    pub mime:     String,
+    /// What the item is for, e.g `Primary`, `MotionPhoto`, `GainMap` or `Depth`
+    pub semantic: String,
+    /// Length of the item in bytes, zero for the primary image
+    pub length:   usize,
+    /// Padding after the item in bytes
+    pub padding:  usize,
+    /// Range of the item in the file
+    pub range:    Range<usize>,
+}
+
+/// A motion photo split into its still image and video
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MotionPhoto
+{
+    /// Range of the still image in the file
+    pub image:                     Range<usize>,
+    /// Range of the video in the file
+    pub video:                     Range<usize>,
+    /// Presentation timestamp of the frame matching the still image, in microseconds,
+    /// if known
+    pub presentation_timestamp_us: Option<i64>,
+}
+
+/// Parse the container directory of an XMP packet and resolve item ranges for a
+/// file of `file_length` bytes
+fn parse_container_items(xmp: &str, file_length: usize) -> Option<Vec<ContainerItem>>
+{
+    // Older files use the GContainer and GContainerItem prefixes
+    let (items, prefix) = match property_values(xmp, "Container:Directory")
+    {
+        Some(items) => (items, "Item:"),
+        None => (property_values(xmp, "GContainer:Directory")?, "GContainerItem:"),
+    };
+    let property = |item: &str, name: &str| {
+        property_value(item, &format!("{prefix}{name}")).map(str::to_string)
+    };
+
+    let mut parsed = items
+        .iter()
+        .map(|item| {
+            Some(ContainerItem {
+                mime:     property(item, "Mime")?,
+                semantic: property(item, "Semantic").unwrap_or_default(),
+                length:   property(item, "Length").map_or(Some(0), |x| x.parse().ok())?,
+                padding:  property(item, "Padding").map_or(Some(0), |x| x.parse().ok())?,
+                range:    0..0,
+            })
+        })
+        .collect::<Option<Vec<ContainerItem>>>()?;
+
+    // Items after the primary are at the end of the file, walk backwards from it
+    let mut end = file_length;
+
+    for item in parsed.iter_mut().skip(1).rev()
+    {
+        end = end.checked_sub(item.padding)?;
+
+        let start = end.checked_sub(item.length)?;
+
+        item.range = start..end;
+        end = start;
+    }
+    if let Some(primary) = parsed.first_mut()
+    {
+        primary.range = 0..end.checked_sub(primary.padding)?;
+    }
+    Some(parsed)
+}
+
+impl Decoder
+{
+    /// Returns the range of bytes after the end of image marker of the primary
+    /// image in `buf`
+    ///
+    /// The range is empty if nothing follows the image, and `None` if the end of image
+    /// could not be found because the file is corrupt or truncated.
+    ///
+    /// This does not need headers to be decoded, but does need to walk over the
+    /// entropy coded data to find the end of image, prefer [`container_items`](Self::container_items)
+    /// or [`motion_photo`](Self::motion_photo) if the file describes its contents.
+    #[must_use]
+    pub fn trailing_data(&self, buf: &[u8]) -> Option<Range<usize>>
+    {
+        end_of_image(buf).map(|end| end..buf.len())
+    }
+
+    /// Returns the items described by the container directory in the XMP of the image
+    /// with their ranges in `buf`
+    ///
+    /// `buf` must be the same buffer whose headers were read by this decoder.
+    ///
+    /// Returns `None` if the image has no container directory or it is inconsistent
+    /// with the length of `buf`.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    #[must_use]
+    pub fn container_items(&self, buf: &[u8]) -> Option<Vec<ContainerItem>>
+    {
+        let xmp = std::str::from_utf8(self.xmp_data.as_ref()?).ok()?;
+
+        parse_container_items(xmp, buf.len())
+    }
+
+    /// Split a motion photo into its still image and video
+    ///
+    /// The video is located from the `MotionPhoto` item of the container directory,
+    /// or from `GCamera:MicroVideoOffset` for older files, neither needs scanning the
+    /// file.
+    ///
+    /// `buf` must be the same buffer whose headers were read by this decoder.
+    ///
+    /// Returns `None` if the image is not a motion photo.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let mut decoder = Decoder::new();
+    /// let img_data = std::fs::read("a_motion_photo.jpeg").unwrap();
+    /// decoder.read_headers(&img_data).unwrap();
+    ///
+    /// if let Some(motion_photo) = decoder.motion_photo(&img_data)
+    /// {
+    ///     std::fs::write("video.mp4", &img_data[motion_photo.video]).unwrap();
+    /// }
+    /// ```
+    #[must_use]
+    pub fn motion_photo(&self, buf: &[u8]) -> Option<MotionPhoto>
+    {
+        let xmp = std::str::from_utf8(self.xmp_data.as_ref()?).ok()?;
+
+        let timestamp = |name: &str| property_value(xmp, name)?.parse().ok();
+
+        if let Some(items) = parse_container_items(xmp, buf.len())
+        {
+            if let Some(video) = items.iter().find(|x| x.semantic == "MotionPhoto")
+            {
+                let timestamp = timestamp("Camera:MotionPhotoPresentationTimestampUs")
+                    .or_else(|| timestamp("GCamera:MotionPhotoPresentationTimestampUs"));
+
+                return Some(MotionPhoto {
+                    image:                     items[0].range.clone(),
+                    video:                     video.range.clone(),
+                    presentation_timestamp_us: timestamp.filter(|&x| x >= 0),
+                });
+            }
+        }
+        let offset: usize = property_value(xmp, "GCamera:MicroVideoOffset")?
+            .parse()
+            .ok()?;
+
+        if offset == 0 || offset > buf.len()
+        {
+            warn!("Invalid MicroVideoOffset {offset}, file has {} bytes", buf.len());
+            return None;
+        }
+        let start = buf.len() - offset;
+
+        Some(MotionPhoto {
+            image:                     0..start,
+            video:                     start..buf.len(),
+            presentation_timestamp_us: timestamp("GCamera:MicroVideoPresentationTimestampUs")
+                .filter(|&x| x >= 0),
+        })
+    }
+}
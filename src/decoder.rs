@@ -6,14 +6,21 @@ use std::io::{BufRead, Cursor, Read};
 use std::num::NonZeroU32;
 use std::path::Path;
 
+use crate::arithmetic::Conditioning;
 use crate::color_convert::choose_ycbcr_to_rgb_convert_func;
 use crate::components::{ComponentID, Components, SubSampRatios};
+use crate::description::{HuffmanTableDescription, ScanDescription};
 use crate::errors::{DecodeErrors, UnsupportedSchemes};
-use crate::headers::{parse_dqt, parse_huffman, parse_sos, parse_start_of_frame};
+use crate::exif::find_thumbnail;
+use crate::headers::{
+    parse_app, parse_dac, parse_dqt, parse_huffman, parse_sos, parse_start_of_frame,
+};
 use crate::huffman::HuffmanTable;
 use crate::idct::choose_idct_func;
 use crate::marker::Marker;
 use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers};
+use crate::region::Region;
+use crate::restart_index::RestartIndex;
 use crate::upsampler::{
     choose_horizontal_samp_function, choose_hv_samp_function, upsample_vertical,
 };
@@ -92,6 +99,10 @@ pub struct Decoder
     // Progressive image details
     /// Is the image progressive?
     pub(crate) is_progressive:    bool,
+    /// Is the image arithmetic coded?
+    pub(crate) is_arithmetic:     bool,
+    /// Arithmetic coding conditioning, from DAC segments
+    pub(crate) conditioning:      Conditioning,
 
     /// Start of spectral scan
     pub(crate) spec_start:       u8,
@@ -118,6 +129,28 @@ pub struct Decoder
     pub(crate) todo:             usize,
     // decoder options
     pub(crate) options:          ZuneJpegOptions,
+    /// EXIF data found in APP(1), starting at the TIFF header
+    pub(crate) exif_data:        Option<Vec<u8>>,
+    /// MP header found in APP(2), starting at the TIFF header
+    pub(crate) mpf_data:         Option<Vec<u8>>,
+    /// XMP packet found in APP(1)
+    pub(crate) xmp_data:         Option<Vec<u8>>,
+    /// ISO 21496-1 gain map metadata found in APP(2)
+    pub(crate) iso_gainmap_data: Option<Vec<u8>>,
+    /// APP(11) JPEG XT packets, after the `JP` identifier
+    pub(crate) jumbf_packets:    Vec<Vec<u8>>,
+    /// JPS stereo descriptor found in APP(3), after the identifier
+    pub(crate) jps_data:         Option<Vec<u8>>,
+    /// Photoshop image resources found in APP(13), after the `Photoshop 3.0\0` identifier
+    pub(crate) photoshop_data:   Option<Vec<u8>>,
+    /// Huffman tables in the order DHT segments defined them
+    pub(crate) huffman_specs:    Vec<HuffmanTableDescription>,
+    /// Scan headers in the order they were read
+    pub(crate) scans:            Vec<ScanDescription>,
+    /// Region to decode instead of the whole image, set by `decode_region`
+    pub(crate) region:           Option<Region>,
+    /// Restart index to start decoding a region with, set by `decode_region_with_index`
+    pub(crate) restart_index:    Option<RestartIndex>,
 }
 
 impl Decoder
@@ -125,7 +158,7 @@ impl Decoder
     fn default(options: ZuneJpegOptions) -> Self
     {
         let color_convert =
-            choose_ycbcr_to_rgb_convert_func(ColorSpace::RGB, options.get_use_unsafe()).unwrap();
+            choose_ycbcr_to_rgb_convert_func(ColorSpace::RGB, &options).unwrap();
         Decoder {
             info: ImageInfo::default(),
             qt_tables: [None, None, None, None],
@@ -145,6 +178,8 @@ impl Decoder
 
             // Progressive information
             is_progressive: false,
+            is_arithmetic: false,
+            conditioning: Conditioning::default(),
             spec_start: 0,
             spec_end: 0,
             succ_high: 0,
@@ -152,7 +187,7 @@ impl Decoder
             num_scans: 0,
 
             // Function pointers
-            idct_func: choose_idct_func(options.get_use_unsafe()),
+            idct_func: choose_idct_func(&options),
             color_convert_16: color_convert,
 
             // Colorspace
@@ -166,6 +201,17 @@ impl Decoder
             todo: 0x7fff_ffff,
             // options
             options,
+            exif_data: None,
+            mpf_data: None,
+            xmp_data: None,
+            iso_gainmap_data: None,
+            jumbf_packets: vec![],
+            jps_data: None,
+            photoshop_data: None,
+            huffman_specs: vec![],
+            scans: vec![],
+            region: None,
+            restart_index: None,
         }
     }
     /// Decode a buffer already in memory
@@ -221,6 +267,85 @@ impl Decoder
         return Some(self.info.clone());
     }
 
+    /// Returns the raw EXIF data of the image, if present
+    ///
+    /// The data starts at the TIFF header, i.e. the `Exif\0\0` identifier of the
+    /// APP(1) segment is not included.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    #[must_use]
+    pub fn exif(&self) -> Option<&[u8]>
+    {
+        self.exif_data.as_deref()
+    }
+
+    /// Returns the XMP packet found in an APP(1) segment, if present
+    ///
+    /// Only the main packet is returned, extended XMP is not included.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    #[must_use]
+    pub fn xmp(&self) -> Option<&[u8]>
+    {
+        self.xmp_data.as_deref()
+    }
+
+    /// Returns the bytes of the JPEG thumbnail embedded in IFD1 of the EXIF data,
+    /// if present
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    #[must_use]
+    pub fn exif_thumbnail(&self) -> Option<&[u8]>
+    {
+        find_thumbnail(self.exif_data.as_ref()?)
+    }
+
+    /// Decode the JPEG thumbnail embedded in the EXIF data of the image
+    ///
+    /// Cameras store a small (usually 160x120) JPEG thumbnail in IFD1 of the EXIF
+    /// data, this locates and decodes it using the same options as this decoder,
+    /// without decoding the main image.
+    ///
+    /// Headers must have been read by `read_headers` or a decode call before this.
+    ///
+    /// # Returns
+    /// - `Ok(None)`: If the image has no EXIF thumbnail
+    /// - `Ok(Some((pixels, info)))`: Pixels of the thumbnail in the output colorspace
+    ///   and its image information.
+    ///
+    /// # Errors
+    /// If the thumbnail is corrupt. See DecodeErrors enum for list of possible errors
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let mut decoder = Decoder::new();
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// decoder.read_headers(&img_data).unwrap();
+    ///
+    /// if let Some((pixels, info)) = decoder.decode_exif_thumbnail().unwrap()
+    /// {
+    ///     println!("Thumbnail is {}x{}", info.width, info.height);
+    /// }
+    /// ```
+    pub fn decode_exif_thumbnail(&self) -> Result<Option<(Vec<u8>, ImageInfo)>, DecodeErrors>
+    {
+        match self.exif_thumbnail()
+        {
+            Some(thumbnail) =>
+            {
+                let mut decoder = Decoder::new_with_options(self.options);
+                let pixels = decoder.decode_buffer(thumbnail)?;
+
+                Ok(Some((pixels, decoder.info.clone())))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Decode Decoder headers
     ///
     /// This routine takes care of parsing supported headers from a Decoder
@@ -231,12 +356,13 @@ impl Decoder
     ///  - SOF(O)
     ///  - DQT -> Quantization tables
     ///  - DHT -> Huffman tables
+    ///  - DAC -> Arithmetic coding conditioning
     ///  - SOS -> Start of Scan
     /// # Unsupported Headers
-    ///  - SOF(n) -> Decoder images which are not baseline/progressive
-    ///  - DAC -> Images using Arithmetic tables
+    ///  - SOF(n) -> Decoder images which are not baseline/progressive, arithmetic
+    ///    coded images are only decoded to coefficients
     ///  - JPG(n)
-    fn decode_headers_internal<R>(&mut self, buf: &mut R) -> Result<(), DecodeErrors>
+    pub(crate) fn decode_headers_internal<R>(&mut self, buf: &mut R) -> Result<(), DecodeErrors>
     where
         R: Read + BufRead,
     {
@@ -246,6 +372,17 @@ impl Decoder
         let mut last_byte = 0;
         let mut bytes_before_marker = 0;
 
+        self.exif_data = None;
+        self.mpf_data = None;
+        self.xmp_data = None;
+        self.iso_gainmap_data = None;
+        self.jumbf_packets.clear();
+        self.jps_data = None;
+        self.photoshop_data = None;
+        self.huffman_specs.clear();
+        self.scans.clear();
+        self.conditioning = Conditioning::default();
+
         if magic_bytes != 0xffd8
         {
             return Err(DecodeErrors::IllegalMagicBytes(magic_bytes));
@@ -307,30 +444,29 @@ impl Decoder
     {
         match m
         {
-            Marker::SOF(0 | 2) =>
+            Marker::SOF(0 | 1 | 2 | 9 | 10) =>
             {
-                let marker = {
-                    // choose marker
-                    if m == Marker::SOF(0)
-                    {
-                        SOFMarkers::BaselineDct
-                    }
-                    else
-                    {
-                        self.is_progressive = true;
-
-                        SOFMarkers::ProgressiveDctHuffman
-                    }
+                let marker = match m
+                {
+                    Marker::SOF(0) => SOFMarkers::BaselineDct,
+                    // decoded like baseline, it only allows more tables and 16 bit
+                    // quantization tables
+                    Marker::SOF(1) => SOFMarkers::ExtendedSequentialHuffman,
+                    Marker::SOF(2) => SOFMarkers::ProgressiveDctHuffman,
+                    Marker::SOF(9) => SOFMarkers::ExtendedSequentialDctArithmetic,
+                    _ => SOFMarkers::ProgressiveDctArithmetic,
                 };
+                self.is_progressive = marker.is_progressive();
+                self.is_arithmetic = matches!(m, Marker::SOF(9 | 10));
 
                 info!("Image encoding scheme =`{:?}`", marker);
                 // get components
                 parse_start_of_frame(buf, marker, self)?;
             }
             // Start of Frame Segments not supported
-            Marker::SOF(v) =>
+            Marker::SOF(_) =>
             {
-                let feature = UnsupportedSchemes::from_int(v);
+                let feature = m.to_u8().and_then(UnsupportedSchemes::from_int);
 
                 if let Some(feature) = feature
                 {
@@ -339,11 +475,11 @@ impl Decoder
 
                 return Err(DecodeErrors::Format("Unsupported image format".to_string()));
             }
-            // APP(0) segment
-            // Marker::APP(0 | 1) =>
-            // {
-            //     parse_app(buf, m, &mut self.info)?;
-            // }
+            // APP(0), APP(1), APP(2), APP(3), APP(11) and APP(13) segments
+            Marker::APP(0..=3 | 11 | 13) =>
+            {
+                parse_app(buf, m, self)?;
+            }
             // Quantization tables
             Marker::DQT =>
             {
@@ -365,7 +501,12 @@ impl Decoder
             }
             Marker::EOI => return Err(DecodeErrors::Format("Premature End of image".to_string())),
 
-            Marker::DAC | Marker::DNL =>
+            // Arithmetic coding conditioning
+            Marker::DAC =>
+            {
+                parse_dac(self, buf)?;
+            }
+            Marker::DNL =>
             {
                 return Err(DecodeErrors::Format(format!(
                     "Parsing of the following header `{:?}` is not supported,\
@@ -387,6 +528,11 @@ impl Decoder
                 self.restart_interval = usize::from(read_u16_be(buf)?);
                 self.todo = self.restart_interval;
             }
+            // Markers without a length, nothing to skip
+            Marker::SOI | Marker::RST(_) | Marker::TEM =>
+            {
+                warn!("Unexpected marker \"{m:?}\" in headers, ignoring");
+            }
             _ =>
             {
                 warn!(
@@ -429,7 +575,7 @@ impl Decoder
         }
         else
         {
-            self.decode_mcu_ycbcr_baseline(&mut buf)
+            self.decode_mcu_ycbcr_baseline(&mut buf, 8)
         }
     }
     /// Read only headers from a jpeg image buffer
@@ -483,7 +629,7 @@ impl Decoder
                 // horizontal sub-sampling
                 info!("Horizontal sub-sampling (2,1)");
 
-                let up_sampler = choose_horizontal_samp_function(self.options.get_use_unsafe());
+                let up_sampler = choose_horizontal_samp_function(&self.options);
 
                 self.components[1..]
                     .iter_mut()
@@ -506,7 +652,7 @@ impl Decoder
                 info!("Vertical and horizontal sub-sampling(2,2)");
 
                 self.components[1..].iter_mut().for_each(|x| {
-                    x.up_sampler = choose_hv_samp_function(self.options.get_use_unsafe());
+                    x.up_sampler = choose_hv_samp_function(&self.options);
                 });
             }
             (_, _) =>
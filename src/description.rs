@@ -0,0 +1,164 @@
+//! A read-only description of the frame and scan headers of an image
+//!
+//! This exposes what the decoder learnt from the SOF, DQT, DHT, DRI and SOS
+//! segments, for tools that route images or audit how they were encoded, without
+//! giving access to the decoder's internal state.
+//!
+//! Headers up to the first scan are read by `read_headers`, later scans of progressive
+//! images, and the tables defined between them, are only seen by a decode call.
+
+use crate::Decoder;
+
+/// Which kind of coefficients a Huffman table codes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HuffmanClass
+{
+    /// DC coefficients, or DC and lossless coding
+    Dc,
+    /// AC coefficients
+    Ac,
+}
+
+/// A Huffman table as defined by a DHT segment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HuffmanTableDescription
+{
+    /// Whether the table codes DC or AC coefficients
+    pub class:  HuffmanClass,
+    /// Table destination, from 0 to 3
+    pub index:  u8,
+    /// Number of codes of each length, `bits[0]` counts codes of length 1
+    pub bits:   [u8; 16],
+    /// Symbols in order of increasing code length
+    pub values: Vec<u8>,
+}
+
+/// A component of the frame
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ComponentDescription
+{
+    /// Component identifier as stored in the frame header
+    pub id:                        u8,
+    /// Horizontal sampling factor
+    pub horizontal_sample:         u8,
+    /// Vertical sampling factor
+    pub vertical_sample:           u8,
+    /// Quantization table destination
+    pub quantization_table_number: u8,
+    /// Quantization table used by this component, in natural (not zig-zag) order
+    pub quantization_table:        [u16; 64],
+}
+
+/// A component taking part in a scan
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ScanComponent
+{
+    /// Component identifier, matching [`ComponentDescription::id`]
+    pub id:       u8,
+    /// DC Huffman table destination
+    pub dc_table: u8,
+    /// AC Huffman table destination
+    pub ac_table: u8,
+}
+
+/// The header of a scan
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScanDescription
+{
+    /// Components in the scan, in scan order
+    pub components: Vec<ScanComponent>,
+    /// Start of spectral selection
+    pub spec_start: u8,
+    /// End of spectral selection
+    pub spec_end:   u8,
+    /// Successive approximation bit position high
+    pub succ_high:  u8,
+    /// Successive approximation bit position low
+    pub succ_low:   u8,
+}
+
+/// The frame header and tables of an image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FrameDescription
+{
+    /// Width of the image
+    pub width:            u16,
+    /// Height of the image
+    pub height:           u16,
+    /// Sample precision in bits
+    pub precision:        u8,
+    /// Whether the image is progressive
+    pub progressive:      bool,
+    /// Whether the image is arithmetic instead of Huffman coded
+    pub arithmetic:       bool,
+    /// Components, in frame header order
+    pub components:       Vec<ComponentDescription>,
+    /// Huffman tables in the order they were defined
+    ///
+    /// A destination may be defined more than once, e.g progressive encoders
+    /// often define new tables before each scan.
+    pub huffman_tables:   Vec<HuffmanTableDescription>,
+    /// Restart interval in MCUs, zero if restart markers are not used
+    pub restart_interval: u16,
+    /// Scans in the order they were read
+    pub scans:            Vec<ScanDescription>,
+}
+
+impl Decoder
+{
+    /// Returns a description of the frame header, tables and scans of the image
+    ///
+    /// After `read_headers` only the first scan is described, all scans are after
+    /// a decode call.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let mut decoder = Decoder::new();
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// decoder.read_headers(&img_data).unwrap();
+    ///
+    /// let frame = decoder.frame_description().unwrap();
+    ///
+    /// for component in &frame.components
+    /// {
+    ///     let (h, v) = (component.horizontal_sample, component.vertical_sample);
+    ///     println!("{}: {h}x{v}", component.id);
+    /// }
+    /// ```
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn frame_description(&self) -> Option<FrameDescription>
+    {
+        if self.components.is_empty()
+        {
+            return None;
+        }
+        let components = self
+            .components
+            .iter()
+            .map(|component| ComponentDescription {
+                id:                        component.id,
+                horizontal_sample:         component.horizontal_sample as u8,
+                vertical_sample:           component.vertical_sample as u8,
+                quantization_table_number: component.quantization_table_number,
+                quantization_table:        component.quantization_table.0.map(|x| x as u16),
+            })
+            .collect();
+
+        Some(FrameDescription {
+            width: self.info.width,
+            height: self.info.height,
+            precision: self.info.pixel_density,
+            progressive: self.is_progressive,
+            arithmetic: self.is_arithmetic,
+            components,
+            huffman_tables: self.huffman_specs.clone(),
+            restart_interval: self.restart_interval as u16,
+            scans: self.scans.clone(),
+        })
+    }
+}
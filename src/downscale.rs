@@ -0,0 +1,195 @@
+//! Halving images in the DCT domain
+//!
+//! The DCT is linear, so averaging 2x2 pixels of the samples of a 2x2 group of
+//! blocks and transforming the 8x8 result is a linear map from the 256 coefficients
+//! of the group to the 64 of the new block. It is separable, an 8x16 matrix applied
+//! to rows and then columns, so [`Coefficients::downscale`] halves an image without
+//! an IDCT and forward DCT per block, and without rounding to pixels in between.
+//!
+//! Coefficients are dequantized, mapped, and quantized again with the same tables,
+//! the only loss is that of rounding to the steps of the tables. Repeating it gives
+//! the levels of a thumbnail pyramid, [`Transcoder::set_downscale`] writes the result
+//! to a baseline JPEG file.
+//!
+//! # Examples
+//! Halve an image
+//! ```no_run
+//! use zune_jpeg::transcode::Transcoder;
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//!
+//! let half = Transcoder::new()
+//!     .set_downscale(true)
+//!     .transcode(&img_data)
+//!     .unwrap();
+//! ```
+//!
+//! [`Transcoder::set_downscale`]: crate::transcode::Transcoder::set_downscale
+
+use crate::coefficients::{ComponentCoefficients, Coefficients};
+use crate::fdct::dct_basis;
+use crate::mcu::DCT_BLOCK;
+
+/// Map from the coefficients of two neighbouring rows or columns of 8 samples,
+/// `[..8]` the first and `[8..]` the second, to the coefficients of their average
+/// pairs
+fn downscale_matrix() -> [[f32; 16]; 8]
+{
+    let basis = dct_basis();
+    let mut matrix = [[0.0; 16]; 8];
+
+    for (v, row) in matrix.iter_mut().enumerate()
+    {
+        for (k, value) in row.iter_mut().enumerate()
+        {
+            let (block, u) = (k / 8, k % 8);
+
+            // samples 4 * block.. of the result average pairs of samples of the block
+            *value = (0..4)
+                .map(|i| {
+                    let average = 0.5 * (basis[u][2 * i] + basis[u][2 * i + 1]);
+
+                    basis[v][4 * block + i] * average
+                })
+                .sum();
+        }
+    }
+    matrix
+}
+
+impl Coefficients
+{
+    /// Halve the width and height of the image, rounding up
+    ///
+    /// Each 2x2 group of blocks of a component becomes a single block, see the
+    /// [module docs](crate::downscale). Sampling factors and quantization tables
+    /// stay the same.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn downscale(&self) -> Coefficients
+    {
+        let matrix = downscale_matrix();
+        let (mcu_width, mcu_height) = self.mcu_size();
+
+        let new_width = usize::from(self.width).div_ceil(2);
+        let new_height = usize::from(self.height).div_ceil(2);
+
+        let components = self
+            .components
+            .iter()
+            .map(|source| {
+                let (h_samp, v_samp) = self.sampling(source);
+
+                let blocks_wide = new_width.div_ceil(mcu_width) * h_samp;
+                let blocks_high = new_height.div_ceil(mcu_height) * v_samp;
+                let mut coefficients = Vec::with_capacity(blocks_wide * blocks_high * DCT_BLOCK);
+
+                for block_y in 0..blocks_high
+                {
+                    for block_x in 0..blocks_wide
+                    {
+                        let block = downscale_block(&matrix, source, block_x, block_y);
+
+                        coefficients.extend_from_slice(&block);
+                    }
+                }
+                ComponentCoefficients {
+                    width: (new_width * h_samp).div_ceil(mcu_width / 8),
+                    height: (new_height * v_samp).div_ceil(mcu_height / 8),
+                    blocks_wide,
+                    blocks_high,
+                    coefficients,
+                    ..source.clone()
+                }
+            })
+            .collect();
+
+        Coefficients {
+            // half of a u16 fits in a u16
+            width: new_width as u16,
+            height: new_height as u16,
+            colorspace: self.colorspace,
+            components,
+        }
+    }
+}
+
+/// Combine the 2x2 group of blocks of `source` at the new block `x`, `y` into one
+///
+/// Blocks past the end of the plane, which are past the edge of the image, repeat
+/// the last block of the row or column.
+#[allow(clippy::cast_possible_truncation)]
+fn downscale_block(
+    matrix: &[[f32; 16]; 8], source: &ComponentCoefficients, x: usize, y: usize,
+) -> [i16; 64]
+{
+    let table = &source.quantization_table;
+
+    // dequantized coefficients of the group, rows of vertical frequencies
+    let mut group = [[0.0_f32; 16]; 16];
+
+    for (dy, dx) in [(0, 0), (0, 1), (1, 0), (1, 1)]
+    {
+        let block_x = (2 * x + dx).min(source.blocks_wide - 1);
+        let block_y = (2 * y + dy).min(source.blocks_high - 1);
+        let block = source.block(block_x, block_y);
+
+        for (k, (&value, &step)) in block.iter().zip(table).enumerate()
+        {
+            group[8 * dy + k / 8][8 * dx + k % 8] = f32::from(value) * f32::from(step);
+        }
+    }
+    // rows first, then columns
+    let mut rows = [[0.0_f32; 8]; 16];
+
+    for (values, result) in group.iter().zip(&mut rows)
+    {
+        for (u, value) in result.iter_mut().enumerate()
+        {
+            *value = matrix[u].iter().zip(values).map(|(m, x)| m * x).sum();
+        }
+    }
+    let mut block = [0; 64];
+
+    for v in 0..8
+    {
+        for u in 0..8
+        {
+            let value = (0..16).map(|p| matrix[v][p] * rows[p][u]).sum::<f32>();
+
+            block[v * 8 + u] = (value / f32::from(table[v * 8 + u])).round() as i16;
+        }
+    }
+    block
+}
+
+//---------------------------------------------
+// TEST
+//----------------------------------------------
+#[test]
+fn downscale_flat_blocks()
+{
+    let matrix = downscale_matrix();
+
+    // a flat row stays flat, with the same DC
+    let mut row = [0.0; 16];
+
+    row[0] = 10.0;
+    row[8] = 10.0;
+
+    for (u, coefficients) in matrix.iter().enumerate()
+    {
+        let value = coefficients.iter().zip(&row).map(|(m, x)| m * x).sum::<f32>();
+        let expected = if u == 0 { 10.0 } else { 0.0 };
+
+        assert!((value - expected).abs() < 1e-4, "{u} {value}");
+    }
+    // AC coefficients don't change the mean
+    let mut row = [0.0; 16];
+
+    row[7] = 10.0;
+    row[15] = 10.0;
+
+    let dc = matrix[0].iter().zip(&row).map(|(m, x)| m * x).sum::<f32>();
+
+    assert!(dc.abs() < 1e-4);
+}
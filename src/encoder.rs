@@ -0,0 +1,414 @@
+//! JPEG encoding
+//!
+//! [`Encoder`] writes JFIF files from RGB, RGBA, RGBX, YCbCr or grayscale pixels.
+//! RGB is converted to YCbCr as in JFIF, alpha is dropped. Chrominance may be
+//! subsampled by averaging, see [`Subsampling`], and samples past the right and bottom
+//! edge of the image are filled by repeating the last column and row, so whole MCUs
+//! don't add artifacts near the edge.
+//!
+//! Blocks are transformed with a floating point DCT and quantized with the tables
+//! libjpeg uses for the quality, see [`quality_tables`], or any other tables. Coded
+//! the same way as by [`Transcoder`], by default a baseline file with the Huffman
+//! tables of ITU-T T.81 Annex K, [`Encoder::set_optimize`] builds optimal tables and
+//! [`Encoder::set_progressive`] and [`Encoder::set_scan_script`] write a progressive
+//! file.
+//!
+//! Metadata is embedded by a [`MetadataRewriter`] applied to the encoded file, see
+//! [`Encoder::set_metadata`].
+//!
+//! # Examples
+//! Encode a gradient as a progressive file with an ICC profile
+//! ```no_run
+//! use zune_jpeg::encoder::Encoder;
+//! use zune_jpeg::rewrite::MetadataRewriter;
+//! use zune_jpeg::ColorSpace;
+//! let icc_profile = std::fs::read("profile.icc").unwrap();
+//! let pixels = (0..64 * 64 * 3).map(|x| (x % 256) as u8).collect::<Vec<u8>>();
+//!
+//! let jpeg = Encoder::new()
+//!     .set_quality(85)
+//!     .set_progressive(true)
+//!     .set_metadata(MetadataRewriter::new().set_icc_profile(icc_profile))
+//!     .encode(&pixels, 64, 64, ColorSpace::RGB)
+//!     .unwrap();
+//! ```
+//!
+//! [`quality_tables`]: crate::requantize::quality_tables
+//! [`Transcoder`]: crate::transcode::Transcoder
+
+use crate::coefficients::{ComponentCoefficients, Coefficients};
+use crate::errors::DecodeErrors;
+use crate::fdct::ForwardDct;
+use crate::marker::Marker;
+use crate::mcu::DCT_BLOCK;
+use crate::requantize::quality_tables;
+use crate::rewrite::{write_segment, MetadataRewriter};
+use crate::transcode::{write_image, Coding, Scan};
+use crate::ColorSpace;
+
+/// APP0 JFIF 1.01 segment, no units, 1:1 pixel aspect ratio and no thumbnail
+const JFIF: [u8; 14] = *b"JFIF\0\x01\x01\x00\x00\x01\x00\x01\x00\x00";
+
+/// Chroma subsampling of YCbCr images
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Subsampling
+{
+    /// Full resolution chrominance
+    Chroma444,
+    /// Chrominance at half the horizontal resolution
+    Chroma422,
+    /// Chrominance at half the horizontal and vertical resolution
+    #[default]
+    Chroma420,
+}
+
+impl Subsampling
+{
+    /// Horizontal and vertical sampling factors of luminance, chrominance has 1x1
+    const fn luminance_sampling(self) -> (u8, u8)
+    {
+        match self
+        {
+            Subsampling::Chroma444 => (1, 1),
+            Subsampling::Chroma422 => (2, 1),
+            Subsampling::Chroma420 => (2, 2),
+        }
+    }
+}
+
+/// Options for encoding pixels to a JPEG file
+#[derive(Clone, Debug)]
+pub struct Encoder
+{
+    quality:          u8,
+    tables:           Option<[[u16; 64]; 2]>,
+    subsampling:      Subsampling,
+    optimize:         bool,
+    progressive:      bool,
+    scan_script:      Option<Vec<Scan>>,
+    restart_interval: u16,
+    metadata:         Option<MetadataRewriter>,
+}
+
+impl Default for Encoder
+{
+    fn default() -> Self
+    {
+        Encoder::new()
+    }
+}
+
+impl Encoder
+{
+    /// Create an encoder writing baseline files at quality 75 with 4:2:0 subsampling,
+    /// the defaults of libjpeg
+    #[must_use]
+    pub fn new() -> Encoder
+    {
+        Encoder {
+            quality:          75,
+            tables:           None,
+            subsampling:      Subsampling::default(),
+            optimize:         false,
+            progressive:      false,
+            scan_script:      None,
+            restart_interval: 0,
+            metadata:         None,
+        }
+    }
+
+    /// Quantize with the tables libjpeg uses for `quality`, from 1 to 100
+    #[must_use]
+    pub fn set_quality(mut self, quality: u8) -> Encoder
+    {
+        self.quality = quality;
+        self
+    }
+
+    /// Quantize with a luminance and a chrominance table in natural order, this takes
+    /// precedence over [`set_quality`](Self::set_quality)
+    ///
+    /// Values must be from 1 to 255.
+    #[must_use]
+    pub fn set_quantization_tables(mut self, tables: [[u16; 64]; 2]) -> Encoder
+    {
+        self.tables = Some(tables);
+        self
+    }
+
+    /// Subsample chrominance of color images, see [`Subsampling`]
+    #[must_use]
+    pub fn set_subsampling(mut self, subsampling: Subsampling) -> Encoder
+    {
+        self.subsampling = subsampling;
+        self
+    }
+
+    /// Build optimal Huffman tables for the image instead of using the tables of
+    /// ITU-T T.81 Annex K
+    #[must_use]
+    pub fn set_optimize(mut self, optimize: bool) -> Encoder
+    {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Write a progressive image with the scans of [`Scan::progressive`], progressive
+    /// scans are always coded with optimal tables
+    #[must_use]
+    pub fn set_progressive(mut self, progressive: bool) -> Encoder
+    {
+        self.progressive = progressive;
+        self
+    }
+
+    /// Write the image with the scans of `scans`, this takes precedence over
+    /// [`set_progressive`](Self::set_progressive)
+    ///
+    /// See [`Transcoder::set_scan_script`](crate::transcode::Transcoder::set_scan_script).
+    #[must_use]
+    pub fn set_scan_script(mut self, scans: Vec<Scan>) -> Encoder
+    {
+        self.scan_script = Some(scans);
+        self
+    }
+
+    /// Write a restart marker every `interval` MCUs, zero for none
+    #[must_use]
+    pub fn set_restart_interval(mut self, interval: u16) -> Encoder
+    {
+        self.restart_interval = interval;
+        self
+    }
+
+    /// Apply `metadata` to the encoded file, inserting EXIF, XMP, ICC profiles and
+    /// other segments after the JFIF segment
+    #[must_use]
+    pub fn set_metadata(mut self, metadata: MetadataRewriter) -> Encoder
+    {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Encode `pixels`, `width` by `height` pixels of `colorspace` in raster order
+    ///
+    /// # Errors
+    /// If the colorspace is not supported, the image is empty, `pixels` has the wrong
+    /// length, a quantization table value is zero or larger than 255, the scan script
+    /// is not valid for the image, or the metadata can't be written, see
+    /// [`MetadataRewriter::rewrite`]
+    pub fn encode(
+        &self, pixels: &[u8], width: u16, height: u16, colorspace: ColorSpace,
+    ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let image = self.coefficients(pixels, width, height, colorspace)?;
+
+        let scans = match &self.scan_script
+        {
+            Some(scans) => scans.clone(),
+            None if self.progressive => Scan::progressive(image.components.len()),
+            None => Scan::sequential(image.components.len()),
+        };
+        let mut output = vec![0xFF, Marker::SOI.to_u8().unwrap()];
+
+        write_segment(&mut output, Marker::APP(0), &JFIF)?;
+
+        let coding = Coding {
+            source_tables:    &[],
+            optimize:         self.optimize,
+            arithmetic:       false,
+            restart_interval: self.restart_interval,
+        };
+
+        write_image(&mut output, &image, &scans, &coding)?;
+
+        match &self.metadata
+        {
+            Some(metadata) => metadata.rewrite(&output),
+            None => Ok(output),
+        }
+    }
+
+    /// Convert, subsample, transform and quantize `pixels`
+    #[allow(clippy::cast_possible_truncation)]
+    fn coefficients(
+        &self, pixels: &[u8], width: u16, height: u16, colorspace: ColorSpace,
+    ) -> Result<Coefficients, DecodeErrors>
+    {
+        let grayscale = match colorspace
+        {
+            ColorSpace::GRAYSCALE => true,
+            ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX | ColorSpace::YCbCr => false,
+            _ =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Cannot encode images with colorspace {colorspace:?}"
+                )))
+            }
+        };
+        let (width, height) = (usize::from(width), usize::from(height));
+        let channels = colorspace.num_components();
+
+        if width == 0 || height == 0
+        {
+            return Err(DecodeErrors::FormatStatic("Cannot encode an empty image"));
+        }
+        if pixels.len() != width * height * channels
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Expected {} bytes for a {width}x{height} {colorspace:?} image, found {}",
+                width * height * channels,
+                pixels.len()
+            )));
+        }
+        let tables = self.tables.unwrap_or_else(|| quality_tables(self.quality));
+
+        if tables.iter().flatten().any(|&x| x == 0 || x > 255)
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Quantization table values must be from 1 to 255",
+            ));
+        }
+        let (h_max, v_max) = if grayscale
+        {
+            (1, 1)
+        }
+        else
+        {
+            self.subsampling.luminance_sampling()
+        };
+        // MCUs cover the image, samples past its edges repeat the last column and row
+        let (mcu_width, mcu_height) = (8 * usize::from(h_max), 8 * usize::from(v_max));
+        let mcu_x = width.div_ceil(mcu_width);
+        let mcu_y = height.div_ceil(mcu_height);
+        let (padded_width, padded_height) = (mcu_x * mcu_width, mcu_y * mcu_height);
+
+        let planes = to_planes(pixels, width, height, colorspace, padded_width, padded_height);
+        let dct = ForwardDct::new();
+
+        let components = planes
+            .iter()
+            .enumerate()
+            .map(|(pos, plane)| {
+                let (h_samp, v_samp) = if pos == 0 { (h_max, v_max) } else { (1, 1) };
+                let h_factor = usize::from(h_max / h_samp);
+                let v_factor = usize::from(v_max / v_samp);
+                let quantization_table = tables[usize::from(pos > 0)];
+
+                let blocks_wide = mcu_x * usize::from(h_samp);
+                let blocks_high = mcu_y * usize::from(v_samp);
+                let mut coefficients = Vec::with_capacity(blocks_wide * blocks_high * DCT_BLOCK);
+
+                for block_y in 0..blocks_high
+                {
+                    for block_x in 0..blocks_wide
+                    {
+                        let mut block = [0.0; 64];
+
+                        for (k, sample) in block.iter_mut().enumerate()
+                        {
+                            let x = (block_x * 8 + k % 8) * h_factor;
+                            let y = (block_y * 8 + k / 8) * v_factor;
+
+                            *sample =
+                                average(plane, padded_width, x, y, h_factor, v_factor) - 128.0;
+                        }
+                        let transformed = dct.forward(&block);
+
+                        coefficients.extend(transformed.iter().zip(&quantization_table).map(
+                            |(value, &step)| quantize(*value, step),
+                        ));
+                    }
+                }
+                // at most three components
+                ComponentCoefficients {
+                    id: pos as u8 + 1,
+                    horizontal_sample: h_samp,
+                    vertical_sample: v_samp,
+                    width: (width * usize::from(h_samp)).div_ceil(usize::from(h_max)),
+                    height: (height * usize::from(v_samp)).div_ceil(usize::from(v_max)),
+                    blocks_wide,
+                    blocks_high,
+                    quantization_table,
+                    coefficients,
+                }
+            })
+            .collect();
+
+        Ok(Coefficients {
+            width: width as u16,
+            height: height as u16,
+            colorspace: if grayscale { ColorSpace::GRAYSCALE } else { ColorSpace::YCbCr },
+            components,
+        })
+    }
+}
+
+/// Split `pixels` into full resolution planes of Y, Cb and Cr, or a single plane of
+/// gray, `padded_width` by `padded_height` samples repeating the last column and row
+fn to_planes(
+    pixels: &[u8], width: usize, height: usize, colorspace: ColorSpace, padded_width: usize,
+    padded_height: usize,
+) -> Vec<Vec<f32>>
+{
+    let channels = colorspace.num_components();
+    let count = if colorspace == ColorSpace::GRAYSCALE { 1 } else { 3 };
+    let mut planes = vec![Vec::with_capacity(padded_width * padded_height); count];
+
+    for y in 0..padded_height
+    {
+        let row = y.min(height - 1) * width;
+
+        for x in 0..padded_width
+        {
+            let start = (row + x.min(width - 1)) * channels;
+            let pixel = &pixels[start..start + channels];
+
+            let samples = match colorspace
+            {
+                ColorSpace::GRAYSCALE => [f32::from(pixel[0]), 0.0, 0.0],
+                ColorSpace::YCbCr => [0, 1, 2].map(|c| f32::from(pixel[c])),
+                _ => rgb_to_ycbcr(pixel[0], pixel[1], pixel[2]),
+            };
+            for (plane, sample) in planes.iter_mut().zip(samples)
+            {
+                plane.push(sample);
+            }
+        }
+    }
+    planes
+}
+
+/// Convert a pixel from RGB to YCbCr as in JFIF
+fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> [f32; 3]
+{
+    let (r, g, b) = (f32::from(r), f32::from(g), f32::from(b));
+
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0,
+        0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0,
+    ]
+}
+
+/// Average of the `h_factor` by `v_factor` samples of `plane` at `x`, `y`
+#[allow(clippy::cast_precision_loss)]
+fn average(
+    plane: &[f32], stride: usize, x: usize, y: usize, h_factor: usize, v_factor: usize,
+) -> f32
+{
+    let mut sum = 0.0;
+
+    for row in y..y + v_factor
+    {
+        sum += plane[row * stride + x..row * stride + x + h_factor].iter().sum::<f32>();
+    }
+    sum / (h_factor * v_factor) as f32
+}
+
+/// Quantize a DCT coefficient with `step`, rounding to nearest
+#[allow(clippy::cast_possible_truncation)]
+fn quantize(value: f32, step: u16) -> i16
+{
+    (value / f32::from(step)).round() as i16
+}
@@ -0,0 +1,400 @@
+//! Minimal EXIF (TIFF) parsing
+//!
+//! EXIF data is stored in an APP1 segment starting with `Exif\0\0` followed by
+//! a TIFF structure, a header giving the byte order and the offset of the first
+//! image file directory(IFD), each IFD containing a list of 12 byte entries and
+//! an offset to the next IFD.
+//!
+//! We only parse what we need, which currently is locating the embedded thumbnail
+//! in IFD1, see [CIPA DC-008] 4.5.4 and 4.6.3, and editing the few fields the
+//! metadata rewriter touches.
+//!
+//! All offsets inside a TIFF structure are relative to the start of the TIFF header,
+//! not the start of the APP1 segment.
+//!
+//! [CIPA DC-008]: https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
+
+/// Offset to the first IFD of a JPEG thumbnail
+pub(crate) const TAG_JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+
+/// Number of bytes of JPEG thumbnail data
+pub(crate) const TAG_JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+
+/// Image orientation, a SHORT from 1 to 8
+pub(crate) const TAG_ORIENTATION: u16 = 0x0112;
+
+/// Offset of the GPS IFD
+pub(crate) const TAG_GPS_INFO: u16 = 0x8825;
+
+/// Manufacturer of the recording equipment, an ASCII string
+pub(crate) const TAG_MAKE: u16 = 0x010F;
+
+/// A single 12 byte IFD entry
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct IfdEntry
+{
+    /// Tag identifying the field
+    pub tag:          u16,
+    /// Field type, e.g 3 for SHORT and 4 for LONG
+    pub field_type:   u16,
+    /// Number of values
+    pub count:        u32,
+    /// Offset of the 4 byte value/offset field of this entry
+    /// from the start of the TIFF header.
+    pub value_offset: usize,
+}
+
+/// A reader for a TIFF structure stored in memory
+pub(crate) struct TiffReader<'a>
+{
+    data:          &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> TiffReader<'a>
+{
+    /// Create a new reader from data starting with a TIFF header
+    ///
+    /// Returns `None` if the byte order mark or magic number is invalid
+    pub fn new(data: &'a [u8]) -> Option<TiffReader<'a>>
+    {
+        let little_endian = match data.get(0..2)?
+        {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let reader = TiffReader {
+            data,
+            little_endian,
+        };
+
+        if reader.read_u16(2)? != 42
+        {
+            return None;
+        }
+
+        Some(reader)
+    }
+
+    pub fn read_u16(&self, offset: usize) -> Option<u16>
+    {
+        let bytes: [u8; 2] = self.data.get(offset..offset.checked_add(2)?)?.try_into().ok()?;
+
+        if self.little_endian
+        {
+            Some(u16::from_le_bytes(bytes))
+        }
+        else
+        {
+            Some(u16::from_be_bytes(bytes))
+        }
+    }
+
+    pub fn read_u32(&self, offset: usize) -> Option<u32>
+    {
+        let bytes: [u8; 4] = self.data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+
+        if self.little_endian
+        {
+            Some(u32::from_le_bytes(bytes))
+        }
+        else
+        {
+            Some(u32::from_be_bytes(bytes))
+        }
+    }
+
+    /// Encode `value` in the byte order of this structure
+    pub fn encode_u16(&self, value: u16) -> [u8; 2]
+    {
+        if self.little_endian
+        {
+            value.to_le_bytes()
+        }
+        else
+        {
+            value.to_be_bytes()
+        }
+    }
+
+    /// Encode `value` in the byte order of this structure
+    pub fn encode_u32(&self, value: u32) -> [u8; 4]
+    {
+        if self.little_endian
+        {
+            value.to_le_bytes()
+        }
+        else
+        {
+            value.to_be_bytes()
+        }
+    }
+
+    /// Offset of IFD0, stored in the TIFF header
+    pub fn first_ifd(&self) -> Option<usize>
+    {
+        self.read_u32(4).map(|x| x as usize)
+    }
+
+    /// Read all entries of the IFD starting at `offset`
+    pub fn entries(&self, offset: usize) -> Option<Vec<IfdEntry>>
+    {
+        let count = usize::from(self.read_u16(offset)?);
+        let mut entries = Vec::with_capacity(count);
+
+        for i in 0..count
+        {
+            let start = offset + 2 + i * 12;
+
+            entries.push(IfdEntry {
+                tag:          self.read_u16(start)?,
+                field_type:   self.read_u16(start + 2)?,
+                count:        self.read_u32(start + 4)?,
+                value_offset: start + 8,
+            });
+        }
+
+        Some(entries)
+    }
+
+    /// Offset of the IFD following the one at `offset`, `None` if this is the last one
+    pub fn next_ifd(&self, offset: usize) -> Option<usize>
+    {
+        let count = usize::from(self.read_u16(offset)?);
+        let next = self.read_u32(offset + 2 + count * 12)? as usize;
+
+        if next == 0 || next == offset
+        {
+            return None;
+        }
+
+        Some(next)
+    }
+
+    /// Range of the values of an entry stored outside of the entry, `None` if
+    /// they fit in the 4 byte value field
+    pub fn entry_data(&self, entry: &IfdEntry) -> Option<std::ops::Range<usize>>
+    {
+        let size = match entry.field_type
+        {
+            // BYTE, ASCII, SBYTE, UNDEFINED
+            1 | 2 | 6 | 7 => 1,
+            // SHORT, SSHORT
+            3 | 8 => 2,
+            // LONG, SLONG, FLOAT
+            4 | 9 | 11 => 4,
+            // RATIONAL, SRATIONAL, DOUBLE
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        let length = (entry.count as usize).checked_mul(size)?;
+
+        if length <= 4
+        {
+            return None;
+        }
+        let start = self.read_u32(entry.value_offset)? as usize;
+
+        Some(start..start.checked_add(length)?)
+    }
+
+    /// Read the first value of an entry holding a SHORT or LONG
+    pub fn entry_u32(&self, entry: &IfdEntry) -> Option<u32>
+    {
+        if entry.count == 0
+        {
+            return None;
+        }
+        match entry.field_type
+        {
+            // SHORT
+            3 => self.read_u16(entry.value_offset).map(u32::from),
+            // LONG
+            4 => self.read_u32(entry.value_offset),
+            _ => None,
+        }
+    }
+}
+
+/// Find the JPEG thumbnail stored in IFD1 of an EXIF structure.
+///
+/// `exif` should point to the TIFF header (i.e. after the `Exif\0\0` identifier).
+///
+/// Returns the thumbnail bytes, or `None` if there is no thumbnail or the structure is
+/// corrupt.
+pub(crate) fn find_thumbnail(exif: &[u8]) -> Option<&[u8]>
+{
+    let reader = TiffReader::new(exif)?;
+    let ifd0 = reader.first_ifd()?;
+    let ifd1 = reader.next_ifd(ifd0)?;
+
+    let mut start = None;
+    let mut length = None;
+
+    for entry in reader.entries(ifd1)?
+    {
+        match entry.tag
+        {
+            TAG_JPEG_INTERCHANGE_FORMAT => start = reader.entry_u32(&entry),
+            TAG_JPEG_INTERCHANGE_FORMAT_LENGTH => length = reader.entry_u32(&entry),
+            _ => (),
+        }
+    }
+    let start = start? as usize;
+    let end = start.checked_add(length? as usize)?;
+
+    exif.get(start..end)
+}
+
+/// Read the camera manufacturer stored in IFD0 of an EXIF structure
+///
+/// The string ends at the first NUL byte and surrounding whitespace is removed,
+/// returns `None` if there is no make or it is empty.
+pub(crate) fn camera_make(exif: &[u8]) -> Option<String>
+{
+    let reader = TiffReader::new(exif)?;
+    let entry = reader
+        .entries(reader.first_ifd()?)?
+        .into_iter()
+        .find(|x| x.tag == TAG_MAKE && x.field_type == 2)?;
+
+    let range = reader
+        .entry_data(&entry)
+        .unwrap_or(entry.value_offset..entry.value_offset + entry.count as usize);
+
+    let make = String::from_utf8_lossy(exif.get(range)?);
+    let make = make.split('\0').next()?.trim();
+
+    if make.is_empty()
+    {
+        return None;
+    }
+
+    Some(make.to_string())
+}
+
+/// Remove the GPS IFD from an EXIF structure
+///
+/// The GPS IFD and the values it points to are zeroed and its entry removed from
+/// IFD0, everything else keeps its position so no offsets need updating.
+///
+/// Returns `None` if the structure is corrupt, and `Some(false)` if it has no GPS IFD.
+pub(crate) fn remove_gps(exif: &mut [u8]) -> Option<bool>
+{
+    let reader = TiffReader::new(exif)?;
+    let ifd0 = reader.first_ifd()?;
+    let entries = reader.entries(ifd0)?;
+
+    let Some(index) = entries.iter().position(|x| x.tag == TAG_GPS_INFO)
+    else
+    {
+        return Some(false);
+    };
+    let gps = reader.entry_u32(&entries[index])? as usize;
+    let gps_entries = reader.entries(gps)?;
+
+    // the IFD itself, its entries, the next IFD offset and the values stored elsewhere
+    let cleared = std::iter::once(gps..gps + 2 + gps_entries.len() * 12 + 4)
+        .chain(gps_entries.iter().filter_map(|x| reader.entry_data(x)))
+        .collect::<Vec<_>>();
+
+    let count = reader.encode_u16(u16::try_from(entries.len() - 1).ok()?);
+    let ifd0_end = ifd0 + 2 + entries.len() * 12 + 4;
+
+    if cleared.iter().chain([&(ifd0..ifd0_end)]).any(|x| x.end > exif.len())
+    {
+        return None;
+    }
+    for range in cleared
+    {
+        exif[range].fill(0);
+    }
+    // shift the entries after the GPS entry and the next IFD offset down
+    let removed = ifd0 + 2 + index * 12;
+
+    exif.copy_within(removed + 12..ifd0_end, removed);
+    exif[ifd0_end - 12..ifd0_end].fill(0);
+    exif[ifd0..ifd0 + 2].copy_from_slice(&count);
+
+    Some(true)
+}
+
+/// Set the orientation in IFD0 of an EXIF structure
+///
+/// If IFD0 has no orientation a copy of it with the orientation added is appended to
+/// the structure, offsets of other values stay valid since nothing moves.
+///
+/// Returns `None` if the structure is corrupt.
+pub(crate) fn set_orientation(exif: &mut Vec<u8>, orientation: u16) -> Option<()>
+{
+    let reader = TiffReader::new(exif)?;
+    let ifd0 = reader.first_ifd()?;
+    let entries = reader.entries(ifd0)?;
+    let value = reader.encode_u16(orientation);
+
+    if let Some(entry) = entries.iter().find(|x| x.tag == TAG_ORIENTATION)
+    {
+        if entry.field_type != 3
+        {
+            return None;
+        }
+        let offset = entry.value_offset;
+
+        exif.get_mut(offset..offset + 2)?.copy_from_slice(&value);
+
+        return Some(());
+    }
+    let next_ifd = reader.read_u32(ifd0 + 2 + entries.len() * 12)?;
+
+    let mut added = reader.encode_u16(TAG_ORIENTATION).to_vec();
+
+    added.extend_from_slice(&reader.encode_u16(3));
+    added.extend_from_slice(&reader.encode_u32(1));
+    added.extend_from_slice(&value);
+    added.extend_from_slice(&[0, 0]);
+
+    let mut ifd = reader
+        .encode_u16(u16::try_from(entries.len() + 1).ok()?)
+        .to_vec();
+
+    // entries must be sorted by tag
+    let position = entries.partition_point(|x| x.tag < TAG_ORIENTATION);
+
+    for entry in &entries[..position]
+    {
+        ifd.extend_from_slice(exif.get(entry.value_offset - 8..entry.value_offset + 4)?);
+    }
+    ifd.extend_from_slice(&added);
+
+    for entry in &entries[position..]
+    {
+        ifd.extend_from_slice(exif.get(entry.value_offset - 8..entry.value_offset + 4)?);
+    }
+    ifd.extend_from_slice(&reader.encode_u32(next_ifd));
+
+    // IFDs start on a word boundary
+    let start = exif.len() + exif.len() % 2;
+    let new_ifd = reader.encode_u32(u32::try_from(start).ok()?);
+
+    exif.resize(start, 0);
+    exif[4..8].copy_from_slice(&new_ifd);
+    exif.extend_from_slice(&ifd);
+
+    Some(())
+}
+
+/// A big endian EXIF structure holding only an orientation
+pub(crate) fn orientation_only(orientation: u16) -> Vec<u8>
+{
+    let mut exif = b"MM\0\x2A\0\0\0\x08".to_vec();
+
+    exif.extend_from_slice(&1_u16.to_be_bytes());
+    exif.extend_from_slice(&TAG_ORIENTATION.to_be_bytes());
+    exif.extend_from_slice(&3_u16.to_be_bytes());
+    exif.extend_from_slice(&1_u32.to_be_bytes());
+    exif.extend_from_slice(&orientation.to_be_bytes());
+    exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+    exif
+}
@@ -0,0 +1,620 @@
+//! Ultra HDR gain map decoding
+//!
+//! An Ultra HDR (or ISO 21496-1) JPEG is a backwards compatible SDR image with
+//! a second JPEG, the gain map, appended to it and linked via an MPF segment.
+//! The gain map stores, per pixel, how much brighter the HDR rendition is than
+//! the SDR one, and metadata describing how to interpret it is stored either in the
+//! XMP of the gain map image (`hdrgm` namespace) or in an APP2 segment
+//! with the `urn:iso:std:iso:ts:21496:-1` identifier.
+//!
+//! Applying a gain map for a display that can show `display_boost` times
+//! SDR white goes as follows (all gains are in log2 space)
+//!
+//! ```text
+//! recovery = gain_map ^ (1 / gamma)
+//! log_boost = gain_map_min * (1 - recovery) + gain_map_max * recovery
+//! weight = clamp((log2(display_boost) - base_headroom) / (alternate_headroom - base_headroom), 0, 1)
+//! hdr = (sdr + offset_base) * 2 ^ (log_boost * weight) - offset_alternate
+//! ```
+//!
+//! where `sdr` is the base image in linear light.
+//!
+//! # Limitations
+//! - The base image is assumed to use the sRGB transfer function, no gamut conversion
+//!   is done so output is in the primaries of the base image (commonly sRGB or Display P3).
+//! - HLG output assumes a 1000 nit nominal peak and does not apply the inverse OOTF.
+//!
+//! See <https://developer.android.com/media/platform/hdr-image-format>
+
+use std::cmp::min;
+
+use crate::errors::DecodeErrors;
+use crate::xmp::{property_value, property_values};
+use crate::{ColorSpace, Decoder};
+
+/// Luminance of SDR white, in nits, as recommended by ITU-R BT.2408
+const SDR_WHITE_NITS: f32 = 203.0;
+
+/// Peak luminance of the PQ transfer function
+const PQ_MAX_NITS: f32 = 10000.0;
+
+/// Nominal peak luminance for HLG output
+const HLG_MAX_NITS: f32 = 1000.0;
+
+/// Metadata describing how a gain map should be applied
+///
+/// Gains and headrooms are in log2 space, so a `gain_map_max` of 2.0 means the
+/// brightest pixels of the HDR rendition are 4 times brighter than in the SDR rendition.
+///
+/// Per channel values are stored for red, green and blue, for single channel gain maps
+/// all three are the same.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GainMapMetadata
+{
+    /// log2 of the gain for a gain map value of 0
+    pub gain_map_min:          [f32; 3],
+    /// log2 of the gain for a gain map value of 1
+    pub gain_map_max:          [f32; 3],
+    /// Gamma applied to gain map values
+    pub gamma:                 [f32; 3],
+    /// Offset added to SDR values before applying the gain
+    pub offset_sdr:            [f32; 3],
+    /// Offset subtracted from HDR values after applying the gain
+    pub offset_hdr:            [f32; 3],
+    /// log2 of the display boost at which the gain map starts to be applied
+    pub hdr_capacity_min:      f32,
+    /// log2 of the display boost at which the gain map is fully applied
+    pub hdr_capacity_max:      f32,
+    /// Whether the base image is the HDR rendition, and the gain map produces SDR
+    pub base_rendition_is_hdr: bool,
+}
+
+impl GainMapMetadata
+{
+    /// Parse metadata from the `hdrgm` namespace of an XMP packet
+    ///
+    /// Returns `None` if the packet has no gain map metadata or it is invalid.
+    pub(crate) fn from_xmp(xmp: &[u8]) -> Option<GainMapMetadata>
+    {
+        let xmp = std::str::from_utf8(xmp).ok()?;
+
+        property_value(xmp, "hdrgm:Version")?;
+
+        let channels = |name: &str, default: f32| -> Option<[f32; 3]> {
+            let Some(values) = property_values(xmp, name)
+            else
+            {
+                return Some([default; 3]);
+            };
+            let values = values
+                .iter()
+                .map(|x| x.parse::<f32>().ok())
+                .collect::<Option<Vec<f32>>>()?;
+
+            match values.len()
+            {
+                1 => Some([values[0]; 3]),
+                3 => Some([values[0], values[1], values[2]]),
+                _ => None,
+            }
+        };
+        let gain_map_max = channels("hdrgm:GainMapMax", f32::NAN)?;
+
+        if gain_map_max.iter().any(|x| x.is_nan())
+        {
+            warn!("XMP gain map metadata has no hdrgm:GainMapMax, ignoring it");
+            return None;
+        }
+        let hdr_capacity_max = match property_value(xmp, "hdrgm:HDRCapacityMax")
+        {
+            Some(value) => value.parse().ok()?,
+            None => gain_map_max.iter().copied().fold(0.0, f32::max),
+        };
+
+        Some(GainMapMetadata {
+            gain_map_min: channels("hdrgm:GainMapMin", 0.0)?,
+            gain_map_max,
+            gamma: channels("hdrgm:Gamma", 1.0)?,
+            offset_sdr: channels("hdrgm:OffsetSDR", 1.0 / 64.0)?,
+            offset_hdr: channels("hdrgm:OffsetHDR", 1.0 / 64.0)?,
+            hdr_capacity_min: match property_value(xmp, "hdrgm:HDRCapacityMin")
+            {
+                Some(value) => value.parse().ok()?,
+                None => 0.0,
+            },
+            hdr_capacity_max,
+            base_rendition_is_hdr: property_value(xmp, "hdrgm:BaseRenditionIsHDR")
+                .is_some_and(|x| x.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// Parse metadata from an ISO 21496-1 APP2 segment, after the identifier
+    ///
+    /// Returns `None` if the segment only carries a version (as is the case for the
+    /// primary image) or is invalid.
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn from_iso(data: &[u8]) -> Option<GainMapMetadata>
+    {
+        let mut reader = IsoReader { data, position: 0 };
+
+        let minimum_version = reader.u16()?;
+        let _writer_version = reader.u16()?;
+
+        if minimum_version != 0
+        {
+            warn!("Unsupported ISO 21496-1 gain map version {minimum_version}");
+            return None;
+        }
+        // segments in the primary image only carry the version and end here
+        let flags = reader.u8()?;
+
+        let channel_count = if flags & 0x80 != 0 { 3 } else { 1 };
+        let backward_direction = flags & 0x04 != 0;
+        let common_denominator = flags & 0x08 != 0;
+
+        // gain map min, max, gamma, base offset and alternate offset for each channel
+        let mut channels = [[0.0_f32; 5]; 3];
+        let (base_headroom, alternate_headroom);
+
+        if common_denominator
+        {
+            let denominator = reader.u32()?;
+
+            if denominator == 0
+            {
+                return None;
+            }
+            let d = denominator as f32;
+
+            base_headroom = reader.u32()? as f32 / d;
+            alternate_headroom = reader.u32()? as f32 / d;
+
+            for channel in channels.iter_mut().take(channel_count)
+            {
+                *channel = [
+                    reader.i32()? as f32 / d,
+                    reader.i32()? as f32 / d,
+                    reader.u32()? as f32 / d,
+                    reader.i32()? as f32 / d,
+                    reader.i32()? as f32 / d,
+                ];
+            }
+        }
+        else
+        {
+            base_headroom = reader.unsigned_fraction()?;
+            alternate_headroom = reader.unsigned_fraction()?;
+
+            for channel in channels.iter_mut().take(channel_count)
+            {
+                *channel = [
+                    reader.signed_fraction()?,
+                    reader.signed_fraction()?,
+                    reader.unsigned_fraction()?,
+                    reader.signed_fraction()?,
+                    reader.signed_fraction()?,
+                ];
+            }
+        }
+        if channel_count == 1
+        {
+            channels = [channels[0]; 3];
+        }
+        let [gain_map_min, gain_map_max, gamma, offset_base, offset_alternate] =
+            [0, 1, 2, 3, 4].map(|i| channels.map(|channel| channel[i]));
+
+        let (offset_sdr, offset_hdr, hdr_capacity_min, hdr_capacity_max) = if backward_direction
+        {
+            (offset_alternate, offset_base, alternate_headroom, base_headroom)
+        }
+        else
+        {
+            (offset_base, offset_alternate, base_headroom, alternate_headroom)
+        };
+
+        Some(GainMapMetadata {
+            gain_map_min,
+            gain_map_max,
+            gamma,
+            offset_sdr,
+            offset_hdr,
+            hdr_capacity_min,
+            hdr_capacity_max,
+            base_rendition_is_hdr: backward_direction,
+        })
+    }
+
+    /// Weight of the gain map for a display able to show `display_boost` times SDR white
+    fn weight(&self, display_boost: f32) -> f32
+    {
+        let (base, alternate) = if self.base_rendition_is_hdr
+        {
+            (self.hdr_capacity_max, self.hdr_capacity_min)
+        }
+        else
+        {
+            (self.hdr_capacity_min, self.hdr_capacity_max)
+        };
+        let headroom = display_boost.log2();
+
+        if (alternate - base).abs() < f32::EPSILON
+        {
+            return if headroom >= alternate { 1.0 } else { 0.0 };
+        }
+        ((headroom - base) / (alternate - base)).clamp(0.0, 1.0)
+    }
+}
+
+/// Big endian reader for ISO 21496-1 metadata
+struct IsoReader<'a>
+{
+    data:     &'a [u8],
+    position: usize,
+}
+
+impl IsoReader<'_>
+{
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]>
+    {
+        let bytes = self.data.get(self.position..self.position + N)?.try_into().ok()?;
+
+        self.position += N;
+
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8>
+    {
+        self.bytes::<1>().map(|x| x[0])
+    }
+
+    fn u16(&mut self) -> Option<u16>
+    {
+        self.bytes().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32>
+    {
+        self.bytes().map(u32::from_be_bytes)
+    }
+
+    fn i32(&mut self) -> Option<i32>
+    {
+        self.bytes().map(i32::from_be_bytes)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn unsigned_fraction(&mut self) -> Option<f32>
+    {
+        let (numerator, denominator) = (self.u32()?, self.u32()?);
+
+        (denominator != 0).then(|| numerator as f32 / denominator as f32)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn signed_fraction(&mut self) -> Option<f32>
+    {
+        let (numerator, denominator) = (self.i32()?, self.u32()?);
+
+        (denominator != 0).then(|| numerator as f32 / denominator as f32)
+    }
+}
+
+/// An SDR base image together with its gain map
+///
+/// Created by [`Decoder::decode_ultra_hdr`], use [`to_linear`](Self::to_linear),
+/// [`to_pq`](Self::to_pq) or [`to_hlg`](Self::to_hlg) to get the HDR rendition.
+#[derive(Clone, Debug)]
+pub struct UltraHdrImage
+{
+    /// Pixels of the base image, in RGB
+    pub base:              Vec<u8>,
+    /// Width of the base image
+    pub width:             usize,
+    /// Height of the base image
+    pub height:            usize,
+    /// Pixels of the gain map, with `gain_map_channels` channels
+    pub gain_map:          Vec<u8>,
+    /// Width of the gain map
+    pub gain_map_width:    usize,
+    /// Height of the gain map
+    pub gain_map_height:   usize,
+    /// Number of channels in the gain map, 1 or 3
+    pub gain_map_channels: usize,
+    /// How the gain map should be applied
+    pub metadata:          GainMapMetadata,
+}
+
+impl UltraHdrImage
+{
+    /// Apply the gain map for a display that can show `display_boost` times
+    /// the brightness of SDR white, returning linear RGB where 1.0 is SDR white
+    ///
+    /// A `display_boost` of 1.0 (or less) returns the SDR rendition, the gain map is
+    /// fully applied once `display_boost` reaches `2^hdr_capacity_max`.
+    ///
+    /// The gain map is up-sampled to the size of the base image using
+    /// bilinear interpolation.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_linear(&self, display_boost: f32) -> Vec<f32>
+    {
+        let metadata = &self.metadata;
+        let weight = metadata.weight(display_boost.max(1.0));
+
+        let (offset_base, offset_alternate) = if metadata.base_rendition_is_hdr
+        {
+            (metadata.offset_hdr, metadata.offset_sdr)
+        }
+        else
+        {
+            (metadata.offset_sdr, metadata.offset_hdr)
+        };
+        // sRGB EOTF for every possible base value
+        let linear: Vec<f32> = (0..=255_u8)
+            .map(|x| {
+                let x = f32::from(x) / 255.0;
+
+                if x <= 0.040_45
+                {
+                    x / 12.92
+                }
+                else
+                {
+                    ((x + 0.055) / 1.055).powf(2.4)
+                }
+            })
+            .collect();
+
+        // weighted log2 gain for every possible gain map value
+        let log_gain: Vec<[f32; 256]> = (0..3)
+            .map(|c| {
+                let mut table = [0.0; 256];
+
+                for (value, gain) in table.iter_mut().enumerate()
+                {
+                    let recovery = (value as f32 / 255.0).powf(1.0 / metadata.gamma[c]);
+
+                    *gain = (metadata.gain_map_min[c] * (1.0 - recovery)
+                        + metadata.gain_map_max[c] * recovery)
+                        * weight;
+                }
+                table
+            })
+            .collect();
+
+        let x_taps: Vec<(usize, usize, f32)> = (0..self.width)
+            .map(|x| bilinear_taps(x, self.width, self.gain_map_width))
+            .collect();
+
+        let channels = self.gain_map_channels;
+        let mut output = vec![0.0; self.width * self.height * 3];
+
+        for (y, (out_row, base_row)) in output
+            .chunks_exact_mut(self.width * 3)
+            .zip(self.base.chunks_exact(self.width * 3))
+            .enumerate()
+        {
+            let (y0, y1, fy) = bilinear_taps(y, self.height, self.gain_map_height);
+
+            let row0 = &self.gain_map[y0 * self.gain_map_width * channels..];
+            let row1 = &self.gain_map[y1 * self.gain_map_width * channels..];
+
+            for ((out, base), &(x0, x1, fx)) in out_row
+                .chunks_exact_mut(3)
+                .zip(base_row.chunks_exact(3))
+                .zip(&x_taps)
+            {
+                for c in 0..3
+                {
+                    let gc = min(c, channels - 1);
+                    let table = &log_gain[c];
+
+                    let top = table[usize::from(row0[x0 * channels + gc])] * (1.0 - fx)
+                        + table[usize::from(row0[x1 * channels + gc])] * fx;
+                    let bottom = table[usize::from(row1[x0 * channels + gc])] * (1.0 - fx)
+                        + table[usize::from(row1[x1 * channels + gc])] * fx;
+                    let gain = top * (1.0 - fy) + bottom * fy;
+
+                    out[c] = (linear[usize::from(base[c])] + offset_base[c]) * gain.exp2()
+                        - offset_alternate[c];
+                }
+            }
+        }
+        output
+    }
+
+    /// Apply the gain map and encode the result with the PQ (SMPTE ST 2084)
+    /// transfer function, using the full range of a `u16`
+    ///
+    /// SDR white is mapped to 203 nits.
+    ///
+    /// See [`to_linear`](Self::to_linear) for the meaning of `display_boost`
+    #[must_use]
+    pub fn to_pq(&self, display_boost: f32) -> Vec<u16>
+    {
+        const M1: f32 = 0.159_301_76;
+        const M2: f32 = 78.843_75;
+        const C1: f32 = 0.835_937_5;
+        const C2: f32 = 18.851_563;
+        const C3: f32 = 18.6875;
+
+        encode_u16(self.to_linear(display_boost), |x| {
+            let y = (x * SDR_WHITE_NITS / PQ_MAX_NITS).clamp(0.0, 1.0).powf(M1);
+
+            ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+        })
+    }
+
+    /// Apply the gain map and encode the result with the HLG (ITU-R BT.2100)
+    /// transfer function, using the full range of a `u16`
+    ///
+    /// SDR white is mapped to 203 nits, with a nominal peak of 1000 nits.
+    ///
+    /// See [`to_linear`](Self::to_linear) for the meaning of `display_boost`
+    #[must_use]
+    pub fn to_hlg(&self, display_boost: f32) -> Vec<u16>
+    {
+        const A: f32 = 0.178_832_77;
+        const B: f32 = 0.284_668_92;
+        const C: f32 = 0.559_910_7;
+
+        encode_u16(self.to_linear(display_boost), |x| {
+            let e = (x * SDR_WHITE_NITS / HLG_MAX_NITS).clamp(0.0, 1.0);
+
+            if e <= 1.0 / 12.0
+            {
+                (3.0 * e).sqrt()
+            }
+            else
+            {
+                A * (12.0 * e - B).ln() + C
+            }
+        })
+    }
+}
+
+/// Encode linear values into `u16` with the transfer function `oetf`, which
+/// maps to `[0, 1]`
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn encode_u16(linear: Vec<f32>, oetf: impl Fn(f32) -> f32) -> Vec<u16>
+{
+    linear
+        .into_iter()
+        .map(|x| (oetf(x) * 65535.0).round() as u16)
+        .collect()
+}
+
+/// Source samples and weight of the second sample for bilinear interpolation of
+/// output position `x` when scaling `size` samples to `output_size`
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn bilinear_taps(x: usize, output_size: usize, size: usize) -> (usize, usize, f32)
+{
+    let position = ((x as f32 + 0.5) * size as f32 / output_size as f32 - 0.5).max(0.0);
+    let x0 = min(position as usize, size - 1);
+    let x1 = min(x0 + 1, size - 1);
+
+    (x0, x1, position - x0 as f32)
+}
+
+impl Decoder
+{
+    /// Decode an Ultra HDR (or ISO 21496-1) image, returning the SDR base image
+    /// and its gain map
+    ///
+    /// The gain map is the first secondary image of the MPF segment that carries
+    /// gain map metadata, in its ISO 21496-1 APP2 segment or XMP.
+    ///
+    /// Both images are decoded with the options of this decoder, except the base image
+    /// is always decoded to RGB, a grayscale one having its samples repeated, and the
+    /// gain map to grayscale or RGB depending on its number of components. MPF images
+    /// out of the bounds of `buf` are skipped.
+    ///
+    /// # Errors
+    /// - The base image isn't grayscale or YCbCr
+    /// - The image has no gain map
+    /// - Any image is corrupt, see DecodeErrors enum for list of possible errors
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let img_data = std::fs::read("an_ultra_hdr.jpeg").unwrap();
+    /// let image = Decoder::new().decode_ultra_hdr(&img_data).unwrap();
+    /// // for a display that can show 4 times SDR white
+    /// let hdr = image.to_pq(4.0);
+    ///
+    /// assert_eq!(hdr.len(), image.width * image.height * 3);
+    /// ```
+    pub fn decode_ultra_hdr(&mut self, buf: &[u8]) -> Result<UltraHdrImage, DecodeErrors>
+    {
+        self.read_headers(buf)?;
+
+        let grayscale = match self.input_colorspace
+        {
+            ColorSpace::GRAYSCALE => true,
+            ColorSpace::YCbCr => false,
+            _ =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Ultra HDR base image should be grayscale or YCbCr, found {:?}",
+                    self.input_colorspace
+                )))
+            }
+        };
+        let mut images = self.secondary_images(buf);
+
+        let (gain_map, metadata) = loop
+        {
+            let Some(next) = images.next_data()
+            else
+            {
+                return Err(DecodeErrors::FormatStatic("Image has no gain map"));
+            };
+            let Ok((_, data)) = next
+            else
+            {
+                warn!("MPF image out of bounds, ignoring it");
+                continue;
+            };
+
+            let mut decoder = Decoder::new_with_options(self.options);
+
+            if decoder.read_headers(data).is_err()
+            {
+                warn!("Corrupt MPF image, ignoring it");
+                continue;
+            }
+            let metadata = decoder
+                .iso_gainmap_data
+                .as_deref()
+                .and_then(GainMapMetadata::from_iso)
+                .or_else(|| GainMapMetadata::from_xmp(decoder.xmp_data.as_deref()?));
+
+            if let Some(metadata) = metadata
+            {
+                break (data, metadata);
+            }
+        };
+
+        let base_colorspace = if grayscale { ColorSpace::GRAYSCALE } else { ColorSpace::RGB };
+        let mut base_decoder =
+            Decoder::new_with_options(self.options.set_out_colorspace(base_colorspace));
+        let mut base = base_decoder.decode_buffer(buf)?;
+
+        if grayscale
+        {
+            base = base.iter().flat_map(|&x| [x; 3]).collect();
+        }
+
+        let mut gain_map_decoder = Decoder::new_with_options(self.options);
+
+        gain_map_decoder.read_headers(gain_map)?;
+
+        let gain_map_colorspace = if gain_map_decoder.input_colorspace == ColorSpace::GRAYSCALE
+        {
+            ColorSpace::GRAYSCALE
+        }
+        else
+        {
+            ColorSpace::RGB
+        };
+        let mut gain_map_decoder =
+            Decoder::new_with_options(self.options.set_out_colorspace(gain_map_colorspace));
+        let gain_map_pixels = gain_map_decoder.decode_buffer(gain_map)?;
+
+        Ok(UltraHdrImage {
+            base,
+            width: usize::from(base_decoder.width()),
+            height: usize::from(base_decoder.height()),
+            gain_map: gain_map_pixels,
+            gain_map_width: usize::from(gain_map_decoder.width()),
+            gain_map_height: usize::from(gain_map_decoder.height()),
+            gain_map_channels: gain_map_colorspace.num_components(),
+            metadata,
+        })
+    }
+}
@@ -7,14 +7,19 @@ use std::cmp::max;
 use std::io::{BufRead, Read};
 
 use crate::components::Components;
-use crate::decoder::{Decoder, ImageInfo, MAX_COMPONENTS};
+use crate::decoder::{Decoder, MAX_COMPONENTS};
+use crate::description::{HuffmanClass, HuffmanTableDescription, ScanComponent, ScanDescription};
 use crate::errors::DecodeErrors;
 use crate::huffman::HuffmanTable;
 use crate::marker::Marker;
 use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers, UN_ZIGZAG};
 
 ///**B.2.4.2 Huffman table-specification syntax**
-#[allow(clippy::similar_names)]
+#[allow(
+    clippy::similar_names,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
 pub(crate) fn parse_huffman<R>(decoder: &mut Decoder, mut buf: &mut R) -> Result<(), DecodeErrors>
 where
     R: Read,
@@ -86,6 +91,12 @@ where
             .map_err(|x| {
                 DecodeErrors::Format(format!("Could not read symbols into the buffer\n{}", x))
             })?;
+        decoder.huffman_specs.push(HuffmanTableDescription {
+            class:  if dc_or_ac == 0 { HuffmanClass::Dc } else { HuffmanClass::Ac },
+            index:  index as u8,
+            bits:   num_symbols[1..].try_into().unwrap(),
+            values: symbols[..symbols_sum as usize].to_vec(),
+        });
         // store
         match dc_or_ac
         {
@@ -166,11 +177,20 @@ where
             }
             1 =>
             {
-                // 16 bit quantization tables
-                //(cae) Before we enable this. Should 16 bit QT cause any other lib changes
-                return Err(DecodeErrors::DqtError(
-                    "Support for 16 bit quantization table is not complete".to_string(),
-                ));
+                // 16 bit quantization tables, big endian
+                let mut qt_values = [0; 128];
+
+                buf.read_exact(&mut qt_values).map_err(|x| {
+                    DecodeErrors::Format(format!("Could not read symbols into the buffer\n{}", x))
+                })?;
+                qt_length -= (precision_value as u16) + 1 /*QT BIT*/;
+
+                let qt_values = qt_values
+                    .chunks_exact(2)
+                    .map(|x| u16::from_be_bytes([x[0], x[1]]))
+                    .collect::<Vec<u16>>();
+
+                un_zig_zag(&qt_values)
             }
             _ =>
             {
@@ -195,6 +215,70 @@ where
     return Ok(());
 }
 
+///**B.2.4.3 Arithmetic conditioning table-specification syntax**
+pub(crate) fn parse_dac<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    let length = read_u16_be(buf)?
+        .checked_sub(2)
+        .ok_or(DecodeErrors::FormatStatic("Invalid DAC length"))?;
+
+    if length % 2 != 0
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid DAC length {length}, conditioning tables take two bytes"
+        )));
+    }
+    for _ in 0..length / 2
+    {
+        let table_info = read_byte(buf)?;
+        let value = read_byte(buf)?;
+        // upper four bits are the class, 0 for DC and 1 for AC
+        let index = usize::from(table_info & 0xF);
+
+        if index >= MAX_COMPONENTS
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Invalid DAC index {index}, expected between 0 and 3"
+            )));
+        }
+        match table_info >> 4
+        {
+            0 =>
+            {
+                // lower and upper bound of small DC differences
+                let (lower, upper) = (value & 0xF, value >> 4);
+
+                if lower > upper
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid DC conditioning L={lower} U={upper}"
+                    )));
+                }
+                decoder.conditioning.dc[index] = (lower, upper);
+            }
+            1 =>
+            {
+                if !(1..=63).contains(&value)
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid AC conditioning Kx={value}"
+                    )));
+                }
+                decoder.conditioning.ac[index] = value;
+            }
+            class =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Invalid DAC class {class}, should be 0 or 1"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Section:`B.2.2 Frame header syntax`
 
 pub(crate) fn parse_start_of_frame<R>(
@@ -384,6 +468,8 @@ where
         ));
     }
 
+    let mut scan_components = Vec::with_capacity(usize::from(ns));
+
     // consume spec parameters
     for i in 0..ns
     {
@@ -432,6 +518,11 @@ where
             )));
         }
 
+        scan_components.push(ScanComponent {
+            id,
+            dc_table: (y >> 4) & 0xF,
+            ac_table: y & 0xF,
+        });
         image.components[usize::from(j)].dc_huff_table = usize::from((y >> 4) & 0xF);
         image.components[usize::from(j)].ac_huff_table = usize::from(y & 0xF);
         image.z_order[i as usize] = j as usize;
@@ -472,20 +563,48 @@ where
             image.succ_low
         )));
     }
+    image.scans.push(ScanDescription {
+        components: scan_components,
+        spec_start: image.spec_start,
+        spec_end:   image.spec_end,
+        succ_high:  image.succ_high,
+        succ_low:   image.succ_low,
+    });
 
     Ok(())
 }
 
-pub(crate) fn _parse_app<R>(
-    buf: &mut R, marker: Marker, _info: &mut ImageInfo,
-) -> Result<(), DecodeErrors>
+/// The first bytes of a segment of `length` bytes, without consuming them
+fn peek<R: BufRead>(buf: &mut R, length: u16) -> Result<&[u8], DecodeErrors>
+{
+    let head = buf.fill_buf()?;
+
+    Ok(&head[..head.len().min(usize::from(length))])
+}
+
+/// Read `length` bytes
+fn read_bytes<R: Read>(buf: &mut R, length: usize) -> Result<Vec<u8>, DecodeErrors>
+{
+    let mut data = vec![0; length];
+
+    buf.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+/// Parse an application segment
+///
+/// Currently we only extract EXIF and XMP data from APP(1), MPF and ISO 21496-1
+/// gain map data from APP(2), JPS descriptors from APP(3), JPEG XT boxes from APP(11)
+/// and Photoshop image resources from APP(13), everything else is skipped.
+pub(crate) fn parse_app<R>(buf: &mut R, marker: Marker, decoder: &mut Decoder) -> Result<(), DecodeErrors>
 where
     R: BufRead + Read,
 {
     let length = read_u16_be(buf)?
         .checked_sub(2)
         .ok_or(DecodeErrors::Format(
-            "Invalid APP0 length. Length should be greater than 2".to_string(),
+            "Invalid APP length. Length should be greater than 2".to_string(),
         ))?;
 
     let mut bytes_read = 0;
@@ -498,45 +617,64 @@ where
             {
                 warn!("Incorrect length of APP0 ,{}, should be 14", length);
             }
-            // Don't handle APP0 as of now
-            buf.consume(length as usize);
         }
-        Marker::APP(1) =>
+        Marker::APP(n @ (1..=3 | 13)) =>
         {
-            if length >= 6
-            {
-                let mut buffer = [0_u8; 6];
-
-                buf.read_exact(&mut buffer).map_err(|x| {
-                    DecodeErrors::Format(format!("Could not read Exif data\n{}", x))
-                })?;
-
-                bytes_read += 6;
-
+            let segments = [
                 // https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
                 // 4.5.4 Basic Structure of Decoder Compressed Data
-                if &buffer == b"Exif\x00\x00"
-                {
-                    buf.consume(length as usize - bytes_read);
-                }
+                (1, &b"Exif\x00\x00"[..], &mut decoder.exif_data),
+                // XMP Specification Part 3, 1.1.3 JPEG
+                (1, &b"http://ns.adobe.com/xap/1.0/\x00"[..], &mut decoder.xmp_data),
+                // CIPA DC-007 5.2 Multi-Picture Format
+                (2, &b"MPF\x00"[..], &mut decoder.mpf_data),
+                // ISO 21496-1 gain map metadata
+                (2, &b"urn:iso:std:iso:ts:21496:-1\x00"[..], &mut decoder.iso_gainmap_data),
+                // Stereoscopic JPEG descriptor
+                (3, &b"_JPSJPS_"[..], &mut decoder.jps_data),
+                // Photoshop File Formats Specification, Image Resource Blocks
+                (13, &b"Photoshop 3.0\x00"[..], &mut decoder.photoshop_data),
+            ];
+            // look at the identifier before copying anything, most segments, like ICC
+            // profile chunks, aren't kept
+            let head = peek(buf, length)?;
+
+            let kept = segments
+                .into_iter()
+                .find(|(app, identifier, _)| *app == n && head.starts_with(identifier));
+
+            // keep the first one, that's the one readers are supposed to use
+            if let Some((_, identifier, slot @ None)) = kept
+            {
+                buf.consume(identifier.len());
+                bytes_read = usize::from(length);
+                *slot = Some(read_bytes(buf, bytes_read - identifier.len())?);
             }
         }
+        // ISO/IEC 18477-3 JPEG XT box, reassembled later
+        Marker::APP(11) if peek(buf, length)?.starts_with(b"JP") =>
+        {
+            buf.consume(2);
+            bytes_read = usize::from(length);
+            decoder.jumbf_packets.push(read_bytes(buf, bytes_read - 2)?);
+        }
         _ =>
         {}
     }
+    buf.consume(usize::from(length) - bytes_read);
 
     Ok(())
 }
 
 /// Small utility function to print Un-zig-zagged quantization tables
 
-fn un_zig_zag(a: &[u8]) -> [i32; 64]
+fn un_zig_zag<T: Copy + Into<i32>>(a: &[T]) -> [i32; 64]
 {
     let mut output = [0; 64];
 
     for i in 0..64
     {
-        output[UN_ZIGZAG[i]] = i32::from(a[i]);
+        output[UN_ZIGZAG[i]] = a[i].into();
     }
 
     output
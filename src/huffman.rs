@@ -245,8 +245,9 @@ impl HuffmanTable
                             k += (!0_i16 << mag_bits) + 1;
                         };
 
-                        // if result is small enough fit into fast ac table
-                        if (-128..=127).contains(&k)
+                        // if result is small enough fit into fast ac table, the run
+                        // and length take the lower 10 bits, leaving 6 for the value
+                        if (-32..=31).contains(&k)
                         {
                             fast_ac[i] = (k << 10) + (run << 4) + (len + mag_bits);
                         }
@@ -0,0 +1,556 @@
+//! Huffman entropy encoding of sequential scans
+//!
+//! This is the inverse of the decoding done by `BitStream`, it is used to write
+//! images whose coefficients were decoded and possibly transformed, so it only
+//! needs to code blocks of quantized coefficients. Progressive scans are coded in
+//! `huffman_encoder_prog.rs`.
+//!
+//! Tables are described the same way as in a DHT segment, by the number of codes of
+//! each length and the symbols in order of increasing code length, so tables of
+//! a decoded image can be reused as is.
+#![allow(clippy::module_name_repetitions)]
+
+use crate::errors::DecodeErrors;
+use crate::huffman::HuffmanTable;
+use crate::misc::UN_ZIGZAG;
+
+/// ITU-T T.81 Table K.3, DC luminance code lengths, `[0]` is unused
+const DC_LUMINANCE_BITS: [u8; 17] = [0, 0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+
+/// ITU-T T.81 Table K.4, DC chrominance code lengths, `[0]` is unused
+const DC_CHROMINANCE_BITS: [u8; 17] = [0, 0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+
+/// Symbols of both DC tables
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+/// ITU-T T.81 Table K.5, AC luminance code lengths, `[0]` is unused
+const AC_LUMINANCE_BITS: [u8; 17] = [0, 0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+
+/// ITU-T T.81 Table K.5, AC luminance symbols
+#[rustfmt::skip]
+const AC_LUMINANCE_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// ITU-T T.81 Table K.6, AC chrominance code lengths, `[0]` is unused
+const AC_CHROMINANCE_BITS: [u8; 17] = [0, 0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+
+/// ITU-T T.81 Table K.6, AC chrominance symbols
+#[rustfmt::skip]
+const AC_CHROMINANCE_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// Largest DC difference category for 8 bit samples
+const MAX_DC_CATEGORY: u8 = 11;
+
+/// Largest AC coefficient category for 8 bit samples
+const MAX_AC_CATEGORY: u8 = 10;
+
+/// Codes of a Huffman table, for encoding
+#[derive(Clone)]
+pub(crate) struct HuffmanEncoder
+{
+    /// Number of codes of each length, `bits[0]` is unused
+    pub(crate) bits:   [u8; 17],
+    /// Symbols in order of increasing code length
+    pub(crate) values: Vec<u8>,
+    /// Code of each symbol, right aligned
+    codes:             [u16; 256],
+    /// Length of the code of each symbol, zero if the table has no code for it
+    lengths:           [u8; 256],
+}
+
+impl HuffmanEncoder
+{
+    /// Create an encoder from code lengths and symbols as stored in a DHT segment
+    ///
+    /// Codes are assigned as in ITU-T T.81 Annex C
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(bits: &[u8; 17], values: &[u8]) -> Result<HuffmanEncoder, DecodeErrors>
+    {
+        let count = bits[1..].iter().map(|&x| usize::from(x)).sum::<usize>();
+
+        if count > 256 || count > values.len()
+        {
+            return Err(DecodeErrors::HuffmanDecode("Bad Huffman Table".to_string()));
+        }
+        let mut encoder = HuffmanEncoder {
+            bits:    *bits,
+            values:  values[..count].to_vec(),
+            codes:   [0; 256],
+            lengths: [0; 256],
+        };
+        let mut code = 0_u32;
+        let mut symbols = encoder.values.iter();
+
+        for (length, &count) in bits.iter().enumerate().skip(1)
+        {
+            for _ in 0..count
+            {
+                let symbol = usize::from(*symbols.next().unwrap());
+
+                // codes of all ones are reserved
+                if code + 1 >= 1 << length
+                {
+                    return Err(DecodeErrors::HuffmanDecode("Bad Huffman Table".to_string()));
+                }
+                encoder.codes[symbol] = code as u16;
+                encoder.lengths[symbol] = length as u8;
+                code += 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(encoder)
+    }
+
+    /// Create an encoder reusing the codes of a table used for decoding
+    pub fn from_table(table: &HuffmanTable) -> Result<HuffmanEncoder, DecodeErrors>
+    {
+        HuffmanEncoder::new(&table.bits, &table.values)
+    }
+
+    /// The DC table of ITU-T T.81 Annex K for luminance or chrominance
+    pub fn std_dc(chrominance: bool) -> HuffmanEncoder
+    {
+        let bits = if chrominance { &DC_CHROMINANCE_BITS } else { &DC_LUMINANCE_BITS };
+
+        HuffmanEncoder::new(bits, &DC_VALUES).unwrap()
+    }
+
+    /// The AC table of ITU-T T.81 Annex K for luminance or chrominance
+    pub fn std_ac(chrominance: bool) -> HuffmanEncoder
+    {
+        if chrominance
+        {
+            HuffmanEncoder::new(&AC_CHROMINANCE_BITS, &AC_CHROMINANCE_VALUES).unwrap()
+        }
+        else
+        {
+            HuffmanEncoder::new(&AC_LUMINANCE_BITS, &AC_LUMINANCE_VALUES).unwrap()
+        }
+    }
+
+    /// Build the optimal table for symbols occurring with `frequencies`
+    ///
+    /// This follows ITU-T T.81 Annex K.2 as libjpeg does: a reserved symbol keeps
+    /// codes of all ones out of the table, and codes longer than 16 bits are
+    /// shortened at the expense of longer codes for rarer symbols.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn optimal(frequencies: &[u32; 256]) -> HuffmanEncoder
+    {
+        let mut freq = [0_u64; 257];
+        // code length of each symbol
+        let mut code_size = [0_usize; 257];
+        // next symbol in the chain of the same tree branch
+        let mut others = [usize::MAX; 257];
+
+        for (out, &frequency) in freq.iter_mut().zip(frequencies)
+        {
+            *out = u64::from(frequency);
+        }
+        // the reserved symbol, it gets the code of all ones
+        freq[256] = 1;
+
+        loop
+        {
+            // the two least frequent symbols, ties go to the largest symbol
+            let mut c1 = None;
+            let mut c2 = None;
+
+            for (symbol, &frequency) in freq.iter().enumerate()
+            {
+                if frequency == 0
+                {
+                    continue;
+                }
+                if c1.is_none_or(|c: usize| frequency <= freq[c])
+                {
+                    c2 = c1;
+                    c1 = Some(symbol);
+                }
+                else if c2.is_none_or(|c: usize| frequency <= freq[c])
+                {
+                    c2 = Some(symbol);
+                }
+            }
+            let (Some(mut c1), Some(mut c2)) = (c1, c2)
+            else
+            {
+                break;
+            };
+            freq[c1] += freq[c2];
+            freq[c2] = 0;
+
+            // everything in both branches gets one bit longer
+            code_size[c1] += 1;
+
+            while others[c1] != usize::MAX
+            {
+                c1 = others[c1];
+                code_size[c1] += 1;
+            }
+            others[c1] = c2;
+            code_size[c2] += 1;
+
+            while others[c2] != usize::MAX
+            {
+                c2 = others[c2];
+                code_size[c2] += 1;
+            }
+        }
+        let mut bits = [0_u32; 258];
+
+        for &size in code_size.iter().filter(|x| **x > 0)
+        {
+            bits[size] += 1;
+        }
+        // limit code lengths to 16, see Figure K.3
+        for length in (17..bits.len()).rev()
+        {
+            while bits[length] > 0
+            {
+                let mut shorter = length - 2;
+
+                while bits[shorter] == 0
+                {
+                    shorter -= 1;
+                }
+                bits[length] -= 2;
+                bits[length - 1] += 1;
+                bits[shorter + 1] += 2;
+                bits[shorter] -= 1;
+            }
+        }
+        // drop the reserved symbol, which has one of the longest codes
+        if let Some(length) = (1..=16).rev().find(|x| bits[*x] > 0)
+        {
+            bits[length] -= 1;
+        }
+        let mut table_bits = [0; 17];
+
+        for (out, &count) in table_bits.iter_mut().zip(&bits).skip(1)
+        {
+            *out = count as u8;
+        }
+        let mut values = Vec::new();
+
+        for length in 1..code_size.len()
+        {
+            values.extend((0..256).filter(|x| code_size[*x] == length).map(|x| x as u8));
+        }
+
+        HuffmanEncoder::new(&table_bits, &values).unwrap()
+    }
+
+    /// Whether the table has a code for every symbol with a non zero frequency
+    pub fn covers(&self, frequencies: &[u32; 256]) -> bool
+    {
+        frequencies
+            .iter()
+            .zip(self.lengths)
+            .all(|(&frequency, length)| frequency == 0 || length != 0)
+    }
+
+    /// Write the code of `symbol`
+    fn put(&self, writer: &mut BitWriter, symbol: u8)
+    {
+        let symbol = usize::from(symbol);
+
+        writer.put(u32::from(self.codes[symbol]), self.lengths[symbol]);
+    }
+}
+
+/// Writes variable length codes to a byte buffer, stuffing a zero byte after
+/// each `0xFF` byte
+pub(crate) struct BitWriter
+{
+    /// Bytes written so far
+    pub(crate) data: Vec<u8>,
+    /// Bits not yet written, right aligned
+    buffer:          u32,
+    /// Number of bits in `buffer`
+    count:           u8,
+}
+
+impl BitWriter
+{
+    pub fn new() -> BitWriter
+    {
+        BitWriter {
+            data:   Vec::new(),
+            buffer: 0,
+            count:  0,
+        }
+    }
+
+    /// Write the `length` lower bits of `bits`, most significant first
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn put(&mut self, bits: u32, length: u8)
+    {
+        // lengths are at most 16, so the buffer never holds more than 23 bits
+        self.buffer = (self.buffer << length) | (bits & ((1 << length) - 1));
+        self.count += length;
+
+        while self.count >= 8
+        {
+            let byte = (self.buffer >> (self.count - 8)) as u8;
+
+            self.data.push(byte);
+
+            if byte == 0xFF
+            {
+                self.data.push(0x00);
+            }
+            self.count -= 8;
+        }
+        self.buffer &= (1 << self.count) - 1;
+    }
+
+    /// Pad the last byte with one bits, as required before a marker
+    pub fn flush(&mut self)
+    {
+        self.pad(true);
+    }
+
+    /// Pad the last byte with one or zero bits, some encoders pad with zeroes
+    pub fn pad(&mut self, ones: bool)
+    {
+        if self.count > 0
+        {
+            self.put(if ones { 0x7F } else { 0 }, 8 - self.count);
+        }
+    }
+}
+
+/// Number of bits needed to represent the magnitude of `value`
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn category(value: i32) -> u8
+{
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Bits following a category symbol, negative values are stored as `value - 1`
+#[allow(clippy::cast_sign_loss)]
+pub(crate) fn magnitude_bits(value: i32) -> u32
+{
+    if value < 0
+    {
+        (value - 1) as u32
+    }
+    else
+    {
+        value as u32
+    }
+}
+
+/// Receives the output of coding a scan
+///
+/// Scans are coded twice, first into a [`SymbolCounter`] to pick or build Huffman
+/// tables, then into a [`SymbolWriter`] with those tables, so both passes see exactly
+/// the same symbols.
+pub(crate) trait SymbolSink
+{
+    /// A Huffman coded `symbol`, from the DC or AC table of table slot `slot`
+    fn symbol(&mut self, dc: bool, slot: usize, symbol: u8);
+
+    /// Bits written as is, e.g magnitude bits following a symbol
+    fn bits(&mut self, bits: u32, length: u8);
+}
+
+/// Counts how often each symbol of each table occurs
+pub(crate) struct SymbolCounter
+{
+    /// DC symbol frequencies of each table slot
+    pub(crate) dc: Vec<[u32; 256]>,
+    /// AC symbol frequencies of each table slot
+    pub(crate) ac: Vec<[u32; 256]>,
+    /// Whether all symbols fit images with 8 bit samples
+    valid:         bool,
+}
+
+impl SymbolCounter
+{
+    pub fn new(slots: usize) -> SymbolCounter
+    {
+        SymbolCounter {
+            dc:    vec![[0; 256]; slots],
+            ac:    vec![[0; 256]; slots],
+            valid: true,
+        }
+    }
+
+    /// Check that the counted symbols can be coded
+    ///
+    /// # Errors
+    /// If a coefficient is too large to be coded in an image with 8 bit samples
+    pub fn check(self) -> Result<SymbolCounter, DecodeErrors>
+    {
+        if !self.valid
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Coefficient out of range for an image with 8 bit samples",
+            ));
+        }
+        Ok(self)
+    }
+}
+
+impl SymbolSink for SymbolCounter
+{
+    fn symbol(&mut self, dc: bool, slot: usize, symbol: u8)
+    {
+        if dc
+        {
+            self.valid &= symbol <= MAX_DC_CATEGORY;
+            self.dc[slot][usize::from(symbol)] += 1;
+        }
+        else
+        {
+            self.valid &= symbol & 15 <= MAX_AC_CATEGORY;
+            self.ac[slot][usize::from(symbol)] += 1;
+        }
+    }
+
+    fn bits(&mut self, _: u32, _: u8) {}
+}
+
+/// Writes symbols with the DC and AC tables of each table slot
+///
+/// Tables must cover all symbols written, slots or classes without symbols may
+/// have no table.
+pub(crate) struct SymbolWriter<'a>
+{
+    pub(crate) writer: BitWriter,
+    tables:            &'a [(Option<HuffmanEncoder>, Option<HuffmanEncoder>)],
+}
+
+impl<'a> SymbolWriter<'a>
+{
+    pub fn new(tables: &'a [(Option<HuffmanEncoder>, Option<HuffmanEncoder>)]) -> SymbolWriter<'a>
+    {
+        SymbolWriter {
+            writer: BitWriter::new(),
+            tables,
+        }
+    }
+}
+
+impl SymbolSink for SymbolWriter<'_>
+{
+    fn symbol(&mut self, dc: bool, slot: usize, symbol: u8)
+    {
+        let (dc_table, ac_table) = &self.tables[slot];
+        let table = if dc { dc_table } else { ac_table };
+
+        table
+            .as_ref()
+            .expect("No Huffman table for a counted symbol")
+            .put(&mut self.writer, symbol);
+    }
+
+    fn bits(&mut self, bits: u32, length: u8)
+    {
+        self.writer.put(bits, length);
+    }
+}
+
+/// Code a block of a sequential scan with the tables of `slot`
+///
+/// `block` is in natural order and `dc_pred` is the DC coefficient of the previous
+/// block of the same component.
+pub(crate) fn encode_block(
+    sink: &mut impl SymbolSink, slot: usize, block: &[i16; 64], dc_pred: &mut i32,
+)
+{
+    let dc = i32::from(block[0]);
+    let difference = dc - *dc_pred;
+
+    *dc_pred = dc;
+
+    sink.symbol(true, slot, category(difference));
+    sink.bits(magnitude_bits(difference), category(difference));
+
+    let mut run = 0;
+
+    for &position in &UN_ZIGZAG[1..64]
+    {
+        let value = i32::from(block[position]);
+
+        if value == 0
+        {
+            run += 1;
+            continue;
+        }
+        while run > 15
+        {
+            // ZRL, sixteen zeroes
+            sink.symbol(false, slot, 0xF0);
+            run -= 16;
+        }
+        sink.symbol(false, slot, (run << 4) | category(value));
+        sink.bits(magnitude_bits(value), category(value));
+        run = 0;
+    }
+    if run > 0
+    {
+        // EOB
+        sink.symbol(false, slot, 0x00);
+    }
+}
+
+//---------------------------------------------
+// TEST
+//----------------------------------------------
+#[test]
+fn bit_writer_stuffing()
+{
+    let mut writer = BitWriter::new();
+
+    writer.put(0xFF, 8);
+    writer.put(0b101, 3);
+    writer.flush();
+
+    assert_eq!(writer.data, [0xFF, 0x00, 0b1011_1111]);
+}
+
+#[test]
+fn optimal_table_lengths()
+{
+    // Fibonacci frequencies make the longest codes of an unlimited Huffman code
+    let mut frequencies = [0; 256];
+    let (mut a, mut b) = (1, 1);
+
+    for frequency in frequencies.iter_mut().take(30)
+    {
+        *frequency = a;
+        (a, b) = (b, a + b);
+    }
+    let table = HuffmanEncoder::optimal(&frequencies);
+
+    assert_eq!(table.bits[1..].iter().map(|x| u32::from(*x)).sum::<u32>(), 30);
+    // more frequent symbols never get longer codes, the first two are equally frequent
+    assert!(table.lengths[1..30].windows(2).all(|x| x[0] >= x[1]));
+    assert!(table.lengths[..30].iter().all(|x| (1..=16).contains(x)));
+}
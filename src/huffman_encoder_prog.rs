@@ -0,0 +1,202 @@
+//! Huffman entropy encoding of progressive scans
+//!
+//! This mirrors the decoding in `mcu_prog.rs`, following ITU-T T.81 Annex G.1.2 as
+//! libjpeg does. A progressive scan codes either DC coefficients of one or more
+//! components, or a band of AC coefficients of a single component, and either the
+//! first bits of them (successive approximation high `Ah` of zero) or one more bit
+//! of coefficients sent before.
+//!
+//! AC scans code runs of blocks with no more coefficients in the band with a single
+//! end of band symbol, so their state is kept across blocks in [`AcEncoder`].
+#![allow(clippy::module_name_repetitions)]
+
+use crate::huffman_encoder::{category, magnitude_bits, SymbolSink};
+use crate::misc::UN_ZIGZAG;
+
+/// Longest end of band run a symbol can code
+const MAX_EOB_RUN: u32 = 0x7FFF;
+
+/// Most correction bits kept back while an end of band run grows
+const MAX_CORRECTION_BITS: usize = 1000;
+
+/// Code the DC coefficient of a block in a first DC scan
+///
+/// `dc_pred` holds the shifted DC coefficient of the previous block of the same
+/// component.
+pub(crate) fn encode_dc_first(
+    sink: &mut impl SymbolSink, slot: usize, block: &[i16; 64], dc_pred: &mut i32, al: u8,
+)
+{
+    let dc = i32::from(block[0]) >> al;
+    let difference = dc - *dc_pred;
+
+    *dc_pred = dc;
+
+    sink.symbol(true, slot, category(difference));
+    sink.bits(magnitude_bits(difference), category(difference));
+}
+
+/// Code the DC coefficient of a block in a DC refinement scan
+///
+/// Refinement scans send the next bit of each DC coefficient as is.
+pub(crate) fn encode_dc_refine(sink: &mut impl SymbolSink, block: &[i16; 64], al: u8)
+{
+    sink.bits(u32::from((block[0] >> al) & 1 == 1), 1);
+}
+
+/// Codes blocks of an AC scan
+pub(crate) struct AcEncoder
+{
+    /// Table slot of the component of the scan
+    slot:        usize,
+    /// First coefficient of the band, in zigzag order
+    spec_start:  usize,
+    /// Last coefficient of the band, in zigzag order
+    spec_end:    usize,
+    /// Bit position of the scan
+    al:          u8,
+    /// Blocks with no more coefficients in the band, not coded yet
+    eob_run:     u32,
+    /// Correction bits of the blocks in `eob_run`, sent after the run
+    corrections: Vec<bool>,
+}
+
+impl AcEncoder
+{
+    pub fn new(slot: usize, spec_start: u8, spec_end: u8, al: u8) -> AcEncoder
+    {
+        AcEncoder {
+            slot,
+            spec_start: usize::from(spec_start),
+            spec_end: usize::from(spec_end),
+            al,
+            eob_run: 0,
+            corrections: Vec::new(),
+        }
+    }
+
+    /// Code the band of a block in a first AC scan
+    pub fn encode_first(&mut self, sink: &mut impl SymbolSink, block: &[i16; 64])
+    {
+        let mut run = 0;
+
+        for &position in &UN_ZIGZAG[self.spec_start..=self.spec_end]
+        {
+            // point transform, magnitudes are shifted before the sign is applied
+            let coefficient = i32::from(block[position]);
+            let value = (coefficient.abs() >> self.al) * coefficient.signum();
+
+            if value == 0
+            {
+                run += 1;
+                continue;
+            }
+            self.flush(sink);
+
+            while run > 15
+            {
+                // ZRL, sixteen zeroes
+                sink.symbol(false, self.slot, 0xF0);
+                run -= 16;
+            }
+            sink.symbol(false, self.slot, (run << 4) | category(value));
+            sink.bits(magnitude_bits(value), category(value));
+            run = 0;
+        }
+        if run > 0
+        {
+            self.eob_run += 1;
+
+            if self.eob_run == MAX_EOB_RUN
+            {
+                self.flush(sink);
+            }
+        }
+    }
+
+    /// Code the band of a block in an AC refinement scan
+    ///
+    /// Coefficients that become non zero with this bit are coded like in a first
+    /// scan, with a single magnitude bit for the sign. Coefficients already non
+    /// zero get a correction bit, sent after the next symbol.
+    pub fn encode_refine(&mut self, sink: &mut impl SymbolSink, block: &[i16; 64])
+    {
+        let band = &UN_ZIGZAG[self.spec_start..=self.spec_end];
+        let al = self.al;
+        let magnitude = |position: usize| i32::from(block[position]).abs() >> al;
+
+        // coefficients after the last newly non zero one go in the end of band
+        let last_new = band.iter().rposition(|&position| magnitude(position) == 1);
+
+        let mut run = 0;
+        let mut pending = Vec::new();
+
+        for (k, &position) in band.iter().enumerate()
+        {
+            let value = magnitude(position);
+
+            if value == 0
+            {
+                run += 1;
+                continue;
+            }
+            while run > 15 && last_new.is_some_and(|last| k <= last)
+            {
+                self.flush(sink);
+                // ZRL, sixteen zeroes
+                sink.symbol(false, self.slot, 0xF0);
+                run -= 16;
+
+                pending.drain(..).for_each(|bit| sink.bits(u32::from(bit), 1));
+            }
+            if value > 1
+            {
+                // already non zero, send its next bit
+                pending.push(value & 1 == 1);
+                continue;
+            }
+            self.flush(sink);
+
+            sink.symbol(false, self.slot, (run << 4) | 1);
+            sink.bits(u32::from(block[position] >= 0), 1);
+
+            pending.drain(..).for_each(|bit| sink.bits(u32::from(bit), 1));
+            run = 0;
+        }
+        if run > 0 || !pending.is_empty()
+        {
+            self.eob_run += 1;
+            self.corrections.append(&mut pending);
+
+            // keep the buffer small, another block may add up to 63 bits
+            if self.eob_run == MAX_EOB_RUN || self.corrections.len() > MAX_CORRECTION_BITS - 63
+            {
+                self.flush(sink);
+            }
+        }
+    }
+
+    /// Code the pending end of band run and its correction bits
+    ///
+    /// This must be called after the last block of the scan.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn flush(&mut self, sink: &mut impl SymbolSink)
+    {
+        if self.eob_run == 0
+        {
+            return;
+        }
+        // EOBn codes runs of 2^n to 2^(n+1) - 1 blocks, with n extra bits
+        let extra = self.eob_run.ilog2() as u8;
+
+        sink.symbol(false, self.slot, extra << 4);
+        sink.bits(self.eob_run, extra);
+
+        self.eob_run = 0;
+
+        for bit in self.corrections.drain(..)
+        {
+            sink.bits(u32::from(bit), 1);
+        }
+    }
+}
@@ -17,6 +17,8 @@
 //! The AVX code also has some cool transpose instructions which look so complicated to be cool
 //! (spoiler alert, i barely understand how it works, that's why I credited the owner).
 //!
+//! Reduced size decoding uses the transforms to 1x1 up to 8x8 samples in `scaled` instead.
+//!
 #![allow(
     clippy::excessive_precision,
     clippy::unreadable_literal,
@@ -29,26 +31,25 @@ use crate::decoder::IDCTPtr;
 #[cfg(feature = "X86")]
 use crate::idct::avx2::dequantize_and_idct_avx2;
 use crate::idct::scalar::dequantize_and_idct_int;
+use crate::ZuneJpegOptions;
 
 #[cfg(feature = "x86")]
 mod avx2;
 
+pub(crate) mod scaled;
 mod scalar;
 
 /// Choose an appropriate IDCT function
 
-pub fn choose_idct_func(use_unsafe: bool) -> IDCTPtr
+pub fn choose_idct_func(options: &ZuneJpegOptions) -> IDCTPtr
 {
-    if use_unsafe
+    #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
     {
-        #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+        if options.use_avx2()
         {
-            if is_x86_feature_detected!("avx2")
-            {
-                debug!("Using AVX optimized integer IDCT");
-                // use avx one
-                return crate::idct::avx2::dequantize_and_idct_avx2;
-            }
+            debug!("Using AVX optimized integer IDCT");
+            // use avx one
+            return crate::idct::avx2::dequantize_and_idct_avx2;
         }
     }
     debug!("Using scalar integer IDCT");
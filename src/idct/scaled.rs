@@ -0,0 +1,156 @@
+//! Reduced size IDCT
+//!
+//! The N point IDCT of the N lowest frequencies of a block gives N samples spread over
+//! the same area as the 8 of the full transform, so transforming the NxN top left
+//! coefficients of a block gives the block downscaled by N/8. This is how libjpeg
+//! implements `scale_num/scale_denom`.
+//!
+//! The transform is a plain separable floating point one, at reduced sizes it costs
+//! less than the full integer IDCT anyway.
+
+use std::f32::consts::PI;
+
+/// Dequantizes and transforms blocks to `size` by `size` samples
+pub(crate) struct ScaledIdct
+{
+    size:  usize,
+    /// `basis[x][u]` is the weight of frequency `u` in sample `x`
+    basis: [[f32; 8]; 8],
+}
+
+impl ScaledIdct
+{
+    /// Create a transform to `size` by `size` samples, `size` from 1 to 8
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(size: usize) -> ScaledIdct
+    {
+        assert!((1..=8).contains(&size));
+
+        let mut basis = [[0.0_f32; 8]; 8];
+
+        for (x, row) in basis.iter_mut().enumerate().take(size)
+        {
+            for (u, value) in row.iter_mut().enumerate().take(size)
+            {
+                // the scale of the 8 point transform, so DC stays the block average
+                let scale = if u == 0 { (1.0_f32 / 8.0).sqrt() } else { 0.5 };
+                let angle = (2 * x + 1) as f32 * u as f32 * PI / (2 * size) as f32;
+
+                *value = scale * angle.cos();
+            }
+        }
+        ScaledIdct { size, basis }
+    }
+
+    /// Number of rows and columns of samples a block is transformed to
+    pub fn size(&self) -> usize
+    {
+        self.size
+    }
+
+    /// Dequantize and transform `block`, coefficients in natural order, writing `size`
+    /// rows of `size` level shifted samples clamped to 0..=255 to `output`, rows `stride`
+    /// apart
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    pub fn dequantize_and_idct(
+        &self, block: &[i16], qt_table: &[i32; 64], output: &mut [i16], stride: usize,
+    )
+    {
+        let size = self.size;
+
+        // only the top left `size` by `size` coefficients contribute
+        let flat = (0..size).all(|v| {
+            block[v * 8..v * 8 + size]
+                .iter()
+                .skip(usize::from(v == 0))
+                .all(|&x| x == 0)
+        });
+
+        if flat
+        {
+            // same as the full IDCT for a block with only a DC coefficient
+            let dc = ((i32::from(block[0]) * qt_table[0]) >> 3) + 128;
+            let dc = dc.clamp(0, 255) as i16;
+
+            for row in output.chunks_mut(stride).take(size)
+            {
+                row[..size].fill(dc);
+            }
+            return;
+        }
+        // columns first, rows of `columns` are samples, columns are horizontal frequencies
+        let mut columns = [[0.0_f32; 8]; 8];
+
+        for (y, row) in columns.iter_mut().enumerate().take(size)
+        {
+            for (u, value) in row.iter_mut().enumerate().take(size)
+            {
+                *value = (0..size)
+                    .map(|v| {
+                        let k = v * 8 + u;
+
+                        self.basis[y][v] * (i32::from(block[k]) * qt_table[k]) as f32
+                    })
+                    .sum();
+            }
+        }
+        for (row, values) in output.chunks_mut(stride).zip(&columns).take(size)
+        {
+            for (x, sample) in row[..size].iter_mut().enumerate()
+            {
+                let value = (0..size).map(|u| self.basis[x][u] * values[u]).sum::<f32>();
+
+                *sample = (value.round() + 128.0).clamp(0.0, 255.0) as i16;
+            }
+        }
+    }
+}
+
+//---------------------------------------------
+// TEST
+//----------------------------------------------
+#[test]
+fn scaled_idct_blocks()
+{
+    // a horizontal cosine of the lowest frequency, at full size the left half of the
+    // block is brighter than the right
+    let mut block = [0; 64];
+
+    block[0] = 8;
+    block[1] = 20;
+
+    let mut output = [0; 64];
+
+    ScaledIdct::new(2).dequantize_and_idct(&block, &[1; 64], &mut output, 8);
+
+    assert!(output[0] > output[1] && output[0] == output[8] && output[1] == output[9]);
+    assert_eq!(i32::from(output[0]) + i32::from(output[1]), 2 * 129);
+
+    // a lone DC coefficient is flat at any size
+    let mut block = [0; 64];
+
+    block[0] = 16;
+
+    ScaledIdct::new(4).dequantize_and_idct(&block, &[2; 64], &mut output, 8);
+
+    assert!(output.chunks(8).take(4).all(|row| row[..4] == [132; 4]));
+}
+
+#[test]
+fn scaled_idct_dc_extremes()
+{
+    // the largest baseline DC coefficients and quantization value overflow an i16
+    // before the level shift, a thumbnail's DC only decode still clamps them
+    let mut output = [0; 64];
+
+    for (dc, expected) in [(2047, 255), (-2048, 0)]
+    {
+        let mut block = [0; 64];
+
+        block[0] = dc;
+
+        ScaledIdct::new(1).dequantize_and_idct(&block, &[255; 64], &mut output, 8);
+
+        assert_eq!(output[0], expected);
+    }
+}
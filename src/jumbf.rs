@@ -0,0 +1,381 @@
+//! JPEG XT boxes and JUMBF (JPEG Universal Metadata Box Format)
+//!
+//! JPEG XT (ISO/IEC 18477-3) stores ISO BMFF style boxes in APP11 segments, since a
+//! box may be larger than a segment it is split into packets, each APP11 segment
+//! containing
+//!
+//! | Field                          | Size     |
+//! |--------------------------------|----------|
+//! | Common identifier `JP`         | 2        |
+//! | Box instance number (En)       | 2        |
+//! | Packet sequence number (Z)     | 4        |
+//! | LBox, TBox and optional XLBox  | 8 or 16  |
+//! | Box contents                   | Variable |
+//!
+//! The box header is repeated in every packet, so reassembly concatenates contents of
+//! all packets with the same box type and instance number in sequence number order.
+//!
+//! JUMBF (ISO/IEC 19566-5) builds on that with superboxes (`jumb`) holding a
+//! description box (`jumd`) followed by content boxes or more superboxes. C2PA content
+//! credentials are stored as a JUMBF superbox labelled `c2pa`, holding manifests,
+//! which in turn hold assertions, a claim and a signature as CBOR or JSON content boxes.
+//!
+//! Signatures are not verified here, we only extract the payloads.
+
+use std::collections::HashMap;
+
+use crate::Decoder;
+
+/// Box type of a JUMBF superbox
+const JUMBF_SUPERBOX: [u8; 4] = *b"jumb";
+
+/// Deepest nesting of superboxes parsed, deeper ones are treated as corrupt
+const MAX_DEPTH: usize = 16;
+
+/// Box type of a JUMBF description box
+const JUMBF_DESCRIPTION: [u8; 4] = *b"jumd";
+
+/// Label of the C2PA manifest store superbox
+const C2PA_LABEL: &str = "c2pa";
+
+/// The description box of a JUMBF superbox
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JumbfDescription
+{
+    /// UUID identifying the type of the superbox contents
+    pub content_type: [u8; 16],
+    /// Whether the superbox can be requested by its label
+    pub requestable:  bool,
+    /// Label of the superbox
+    pub label:        Option<String>,
+    /// ID of the superbox
+    pub id:           Option<u32>,
+    /// SHA-256 hash of the superbox contents
+    pub signature:    Option<[u8; 32]>,
+}
+
+/// A JUMBF superbox, a description followed by child boxes
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JumbfSuperbox
+{
+    /// The description box
+    pub description: JumbfDescription,
+    /// Child boxes, in file order
+    pub boxes:       Vec<JumbfBox>,
+}
+
+/// A box found in APP11 segments
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JumbfBox
+{
+    /// A JUMBF superbox
+    Superbox(JumbfSuperbox),
+    /// Any other box, e.g a JUMBF `json` or `cbor` content box, or a JPEG XT box
+    Content
+    {
+        /// Four character box type
+        box_type: [u8; 4],
+        /// Contents of the box, without the header
+        data:     Vec<u8>,
+    },
+}
+
+/// A content box inside a JUMBF superbox together with the labels leading to it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JumbfPayload<'a>
+{
+    /// Labels of the superboxes containing this box, from the outermost, joined by `/`
+    ///
+    /// Superboxes without labels are skipped.
+    pub label:    String,
+    /// Four character box type, e.g `cbor` or `json`
+    pub box_type: [u8; 4],
+    /// Contents of the box
+    pub data:     &'a [u8],
+}
+
+impl JumbfSuperbox
+{
+    /// Label of this superbox, or an empty string if it has none
+    #[must_use]
+    pub fn label(&self) -> &str
+    {
+        self.description.label.as_deref().unwrap_or_default()
+    }
+
+    /// All content boxes in this superbox and its children, depth first
+    #[must_use]
+    pub fn payloads(&self) -> Vec<JumbfPayload<'_>>
+    {
+        let mut payloads = vec![];
+
+        self.collect_payloads(String::new(), &mut payloads);
+
+        payloads
+    }
+
+    fn collect_payloads<'a>(&'a self, parent: String, payloads: &mut Vec<JumbfPayload<'a>>)
+    {
+        let label = match (&self.description.label, parent.is_empty())
+        {
+            (Some(label), true) => label.clone(),
+            (Some(label), false) => format!("{parent}/{label}"),
+            (None, _) => parent,
+        };
+
+        for child in &self.boxes
+        {
+            match child
+            {
+                JumbfBox::Superbox(superbox) => superbox.collect_payloads(label.clone(), payloads),
+                JumbfBox::Content { box_type, data } => payloads.push(JumbfPayload {
+                    label:    label.clone(),
+                    box_type: *box_type,
+                    data,
+                }),
+            }
+        }
+    }
+}
+
+/// Read a box header at the start of `data`
+///
+/// Returns the box type, header length and total length of the box including
+/// the header.
+fn box_header(data: &[u8]) -> Option<([u8; 4], usize, usize)>
+{
+    let length = u32::from_be_bytes(data.get(0..4)?.try_into().unwrap());
+    let box_type: [u8; 4] = data.get(4..8)?.try_into().unwrap();
+
+    match length
+    {
+        // box extends to the end of the data
+        0 => Some((box_type, 8, data.len())),
+        1 =>
+        {
+            let length = u64::from_be_bytes(data.get(8..16)?.try_into().unwrap());
+
+            Some((box_type, 16, usize::try_from(length).ok()?))
+        }
+        length => Some((box_type, 8, length as usize)),
+    }
+}
+
+/// Parse a sequence of boxes filling `data`, `depth` superboxes down
+fn parse_boxes(mut data: &[u8], depth: usize) -> Option<Vec<JumbfBox>>
+{
+    let mut boxes = vec![];
+
+    while !data.is_empty()
+    {
+        let (box_type, header_length, length) = box_header(data)?;
+        let contents = data.get(header_length..length)?;
+
+        boxes.push(parse_box(box_type, contents, depth)?);
+
+        data = &data[length..];
+    }
+    Some(boxes)
+}
+
+/// Parse a single box given its type and contents, `depth` superboxes down
+fn parse_box(box_type: [u8; 4], contents: &[u8], depth: usize) -> Option<JumbfBox>
+{
+    if box_type != JUMBF_SUPERBOX
+    {
+        return Some(JumbfBox::Content {
+            box_type,
+            data: contents.to_vec(),
+        });
+    }
+    if depth >= MAX_DEPTH
+    {
+        warn!("JUMBF superboxes nested more than {MAX_DEPTH} deep");
+        return None;
+    }
+    // the first box of a superbox is always the description box
+    let (description_type, header_length, length) = box_header(contents)?;
+
+    if description_type != JUMBF_DESCRIPTION
+    {
+        warn!("JUMBF superbox does not start with a description box");
+        return None;
+    }
+    let description = parse_description(contents.get(header_length..length)?)?;
+
+    Some(JumbfBox::Superbox(JumbfSuperbox {
+        description,
+        boxes: parse_boxes(&contents[length..], depth + 1)?,
+    }))
+}
+
+/// Parse the contents of a JUMBF description box
+///
+/// See ISO/IEC 19566-5 Table B.2
+fn parse_description(data: &[u8]) -> Option<JumbfDescription>
+{
+    let content_type: [u8; 16] = data.get(0..16)?.try_into().unwrap();
+    let toggles = *data.get(16)?;
+    let mut rest = &data[17..];
+
+    let label = if toggles & 0x02 != 0
+    {
+        let end = rest.iter().position(|&x| x == 0)?;
+        let label = String::from_utf8(rest[..end].to_vec()).ok()?;
+
+        rest = &rest[end + 1..];
+
+        Some(label)
+    }
+    else
+    {
+        None
+    };
+    let id = if toggles & 0x04 != 0
+    {
+        let id = u32::from_be_bytes(rest.get(0..4)?.try_into().unwrap());
+
+        rest = &rest[4..];
+
+        Some(id)
+    }
+    else
+    {
+        None
+    };
+    let signature = if toggles & 0x08 != 0
+    {
+        Some(rest.get(0..32)?.try_into().unwrap())
+    }
+    else
+    {
+        None
+    };
+
+    Some(JumbfDescription {
+        content_type,
+        requestable: toggles & 0x01 != 0,
+        label,
+        id,
+        signature,
+    })
+}
+
+/// Reassemble boxes split across APP11 packets
+///
+/// `packets` are APP11 segment contents after the `JP` common identifier, in file
+/// order.
+fn reassemble(packets: &[Vec<u8>]) -> Vec<Vec<u8>>
+{
+    // (box type, instance number, sequence number, box header length, packet)
+    let parsed = packets
+        .iter()
+        .filter_map(|packet| {
+            let instance = u16::from_be_bytes(packet.get(0..2)?.try_into().unwrap());
+            let sequence = u32::from_be_bytes(packet.get(2..6)?.try_into().unwrap());
+            let (box_type, header_length, _) = box_header(packet.get(6..)?)?;
+
+            Some((box_type, instance, sequence, header_length, &packet[6..]))
+        })
+        .collect::<Vec<_>>();
+
+    if parsed.len() != packets.len()
+    {
+        warn!("Ignoring corrupt APP11 segments");
+    }
+    // group packets of the same box in the order of their first packets, so boxes stay
+    // in file order
+    let mut groups: Vec<Vec<(u32, usize, &[u8])>> = vec![];
+    let mut group_of = HashMap::new();
+
+    for (box_type, instance, sequence, header_length, packet) in parsed
+    {
+        let group = *group_of.entry((box_type, instance)).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+
+        groups[group].push((sequence, header_length, packet));
+    }
+
+    groups
+        .into_iter()
+        .map(|mut packets| {
+            packets.sort_by_key(|&(sequence, ..)| sequence);
+
+            if packets[0].0 != 1
+            {
+                warn!("APP11 box starts with packet {} instead of 1", packets[0].0);
+            }
+            let mut data = packets[0].2.to_vec();
+
+            // the header is repeated, only append the contents
+            for (_, header_length, packet) in &packets[1..]
+            {
+                data.extend_from_slice(&packet[*header_length..]);
+            }
+            data
+        })
+        .collect()
+}
+
+impl Decoder
+{
+    /// Returns all boxes stored in APP11 segments, after reassembling boxes split across
+    /// multiple segments
+    ///
+    /// This includes JUMBF superboxes and JPEG XT boxes, corrupt boxes are skipped.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return an empty vector
+    #[must_use]
+    pub fn app11_boxes(&self) -> Vec<JumbfBox>
+    {
+        reassemble(&self.jumbf_packets)
+            .iter()
+            .filter_map(|data| {
+                let (box_type, header_length, length) = box_header(data)?;
+
+                if length != data.len()
+                {
+                    warn!("APP11 box has {} bytes, expected {length}", data.len());
+                    return None;
+                }
+                parse_box(box_type, &data[header_length..], 0)
+            })
+            .collect()
+    }
+
+    /// Returns the C2PA manifest store, the JUMBF superbox labelled `c2pa`, if present
+    ///
+    /// Use [`JumbfSuperbox::payloads`] to get the CBOR and JSON payloads of the
+    /// manifests with their labels. Signatures are not verified.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let mut decoder = Decoder::new();
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// decoder.read_headers(&img_data).unwrap();
+    ///
+    /// if let Some(store) = decoder.c2pa_manifest_store()
+    /// {
+    ///     for payload in store.payloads()
+    ///     {
+    ///         println!("{}: {} bytes", payload.label, payload.data.len());
+    ///     }
+    /// }
+    /// ```
+    #[must_use]
+    pub fn c2pa_manifest_store(&self) -> Option<JumbfSuperbox>
+    {
+        self.app11_boxes().into_iter().find_map(|x| match x
+        {
+            JumbfBox::Superbox(superbox) if superbox.label() == C2PA_LABEL => Some(superbox),
+            _ => None,
+        })
+    }
+}
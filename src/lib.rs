@@ -87,22 +87,54 @@
 extern crate log;
 
 pub use crate::decoder::{Decoder, ImageInfo};
+pub use crate::marker::Marker;
 pub use crate::misc::ColorSpace;
 pub use crate::options::ZuneJpegOptions;
 
+pub mod archive;
+mod archive_model;
+mod arithmetic;
+mod arithmetic_encoder;
 mod bitstream;
+pub mod coefficients;
 mod color_convert;
 mod components;
+pub mod container;
 mod decoder;
+pub mod description;
+pub mod downscale;
+pub mod encoder;
 pub mod errors;
+mod exif;
+mod fdct;
+pub mod gainmap;
 mod headers;
 mod huffman;
+mod huffman_encoder;
+mod huffman_encoder_prog;
 mod idct;
+pub mod jumbf;
 mod marker;
 mod mcu;
+mod mcu_arith;
+mod mcu_parallel;
 mod mcu_prog;
+mod md5;
 mod misc;
+pub mod mpf;
 mod options;
+pub mod quality;
+mod region;
+pub mod requantize;
+pub mod restart_index;
+pub mod rewrite;
+mod scale;
+pub mod segments;
+pub mod stereo;
+pub mod thumbnail;
+pub mod transcode;
+pub mod transform;
 mod unsafe_utils;
 mod upsampler;
 mod worker;
+mod xmp;
@@ -1,5 +1,8 @@
 #![allow(clippy::upper_case_acronyms)]
 
+/// A JPEG marker, the byte following `0xFF`
+///
+/// See ITU-T T.81 Table B.1
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Marker
 {
@@ -41,39 +44,101 @@ pub enum Marker
     APP(u8),
     /// Comment
     COM,
+    /// Reserved for JPEG extensions
+    JPG,
+    /// Reserved for JPEG extensions, `JPG(0)` to `JPG(13)`
+    JPGn(u8),
+    /// Define hierarchical progression
+    DHP,
+    /// Expand reference component(s)
+    EXP,
+    /// For temporary private use in arithmetic coding
+    TEM,
+    /// Reserved markers `0xFF02` to `0xFFBF`, holding the second byte of the marker
+    RES(u8),
 }
 
 impl Marker
 {
+    /// Returns the marker for the byte following `0xFF`
+    ///
+    /// Returns `None` for `0x00`, which is a stuffed byte in entropy coded data,
+    /// and `0xFF`, which is a fill byte.
+    #[must_use]
     pub fn from_u8(n: u8) -> Option<Marker>
     {
-        use self::Marker::{APP, COM, DAC, DHT, DNL, DQT, DRI, EOI, RST, SOF, SOI, SOS};
+        use self::Marker::{
+            APP, COM, DAC, DHP, DHT, DNL, DQT, DRI, EOI, EXP, JPG, JPGn, RES, RST, SOF, SOI, SOS,
+            TEM,
+        };
 
         match n
         {
-            0xFE => Some(COM),
-            0xC0 => Some(SOF(0)),
-            0xC2 => Some(SOF(2)),
+            0x00 | 0xFF => None,
+            0x01 => Some(TEM),
+            0x02..=0xBF => Some(RES(n)),
             0xC4 => Some(DHT),
+            0xC8 => Some(JPG),
             0xCC => Some(DAC),
-            0xD0 => Some(RST(0)),
-            0xD1 => Some(RST(1)),
-            0xD2 => Some(RST(2)),
-            0xD3 => Some(RST(3)),
-            0xD4 => Some(RST(4)),
-            0xD5 => Some(RST(5)),
-            0xD6 => Some(RST(6)),
-            0xD7 => Some(RST(7)),
+            0xC0..=0xCF => Some(SOF(n - 0xC0)),
+            0xD0..=0xD7 => Some(RST(n - 0xD0)),
             0xD8 => Some(SOI),
             0xD9 => Some(EOI),
             0xDA => Some(SOS),
             0xDB => Some(DQT),
             0xDC => Some(DNL),
             0xDD => Some(DRI),
-            0xE0 => Some(APP(0)),
-            0xE1 => Some(APP(1)),
-            0xEE => Some(APP(14)),
-            _ => None,
+            0xDE => Some(DHP),
+            0xDF => Some(EXP),
+            0xE0..=0xEF => Some(APP(n - 0xE0)),
+            0xF0..=0xFD => Some(JPGn(n - 0xF0)),
+            0xFE => Some(COM),
         }
     }
+
+    /// Returns the byte following `0xFF` for this marker
+    ///
+    /// Returns `None` when the number a marker holds is out of its range, like
+    /// `APP(16)`, or belongs to another marker, like `SOF(4)`, which is DHT, or
+    /// `RES(0xD8)`, which is SOI.
+    #[must_use]
+    pub fn to_u8(self) -> Option<u8>
+    {
+        let byte = match self
+        {
+            Marker::TEM => 0x01,
+            Marker::RES(n @ 0x02..=0xBF) => n,
+            Marker::SOF(n @ (0..=3 | 5..=7 | 9..=11 | 13..=15)) => 0xC0 + n,
+            Marker::DHT => 0xC4,
+            Marker::JPG => 0xC8,
+            Marker::DAC => 0xCC,
+            Marker::RST(n @ 0..=7) => 0xD0 + n,
+            Marker::SOI => 0xD8,
+            Marker::EOI => 0xD9,
+            Marker::SOS => 0xDA,
+            Marker::DQT => 0xDB,
+            Marker::DNL => 0xDC,
+            Marker::DRI => 0xDD,
+            Marker::DHP => 0xDE,
+            Marker::EXP => 0xDF,
+            Marker::APP(n @ 0..=15) => 0xE0 + n,
+            Marker::JPGn(n @ 0..=13) => 0xF0 + n,
+            Marker::COM => 0xFE,
+            Marker::RES(_) | Marker::SOF(_) | Marker::RST(_) | Marker::APP(_) | Marker::JPGn(_) =>
+            {
+                return None
+            }
+        };
+
+        Some(byte)
+    }
+
+    /// Whether this marker stands alone, without a length and contents
+    ///
+    /// These are SOI, EOI, RST(n) and TEM.
+    #[must_use]
+    pub const fn is_standalone(self) -> bool
+    {
+        matches!(self, Marker::SOI | Marker::EOI | Marker::RST(_) | Marker::TEM)
+    }
 }
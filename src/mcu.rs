@@ -60,14 +60,40 @@ use std::sync::Arc;
 use crate::bitstream::BitStream;
 use crate::components::{ComponentID, SubSampRatios};
 use crate::errors::DecodeErrors;
+use crate::idct::scaled::ScaledIdct;
 use crate::marker::Marker;
-use crate::worker::post_process;
+use crate::region::Window;
+use crate::worker::{post_process, post_process_scaled};
 use crate::{ColorSpace, Decoder};
 
 /// The size of a DC block for a MCU.
 
 pub const DCT_BLOCK: usize = 64;
 
+/// Fill the block rows of a component after its first `rows` ones with them upside
+/// down, `blocks` are rows of `row_len` blocks
+///
+/// Chunks of two MCU rows may have one more row than the image, mirroring the last MCU
+/// row into it makes the vertical up-sampling filter repeat the last pixel row, like
+/// libjpeg does. Flipping a block negates its coefficients of odd vertical frequencies.
+pub(crate) fn mirror_block_rows(blocks: &mut [i16], row_len: usize, rows: usize)
+{
+    let row_len = row_len * DCT_BLOCK;
+    let (image, padding) = blocks.split_at_mut(rows * row_len);
+
+    for (padding, row) in padding
+        .chunks_exact_mut(row_len)
+        .zip(image.chunks_exact(row_len).rev())
+    {
+        for (pos, (padding, coefficient)) in padding.iter_mut().zip(row).enumerate()
+        {
+            let odd = (pos % DCT_BLOCK / 8) % 2 == 1;
+
+            *padding = if odd { -*coefficient } else { *coefficient };
+        }
+    }
+}
+
 impl Decoder
 {
     /// Check for existence of DC and AC Huffman Tables
@@ -121,13 +147,16 @@ impl Decoder
     ///
     /// Because of this, we pull in some very crazy optimization tricks hence readability is a pinch
     /// here.
+    ///
+    /// The image is decoded at `scale`/8 of its size, 8 for a full decode.
     #[allow(clippy::similar_names,clippy::too_many_lines,clippy::cast_possible_truncation)]
     #[inline(never)]
     #[rustfmt::skip]
     pub(crate) fn decode_mcu_ycbcr_baseline(
-        &mut self, reader: &mut Cursor<Vec<u8>>,
+        &mut self, reader: &mut Cursor<Vec<u8>>, scale: usize,
     ) -> Result<Vec<u8>, DecodeErrors>
     {
+        self.check_huffman_coding()?;
         self.check_component_dimensions()?;
         // check dc and AC tables
         self.check_tables()?;
@@ -149,13 +178,14 @@ impl Decoder
                 // horizontal sub-sampling.
 
                 // Values for horizontal samples end halfway the image and do not complete an MCU width.
-                // To make it complete we multiply width by 2 and divide mcu_height by 2
+                // To make it complete we multiply width by 2 and divide mcu_height by 2,
+                // an odd last MCU row makes a chunk of its own
                 mcu_width = self.mcu_x * 2;
-                mcu_height = self.mcu_y / 2;
+                mcu_height = self.mcu_y.div_ceil(2);
             } else if self.sub_sample_ratio == SubSampRatios::HV
             {
                 mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y / 2;
+                mcu_height = self.mcu_y.div_ceil(2);
                 bias = 2;
             } else {
                 mcu_width = self.mcu_x;
@@ -194,23 +224,47 @@ impl Decoder
             mcu_height = ((self.info.height + 7) / 8) as usize;
             bias = 1;
         }
+        // Only the chunks and MCU columns touching a region are post processed
+        let window = self.region.map(|region| Window::new(self, region));
+        // With a restart index, entropy decoding starts at the restart interval holding the
+        // first row of the region, skipping MCUs of the interval before the chunk
+        let mut first_chunk = 0;
+        let mut skip_mcus = 0;
+
+        if let (Some(window), true) = (&window, self.restart_index.is_some())
+        {
+            let mcus_per_chunk = mcu_width * bias;
+            let rows_per_chunk = mcus_per_chunk / self.restart_mcu_rows().0;
+            let mcu = self.seek_restart_interval(reader, window.first_chunk() * rows_per_chunk)?;
+
+            first_chunk = mcu / mcus_per_chunk;
+            skip_mcus = mcu % mcus_per_chunk;
+        }
+        // MCUs in the image, chunks of two MCU rows may have one more row than the image
+        let mcus = if self.interleaved && matches!(self.sub_sample_ratio, SubSampRatios::H | SubSampRatios::HV)
+        {
+            self.mcu_x * self.mcu_y
+        } else {
+            mcu_height * bias * mcu_width
+        };
+        let (width, height) = self.scaled_dimensions_internal(scale);
         // Size of our output image(width*height)
-        let capacity = usize::from(self.info.width + 8) * usize::from(self.info.height + 8);
+        let capacity = (width + 8) * (height + 8);
         let component_capacity = mcu_width * DCT_BLOCK;
         // Create an Arc of components to prevent cloning on every MCU width
-        let global_component = Arc::new(self.components.clone());
+        let global_component = Arc::new(self.scaled_components(scale));
+        let scaled_idct = (scale != 8).then(|| ScaledIdct::new(scale));
         let is_hv = self.sub_sample_ratio == SubSampRatios::HV;
         // There are some images where we need to overallocate  especially for small buffers,
         // because the chunking calculation will do it wrongly,
         // this only applies to  small down-sampled images
         // See https://github.com/etemesi254/zune-jpeg/issues/11
-        let extra_space = usize::from(self.interleaved) * 128 * usize::from(self.height()) * self.options.get_out_colorspace().num_components();
+        let extra_space = usize::from(self.interleaved) * 128 * height * self.options.get_out_colorspace().num_components();
         // things needed for post processing that we can remove out of the loop
         let input = self.input_colorspace;
         let output = self.options.get_out_colorspace();
         let idct_func = self.idct_func;
         let color_convert_16 = self.color_convert_16;
-        let width = usize::from(self.width());
         let h_max = self.h_max;
         let v_max = self.v_max;
         // Halfway width size, used for vertical sub-sampling to write |Y2| in the right position.
@@ -218,18 +272,40 @@ impl Decoder
         let hv_width_stride = width_stride >> 1;
 
         let mut stream = BitStream::new();
+        let chunk_size = window.as_ref().map_or(width * output.num_components() * scale * h_max * v_max, Window::chunk_len);
         // Storage for decoded pixels
-        let mut global_channel = vec![0; (capacity * self.options.get_out_colorspace().num_components()) + extra_space];
+        let mut global_channel = match &window
+        {
+            Some(window) => vec![0; window.buffer_len()],
+            None => vec![0; ((capacity * self.options.get_out_colorspace().num_components()) + extra_space).max(mcu_height * chunk_size)],
+        };
 
         // Split output into different blocks each containing enough space for an MCU width
-        let mut chunks =
-            global_channel.chunks_exact_mut(width * output.num_components() * 8 * h_max * v_max);
+        let mut chunks = global_channel.chunks_exact_mut(chunk_size);
         let mut tmp = [0; DCT_BLOCK];
+        // Entropy decoding of a full image may be split between the threads ahead of the
+        // loop, across restart intervals or speculatively, the loop then copies blocks out
+        // and decodes whatever is left
+        let decoded = match window
+        {
+            Some(_) => None,
+            None => self.decode_restart_intervals(reader, mcus, &mut scoped_pools)
+                .or_else(|| self.decode_speculatively(reader, &mut stream, mcus, &mut scoped_pools)),
+        };
+        let mut mcu = 0;
+        let window = &window;
 
         // Argument for scoped threadpools, see file docs.
         scoped_pools.scoped::<_, Result<(), DecodeErrors>>(|scope| {
-            for _ in 0..mcu_height
+            for i in first_chunk..mcu_height
             {
+                if window.as_ref().is_some_and(|w| w.finished(i))
+                {
+                    // nothing after the region is needed
+                    break;
+                }
+                // rows before the region are only entropy decoded
+                let skip = window.as_ref().is_some_and(|w| !w.needs(i));
                 // faster to memset than a later memcpy
 
                 // We allocate on every mcu_height since this is sent to a separate
@@ -241,7 +317,7 @@ impl Decoder
                 {
                     // multiply capacity with sampling factor, it  should be 1*1 for un-sampled images
                     // Allocate only needed components.
-                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos
+                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos && !skip
                     {
                         let len = component_capacity * comp.vertical_sample * comp.horizontal_sample * bias;
 
@@ -254,7 +330,19 @@ impl Decoder
                 {
                     for j in 0..mcu_width
                     {
+                        if (i * bias + v) * mcu_width + j >= mcus
+                        {
+                            // the rest of the last chunk is past the image
+                            break;
+                        }
+                        if skip_mcus > 0
+                        {
+                            skip_mcus -= 1;
+                            continue;
+                        }
                         // iterate over components
+                        let mut block = 0;
+                        let ahead = decoded.as_ref().filter(|d| mcu < d.mcus());
 
                         for pos in 0..self.input_colorspace.num_components()
                         {
@@ -284,7 +372,7 @@ impl Decoder
                                 for h_samp in 0..component.horizontal_sample
                                 {
                                     // only decode needed components
-                                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos
+                                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos && !skip
                                     {
                                         // The spec  https://www.w3.org/Graphics/JPEG/itu-t81.pdf page 26
 
@@ -313,20 +401,18 @@ impl Decoder
                                         // It will always be zero since it's initialized per MCU height.
                                         let tmp: &mut [i16; 64] = temporary.get_mut(pos).unwrap().get_mut(start..start + 64).unwrap().try_into().unwrap();
 
-                                        stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?;
-                                    } else {
+                                        match ahead
+                                        {
+                                            Some(decoded) => tmp.copy_from_slice(decoded.block(mcu, block)),
+                                            None => stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?,
+                                        }
+                                    } else if ahead.is_none() {
                                         // component not needed, decode and discard bits
                                         stream.decode_mcu_block(reader, dc_table, ac_table, &mut tmp, &mut component.dc_pred)?;
                                     }
+                                    block += 1;
                                 }
                             }
-                            self.todo = self.todo.wrapping_sub(1);
-                            // after every interleaved MCU that's a mcu, count down restart markers.
-                            if self.todo == 0
-                            {
-                                self.handle_rst(&mut stream)?;
-                            }
-
                             // In some corrupt images, it may occur that header markers occur in the stream.
                             // The spec EXPLICITLY FORBIDS this, specifically, in
                             // routine F.2.2.5  it says
@@ -336,22 +422,42 @@ impl Decoder
                             // allow it because of some weird reason.
                             if let Some(m) = stream.marker
                             {
-                                if m == Marker::EOI
-                                {
-                                    break;
-                                }
-
-                                if let Marker::RST(_) = m { continue }
+                                // the stream reads ahead, so it finds the end of the image with
+                                // blocks of the last MCUs left to decode
+                                if let Marker::RST(_) | Marker::EOI = m { continue }
 
                                 error!("Marker `{:?}` Found within Huffman Stream, possibly corrupt jpeg",m);
                                 self.parse_marker_inner(m, reader)?;
                             }
                         }
+                        mcu += 1;
+                        self.todo = self.todo.wrapping_sub(1);
+                        // after every interleaved MCU that's a mcu, count down restart markers.
+                        if self.todo == 0
+                        {
+                            self.handle_rst(&mut stream)?;
+                        }
+                    }
+                }
+                if skip
+                {
+                    continue;
+                }
+                if (i + 1) * bias * mcu_width > mcus
+                {
+                    // the last chunk only has one of its two MCU rows
+                    for (blocks, comp) in temporary.iter_mut().zip(&self.components)
+                    {
+                        let row_len = comp.width_stride / 8;
+                        let rows = blocks.len() / (row_len * DCT_BLOCK) / 2;
+
+                        mirror_block_rows(blocks, row_len, rows);
                     }
                 }
                 // Clone things, to make multithreading safe
                 let component = global_component.clone();
                 let next_chunk = chunks.next().unwrap();
+                let scaled_idct = scaled_idct.as_ref();
 
                 scope.execute(move || {
 
@@ -361,22 +467,32 @@ impl Decoder
                         coeff[pos] = x;
                     });
 
-                    post_process(&coeff, &component,
-                                 idct_func, color_convert_16,
-                                 input, output, next_chunk,
-                                 width);
+                    match (window, scaled_idct)
+                    {
+                        (Some(window), _) => window.post_process(&coeff, idct_func, color_convert_16,
+                                                                 input, output, next_chunk),
+                        (None, Some(idct)) => post_process_scaled(&coeff, &component,
+                                                                  idct, color_convert_16,
+                                                                  input, output, next_chunk,
+                                                                  width),
+                        (None, None) => post_process(&coeff, &component,
+                                                     idct_func, color_convert_16,
+                                                     input, output, next_chunk,
+                                                     width),
+                    }
                 });
             }
             //everything is okay
             Ok(())
         })?;
         info!("Finished decoding image");
+
+        if let Some(window) = window
+        {
+            return Ok(window.crop(&global_channel));
+        }
         // remove excess allocation for images.
-        global_channel.truncate(
-            usize::from(self.width())
-                * usize::from(self.height())
-                * self.options.get_out_colorspace().num_components(),
-        );
+        global_channel.truncate(width * height * self.options.get_out_colorspace().num_components());
         return Ok(global_channel);
     }
     // handle RST markers.
@@ -401,9 +517,16 @@ impl Decoder
                     self.components.iter_mut().for_each(|x| x.dc_pred = 0);
                     // Start iterating again. from position.
                 }
-                Marker::EOI =>
+                Marker::EOI
+                | Marker::DHT
+                | Marker::DQT
+                | Marker::DRI
+                | Marker::SOS
+                | Marker::APP(_)
+                | Marker::COM =>
                 {
-                    // silent pass
+                    // silent pass, the scan ended, usually with its last MCU, this
+                    // marker is handled after it
                 }
                 _ =>
                 {
@@ -0,0 +1,470 @@
+//! Decoding of arithmetic coded images
+//!
+//! Arithmetic coded images (SOF9 and SOF10) are only decoded into quantized
+//! coefficients, see [`coefficients`](crate::coefficients), e.g to convert them to
+//! Huffman coding with [`Transcoder`](crate::transcode::Transcoder).
+//!
+//! Scans of sequential and progressive images are decoded into the same coefficient
+//! planes, this mirrors `arithmetic_encoder.rs` following ITU-T T.81 Annex F.2.4 and
+//! G.2, as libjpeg's `jdarith.c` does.
+
+use std::io::Cursor;
+
+use crate::arithmetic::{Conditioning, QmDecoder, Statistics};
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::{DecodeErrors, UnsupportedSchemes};
+use crate::headers::parse_sos;
+use crate::marker::Marker;
+use crate::mcu::DCT_BLOCK;
+use crate::misc::UN_ZIGZAG;
+use crate::Decoder;
+
+/// Decodes blocks of a scan
+pub(crate) struct ArithmeticDecoder<'a>
+{
+    coder:        QmDecoder<'a>,
+    statistics:   Statistics,
+    conditioning: Conditioning,
+    /// DC statistics bin of the next block of each component
+    dc_context:   [usize; MAX_COMPONENTS],
+    /// Shifted DC coefficient of the previous block of each component
+    dc_pred:      [i32; MAX_COMPONENTS],
+}
+
+impl<'a> ArithmeticDecoder<'a>
+{
+    /// Start decoding the scan whose entropy coded data starts `data`
+    pub fn new(data: &'a [u8], conditioning: Conditioning) -> ArithmeticDecoder<'a>
+    {
+        ArithmeticDecoder {
+            coder: QmDecoder::new(data),
+            statistics: Statistics::new(),
+            conditioning,
+            dc_context: [0; MAX_COMPONENTS],
+            dc_pred: [0; MAX_COMPONENTS],
+        }
+    }
+
+    /// Read a restart marker and start a new interval
+    fn restart(&mut self)
+    {
+        match self.coder.next_marker()
+        {
+            Some(Marker::RST(_)) => (),
+            Some(marker) =>
+            {
+                // decode zeroes until the end of the scan
+                warn!("Expected a restart marker, found {marker:?}");
+                self.coder.set_marker(marker);
+            }
+            None => warn!("Premature end of buffer, image may be truncated"),
+        }
+        self.coder.reset();
+        self.statistics = Statistics::new();
+        self.dc_context = [0; MAX_COMPONENTS];
+        self.dc_pred = [0; MAX_COMPONENTS];
+    }
+
+    /// Decode a block of a sequential scan
+    pub fn decode_sequential(
+        &mut self, component: usize, dc_table: usize, ac_table: usize, block: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        self.decode_dc_first(component, dc_table, block, 0)?;
+        self.decode_ac_first(ac_table, block, 1, 63, 0)
+    }
+
+    /// Decode the DC coefficient of a block in a first DC scan, F.2.4.1
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub fn decode_dc_first(
+        &mut self, component: usize, table: usize, block: &mut [i16; 64], al: u8,
+    ) -> Result<(), DecodeErrors>
+    {
+        let stats = &mut self.statistics.dc[table];
+        let context = self.dc_context[component];
+
+        if self.coder.decode(&mut stats[context])
+        {
+            let negative = self.coder.decode(&mut stats[context + 1]);
+            let sign_bin = context + 2 + usize::from(negative);
+            let (value, category) = decode_magnitude(&mut self.coder, stats, |bit| {
+                if bit == 0 { sign_bin } else { 19 + bit }
+            })?;
+            let difference = if negative { -(value as i32) - 1 } else { value as i32 + 1 };
+
+            self.dc_context[component] = self.conditioning.dc_context(table, category, negative);
+            self.dc_pred[component] = (self.dc_pred[component] + difference) & 0xFFFF;
+        }
+        else
+        {
+            self.dc_context[component] = 0;
+        }
+        block[0] = (self.dc_pred[component] << al) as i16;
+
+        Ok(())
+    }
+
+    /// Decode the DC coefficient of a block in a DC refinement scan
+    pub fn decode_dc_refine(&mut self, block: &mut [i16; 64], al: u8)
+    {
+        if self.coder.decode(&mut self.statistics.fixed)
+        {
+            block[0] |= 1 << al;
+        }
+    }
+
+    /// Decode the band of a block in a first AC scan, F.2.4.2
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub fn decode_ac_first(
+        &mut self, table: usize, block: &mut [i16; 64], spec_start: u8, spec_end: u8, al: u8,
+    ) -> Result<(), DecodeErrors>
+    {
+        let (spec_start, spec_end) = (usize::from(spec_start), usize::from(spec_end));
+        let stats = &mut self.statistics.ac[table];
+        let mut k = spec_start;
+
+        while k <= spec_end
+        {
+            let mut st = 3 * (k - 1);
+
+            // end of block
+            if self.coder.decode(&mut stats[st])
+            {
+                break;
+            }
+            while !self.coder.decode(&mut stats[st + 1])
+            {
+                st += 3;
+                k += 1;
+
+                if k > spec_end
+                {
+                    return Err(DecodeErrors::FormatStatic(
+                        "Arithmetic coded coefficients past the end of the band",
+                    ));
+                }
+            }
+            let negative = self.coder.decode(&mut self.statistics.fixed);
+            let higher = self.conditioning.ac_magnitude_bin(table, k);
+            let (value, _) = decode_magnitude(&mut self.coder, stats, |bit| {
+                if bit < 2 { st + 2 } else { higher + bit - 2 }
+            })?;
+            let magnitude = ((value + 1) << al) as i16;
+
+            block[UN_ZIGZAG[k]] = if negative { magnitude.wrapping_neg() } else { magnitude };
+            k += 1;
+        }
+        Ok(())
+    }
+
+    /// Decode the band of a block in an AC refinement scan, G.2
+    pub fn decode_ac_refine(
+        &mut self, table: usize, block: &mut [i16; 64], spec_start: u8, spec_end: u8, al: u8,
+    ) -> Result<(), DecodeErrors>
+    {
+        let (spec_start, spec_end) = (usize::from(spec_start), usize::from(spec_end));
+        let stats = &mut self.statistics.ac[table];
+        let bit: i16 = 1 << al;
+        // the end of block of the previous scans, no end of block is coded before it
+        let previous_end = (1..=spec_end)
+            .rev()
+            .find(|k| block[UN_ZIGZAG[*k]] != 0)
+            .unwrap_or(0);
+
+        let mut k = spec_start;
+
+        while k <= spec_end
+        {
+            let mut st = 3 * (k - 1);
+
+            if k > previous_end && self.coder.decode(&mut stats[st])
+            {
+                break;
+            }
+            loop
+            {
+                let coefficient = &mut block[UN_ZIGZAG[k]];
+
+                if *coefficient != 0
+                {
+                    // already non zero, the next bit
+                    if self.coder.decode(&mut stats[st + 2])
+                    {
+                        let correction = if *coefficient < 0 { -bit } else { bit };
+
+                        *coefficient = coefficient.wrapping_add(correction);
+                    }
+                    break;
+                }
+                if self.coder.decode(&mut stats[st + 1])
+                {
+                    // newly non zero
+                    let negative = self.coder.decode(&mut self.statistics.fixed);
+
+                    *coefficient = if negative { -bit } else { bit };
+                    break;
+                }
+                st += 3;
+                k += 1;
+
+                if k > spec_end
+                {
+                    return Err(DecodeErrors::FormatStatic(
+                        "Arithmetic coded coefficients past the end of the band",
+                    ));
+                }
+            }
+            k += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Decode a magnitude less one, see `encode_magnitude`
+///
+/// Returns the value and its category.
+fn decode_magnitude(
+    coder: &mut QmDecoder, stats: &mut [u8], bin: impl Fn(usize) -> usize,
+) -> Result<(u32, usize), DecodeErrors>
+{
+    let mut category = 0;
+
+    while coder.decode(&mut stats[bin(category)])
+    {
+        category += 1;
+
+        // values have at most 15 bits
+        if category == 16
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Arithmetic coded value too large, corrupt image",
+            ));
+        }
+    }
+    let st = bin(category) + 14;
+    let mut value = u32::from(category > 0);
+
+    for _ in 1..category
+    {
+        value = (value << 1) | u32::from(coder.decode(&mut stats[st]));
+    }
+    Ok((value, category))
+}
+
+impl Decoder
+{
+    /// Check that the image is Huffman coded, decoding pixels of arithmetic coded
+    /// images is not supported
+    pub(crate) fn check_huffman_coding(&self) -> Result<(), DecodeErrors>
+    {
+        if !self.is_arithmetic
+        {
+            return Ok(());
+        }
+        Err(DecodeErrors::Unsupported(if self.is_progressive
+        {
+            UnsupportedSchemes::ProgressiveDctArithmetic
+        }
+        else
+        {
+            UnsupportedSchemes::ExtendedSequentialDctArithmetic
+        }))
+    }
+
+    /// Decode quantized coefficients for all components of an arithmetic coded image
+    ///
+    /// Headers should already be decoded, and `reader` positioned at the start of the
+    /// first scan. With `skip_ac` progressive AC scans are skipped.
+    pub(crate) fn decode_arithmetic_coefficients(
+        &mut self, reader: &mut Cursor<Vec<u8>>, skip_ac: bool,
+    ) -> Result<Vec<Vec<i16>>, DecodeErrors>
+    {
+        let mut planes = (0..self.input_colorspace.num_components())
+            .map(|pos| {
+                let (width, height) = self.coefficient_plane_dimensions(pos);
+
+                vec![0; width * height * DCT_BLOCK]
+            })
+            .collect::<Vec<Vec<i16>>>();
+
+        let mut seen_scans = 1;
+
+        loop
+        {
+            let start = usize::try_from(reader.position()).unwrap();
+            let data = reader.get_ref().get(start..).unwrap_or(&[]);
+            let mut decoder = ArithmeticDecoder::new(data, self.conditioning);
+
+            // a skipped scan leaves the decoder at the start of its data, the search for
+            // the next marker goes over it
+            if !(skip_ac && self.is_progressive && self.spec_start > 0)
+            {
+                self.decode_arithmetic_scan(&mut decoder, &mut planes)?;
+            }
+
+            let mut marker = decoder.coder.next_marker();
+
+            reader.set_position((start + decoder.coder.position()) as u64);
+
+            // segments up to the next scan
+            loop
+            {
+                match marker
+                {
+                    None =>
+                    {
+                        warn!("Premature end of buffer, image may be truncated");
+                        return Ok(planes);
+                    }
+                    Some(Marker::EOI) => return Ok(planes),
+                    Some(Marker::SOS) => break,
+                    Some(Marker::RST(_)) => (),
+                    Some(marker) => self.parse_marker_inner(marker, reader)?,
+                }
+                let start = usize::try_from(reader.position()).unwrap();
+                let mut coder = QmDecoder::new(reader.get_ref().get(start..).unwrap_or(&[]));
+
+                marker = coder.next_marker();
+                reader.set_position((start + coder.position()) as u64);
+            }
+            parse_sos(reader, self)?;
+
+            seen_scans += 1;
+
+            if seen_scans > self.options.get_max_scans()
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Too many scans, exceeded limit of {}",
+                    self.options.get_max_scans()
+                )));
+            }
+        }
+    }
+
+    /// Decode the entropy coded data of a single scan into `planes`
+    fn decode_arithmetic_scan(
+        &self, decoder: &mut ArithmeticDecoder, planes: &mut [Vec<i16>],
+    ) -> Result<(), DecodeErrors>
+    {
+        let num_scans = usize::from(self.num_scans);
+        let (ss, se, ah, al) = (self.spec_start, self.spec_end, self.succ_high, self.succ_low);
+
+        if self.z_order[..num_scans].iter().any(|k| *k >= planes.len())
+        {
+            return Err(DecodeErrors::FormatStatic("Cannot find component, corrupt image"));
+        }
+        if self.is_progressive
+            && ((ss == 0) != (se == 0)
+                || ss > se
+                || (ss > 0 && num_scans != 1)
+                || (ah != 0 && ah != al + 1))
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Invalid progressive scan Ss={ss} Se={se} Ah={ah} Al={al}"
+            )));
+        }
+        let mut todo = self.restart_interval;
+        let mut count_down_restart = |decoder: &mut ArithmeticDecoder| {
+            if self.restart_interval > 0
+            {
+                if todo == 0
+                {
+                    decoder.restart();
+                    todo = self.restart_interval;
+                }
+                todo -= 1;
+            }
+        };
+
+        if num_scans == 1
+        {
+            // non-interleaved, blocks in the padding of the plane are not coded
+            let k = self.z_order[0];
+            let component = &self.components[k];
+            let comp_width =
+                (usize::from(self.info.width) * component.horizontal_sample).div_ceil(self.h_max);
+            let comp_height =
+                (usize::from(self.info.height) * component.vertical_sample).div_ceil(self.v_max);
+
+            for block_y in 0..comp_height.div_ceil(8)
+            {
+                for block_x in 0..comp_width.div_ceil(8)
+                {
+                    count_down_restart(decoder);
+                    self.decode_arithmetic_block(decoder, k, &mut planes[k], block_x, block_y)?;
+                }
+            }
+            return Ok(());
+        }
+        for mcu_y in 0..self.mcu_y
+        {
+            for mcu_x in 0..self.mcu_x
+            {
+                count_down_restart(decoder);
+
+                for &k in &self.z_order[..num_scans]
+                {
+                    let h_samp = self.components[k].horizontal_sample;
+                    let v_samp = self.components[k].vertical_sample;
+
+                    for v in 0..v_samp
+                    {
+                        for h in 0..h_samp
+                        {
+                            let (block_x, block_y) = (mcu_x * h_samp + h, mcu_y * v_samp + v);
+
+                            self.decode_arithmetic_block(
+                                decoder,
+                                k,
+                                &mut planes[k],
+                                block_x,
+                                block_y,
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode the block at `block_x`, `block_y` of component `k` in the current scan
+    fn decode_arithmetic_block(
+        &self, decoder: &mut ArithmeticDecoder, k: usize, plane: &mut [i16], block_x: usize,
+        block_y: usize,
+    ) -> Result<(), DecodeErrors>
+    {
+        let (stride, _) = self.coefficient_plane_dimensions(k);
+        let start = DCT_BLOCK * (block_y * stride + block_x);
+        let block: &mut [i16; 64] = plane
+            .get_mut(start..start + DCT_BLOCK)
+            .ok_or(DecodeErrors::FormatStatic("Block out of bounds"))?
+            .try_into()
+            .unwrap();
+        let dc_table = self.components[k].dc_huff_table & 3;
+        let ac_table = self.components[k].ac_huff_table & 3;
+        let (ss, se, ah, al) = (self.spec_start, self.spec_end, self.succ_high, self.succ_low);
+
+        if !self.is_progressive
+        {
+            decoder.decode_sequential(k, dc_table, ac_table, block)
+        }
+        else if ss == 0 && ah == 0
+        {
+            decoder.decode_dc_first(k, dc_table, block, al)
+        }
+        else if ss == 0
+        {
+            decoder.decode_dc_refine(block, al);
+            Ok(())
+        }
+        else if ah == 0
+        {
+            decoder.decode_ac_first(ac_table, block, ss, se, al)
+        }
+        else
+        {
+            decoder.decode_ac_refine(ac_table, block, ss, se, al)
+        }
+    }
+}
@@ -0,0 +1,608 @@
+//! Parallel entropy decoding
+//!
+//! Huffman coded data is one long dependency chain, a code starts where the one before
+//! it ends and DC coefficients are coded as differences to the previous one.
+//!
+//! # Restart intervals
+//! Restart markers break the chain, the bit reader and the DC predictions reset after
+//! each of them, so the restart intervals of a scan can be entropy decoded
+//! independently, each with its own [`BitStream`] and DC predictions.
+//!
+//! Anything unexpected, like a missing restart marker or a marker other than a restart
+//! or the end of the image in the middle of the data, gives up on decoding in parallel
+//! and leaves the image to the main loop, which is more forgiving with corrupt files.
+//!
+//! # Speculative decoding
+//! Without restart markers, the data is split at arbitrary bytes instead, and each
+//! thread decodes from the start of its part as if a block started there. It starts
+//! out decoding garbage, but Huffman codes tend to fall back in step with the data after
+//! a few of them, and from then on it decodes the same blocks a serial decode does.
+//! Each part records where its blocks start, as a bit position and the block's index in
+//! its MCU, which is all the state decoding a block depends on, DC coefficients being
+//! kept as differences until the end. Going through the parts in order, blocks are
+//! decoded serially from the true end of the part before until one starts where a block
+//! of the part does, and taken from the part from there on. That's usually a few blocks,
+//! but a part that never synchronized is decoded serially as a whole.
+//!
+//! The last few bytes, near the marker ending the scan, are always left to the main
+//! loop, which picks up from the state the parts end at.
+//!
+//! # Output
+//! Blocks are decoded into coefficients in coding order, which the main decoder loop
+//! then reads MCUs out of in place of the bitstream, leaving post processing unchanged.
+
+use std::io::Cursor;
+
+use scoped_threadpool::Pool;
+
+use crate::bitstream::BitStream;
+use crate::huffman::HuffmanTable;
+use crate::marker::Marker;
+use crate::mcu::DCT_BLOCK;
+use crate::restart_index::restart_intervals;
+use crate::Decoder;
+
+/// Coefficients of the first MCUs of a scan, entropy decoded ahead of the main loop
+pub(crate) struct DecodedMcus
+{
+    /// Coefficients of runs of MCUs, in coding order
+    buffers:         Vec<Vec<i16>>,
+    /// Number of MCUs in a buffer, all but the last
+    mcus_per_buffer: usize,
+    /// Number of blocks in an MCU
+    blocks_per_mcu:  usize,
+    /// Number of MCUs decoded
+    mcus:            usize,
+}
+
+impl DecodedMcus
+{
+    /// Number of MCUs decoded, the main loop decodes those after
+    pub fn mcus(&self) -> usize
+    {
+        self.mcus
+    }
+
+    /// Block `block` of MCU `mcu`, MCUs counted from the start of the scan and blocks in
+    /// coding order
+    pub fn block(&self, mcu: usize, block: usize) -> &[i16; 64]
+    {
+        let start = ((mcu % self.mcus_per_buffer) * self.blocks_per_mcu + block) * DCT_BLOCK;
+
+        self.buffers[mcu / self.mcus_per_buffer][start..start + DCT_BLOCK]
+            .try_into()
+            .unwrap()
+    }
+}
+
+/// DC table, AC table and number of blocks in an MCU of a component
+type ComponentTables<'a> = (&'a HuffmanTable, &'a HuffmanTable, usize);
+
+impl Decoder
+{
+    /// Entropy decode the first `mcus` MCUs of the scan starting at the position of
+    /// `reader`, a few restart intervals per task
+    ///
+    /// Returns `None` when the image has no restart intervals to split, there is a single
+    /// thread, or the data isn't laid out as the restart interval says, in which case the
+    /// scan should be decoded serially.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn decode_restart_intervals(
+        &self, reader: &Cursor<Vec<u8>>, mcus: usize, pool: &mut Pool,
+    ) -> Option<DecodedMcus>
+    {
+        let restart_interval = self.restart_interval;
+
+        if restart_interval == 0
+            || pool.thread_count() < 2
+            || usize::from(self.num_scans) != self.components.len()
+        {
+            return None;
+        }
+        let count = mcus.div_ceil(restart_interval);
+
+        if count < 2
+        {
+            return None;
+        }
+        let buf = reader.get_ref();
+        let offsets = restart_intervals(buf, reader.position() as usize);
+
+        if offsets.len() < count
+        {
+            warn!("Restart markers missing, decoding serially");
+            return None;
+        }
+        let components = self.components[..self.input_colorspace.num_components()]
+            .iter()
+            .map(|c| {
+                Some((
+                    self.dc_huffman_tables[c.dc_huff_table & 3].as_ref()?,
+                    self.ac_huffman_tables[c.ac_huff_table & 3].as_ref()?,
+                    c.vertical_sample * c.horizontal_sample,
+                ))
+            })
+            .collect::<Option<Vec<ComponentTables>>>()?;
+
+        let mut intervals = vec![None; count];
+        let per_task = count.div_ceil(pool.thread_count() as usize * 4);
+
+        pool.scoped(|scope| {
+            for (task, results) in intervals.chunks_mut(per_task).enumerate()
+            {
+                let (components, offsets) = (&components, &offsets);
+
+                scope.execute(move || {
+                    for (k, result) in (task * per_task..).zip(results)
+                    {
+                        let end = offsets.get(k + 1).copied().unwrap_or(buf.len());
+                        let len = restart_interval.min(mcus - k * restart_interval);
+
+                        *result = decode_interval(&buf[offsets[k]..end], components, len);
+                    }
+                });
+            }
+        });
+
+        Some(DecodedMcus {
+            buffers: intervals.into_iter().collect::<Option<Vec<Vec<i16>>>>()?,
+            mcus_per_buffer: restart_interval,
+            blocks_per_mcu: components.iter().map(|c| c.2).sum(),
+            mcus,
+        })
+    }
+}
+
+/// Entropy decode the `mcus` MCUs of the restart interval in `data`
+fn decode_interval(data: &[u8], components: &[ComponentTables], mcus: usize) -> Option<Vec<i16>>
+{
+    let blocks_per_mcu: usize = components.iter().map(|c| c.2).sum();
+    let mut reader = Cursor::new(data.to_vec());
+    let mut stream = BitStream::new();
+    let mut dc_predictions = vec![0; components.len()];
+    let mut coefficients = vec![0; mcus * blocks_per_mcu * DCT_BLOCK];
+
+    for mcu in coefficients.chunks_exact_mut(blocks_per_mcu * DCT_BLOCK)
+    {
+        let mut blocks = mcu.chunks_exact_mut(DCT_BLOCK);
+
+        for ((dc_table, ac_table, count), dc_prediction) in
+            components.iter().zip(dc_predictions.iter_mut())
+        {
+            for block in blocks.by_ref().take(*count)
+            {
+                let block = block.try_into().unwrap();
+
+                stream
+                    .decode_mcu_block(&mut reader, dc_table, ac_table, block, dc_prediction)
+                    .ok()?;
+            }
+            match stream.marker
+            {
+                None | Some(Marker::RST(_) | Marker::EOI) => (),
+                Some(_) => return None,
+            }
+        }
+    }
+    Some(coefficients)
+}
+
+/// Smallest part of the entropy coded data decoded speculatively
+const MIN_PART: usize = 8192;
+
+/// Most bytes a part is decoded past its end, more than any MCU takes
+const PART_SLACK: usize = 8192;
+
+/// Entropy coded data of a scan without restart markers
+struct ScanData<'a>
+{
+    /// The file
+    buf:      &'a [u8],
+    /// Position of the data in the file
+    start:    usize,
+    /// Position of the marker ending the data
+    end:      usize,
+    /// Position of each stuffed zero byte, in the file and in the data without them
+    stuffing: Vec<(usize, usize)>,
+}
+
+impl<'a> ScanData<'a>
+{
+    fn new(buf: &'a [u8], start: usize) -> ScanData<'a>
+    {
+        let mut stuffing = vec![];
+        let mut position = start;
+        let mut end = buf.len();
+
+        while let Some(found) = buf[position..].iter().position(|x| *x == 0xFF)
+        {
+            position += found + 1;
+
+            if buf.get(position) != Some(&0x00)
+            {
+                end = position - 1;
+                break;
+            }
+            stuffing.push((position, position - start - stuffing.len()));
+            position += 1;
+        }
+        ScanData {
+            buf,
+            start,
+            end,
+            stuffing,
+        }
+    }
+
+    /// Position in the data without stuffing of the next bit a stream reads, having
+    /// read the file up to `position` and holding `bits_left` bits
+    fn bit(&self, position: usize, bits_left: u8) -> usize
+    {
+        let stuffed = self.stuffing.partition_point(|s| s.0 < position);
+
+        (position - self.start - stuffed) * 8 - usize::from(bits_left)
+    }
+
+    /// Position in the file of byte `byte` of the data without stuffing
+    fn position(&self, byte: usize) -> usize
+    {
+        self.start + byte + self.stuffing.partition_point(|s| s.1 <= byte)
+    }
+}
+
+/// Where a block starts, all the state decoding it depends on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct BlockStart
+{
+    /// Position in the data without stuffing
+    bit:   usize,
+    /// Index of the block in its MCU
+    block: usize,
+}
+
+/// Blocks decoded from a start in the data
+struct Part
+{
+    /// Index of the start of another part decoding stopped at, joining it
+    joins:        Option<usize>,
+    /// Start of each block decoded, and where the next one would start
+    starts:       Vec<BlockStart>,
+    /// Coefficients of the blocks, DC coefficients as differences to the one before
+    coefficients: Vec<i16>,
+    /// Whether decoding reached the end of the part, instead of an error or the end of
+    /// the data
+    complete:     bool,
+}
+
+impl Decoder
+{
+    /// Entropy decode the first MCUs of the scan starting at the position of `reader`,
+    /// of up to `mcus`, speculatively on the threads of the pool
+    ///
+    /// `reader`, `stream` and DC predictions are left where the main loop picks up.
+    /// Returns `None` when not enabled in the options, the image has restart markers,
+    /// there is a single thread or the data is too small to split.
+    #[allow(clippy::cast_possible_truncation, clippy::too_many_lines)]
+    pub(crate) fn decode_speculatively(
+        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, mcus: usize,
+        pool: &mut Pool,
+    ) -> Option<DecodedMcus>
+    {
+        if !self.options.get_speculative_decoding()
+            || self.restart_interval != 0
+            || pool.thread_count() < 2
+            || usize::from(self.num_scans) != self.components.len()
+        {
+            return None;
+        }
+        let data = ScanData::new(reader.get_ref(), reader.position() as usize);
+        let count = ((data.end - data.start) / MIN_PART).min(pool.thread_count() as usize);
+
+        if count < 2
+        {
+            return None;
+        }
+        let components = &self.components[..self.input_colorspace.num_components()];
+        let tables = components
+            .iter()
+            .map(|c| {
+                Some((
+                    self.dc_huffman_tables[c.dc_huff_table & 3].as_ref()?,
+                    self.ac_huffman_tables[c.ac_huff_table & 3].as_ref()?,
+                ))
+            })
+            .collect::<Option<Vec<(&HuffmanTable, &HuffmanTable)>>>()?;
+        // component of each block of an MCU
+        let layout = components
+            .iter()
+            .enumerate()
+            .flat_map(|(pos, c)| std::iter::repeat_n(pos, c.vertical_sample * c.horizontal_sample))
+            .collect::<Vec<usize>>();
+        let blocks_per_mcu = layout.len();
+        let needed = mcus * blocks_per_mcu * DCT_BLOCK;
+
+        // parts split the data evenly, but don't start on a stuffed zero byte
+        let starts = (0..count)
+            .map(|k| {
+                let mut position = data.start + (data.end - data.start) * k / count;
+
+                if k > 0 && data.buf[position - 1] == 0xFF
+                {
+                    position += 1;
+                }
+                BlockStart {
+                    bit:   data.bit(position, 0),
+                    block: 0,
+                }
+            })
+            .collect::<Vec<BlockStart>>();
+        let end = |k: usize| starts.get(k + 1).map_or(usize::MAX, |s| s.bit);
+
+        let mut parts = (0..count).map(|_| None).collect::<Vec<Option<Part>>>();
+
+        pool.scoped(|scope| {
+            for (k, part) in parts.iter_mut().enumerate()
+            {
+                let (data, tables, layout, start) = (&data, &tables, &layout, starts[k]);
+                let end = end(k);
+
+                scope.execute(move || {
+                    *part = Some(decode_part(data, tables, layout, start, end, &[]));
+                });
+            }
+        });
+
+        // Going from the start of the data, decode serially from the true end of the part
+        // before until joining blocks of a part, and take them from there
+        let mut coefficients = Vec::with_capacity(needed);
+        let mut boundary = (starts[0], 0);
+
+        for (k, part) in parts.into_iter().flatten().enumerate()
+        {
+            let bridge = decode_part(&data, &tables, &layout, boundary.0, end(k), &part.starts);
+
+            take(&mut coefficients, &mut boundary, &bridge, 0);
+
+            let last = if let Some(i) = bridge.joins
+            {
+                take(&mut coefficients, &mut boundary, &part, i);
+                &part
+            }
+            else
+            {
+                warn!("Speculative decoding of part {k} didn't synchronize, decoded serially");
+                &bridge
+            };
+            if !last.complete || coefficients.len() >= needed
+            {
+                break;
+            }
+        }
+        let state = boundary.0;
+
+        // blocks are taken up to the start of an MCU
+        coefficients.truncate(needed.min(boundary.1 * DCT_BLOCK));
+
+        // DC coefficients from the differences
+        let mut predictions = vec![0_i32; components.len()];
+
+        for (block, pos) in coefficients
+            .chunks_exact_mut(DCT_BLOCK)
+            .zip(layout.iter().cycle())
+        {
+            predictions[*pos] = predictions[*pos].wrapping_add(i32::from(block[0]));
+            block[0] = predictions[*pos] as i16;
+        }
+        let decoded = coefficients.len() / (blocks_per_mcu * DCT_BLOCK);
+
+        if decoded < mcus
+        {
+            // the main loop goes on from the end of the blocks taken
+            let position = reader.position();
+
+            reader.set_position(data.position(state.bit / 8) as u64);
+            *stream = BitStream::new();
+
+            if stream.skip_bits(reader, (state.bit % 8) as u8).is_err()
+            {
+                reader.set_position(position);
+                *stream = BitStream::new();
+
+                return None;
+            }
+            for (component, prediction) in self.components.iter_mut().zip(predictions)
+            {
+                component.dc_pred = prediction;
+            }
+        }
+        Some(DecodedMcus {
+            buffers: vec![coefficients],
+            mcus_per_buffer: mcus.max(1),
+            blocks_per_mcu,
+            mcus: decoded,
+        })
+    }
+}
+
+/// Add the blocks of `part` from its start `first` to `coefficients`, keeping in
+/// `boundary` the last start of an MCU and the number of blocks before it
+fn take(
+    coefficients: &mut Vec<i16>, boundary: &mut (BlockStart, usize), part: &Part, first: usize,
+)
+{
+    let taken = coefficients.len() / DCT_BLOCK;
+
+    if let Some((i, start)) = part
+        .starts
+        .iter()
+        .enumerate()
+        .skip(first)
+        .rfind(|(_, s)| s.block == 0)
+    {
+        *boundary = (*start, taken + i - first);
+    }
+    coefficients.extend_from_slice(&part.coefficients[first * DCT_BLOCK..]);
+}
+
+/// Decode blocks from `start` until an MCU starts at or after bit `end` of the data, or
+/// a block starts where one of `known` does
+///
+/// Decoding stops early at the marker ending the data, the end of a file cut short, or a
+/// Huffman code that isn't in the tables, which speculative decoding runs into before it
+/// synchronizes.
+#[allow(clippy::cast_possible_truncation)]
+fn decode_part(
+    data: &ScanData, tables: &[(&HuffmanTable, &HuffmanTable)], layout: &[usize],
+    start: BlockStart, end: usize, known: &[BlockStart],
+) -> Part
+{
+    let position = data.position(start.bit / 8);
+    let limit = data
+        .position(end / 8)
+        .saturating_add(PART_SLACK)
+        .min(data.buf.len());
+
+    let mut part = Part {
+        joins:        None,
+        starts:       vec![start],
+        coefficients: vec![],
+        complete:     false,
+    };
+    if position >= limit
+    {
+        return part;
+    }
+    let mut reader = Cursor::new(data.buf[position..limit].to_vec());
+    let mut stream = BitStream::new();
+
+    if stream.skip_bits(&mut reader, (start.bit % 8) as u8).is_err()
+    {
+        return part;
+    }
+    let mut block = start.block;
+
+    loop
+    {
+        let current = *part.starts.last().unwrap();
+
+        if let Ok(i) = known.binary_search_by_key(&current.bit, |s| s.bit)
+        {
+            if known[i] == current
+            {
+                part.joins = Some(i);
+                break;
+            }
+        }
+        if block == 0 && current.bit >= end
+        {
+            part.complete = true;
+            break;
+        }
+        let (dc_table, ac_table) = tables[layout[block]];
+        let mut coefficients = [0; DCT_BLOCK];
+        let mut difference = 0;
+
+        // a block read up to the marker, or past the bytes copied, may be short of bits
+        if stream
+            .decode_mcu_block(&mut reader, dc_table, ac_table, &mut coefficients, &mut difference)
+            .is_err()
+            || stream.marker.is_some()
+            || stream.padded
+        {
+            break;
+        }
+        part.coefficients.extend_from_slice(&coefficients);
+        block = (block + 1) % layout.len();
+        part.starts.push(BlockStart {
+            bit: data.bit(position + reader.position() as usize, stream.bits_left),
+            block,
+        });
+    }
+    part
+}
+//----------------------------------------------
+// TEST
+//----------------------------------------------
+/// Call `f` with a 4:2:0 RGB file, the start of its scan data, the Huffman tables of the
+/// components and the component of each block of an MCU
+#[cfg(test)]
+#[allow(clippy::cast_possible_truncation)]
+fn speculative_scan(f: impl Fn(&[u8], usize, &[(&HuffmanTable, &HuffmanTable)], &[usize]))
+{
+    use crate::encoder::{Encoder, Subsampling};
+    use crate::ColorSpace;
+
+    let pixels = (0..128 * 128 * 3)
+        .map(|i: usize| (i % 384 * 3 + i / 384 + (i * i) % 37) as u8)
+        .collect::<Vec<u8>>();
+    let jpeg = Encoder::new()
+        .set_quality(90)
+        .set_subsampling(Subsampling::Chroma420)
+        .encode(&pixels, 128, 128, ColorSpace::RGB)
+        .unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&jpeg).unwrap();
+
+    let sos = jpeg.windows(2).position(|x| x == [0xFF, 0xDA]).unwrap();
+    let start = sos + 2 + usize::from(u16::from_be_bytes([jpeg[sos + 2], jpeg[sos + 3]]));
+    let tables = decoder
+        .components
+        .iter()
+        .map(|c| {
+            (
+                decoder.dc_huffman_tables[c.dc_huff_table].as_ref().unwrap(),
+                decoder.ac_huffman_tables[c.ac_huff_table].as_ref().unwrap(),
+            )
+        })
+        .collect::<Vec<(&HuffmanTable, &HuffmanTable)>>();
+
+    f(&jpeg, start, &tables, &[0, 0, 0, 0, 1, 2]);
+}
+
+#[test]
+fn speculative_part_joins()
+{
+    speculative_scan(|jpeg, start, tables, layout| {
+        let data = ScanData::new(jpeg, start);
+        let first = BlockStart { bit: 0, block: 0 };
+        let serial = decode_part(&data, tables, layout, first, usize::MAX, &[]);
+
+        // a part from the middle of the data, falling in step with the blocks of the
+        // one from its start
+        let middle = BlockStart {
+            bit:   data.bit(usize::midpoint(data.start, data.end), 0),
+            block: 0,
+        };
+        let part = decode_part(&data, tables, layout, middle, usize::MAX, &[]);
+        let bridge = decode_part(&data, tables, layout, first, usize::MAX, &part.starts);
+        let joins = bridge.joins.unwrap();
+
+        assert!(!bridge.complete && joins < part.starts.len());
+        assert_eq!(bridge.starts.last(), part.starts.get(joins));
+
+        let mut coefficients = vec![];
+        let mut boundary = (first, 0);
+
+        take(&mut coefficients, &mut boundary, &bridge, 0);
+        take(&mut coefficients, &mut boundary, &part, joins);
+
+        assert!(coefficients == serial.coefficients);
+        assert_eq!(Some(&boundary.0), serial.starts.iter().rfind(|s| s.block == 0));
+    });
+}
+
+#[test]
+fn speculative_part_truncated()
+{
+    speculative_scan(|jpeg, start, tables, layout| {
+        // without a marker ending the data, decoding stops at the zeroes it pads with
+        let cut = usize::midpoint(start, jpeg.len());
+        let data = ScanData::new(&jpeg[..cut], start);
+        let first = BlockStart { bit: 0, block: 0 };
+        let part = decode_part(&data, tables, layout, first, usize::MAX, &[]);
+
+        assert!(!part.complete && part.joins.is_none());
+        assert!(part.starts.last().unwrap().bit <= data.bit(cut, 0));
+    });
+}
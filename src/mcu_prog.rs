@@ -35,9 +35,12 @@ use crate::decoder::MAX_COMPONENTS;
 use crate::errors::DecodeErrors;
 use crate::errors::DecodeErrors::Format;
 use crate::headers::{parse_huffman, parse_sos};
+use crate::idct::scaled::ScaledIdct;
 use crate::marker::Marker;
+use crate::mcu::{mirror_block_rows, DCT_BLOCK};
 use crate::misc::read_byte;
-use crate::worker::post_process;
+use crate::region::Window;
+use crate::worker::{post_process, post_process_scaled};
 use crate::{ColorSpace, Decoder};
 
 impl Decoder
@@ -49,6 +52,24 @@ impl Decoder
     pub(crate) fn decode_mcu_ycbcr_progressive(
         &mut self, reader: &mut Cursor<Vec<u8>>,
     ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        self.check_huffman_coding()?;
+
+        let (block, mcu_width) = self.decode_progressive_coefficients(reader, false)?;
+
+        self.finish_progressive_decoding(block, mcu_width, 8)
+    }
+
+    /// Decode all scans of a progressive image, returning the coefficients
+    /// of each component and the width of a row of MCU's in coefficients.
+    ///
+    /// Coefficients are stored per component as 64 coefficient blocks in raster order,
+    /// each block in natural (not zig-zag) order. With `skip_ac` the entropy coded data
+    /// of AC scans is skipped, leaving only DC coefficients.
+    #[rustfmt::skip]
+    pub(crate) fn decode_progressive_coefficients(
+        &mut self, reader: &mut Cursor<Vec<u8>>, skip_ac: bool,
+    ) -> Result<([Vec<i16>; 3], usize), DecodeErrors>
     {
         self.check_component_dimensions()?;
         let mcu_height;
@@ -102,11 +123,24 @@ impl Decoder
                         stream.update_progressive_params(self.succ_high, self.succ_low,
                                                          self.spec_start, self.spec_end);
 
-                        // after every SOS, marker, parse data for that scan.
-                        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
-                        // extract marker, might either indicate end of image or we continue
-                        // scanning(hence the continue statement to determine).
-                        marker = get_marker(reader, &mut stream).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+                        if skip_ac && self.spec_start > 0
+                        {
+                            // jump to the first marker after the scan, restart markers
+                            // are part of it
+                            marker = loop {
+                                match get_marker(reader, &mut stream) {
+                                    Some(Marker::RST(_)) => {}
+                                    Some(marker) => break marker,
+                                    None => return Err(DecodeErrors::FormatStatic("Marker missing where expected")),
+                                }
+                            };
+                        } else {
+                            // after every SOS, marker, parse data for that scan.
+                            self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
+                            // extract marker, might either indicate end of image or we continue
+                            // scanning(hence the continue statement to determine).
+                            marker = get_marker(reader, &mut stream).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+                        }
                         seen_scans+=1;
 
                         if seen_scans >  self.options.get_max_scans(){
@@ -125,11 +159,16 @@ impl Decoder
             marker = get_marker(reader, &mut stream).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
         }
 
-        self.finish_progressive_decoding(&block, mcu_width)
+        Ok((block, mcu_width))
     }
 
+    /// Carry out post processing on decoded coefficients, `mcu_width` as returned by
+    /// [`decode_progressive_coefficients`](Self::decode_progressive_coefficients)
+    ///
+    /// The image is decoded at `scale`/8 of its size, 8 for a full decode.
     #[rustfmt::skip]
-    fn finish_progressive_decoding(&mut self, block: &[Vec<i16>; 3], mcu_width: usize) -> Result<Vec<u8>, DecodeErrors> {
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn finish_progressive_decoding(&mut self, mut block: [Vec<i16>; 3], mcu_width: usize, scale: usize) -> Result<Vec<u8>, DecodeErrors> {
         self.set_upsampling()?;
 
         let mut mcu_width = mcu_width;
@@ -166,39 +205,64 @@ impl Decoder
             self.components[0].horizontal_sample = mcu_width;
             bias = 1;
         }
-        // remove items from  top block
-        let y = &block[0];
-        let cb = &block[1];
-        let cr = &block[2];
-        let extra_space = usize::from(self.interleaved) * 128 * usize::from(self.height()) * self.options.get_out_colorspace().num_components();
-        let capacity = usize::from(self.info.width + 8) * usize::from(self.info.height + 8);
+        let (width, height) = self.scaled_dimensions_internal(scale);
+        let extra_space = usize::from(self.interleaved) * 128 * height * self.options.get_out_colorspace().num_components();
+        let capacity = (width + 8) * (height + 8);
+
+        // Only the chunks and MCU columns touching a region are post processed
+        let window = self.region.map(|region| Window::new(self, region));
+        let window = &window;
 
-        let mut out_vector = vec![0_u8; capacity * self.options.get_out_colorspace().num_components() + extra_space];
 
         // Things we need for multithreading.
         let h_max = self.h_max;
         let v_max = self.v_max;
-        let components = Arc::new(self.components.clone());
+        let components = Arc::new(self.scaled_components(scale));
+        let scaled_idct = (scale != 8).then(|| ScaledIdct::new(scale));
+        let scaled_idct = scaled_idct.as_ref();
         let input = self.input_colorspace;
         let output = self.options.get_out_colorspace();
         let idct_func = self.idct_func;
         let color_convert_16 = self.color_convert_16;
-        let width = usize::from(self.width());
-        // Divide the output into small blocks and send to threads/
-        let chunks_size = width * self.options.get_out_colorspace().num_components() * 8 * h_max * v_max;
-        let out_chunks = out_vector.chunks_exact_mut(chunks_size);
         // Chunk sizes. Each determine how many pixels go per thread.
         let y_chunk_size =
             mcu_width * self.components[0].vertical_sample * self.components[0].horizontal_sample * bias;
+        let cb_chunk_size = self.components.get(1)
+            .map_or(0, |c| mcu_width * c.vertical_sample * c.horizontal_sample * bias);
+
+        // chunks of two MCU rows may have one more row than the image, pad it
+        for ((component, chunk_size), comp) in block.iter_mut()
+            .zip([y_chunk_size, cb_chunk_size, cb_chunk_size])
+            .zip(&self.components)
+        {
+            if chunk_size > 0 && component.len() % chunk_size != 0
+            {
+                let row_len = comp.width_stride / 8;
+                let rows = component.len() / (row_len * DCT_BLOCK);
+
+                component.resize(component.len().next_multiple_of(chunk_size), 0);
+                mirror_block_rows(component, row_len, rows);
+            }
+        }
+        // remove items from  top block
+        let y = &block[0];
+        let cb = &block[1];
+        let cr = &block[2];
+        // Divide the output into small blocks and send to threads/
+        let chunks_size = window.as_ref().map_or(width * self.options.get_out_colorspace().num_components() * scale * h_max * v_max, Window::chunk_len);
+        let mut out_vector = match window
+        {
+            Some(window) => vec![0_u8; window.buffer_len()],
+            None => vec![0_u8; (capacity * self.options.get_out_colorspace().num_components() + extra_space).max(y.len() / y_chunk_size * chunks_size)],
+        };
+        let out_chunks = out_vector.chunks_exact_mut(chunks_size);
         // Divide into chunks
         let y_chunk = y.chunks_exact(y_chunk_size);
+        let needed = |i: usize| window.as_ref().is_none_or(|w| w.needs(i));
 
         let mut pool = scoped_threadpool::Pool::new(self.options.get_threads());
 
         if self.input_colorspace.num_components() == 3 {
-            let cb_chunk_size =
-                mcu_width * self.components[1].vertical_sample * self.components[1].horizontal_sample * bias;
-
             let cb_chunk = cb.chunks_exact(cb_chunk_size);
             let cr_chunk = cr.chunks_exact(cb_chunk_size);
             // open threads.
@@ -206,39 +270,63 @@ impl Decoder
                 for (((y, cb), cr), out) in y_chunk
                     .zip(cb_chunk)
                     .zip(cr_chunk)
+                    .enumerate()
+                    .filter(|(i, _)| needed(*i))
+                    .map(|(_, chunk)| chunk)
                     .zip(out_chunks)
                 {
                     let component = components.clone();
 
                     scope.execute(move || {
-                        post_process(&[y, cb, cr], &component, idct_func, color_convert_16,
-                                          input, output, out, width,
-                        );
+                        match (window, scaled_idct)
+                        {
+                            (Some(window), _) => window.post_process(&[y, cb, cr], idct_func, color_convert_16,
+                                                                     input, output, out),
+                            (None, Some(idct)) => post_process_scaled(&[y, cb, cr], &component, idct, color_convert_16,
+                                                                      input, output, out, width,
+                            ),
+                            (None, None) => post_process(&[y, cb, cr], &component, idct_func, color_convert_16,
+                                                         input, output, out, width,
+                            ),
+                        }
                     });
                 }
             });
         } else {
             // one component
             pool.scoped(|scope| {
-                for (y, out) in y_chunk.zip(out_chunks)
+                for (y, out) in y_chunk
+                    .enumerate()
+                    .filter(|(i, _)| needed(*i))
+                    .map(|(_, chunk)| chunk)
+                    .zip(out_chunks)
                 {
                     let component = components.clone();
 
                     scope.execute(move || {
-                        post_process(&[y, &[], &[]], &component, idct_func, color_convert_16,
-                                          input, output, out, width,
-                        );
+                        match (window, scaled_idct)
+                        {
+                            (Some(window), _) => window.post_process(&[y, &[], &[]], idct_func, color_convert_16,
+                                                                     input, output, out),
+                            (None, Some(idct)) => post_process_scaled(&[y, &[], &[]], &component, idct, color_convert_16,
+                                                                      input, output, out, width,
+                            ),
+                            (None, None) => post_process(&[y, &[], &[]], &component, idct_func, color_convert_16,
+                                                         input, output, out, width,
+                            ),
+                        }
                     });
                 }
             });
         }
         debug!("Finished decoding image");
 
-        out_vector.truncate(
-            usize::from(self.width())
-                * usize::from(self.height())
-                * self.options.get_out_colorspace().num_components(),
-        );
+        if let Some(window) = window
+        {
+            return Ok(window.crop(&out_vector));
+        }
+
+        out_vector.truncate(width * height * self.options.get_out_colorspace().num_components());
 
         return Ok(out_vector);
     }
@@ -253,6 +341,8 @@ impl Decoder
         self.check_component_dimensions()?;
         stream.reset();
         self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        // restart intervals start over with every scan
+        self.todo = if self.restart_interval == 0 { 0x7fff_ffff } else { self.restart_interval };
 
         if usize::from(self.num_scans) > self.input_colorspace.num_components() {
             return Err(Format(format!("Number of scans {} cannot be greater than number of components, {}", self.num_scans, self.input_colorspace.num_components())));
@@ -345,9 +435,13 @@ impl Decoder
                                 // which is more faster than a decrement and return since EOB runs can be
                                 // as big as 10,000
 
-                                i += (j + stream.eob_run as usize - 1) / mcu_width;
-                                j = (j + stream.eob_run as usize - 1) % mcu_width;
+                                let skipped = stream.eob_run as usize - 1;
+
+                                i += (j + skipped) / mcu_width;
+                                j = (j + skipped) % mcu_width;
                                 stream.eob_run = 0;
+                                // skipped blocks count towards the restart interval
+                                self.todo = self.todo.saturating_sub(skipped);
                             } else {
                                 stream.decode_mcu_ac_first(reader, ac_table, data)?;
                             }
@@ -357,7 +451,7 @@ impl Decoder
                         }
                     }
                     j += 1;
-                    self.todo -= 1;
+                    self.todo = self.todo.saturating_sub(1);
 
                     if self.todo == 0
                     {
@@ -415,13 +509,13 @@ impl Decoder
                                 }
                             }
                         }
-                        // We want wrapping subtraction here because it means
-                        // we get a higher number in the case this underflows
-                        self.todo = self.todo.wrapping_sub(1);
-                        // after every scan that's a mcu, count down restart markers.
-                        if self.todo == 0 {
-                            self.handle_rst(stream)?;
-                        }
+                    }
+                    // We want wrapping subtraction here because it means
+                    // we get a higher number in the case this underflows
+                    self.todo = self.todo.wrapping_sub(1);
+                    // after every MCU, count down restart markers.
+                    if self.todo == 0 {
+                        self.handle_rst(stream)?;
                     }
                 }
             }
@@ -433,7 +527,7 @@ impl Decoder
 ///Get a marker from the bit-stream.
 ///
 /// This reads until it gets a marker or end of file is encountered
-fn get_marker(reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream) -> Option<Marker>
+pub(crate) fn get_marker(reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream) -> Option<Marker>
 {
     if let Some(marker) = stream.marker
     {
@@ -0,0 +1,114 @@
+//! MD5 message digest (RFC 1321)
+//!
+//! Extended XMP identifies its chunks by the MD5 digest of the extended packet,
+//! compressed archives and restart indexes store digests of the files they belong
+//! to. Inputs are at most a file, so speed does not matter much.
+
+/// Per round shift amounts
+const SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, //
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, //
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, //
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// Integer part of `abs(sin(i + 1)) * 2^32`
+const CONSTANTS: [u32; 64] = [
+    0xd76a_a478, 0xe8c7_b756, 0x2420_70db, 0xc1bd_ceee, 0xf57c_0faf, 0x4787_c62a, 0xa830_4613,
+    0xfd46_9501, 0x6980_98d8, 0x8b44_f7af, 0xffff_5bb1, 0x895c_d7be, 0x6b90_1122, 0xfd98_7193,
+    0xa679_438e, 0x49b4_0821, 0xf61e_2562, 0xc040_b340, 0x265e_5a51, 0xe9b6_c7aa, 0xd62f_105d,
+    0x0244_1453, 0xd8a1_e681, 0xe7d3_fbc8, 0x21e1_cde6, 0xc337_07d6, 0xf4d5_0d87, 0x455a_14ed,
+    0xa9e3_e905, 0xfcef_a3f8, 0x676f_02d9, 0x8d2a_4c8a, 0xfffa_3942, 0x8771_f681, 0x6d9d_6122,
+    0xfde5_380c, 0xa4be_ea44, 0x4bde_cfa9, 0xf6bb_4b60, 0xbebf_bc70, 0x289b_7ec6, 0xeaa1_27fa,
+    0xd4ef_3085, 0x0488_1d05, 0xd9d4_d039, 0xe6db_99e5, 0x1fa2_7cf8, 0xc4ac_5665, 0xf429_2244,
+    0x432a_ff97, 0xab94_23a7, 0xfc93_a039, 0x655b_59c3, 0x8f0c_cc92, 0xffef_f47d, 0x8584_5dd1,
+    0x6fa8_7e4f, 0xfe2c_e6e0, 0xa301_4314, 0x4e08_11a1, 0xf753_7e82, 0xbd3a_f235, 0x2ad7_d2bb,
+    0xeb86_d391,
+];
+
+/// Compute the MD5 digest of `data`
+#[allow(clippy::many_single_char_names)]
+pub(crate) fn md5(data: &[u8]) -> [u8; 16]
+{
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+
+    // pad with a one bit, zeroes up to 56 bytes modulo 64 and the length in bits
+    let mut message = data.to_vec();
+
+    message.push(0x80);
+
+    while message.len() % 64 != 56
+    {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    for block in message.chunks_exact(64)
+    {
+        let mut words = [0_u32; 16];
+
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4))
+        {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64
+        {
+            let (f, g) = match i / 16
+            {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f
+                .wrapping_add(a)
+                .wrapping_add(CONSTANTS[i])
+                .wrapping_add(words[g]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i]));
+        }
+        for (x, y) in state.iter_mut().zip([a, b, c, d])
+        {
+            *x = x.wrapping_add(y);
+        }
+    }
+    let mut digest = [0; 16];
+
+    for (bytes, x) in digest.chunks_exact_mut(4).zip(state)
+    {
+        bytes.copy_from_slice(&x.to_le_bytes());
+    }
+    digest
+}
+
+/// Compute the MD5 digest of `data` as 32 uppercase hexadecimal digits
+pub(crate) fn md5_hex(data: &[u8]) -> String
+{
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+    md5(data)
+        .iter()
+        .flat_map(|x| [DIGITS[usize::from(x >> 4)], DIGITS[usize::from(x & 15)]])
+        .map(char::from)
+        .collect()
+}
+
+//---------------------------------------------
+// TEST
+//----------------------------------------------
+#[test]
+fn md5_test_suite()
+{
+    // RFC 1321 A.5
+    assert_eq!(md5_hex(b""), "D41D8CD98F00B204E9800998ECF8427E");
+    assert_eq!(md5_hex(b"abc"), "900150983CD24FB0D6963F7D28E17F72");
+    assert_eq!(
+        md5_hex(&b"1234567890".repeat(8)),
+        "57EDF4A22BE3C955AC49DA2E2107B67A"
+    );
+}
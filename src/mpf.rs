@@ -0,0 +1,372 @@
+//! Multi-Picture Format (MPF) support
+//!
+//! MPF, defined in [CIPA DC-007], stores extra JPEG images after the end of the
+//! primary image, the primary image carries an APP2 segment starting with `MPF\0`
+//! followed by a TIFF structure (the MP header) whose first IFD, the MP index IFD,
+//! lists every image in the file.
+//!
+//! This is used by stereo cameras, burst shots and phones which store depth maps
+//! and gain maps alongside the primary image.
+//!
+//! Offsets of the images in the MP entry are relative to the start of the MP header,
+//! except for the first image (the primary image) whose offset is zero.
+//!
+//! [CIPA DC-007]: https://web.archive.org/web/20210813084506/https://www.cipa.jp/std/documents/e/DC-X007-KEY_E.pdf
+
+use crate::decoder::ImageInfo;
+use crate::errors::DecodeErrors;
+use crate::exif::TiffReader;
+use crate::marker::Marker;
+use crate::segments::Segments;
+use crate::{Decoder, ZuneJpegOptions};
+
+/// Number of images stored in the file
+const TAG_NUMBER_OF_IMAGES: u16 = 0xB001;
+
+/// Location of the MP entries, 16 bytes for each image
+const TAG_MP_ENTRY: u16 = 0xB002;
+
+/// Size of a single MP entry
+const MP_ENTRY_SIZE: usize = 16;
+
+/// Type of an image stored in a Multi-Picture file
+///
+/// See CIPA DC-007 5.2.3.3.1 Individual Image Attribute
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MpImageType
+{
+    /// Baseline MP primary image
+    BaselinePrimary,
+    /// Large thumbnail, VGA equivalent
+    LargeThumbnailVga,
+    /// Large thumbnail, Full HD equivalent
+    LargeThumbnailFullHd,
+    /// Multi-frame image, panorama
+    Panorama,
+    /// Multi-frame image, disparity (stereo)
+    Disparity,
+    /// Multi-frame image, multi-angle
+    MultiAngle,
+    /// Undefined, this is what phones use for gain maps and depth maps
+    Undefined,
+    /// A type code not defined by the specification
+    Unknown(u32),
+}
+
+impl MpImageType
+{
+    fn from_code(code: u32) -> MpImageType
+    {
+        match code
+        {
+            0x03_0000 => MpImageType::BaselinePrimary,
+            0x01_0001 => MpImageType::LargeThumbnailVga,
+            0x01_0002 => MpImageType::LargeThumbnailFullHd,
+            0x02_0001 => MpImageType::Panorama,
+            0x02_0002 => MpImageType::Disparity,
+            0x02_0003 => MpImageType::MultiAngle,
+            0x00_0000 => MpImageType::Undefined,
+            code => MpImageType::Unknown(code),
+        }
+    }
+}
+
+/// A single entry of the MP index IFD, describing one image in the file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MpEntry
+{
+    /// Raw individual image attribute, containing flags, format and type
+    pub attribute:        u32,
+    /// Type of the image
+    pub image_type:       MpImageType,
+    /// Size of the image in bytes
+    pub size:             u32,
+    /// Offset of the image from the start of the MP header, zero for the primary image
+    pub offset:           u32,
+    /// Entry numbers (starting from 1) of dependent images, zero if none
+    pub dependent_images: [u16; 2],
+}
+
+impl MpEntry
+{
+    /// Whether this image is the parent of dependent images
+    #[must_use]
+    pub const fn is_dependent_parent(&self) -> bool
+    {
+        self.attribute & (1 << 31) != 0
+    }
+
+    /// Whether this image depends on a parent image
+    #[must_use]
+    pub const fn is_dependent_child(&self) -> bool
+    {
+        self.attribute & (1 << 30) != 0
+    }
+
+    /// Whether this image is the representative image of the file
+    #[must_use]
+    pub const fn is_representative(&self) -> bool
+    {
+        self.attribute & (1 << 29) != 0
+    }
+
+    /// Whether this image is stored as a JPEG, the only format defined currently
+    #[must_use]
+    pub const fn is_jpeg(&self) -> bool
+    {
+        self.attribute & 0x0700_0000 == 0
+    }
+}
+
+/// Find the MP entries in the MP index IFD
+///
+/// Returns the offset of the first entry and the number of entries.
+fn mp_entries_position(reader: &TiffReader) -> Option<(usize, usize)>
+{
+    let index_ifd = reader.first_ifd()?;
+
+    let mut number_of_images = None;
+    let mut entries = None;
+
+    for entry in reader.entries(index_ifd)?
+    {
+        match entry.tag
+        {
+            TAG_NUMBER_OF_IMAGES => number_of_images = reader.entry_u32(&entry),
+            // UNDEFINED with more than 4 bytes, so the value is an offset
+            TAG_MP_ENTRY if entry.count as usize >= MP_ENTRY_SIZE =>
+            {
+                let start = reader.read_u32(entry.value_offset)? as usize;
+
+                entries = Some((start, entry.count as usize / MP_ENTRY_SIZE));
+            }
+            _ => (),
+        }
+    }
+    let (start, count) = entries?;
+
+    if let Some(number) = number_of_images
+    {
+        if number as usize != count
+        {
+            warn!(
+                "MPF declares {number} images but has {count} MP entries, using MP entries"
+            );
+        }
+    }
+    Some((start, count))
+}
+
+/// Parse the MP entries from the MP index IFD
+///
+/// `data` should point to the MP header (i.e after the `MPF\0` identifier).
+///
+/// Returns `None` if the structure is corrupt or has no MP entries.
+pub(crate) fn parse_mp_entries(data: &[u8]) -> Option<Vec<MpEntry>>
+{
+    let reader = TiffReader::new(data)?;
+    let (start, count) = mp_entries_position(&reader)?;
+
+    (0..count)
+        .map(|i| {
+            let offset = start + i * MP_ENTRY_SIZE;
+            let attribute = reader.read_u32(offset)?;
+
+            Some(MpEntry {
+                attribute,
+                image_type: MpImageType::from_code(attribute & 0x00FF_FFFF),
+                size: reader.read_u32(offset + 4)?,
+                offset: reader.read_u32(offset + 8)?,
+                dependent_images: [reader.read_u16(offset + 12)?, reader.read_u16(offset + 14)?],
+            })
+        })
+        .collect()
+}
+
+/// Update the MP entries after the primary image changed size
+///
+/// `data` should point to the MP header, `primary_size` is the new size of the
+/// primary image and `delta` how much the distance between the MP header and the
+/// end of the primary image changed, images following it moved by that much.
+///
+/// Returns `None` if the structure is corrupt or an offset would not fit.
+pub(crate) fn relocate_mp_entries(data: &mut [u8], primary_size: u32, delta: i64) -> Option<()>
+{
+    let reader = TiffReader::new(data)?;
+    let (start, count) = mp_entries_position(&reader)?;
+
+    let mut updates = vec![];
+
+    for i in 0..count
+    {
+        let entry = start + i * MP_ENTRY_SIZE;
+        let offset = reader.read_u32(entry + 8)?;
+
+        if offset == 0
+        {
+            updates.push((entry + 4, reader.encode_u32(primary_size)));
+        }
+        else
+        {
+            let offset = u32::try_from(i64::from(offset) + delta).ok()?;
+
+            updates.push((entry + 8, reader.encode_u32(offset)));
+        }
+    }
+    for (position, value) in updates
+    {
+        data[position..position + 4].copy_from_slice(&value);
+    }
+    Some(())
+}
+
+/// Find the position of the MP header in a JPEG file
+///
+/// This walks the segments before the first start of scan looking for an APP2
+/// segment with the `MPF\0` identifier.
+pub(crate) fn mp_header_position(buf: &[u8]) -> Option<usize>
+{
+    Segments::new(buf)
+        .take_while(|segment| segment.marker() != Some(Marker::SOS))
+        .find(|segment| {
+            segment.marker() == Some(Marker::APP(2)) && segment.payload.starts_with(b"MPF\0")
+        })
+        .map(|segment| segment.payload_range().start + 4)
+}
+
+/// A secondary image of a Multi-Picture file, decoded by its own decoder
+#[derive(Clone)]
+pub struct SecondaryImage<'a>
+{
+    /// The MP entry describing this image
+    pub entry:  MpEntry,
+    /// Raw JPEG data of the image
+    pub data:   &'a [u8],
+    /// Pixels in the output colorspace of the decoder
+    pub pixels: Vec<u8>,
+    /// Information of the image
+    pub info:   ImageInfo,
+}
+
+/// An iterator over secondary images of a Multi-Picture file
+///
+/// Images are decoded lazily, as the iterator advances.
+///
+/// Created by [`Decoder::secondary_images`]
+pub struct SecondaryImages<'a>
+{
+    buf:     &'a [u8],
+    /// Position of the MP header in `buf`, `None` if there is no MPF segment
+    base:    Option<usize>,
+    entries: std::vec::IntoIter<MpEntry>,
+    options: ZuneJpegOptions,
+}
+
+impl<'a> SecondaryImages<'a>
+{
+    /// Advance the iterator without decoding, returning the MP entry and raw
+    /// JPEG data of the next secondary image
+    pub(crate) fn next_data(&mut self) -> Option<Result<(MpEntry, &'a [u8]), DecodeErrors>>
+    {
+        let base = self.base?;
+        // offset zero is the primary image, which is decoded by the parent decoder
+        let entry = self.entries.find(|x| x.offset != 0)?;
+
+        let range = base
+            .checked_add(entry.offset as usize)
+            .and_then(|start| Some(start..start.checked_add(entry.size as usize)?))
+            .filter(|range| range.end <= self.buf.len());
+
+        match range
+        {
+            Some(range) => Some(Ok((entry, &self.buf[range]))),
+            None => Some(Err(DecodeErrors::Format(format!(
+                "MPF image of {} bytes at offset {} is out of bounds, file has {} bytes",
+                entry.size,
+                entry.offset,
+                self.buf.len()
+            )))),
+        }
+    }
+}
+
+impl<'a> Iterator for SecondaryImages<'a>
+{
+    type Item = Result<SecondaryImage<'a>, DecodeErrors>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let (entry, data) = match self.next_data()?
+        {
+            Ok(next) => next,
+            Err(err) => return Some(Err(err)),
+        };
+        let mut decoder = Decoder::new_with_options(self.options);
+
+        Some(decoder.decode_buffer(data).map(|pixels| SecondaryImage {
+            entry,
+            data,
+            pixels,
+            info: decoder.info.clone(),
+        }))
+    }
+}
+
+impl Decoder
+{
+    /// Returns the MP header found in an APP(2) segment with the `MPF\0`
+    /// identifier, if present
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    #[must_use]
+    pub fn mpf(&self) -> Option<&[u8]>
+    {
+        self.mpf_data.as_deref()
+    }
+
+    /// Returns the entries of the MP index IFD, one for every image in the file,
+    /// including the primary image
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    #[must_use]
+    pub fn mpf_entries(&self) -> Option<Vec<MpEntry>>
+    {
+        parse_mp_entries(self.mpf_data.as_ref()?)
+    }
+
+    /// Returns an iterator decoding every image in a Multi-Picture file except
+    /// the primary image
+    ///
+    /// `buf` must be the same buffer whose headers were read by this decoder,
+    /// each image is decoded by a new decoder with the same options as this one.
+    ///
+    /// If the image has no MPF segment, the iterator is empty.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let mut decoder = Decoder::new();
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// decoder.read_headers(&img_data).unwrap();
+    ///
+    /// for image in decoder.secondary_images(&img_data)
+    /// {
+    ///     let image = image.unwrap();
+    ///     println!("{:?} is {}x{}", image.entry.image_type, image.info.width, image.info.height);
+    /// }
+    /// ```
+    #[must_use]
+    pub fn secondary_images<'a>(&self, buf: &'a [u8]) -> SecondaryImages<'a>
+    {
+        let entries = self.mpf_entries().unwrap_or_default();
+
+        SecondaryImages {
+            buf,
+            base: if entries.is_empty() { None } else { mp_header_position(buf) },
+            entries: entries.into_iter(),
+            options: self.options,
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::num::NonZeroU32;
 use crate::ColorSpace;
 /// Options available that influence decoding.
 #[derive(Copy, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct ZuneJpegOptions
 {
     /// Whether or not we wre allowed
@@ -22,6 +23,13 @@ pub struct ZuneJpegOptions
     max_scans:      usize,
     /// Treat warnings as errors.
     strict_mode:    bool,
+    /// Whether we may use SSE 4.1 and AVX2
+    /// routines where the CPU has them
+    use_sse41:      bool,
+    use_avx2:       bool,
+    /// Entropy decode images without restart
+    /// markers speculatively in parallel
+    speculative:    bool,
 }
 impl Default for ZuneJpegOptions
 {
@@ -35,6 +43,9 @@ impl Default for ZuneJpegOptions
             max_height:     1 << 14,
             max_scans:      64,
             strict_mode:    false,
+            use_sse41:      true,
+            use_avx2:       true,
+            speculative:    false,
         }
     }
 }
@@ -75,6 +86,54 @@ impl ZuneJpegOptions
         self.use_unsafe = choice;
         self
     }
+    /// Check if we can use SSE 4.1 procedures
+    /// for decoding.
+    ///
+    /// They are only used if unsafe procedures are allowed
+    /// and the CPU supports them
+    #[must_use]
+    pub const fn get_use_sse41(&self) -> bool
+    {
+        self.use_sse41
+    }
+    /// Set whether we can use SSE 4.1 procedures
+    /// for decoding.
+    #[must_use]
+    pub const fn set_use_sse41(mut self, choice: bool) -> ZuneJpegOptions
+    {
+        self.use_sse41 = choice;
+        self
+    }
+    /// Check if we can use AVX2 procedures
+    /// for decoding.
+    ///
+    /// They are only used if unsafe procedures are allowed
+    /// and the CPU supports them
+    #[must_use]
+    pub const fn get_use_avx2(&self) -> bool
+    {
+        self.use_avx2
+    }
+    /// Set whether we can use AVX2 procedures
+    /// for decoding.
+    #[must_use]
+    pub const fn set_use_avx2(mut self, choice: bool) -> ZuneJpegOptions
+    {
+        self.use_avx2 = choice;
+        self
+    }
+    /// Whether SSE 4.1 routines should be used
+    #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    pub(crate) fn use_sse41(&self) -> bool
+    {
+        self.use_unsafe && self.use_sse41 && is_x86_feature_detected!("sse4.1")
+    }
+    /// Whether AVX2 routines should be used
+    #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    pub(crate) fn use_avx2(&self) -> bool
+    {
+        self.use_unsafe && self.use_avx2 && is_x86_feature_detected!("avx2")
+    }
     /// Get number of threads to use to
     /// decode images
     ///
@@ -157,4 +216,27 @@ impl ZuneJpegOptions
         self.strict_mode = choice;
         self
     }
+    /// Get if images without restart markers are entropy decoded
+    /// speculatively in parallel
+    #[must_use]
+    pub const fn get_speculative_decoding(&self) -> bool
+    {
+        self.speculative
+    }
+    /// Set whether to entropy decode sequential images without restart markers
+    /// speculatively in parallel
+    ///
+    /// The entropy coded data is split between threads at arbitrary bytes, each
+    /// decoding from there until Huffman codes fall back in step with the data. The
+    /// results are checked against where the thread before actually ended, and parts
+    /// that didn't synchronize are decoded again serially, so the output is the same
+    /// as without it.
+    ///
+    /// Default is false, it pays off for large images and many threads.
+    #[must_use]
+    pub fn set_speculative_decoding(mut self, choice: bool) -> ZuneJpegOptions
+    {
+        self.speculative = choice;
+        self
+    }
 }
@@ -0,0 +1,336 @@
+//! Estimating the quality setting an image was encoded with
+//!
+//! Most encoders derive their quantization tables by scaling a pair of base tables
+//! with a quality factor, libjpeg (IJG) uses the example tables of ITU-T T.81 Annex K
+//! and scales them by `5000 / q` for qualities below 50 and `200 - 2q` above.
+//! Comparing the tables of an image against the scaled tables for every quality
+//! recovers the setting used, and how well the best match fits tells whether the
+//! encoder used these base tables at all.
+//!
+//! mozjpeg uses the same scaling with different base tables, and is recognised when
+//! its tables match exactly. Photoshop has a fixed pair of tables for each of its
+//! Save As levels instead of scaling, and records the level in the JPEG quality
+//! resource of its APP13 segment, it is recognised by either. Other encoders using
+//! their own tables are recognised by their tables not fitting any known family
+//! together with the segments they write. Camera firmware tables differ between
+//! models, and often between shots, so cameras with fixed tables are recognised by
+//! them and the others by the EXIF make. Quality is reported on the IJG scale for
+//! all of these.
+
+use crate::exif::camera_make;
+use crate::Decoder;
+
+/// ITU-T T.81 Table K.1, luminance quantization table, in natural order
+pub(crate) const ANNEX_K_LUMINANCE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, //
+    12, 12, 14, 19, 26, 58, 60, 55, //
+    14, 13, 16, 24, 40, 57, 69, 56, //
+    14, 17, 22, 29, 51, 87, 80, 62, //
+    18, 22, 37, 56, 68, 109, 103, 77, //
+    24, 35, 55, 64, 81, 104, 113, 92, //
+    49, 64, 78, 87, 103, 121, 120, 101, //
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// ITU-T T.81 Table K.2, chrominance quantization table, in natural order
+pub(crate) const ANNEX_K_CHROMINANCE: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, //
+    18, 21, 26, 66, 99, 99, 99, 99, //
+    24, 26, 56, 99, 99, 99, 99, 99, //
+    47, 66, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// Table used by mozjpeg's default profile for both luminance and chrominance,
+/// in natural order
+const MOZJPEG: [u16; 64] = [
+    16, 16, 16, 18, 25, 37, 56, 85, //
+    16, 17, 20, 27, 34, 40, 53, 75, //
+    16, 20, 24, 31, 43, 62, 91, 135, //
+    18, 27, 31, 40, 53, 74, 106, 156, //
+    25, 34, 43, 53, 69, 94, 131, 189, //
+    37, 40, 62, 74, 94, 124, 169, 238, //
+    56, 53, 91, 106, 131, 169, 226, 311, //
+    85, 75, 135, 156, 189, 238, 311, 418,
+];
+
+/// Photoshop's tables by Save As level, luminance then chrominance, in natural order
+///
+/// Only levels checked against files saved by Photoshop are listed, images saved
+/// at other levels are recognised by the JPEG quality resource of their APP13 segment.
+const PHOTOSHOP: [(u8, [u16; 64], [u16; 64]); 1] = [(
+    12,
+    [
+        1, 1, 1, 1, 1, 1, 1, 1, //
+        1, 1, 1, 1, 1, 1, 1, 1, //
+        1, 1, 1, 1, 1, 1, 1, 2, //
+        1, 1, 1, 1, 1, 1, 2, 2, //
+        1, 1, 1, 1, 1, 2, 2, 3, //
+        1, 1, 1, 1, 2, 2, 3, 3, //
+        1, 1, 1, 2, 2, 3, 3, 3, //
+        1, 1, 2, 2, 3, 3, 3, 3,
+    ],
+    [
+        1, 1, 1, 2, 2, 3, 3, 3, //
+        1, 1, 1, 2, 3, 3, 3, 3, //
+        1, 1, 1, 3, 3, 3, 3, 3, //
+        2, 2, 3, 3, 3, 3, 3, 3, //
+        2, 3, 3, 3, 3, 3, 3, 3, //
+        3, 3, 3, 3, 3, 3, 3, 3, //
+        3, 3, 3, 3, 3, 3, 3, 3, //
+        3, 3, 3, 3, 3, 3, 3, 3,
+    ],
+)];
+
+/// Fixed tables of camera firmware by manufacturer, luminance then chrominance,
+/// in natural order
+///
+/// Only tables checked against files from these cameras are listed.
+const CAMERAS: [(&str, [u16; 64], [u16; 64]); 1] = [(
+    // Pixel phones, HDR+ processing
+    "Google",
+    [
+        3, 3, 1, 1, 1, 3, 7, 8, //
+        4, 5, 4, 4, 4, 6, 8, 10, //
+        2, 4, 4, 4, 5, 7, 9, 10, //
+        1, 4, 4, 5, 7, 8, 9, 10, //
+        1, 3, 5, 7, 8, 9, 11, 12, //
+        3, 6, 7, 9, 11, 11, 12, 11, //
+        6, 7, 8, 9, 11, 12, 12, 11, //
+        8, 7, 7, 8, 9, 10, 11, 10,
+    ],
+    [
+        3, 3, 5, 7, 11, 11, 11, 12, //
+        3, 4, 6, 8, 11, 11, 12, 12, //
+        5, 6, 8, 11, 11, 11, 12, 12, //
+        7, 8, 11, 11, 11, 11, 12, 12, //
+        11, 11, 11, 11, 11, 12, 12, 12, //
+        11, 11, 11, 12, 12, 12, 12, 12, //
+        11, 12, 12, 12, 12, 12, 12, 12, //
+        12, 12, 12, 12, 12, 12, 12, 12,
+    ],
+)];
+
+/// The encoder an image is believed to come from
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EncoderSignature
+{
+    /// libjpeg or another encoder scaling the Annex K tables, e.g libjpeg-turbo,
+    /// GIMP or most web libraries
+    Ijg,
+    /// mozjpeg with its default tables
+    Mozjpeg,
+    /// Photoshop, with the Save As level from 0 to 12 when the tables are the
+    /// ones for that level or Photoshop's APP13 segment records it
+    ///
+    /// Without a level the tables did not match a known family, and the file
+    /// has Photoshop's APP13 segment without a JPEG quality resource. Adobe's APP14
+    /// segment alone isn't enough, other encoders write it too.
+    Photoshop(Option<u8>),
+    /// A camera, with its manufacturer
+    ///
+    /// Cameras with fixed tables are recognised by them, the others by tables
+    /// not matching a known family in a file whose EXIF data names the manufacturer.
+    /// A file from the latter whose EXIF data was stripped is [`Unknown`](Self::Unknown).
+    Camera(String),
+    /// Tables not matching a known family
+    Unknown,
+}
+
+/// Estimated quality of an image
+#[derive(Clone, Debug, PartialEq)]
+pub struct QualityEstimate
+{
+    /// Quality from 1 to 100
+    ///
+    /// This is on mozjpeg's scale for [`EncoderSignature::Mozjpeg`] and on
+    /// the IJG scale otherwise. When several qualities produce the same tables
+    /// the lowest one is reported.
+    pub quality: u8,
+    /// How close the tables are to the scaled tables for `quality`, from 0 to 1
+    ///
+    /// 1 means they are identical, lower values mean the encoder did not use the
+    /// base tables the quality is given for.
+    pub fit:     f32,
+    /// The encoder the image is believed to come from
+    pub encoder: EncoderSignature,
+}
+
+impl QualityEstimate
+{
+    /// Whether the tables are exactly the scaled tables for the estimated quality
+    #[must_use]
+    pub fn is_exact(&self) -> bool
+    {
+        (self.fit - 1.0).abs() < f32::EPSILON
+    }
+}
+
+/// Scale a base table to `quality` the way `jpeg_set_quality` does
+///
+/// Values are clamped to `limit`, 255 for baseline tables and 32767 otherwise.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn scale_table(base: &[u16; 64], quality: u32, limit: u32) -> [u16; 64]
+{
+    let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+
+    // values fit in u16 after clamping
+    base.map(|x| ((u32::from(x) * scale + 50) / 100).clamp(1, limit) as u16)
+}
+
+/// Find the quality whose scaled `bases` best fit `tables`
+///
+/// Returns the quality and the fit, one minus the sum of differences divided by
+/// the sum of the largest of each pair of values.
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn best_fit(tables: &[&[u16; 64]], bases: [&[u16; 64]; 2]) -> (u8, f32)
+{
+    let baseline = tables.iter().all(|table| table.iter().all(|&x| x <= 255));
+    let limit = if baseline { 255 } else { 32767 };
+
+    let mut best = (1, 0.0);
+
+    for quality in 1..=100
+    {
+        let mut difference = 0;
+        let mut total = 0;
+
+        for (table, base) in tables.iter().zip(bases)
+        {
+            let scaled = scale_table(base, quality, limit);
+
+            for (&x, y) in table.iter().zip(scaled)
+            {
+                difference += u32::from(x.abs_diff(y));
+                total += u32::from(x.max(y));
+            }
+        }
+        let fit = 1.0 - difference as f32 / total as f32;
+
+        // strictly better, so ties keep the lowest quality
+        if fit > best.1
+        {
+            best = (quality as u8, fit);
+        }
+    }
+    best
+}
+
+/// Read the Save As level out of the image resources of Photoshop's APP13 segment,
+/// following its `Photoshop 3.0\0` identifier
+///
+/// Resources are `8BIM`, an ID, a padded Pascal string name and a padded size and
+/// data. The JPEG quality resource, ID `0x0406`, starts with the level minus 4.
+fn photoshop_level(mut resources: &[u8]) -> Option<u8>
+{
+    while let [b'8', b'B', b'I', b'M', id_high, id_low, name_len, rest @ ..] = resources
+    {
+        // the length byte and the name are padded to an even size
+        let rest = rest.get(usize::from(*name_len) | 1..)?;
+        let size = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let data = rest.get(4..4 + size)?;
+
+        if [*id_high, *id_low] == [0x04, 0x06]
+        {
+            let level = i16::from_be_bytes(data.get(..2)?.try_into().ok()?) + 4;
+
+            return u8::try_from(level).ok().filter(|level| *level <= 12);
+        }
+        resources = rest.get(4 + size + (size & 1)..)?;
+    }
+    None
+}
+
+impl Decoder
+{
+    /// Estimate the quality the image was encoded with from its quantization tables
+    ///
+    /// The luminance table, and the chrominance table for color images, are
+    /// compared against the IJG and mozjpeg tables scaled for every quality and
+    /// against Photoshop's and cameras' tables. An exact match identifies the encoder,
+    /// otherwise Photoshop's APP13 segment and the EXIF data are used to guess where
+    /// the image comes from.
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    ///
+    /// # Examples
+    /// Never re-encode at a higher quality than the source
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let mut decoder = Decoder::new();
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// decoder.read_headers(&img_data).unwrap();
+    ///
+    /// let quality = decoder
+    ///     .estimate_quality()
+    ///     .map_or(85, |estimate| estimate.quality.min(85));
+    /// ```
+    #[must_use]
+    pub fn estimate_quality(&self) -> Option<QualityEstimate>
+    {
+        let frame = self.frame_description()?;
+
+        let tables = frame
+            .components
+            .iter()
+            .take(2)
+            .map(|component| &component.quantization_table)
+            .collect::<Vec<_>>();
+
+        let (quality, fit) = best_fit(&tables, [&ANNEX_K_LUMINANCE, &ANNEX_K_CHROMINANCE]);
+
+        let estimate = |quality, fit, encoder| {
+            Some(QualityEstimate {
+                quality,
+                fit,
+                encoder,
+            })
+        };
+
+        if (fit - 1.0).abs() < f32::EPSILON
+        {
+            return estimate(quality, fit, EncoderSignature::Ijg);
+        }
+        let (mozjpeg_quality, mozjpeg_fit) = best_fit(&tables, [&MOZJPEG, &MOZJPEG]);
+
+        if (mozjpeg_fit - 1.0).abs() < f32::EPSILON
+        {
+            return estimate(mozjpeg_quality, mozjpeg_fit, EncoderSignature::Mozjpeg);
+        }
+        let matches = |luminance: &[u16; 64], chrominance: &[u16; 64]| {
+            tables.iter().zip([luminance, chrominance]).all(|(x, y)| *x == y)
+        };
+
+        if let Some((level, ..)) = PHOTOSHOP
+            .iter()
+            .find(|(_, luminance, chrominance)| matches(luminance, chrominance))
+        {
+            return estimate(quality, fit, EncoderSignature::Photoshop(Some(*level)));
+        }
+        if let Some((make, ..)) = CAMERAS
+            .iter()
+            .find(|(_, luminance, chrominance)| matches(luminance, chrominance))
+        {
+            return estimate(quality, fit, EncoderSignature::Camera((*make).to_string()));
+        }
+
+        let encoder = if let Some(resources) = &self.photoshop_data
+        {
+            EncoderSignature::Photoshop(photoshop_level(resources))
+        }
+        else if let Some(make) = self.exif_data.as_deref().and_then(camera_make)
+        {
+            EncoderSignature::Camera(make)
+        }
+        else
+        {
+            EncoderSignature::Unknown
+        };
+
+        estimate(quality, fit, encoder)
+    }
+}
@@ -0,0 +1,285 @@
+//! Region of interest decoding
+//!
+//! Decoding a window out of an image still has to entropy decode every MCU before the
+//! window ends, as Huffman coded data can't be skipped, but the rest of the work is only
+//! done for the chunks of MCU rows and the MCU columns the window touches.
+//!
+//! Post processing is done a chunk of MCU rows at a time, the same chunks a full decode
+//! works with, so vertical up-sampling sees the same samples. Horizontal up-sampling
+//! filters a sample with its neighbours in the row, so to get the same pixels as a full
+//! decode the columns of a region are post processed with a column of margin on each
+//! side, see [`Window::new`].
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use crate::components::{Components, SubSampRatios};
+use crate::decoder::{ColorConvert16Ptr, IDCTPtr};
+use crate::errors::DecodeErrors;
+use crate::mcu::DCT_BLOCK;
+use crate::misc::ColorSpace;
+use crate::worker::post_process;
+use crate::Decoder;
+
+/// A rectangle of an image, in pixels
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Region
+{
+    pub x:      usize,
+    pub y:      usize,
+    pub width:  usize,
+    pub height: usize,
+}
+
+/// MCU columns and chunks of MCU rows post processed to decode a region
+pub(crate) struct Window
+{
+    /// The region decoded
+    region:         Region,
+    /// Columns post processed, in the order they are placed in the window
+    columns:        Vec<usize>,
+    /// Number of columns in the image
+    total_columns:  usize,
+    /// Width of a column in pixels
+    column_width:   usize,
+    /// Number of pixel rows in a chunk
+    chunk_height:   usize,
+    /// First chunk post processed
+    first_chunk:    usize,
+    /// Chunk after the last one post processed
+    end_chunk:      usize,
+    /// Components with strides of the window
+    components:     Arc<Vec<Components>>,
+    /// Number of components in the output colorspace
+    num_components: usize,
+}
+
+impl Window
+{
+    /// Create a window for `region` of an image, post processed in chunks of
+    /// `8*h_max*v_max` pixel rows
+    ///
+    /// Sampling factors and strides of components should be final.
+    pub fn new(decoder: &Decoder, region: Region) -> Window
+    {
+        let width = usize::from(decoder.width());
+        let stride = decoder.components[0].width_stride;
+
+        let mut total_columns = width.div_ceil(8 * decoder.h_max);
+
+        // a row of a component which doesn't split into whole blocks per column is
+        // post processed as a whole
+        if decoder
+            .components
+            .iter()
+            .any(|c| (c.width_stride / 8) % total_columns != 0)
+        {
+            total_columns = 1;
+        }
+        let column_width = stride / total_columns;
+        let chunk_height = 8 * decoder.h_max * decoder.v_max;
+
+        let first = region.x / column_width;
+        let end = (region.x + region.width).div_ceil(column_width);
+
+        let mut columns = Vec::with_capacity(total_columns);
+
+        match decoder.sub_sample_ratio
+        {
+            // nothing is filtered across columns
+            SubSampRatios::None | SubSampRatios::V => columns.extend(first..end),
+            // up-sampling filters each sample with its neighbours in the row, one
+            // column of margin on each side gives the columns of the region the
+            // same neighbours they have in a full decode
+            SubSampRatios::H | SubSampRatios::HV =>
+            {
+                columns.extend(first.saturating_sub(1)..(end + 1).min(total_columns));
+            }
+        }
+
+        let components = decoder
+            .components
+            .iter()
+            .map(|c| {
+                let mut component = c.clone();
+
+                component.width_stride = c.width_stride / total_columns * columns.len();
+                component
+            })
+            .collect();
+
+        Window {
+            region,
+            columns,
+            total_columns,
+            column_width,
+            chunk_height,
+            first_chunk: region.y / chunk_height,
+            end_chunk: (region.y + region.height).div_ceil(chunk_height),
+            components: Arc::new(components),
+            num_components: decoder.options.get_out_colorspace().num_components(),
+        }
+    }
+
+    /// First chunk with rows of the region
+    pub fn first_chunk(&self) -> usize
+    {
+        self.first_chunk
+    }
+
+    /// Whether chunk `chunk` has rows of the region
+    pub fn needs(&self, chunk: usize) -> bool
+    {
+        (self.first_chunk..self.end_chunk).contains(&chunk)
+    }
+
+    /// Whether all chunks with rows of the region come before chunk `chunk`
+    pub fn finished(&self, chunk: usize) -> bool
+    {
+        chunk >= self.end_chunk
+    }
+
+    /// Width of the window in pixels
+    fn width(&self) -> usize
+    {
+        self.columns.len() * self.column_width
+    }
+
+    /// Size of the output of a chunk
+    pub fn chunk_len(&self) -> usize
+    {
+        self.width() * self.chunk_height * self.num_components
+    }
+
+    /// Size of the output of all chunks post processed
+    pub fn buffer_len(&self) -> usize
+    {
+        self.chunk_len() * (self.end_chunk - self.first_chunk)
+    }
+
+    /// Dequantize, IDCT, up-sample and color convert the columns of the window
+    /// out of a chunk of coefficients, as laid out for [`post_process`]
+    #[allow(clippy::too_many_arguments)]
+    pub fn post_process(
+        &self, coeff: &[&[i16]; 3], idct_func: IDCTPtr, color_convert_16: ColorConvert16Ptr,
+        input_colorspace: ColorSpace, output_colorspace: ColorSpace, output: &mut [u8],
+    )
+    {
+        let mut window_coeff = [vec![], vec![], vec![]];
+
+        for ((coefficients, component), window) in coeff
+            .iter()
+            .zip(self.components.iter())
+            .zip(window_coeff.iter_mut())
+        {
+            if coefficients.is_empty()
+            {
+                continue;
+            }
+            // coefficients are rows of blocks, as wide as the image
+            let column_len = component.width_stride / self.columns.len() / 8 * DCT_BLOCK;
+            let row_len = column_len * self.total_columns;
+
+            window.reserve(coefficients.len() / self.total_columns * self.columns.len());
+
+            for row in coefficients.chunks_exact(row_len)
+            {
+                for column in &self.columns
+                {
+                    window.extend_from_slice(&row[column * column_len..(column + 1) * column_len]);
+                }
+            }
+        }
+        post_process(
+            &[&window_coeff[0], &window_coeff[1], &window_coeff[2]],
+            &self.components,
+            idct_func,
+            color_convert_16,
+            input_colorspace,
+            output_colorspace,
+            output,
+            self.width(),
+        );
+    }
+
+    /// Copy the region out of the output of the chunks post processed
+    pub fn crop(&self, buffer: &[u8]) -> Vec<u8>
+    {
+        let region = self.region;
+        let first = region.x / self.column_width;
+        // the window may start with a column of margin
+        let position = self.columns.iter().position(|c| *c == first).unwrap();
+        let x = position * self.column_width + region.x - first * self.column_width;
+        let y = region.y - self.first_chunk * self.chunk_height;
+
+        let stride = self.width() * self.num_components;
+        let row_len = region.width * self.num_components;
+
+        let mut pixels = Vec::with_capacity(row_len * region.height);
+
+        for row in buffer.chunks_exact(stride).skip(y).take(region.height)
+        {
+            let start = x * self.num_components;
+
+            pixels.extend_from_slice(&row[start..start + row_len]);
+        }
+        pixels
+    }
+}
+
+impl Decoder
+{
+    /// Decode the `width` by `height` pixels of an image starting at `x`,`y`
+    ///
+    /// The output is in the output colorspace and the same as cropping a full decode
+    /// of the image, but only the MCUs touching the region are dequantized,
+    /// transformed, up-sampled and color converted, and sequential images stop
+    /// decoding after the last row of MCUs the region needs.
+    ///
+    /// # Errors
+    /// If the region is empty or doesn't fit in the image, or the image is corrupt,
+    /// see DecodeErrors enum for list of possible errors
+    ///
+    /// # Examples
+    /// Decode a 256 by 256 tile
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// let mut decoder = Decoder::new();
+    ///
+    /// let pixels = decoder.decode_region(&img_data, 512, 256, 256, 256).unwrap();
+    /// ```
+    pub fn decode_region(
+        &mut self, buf: &[u8], x: usize, y: usize, width: usize, height: usize,
+    ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let mut reader = Cursor::new(buf.to_vec());
+
+        self.decode_headers_internal(&mut reader)?;
+
+        let (image_width, image_height) = (usize::from(self.width()), usize::from(self.height()));
+
+        if width == 0
+            || height == 0
+            || x.saturating_add(width) > image_width
+            || y.saturating_add(height) > image_height
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Region {width}x{height} at {x},{y} is empty or outside the {image_width}x{image_height} image"
+            )));
+        }
+        self.region = Some(Region { x, y, width, height });
+
+        let pixels = if self.is_progressive
+        {
+            self.decode_mcu_ycbcr_progressive(&mut reader)
+        }
+        else
+        {
+            self.decode_mcu_ycbcr_baseline(&mut reader, 8)
+        };
+        self.region = None;
+
+        pixels
+    }
+}
@@ -0,0 +1,321 @@
+//! Requantization of DCT coefficients to a lower quality
+//!
+//! Decoding an image to pixels and encoding it again at a lower quality rounds twice,
+//! once to pixels and once to the new quantization steps, and the encoder sees the
+//! artifacts of the source as detail worth keeping. Requantizing maps each quantized
+//! coefficient straight to the coarser step of a new table, the only loss is that of
+//! the coarser step itself.
+//!
+//! # Rounding
+//! By default coefficients are rounded to the nearest step. Trellis quantization
+//! instead picks per block which AC coefficients to round down or drop, minimising
+//! the squared error plus `lambda` times the bits the block codes to, in units of the
+//! new step size. Bits are estimated from the symbols of the image rounded to the
+//! nearest step, larger `lambda` trades more error for smaller files, see
+//! [`TRELLIS_LAMBDA`].
+//!
+//! New tables are never finer than the tables of the image, a step of the new table
+//! smaller than the step it replaces keeps the old step, as finer steps can't bring
+//! back detail lost already.
+//!
+//! [`Transcoder`] writes the result back to a JPEG file.
+//!
+//! # Examples
+//! Requantize an image to IJG quality 60
+//! ```no_run
+//! use zune_jpeg::requantize::quality_tables;
+//! use zune_jpeg::Decoder;
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//! let image = Decoder::new().decode_coefficients(&img_data).unwrap();
+//!
+//! let smaller = image.requantize(&quality_tables(60), None).unwrap();
+//! ```
+//!
+//! [`Transcoder`]: crate::transcode::Transcoder
+
+use crate::coefficients::Coefficients;
+use crate::errors::DecodeErrors;
+use crate::huffman_encoder::{category, encode_block, SymbolCounter};
+use crate::mcu::DCT_BLOCK;
+use crate::misc::UN_ZIGZAG;
+use crate::quality::{scale_table, ANNEX_K_CHROMINANCE, ANNEX_K_LUMINANCE};
+use crate::ColorSpace;
+
+/// A mild `lambda` for trellis quantization, at the same file size it is usually
+/// closer to the source than rounding to nearest with coarser tables
+pub const TRELLIS_LAMBDA: f32 = 0.05;
+
+/// Bits a symbol that doesn't occur when rounding to the nearest step is assumed
+/// to cost
+const MISSING_SYMBOL_BITS: f32 = 16.0;
+
+/// The luminance and chrominance tables libjpeg uses for `quality`, from 1 to 100,
+/// in natural order
+///
+/// Tables are limited to baseline, values larger than 255 are clamped.
+#[must_use]
+pub fn quality_tables(quality: u8) -> [[u16; 64]; 2]
+{
+    let quality = u32::from(quality.clamp(1, 100));
+
+    [
+        scale_table(&ANNEX_K_LUMINANCE, quality, 255),
+        scale_table(&ANNEX_K_CHROMINANCE, quality, 255),
+    ]
+}
+
+impl Coefficients
+{
+    /// Requantize the image with `tables`, a luminance and a chrominance table in
+    /// natural order
+    ///
+    /// The chrominance table is used for the second and third components of YCbCr and
+    /// YCCK images, the luminance table for all other components. Coefficients are
+    /// rounded to the nearest step, or with trellis quantization if `lambda` is given,
+    /// see the [module docs](crate::requantize).
+    ///
+    /// # Errors
+    /// If a table holds a zero
+    #[allow(clippy::cast_precision_loss)]
+    pub fn requantize(
+        &self, tables: &[[u16; 64]; 2], lambda: Option<f32>,
+    ) -> Result<Coefficients, DecodeErrors>
+    {
+        if tables.iter().any(|table| table.contains(&0))
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Quantization table values must be larger than zero",
+            ));
+        }
+        let chrominance = |pos: usize| {
+            matches!(self.colorspace, ColorSpace::YCbCr | ColorSpace::YCCK)
+                && (pos == 1 || pos == 2)
+        };
+        let mut image = self.clone();
+
+        for (pos, component) in image.components.iter_mut().enumerate()
+        {
+            let table = &tables[usize::from(chrominance(pos))];
+
+            for (old, new) in component.quantization_table.iter_mut().zip(table)
+            {
+                *old = (*old).max(*new);
+            }
+            let steps = &component.quantization_table;
+            let source = &self.components[pos].quantization_table;
+
+            for block in component.coefficients.chunks_exact_mut(DCT_BLOCK)
+            {
+                for (k, value) in block.iter_mut().enumerate()
+                {
+                    *value = round_to_step(*value, source[k], steps[k]);
+                }
+            }
+        }
+        let Some(lambda) = lambda
+        else
+        {
+            return Ok(image);
+        };
+        // bits of each AC symbol of luminance and chrominance, from rounding to nearest
+        let mut counter = SymbolCounter::new(2);
+
+        for (pos, component) in image.components.iter().enumerate()
+        {
+            let mut dc_pred = 0;
+
+            for block in component.coefficients.chunks_exact(DCT_BLOCK)
+            {
+                encode_block(
+                    &mut counter,
+                    usize::from(chrominance(pos)),
+                    block.try_into().unwrap(),
+                    &mut dc_pred,
+                );
+            }
+        }
+        let rates = counter.ac.iter().map(symbol_bits).collect::<Vec<[f32; 256]>>();
+
+        for (pos, component) in image.components.iter_mut().enumerate()
+        {
+            let rates = &rates[usize::from(chrominance(pos))];
+            let steps = &component.quantization_table;
+            let source = &self.components[pos];
+
+            for (block, original) in component
+                .coefficients
+                .chunks_exact_mut(DCT_BLOCK)
+                .zip(source.coefficients.chunks_exact(DCT_BLOCK))
+            {
+                let mut values = [0.0; 64];
+
+                for (k, value) in values.iter_mut().enumerate()
+                {
+                    let position = UN_ZIGZAG[k];
+                    let scaled = i32::from(original[position])
+                        * i32::from(source.quantization_table[position]);
+
+                    *value = scaled as f32 / f32::from(steps[position]);
+                }
+                let rounded = trellis(&values, rates, lambda);
+
+                for k in 1..64
+                {
+                    block[UN_ZIGZAG[k]] = rounded[k];
+                }
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+/// Round `value`, quantized with step `source`, to the nearest multiple of `step`,
+/// halfway cases away from zero
+#[allow(clippy::cast_possible_truncation)]
+fn round_to_step(value: i16, source: u16, step: u16) -> i16
+{
+    let scaled = i32::from(value) * i32::from(source);
+    let step = i32::from(step);
+    let rounded = (scaled.abs() + step / 2) / step;
+
+    // step is at least source, so the magnitude doesn't grow
+    (rounded * scaled.signum()) as i16
+}
+
+/// Estimated bits of the code of each symbol with `frequencies`, the information
+/// content of the symbol
+#[allow(clippy::cast_precision_loss)]
+fn symbol_bits(frequencies: &[u32; 256]) -> [f32; 256]
+{
+    let total = frequencies.iter().map(|&x| u64::from(x)).sum::<u64>() as f32;
+
+    frequencies.map(|x| {
+        if x == 0
+        {
+            MISSING_SYMBOL_BITS
+        }
+        else
+        {
+            (total / x as f32).log2().clamp(1.0, MISSING_SYMBOL_BITS)
+        }
+    })
+}
+
+/// Round the AC coefficients of a block, `values` in zigzag order and units of the
+/// new step, minimising the squared error plus `lambda` times the bits they code to
+///
+/// Each coefficient is rounded to the nearest step, one step towards zero, or zero.
+/// `best[k]` is the lowest cost of coding coefficients up to `k`, with the one at `k`
+/// the last that isn't zero, position 0 standing in for the start of the block.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn trellis(values: &[f32; 64], rates: &[f32; 256], lambda: f32) -> [i16; 64]
+{
+    // squared error of dropping all coefficients from 1 up to k
+    let mut dropped = [0.0_f32; 64];
+
+    for k in 1..64
+    {
+        dropped[k] = dropped[k - 1] + values[k] * values[k];
+    }
+    let mut best = [f32::INFINITY; 64];
+    // value at k and the previous position that isn't zero
+    let mut chosen = [(0_i16, 0_usize); 64];
+
+    best[0] = 0.0;
+
+    for k in 1..64
+    {
+        let nearest = values[k].abs().round();
+
+        for magnitude in [nearest, nearest - 1.0]
+        {
+            if magnitude < 1.0
+            {
+                continue;
+            }
+            let size = category(magnitude as i32);
+            let error = (values[k].abs() - magnitude) * (values[k].abs() - magnitude);
+
+            for previous in 0..k
+            {
+                if best[previous].is_infinite()
+                {
+                    continue;
+                }
+                let run = k - previous - 1;
+                let bits = (run / 16) as f32 * rates[0xF0]
+                    + rates[((run % 16) << 4) | usize::from(size)]
+                    + f32::from(size);
+                let cost = best[previous]
+                    + dropped[k - 1]
+                    - dropped[previous]
+                    + error
+                    + lambda * bits;
+
+                if cost < best[k]
+                {
+                    best[k] = cost;
+                    chosen[k] = ((magnitude as i16) * values[k].signum() as i16, previous);
+                }
+            }
+        }
+    }
+    // end of block after the last coefficient that isn't zero
+    let mut last = 0;
+    let mut total = f32::INFINITY;
+
+    for (k, &cost) in best.iter().enumerate()
+    {
+        let end_of_block = if k < 63 { lambda * rates[0x00] } else { 0.0 };
+        let cost = cost + dropped[63] - dropped[k] + end_of_block;
+
+        if cost < total
+        {
+            total = cost;
+            last = k;
+        }
+    }
+    let mut rounded = [0; 64];
+
+    while last > 0
+    {
+        rounded[last] = chosen[last].0;
+        last = chosen[last].1;
+    }
+    rounded
+}
+
+//---------------------------------------------
+// TEST
+//----------------------------------------------
+#[test]
+fn requantize_rounding()
+{
+    assert_eq!(round_to_step(3, 10, 20), 2);
+    assert_eq!(round_to_step(-3, 10, 20), -2);
+    assert_eq!(round_to_step(2, 10, 30), 1);
+    assert_eq!(round_to_step(1, 10, 30), 0);
+    assert_eq!(round_to_step(5, 7, 7), 5);
+}
+
+#[test]
+fn trellis_lambda_zero_rounds_to_nearest()
+{
+    let mut values = [0.0; 64];
+
+    values[1] = 2.6;
+    values[5] = -0.7;
+    values[20] = 1.2;
+
+    let rounded = trellis(&values, &[4.0; 256], 0.0);
+
+    assert_eq!(rounded[1], 3);
+    assert_eq!(rounded[5], -1);
+    assert_eq!(rounded[20], 1);
+    assert_eq!(rounded.iter().filter(|&&x| x != 0).count(), 3);
+    // an isolated small coefficient far down the block is not worth its bits
+    let rounded = trellis(&values, &[4.0; 256], 1.0);
+
+    assert_eq!(rounded[20], 0);
+}
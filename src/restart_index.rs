@@ -0,0 +1,392 @@
+//! Restart marker indexes for random access decoding
+//!
+//! An image with a restart interval resets its entropy coder, DC predictions
+//! included, at every RST marker, so decoding can start at any of them. A
+//! [`RestartIndex`] records, for every row of MCUs, where the restart interval holding
+//! the first MCU of the row starts in the file, so that
+//! [`decode_region_with_index`](crate::Decoder::decode_region_with_index) can skip
+//! everything above a region instead of entropy decoding it.
+//!
+//! Indexes are built in one pass over the entropy coded data of a file, and can be
+//! stored next to it with [`to_bytes`](RestartIndex::to_bytes).
+//!
+//! # Format
+//! A stored index starts with [`MAGIC`], a version byte and the MD5 digest of the
+//! headers of the file, the bytes before its entropy coded data. Then come, as LEB128
+//! numbers, the length of the file, the restart interval, the number of MCUs in a row,
+//! the number of rows and the offset of each row's restart interval from the one of
+//! the row above, the first from the start of the file.
+//!
+//! # Examples
+//! ```no_run
+//! use zune_jpeg::restart_index::RestartIndex;
+//! use zune_jpeg::Decoder;
+//!
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//! let index = RestartIndex::new(&img_data).unwrap();
+//!
+//! std::fs::write("a_valid.jpeg.rst", index.to_bytes()).unwrap();
+//!
+//! let index = RestartIndex::from_bytes(&std::fs::read("a_valid.jpeg.rst").unwrap()).unwrap();
+//! let pixels = Decoder::new()
+//!     .decode_region_with_index(&img_data, &index, 0, 1024, 256, 256)
+//!     .unwrap();
+//! ```
+
+use std::io::Cursor;
+
+use crate::errors::DecodeErrors;
+use crate::md5::md5;
+use crate::Decoder;
+
+/// First bytes of a stored index
+pub const MAGIC: &[u8; 4] = b"ZJRI";
+
+/// Version of the format of stored indexes
+const VERSION: u8 = 1;
+
+/// Where decoding can start for each row of MCUs of an image, see the
+/// [module docs](self)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RestartIndex
+{
+    /// MD5 digest of the bytes before the entropy coded data
+    header_digest:    [u8; 16],
+    /// Length of the file
+    file_len:         usize,
+    /// Number of MCUs in a restart interval
+    restart_interval: usize,
+    /// Number of MCUs in a row
+    mcus_per_row:     usize,
+    /// For each row, the offset of the restart interval holding its first MCU
+    offsets:          Vec<usize>,
+}
+
+impl RestartIndex
+{
+    /// Build the index of the file in `buf`
+    ///
+    /// # Errors
+    /// If the image is not a sequential Huffman coded image with a restart interval
+    /// and all components in its first scan, or a restart marker is missing
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(buf: &[u8]) -> Result<RestartIndex, DecodeErrors>
+    {
+        let mut decoder = Decoder::new();
+        let mut reader = Cursor::new(buf);
+
+        decoder.decode_headers_internal(&mut reader)?;
+
+        if decoder.is_progressive || decoder.is_arithmetic
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Restart indexes need a sequential Huffman coded image",
+            ));
+        }
+        if usize::from(decoder.num_scans) != decoder.components.len()
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Restart indexes need all components in the first scan",
+            ));
+        }
+        if decoder.restart_interval == 0
+        {
+            return Err(DecodeErrors::FormatStatic("Image has no restart interval"));
+        }
+        let scan_start = reader.position() as usize;
+        let (mcus_per_row, rows) = decoder.restart_mcu_rows();
+
+        let intervals = restart_intervals(buf, scan_start);
+
+        let offsets = (0..rows)
+            .map(|row| {
+                intervals
+                    .get(row * mcus_per_row / decoder.restart_interval)
+                    .copied()
+                    .ok_or_else(|| {
+                        DecodeErrors::Format(format!("Restart marker for MCU row {row} missing"))
+                    })
+            })
+            .collect::<Result<Vec<usize>, DecodeErrors>>()?;
+
+        Ok(RestartIndex {
+            header_digest: md5(&buf[..scan_start]),
+            file_len: buf.len(),
+            restart_interval: decoder.restart_interval,
+            mcus_per_row,
+            offsets,
+        })
+    }
+
+    /// Number of rows of MCUs in the image
+    #[must_use]
+    pub fn rows(&self) -> usize
+    {
+        self.offsets.len()
+    }
+
+    /// Number of MCUs in a restart interval
+    #[must_use]
+    pub fn restart_interval(&self) -> usize
+    {
+        self.restart_interval
+    }
+
+    /// Offset in the file of the restart interval holding the first MCU of `row`, and
+    /// the position of the first MCU of that interval in the image, in raster order
+    #[must_use]
+    pub fn row_start(&self, row: usize) -> Option<(usize, usize)>
+    {
+        let offset = *self.offsets.get(row)?;
+        let mcu = row.checked_mul(self.mcus_per_row)? / self.restart_interval
+            * self.restart_interval;
+
+        Some((offset, mcu))
+    }
+
+    /// Check that the index belongs to the file in `buf`, whose entropy coded data
+    /// starts at `scan_start` and whose headers `decoder` holds
+    pub(crate) fn check(
+        &self, decoder: &Decoder, buf: &[u8], scan_start: usize,
+    ) -> Result<(), DecodeErrors>
+    {
+        if buf.len() != self.file_len || md5(&buf[..scan_start]) != self.header_digest
+        {
+            return Err(DecodeErrors::FormatStatic("Restart index is for another file"));
+        }
+        // the digest matches the headers, the rest of a corrupt or forged index may not
+        let (mcus_per_row, rows) = decoder.restart_mcu_rows();
+        // rows in the same restart interval share its offset
+        let ordered = self.offsets.windows(2).all(|x| x[0] <= x[1]);
+        let in_scan = self
+            .offsets
+            .first()
+            .zip(self.offsets.last())
+            .is_some_and(|(&first, &last)| first == scan_start && last < self.file_len);
+
+        if self.restart_interval != decoder.restart_interval
+            || self.mcus_per_row != mcus_per_row
+            || self.offsets.len() != rows
+            || !ordered
+            || !in_scan
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Restart index doesn't match the image, corrupt index",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Store the index, see the [module docs](self) for the format
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut output = MAGIC.to_vec();
+
+        output.push(VERSION);
+        output.extend_from_slice(&self.header_digest);
+
+        let mut previous = 0;
+
+        for value in [
+            self.file_len,
+            self.restart_interval,
+            self.mcus_per_row,
+            self.offsets.len(),
+        ]
+        .into_iter()
+        .chain(self.offsets.iter().map(|offset| {
+            let delta = offset - previous;
+
+            previous = *offset;
+            delta
+        }))
+        {
+            write_leb128(&mut output, value);
+        }
+        output
+    }
+
+    /// Read an index stored by [`to_bytes`](Self::to_bytes)
+    ///
+    /// # Errors
+    /// If `buf` is not a stored index, or it is truncated
+    pub fn from_bytes(buf: &[u8]) -> Result<RestartIndex, DecodeErrors>
+    {
+        let header = MAGIC.len() + 17;
+
+        if buf.len() < header || !buf.starts_with(MAGIC)
+        {
+            return Err(DecodeErrors::FormatStatic("Not a restart index"));
+        }
+        if buf[MAGIC.len()] != VERSION
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Unknown restart index version {}",
+                buf[MAGIC.len()]
+            )));
+        }
+        let header_digest = buf[MAGIC.len() + 1..header].try_into().unwrap();
+        let mut position = header;
+        let mut read = || read_leb128(buf, &mut position);
+
+        let file_len = read()?;
+        let restart_interval = read()?;
+        let mcus_per_row = read()?;
+        let rows = read()?;
+
+        if restart_interval == 0 || rows > buf.len()
+        {
+            return Err(DecodeErrors::FormatStatic("Corrupt restart index"));
+        }
+        let mut offsets = Vec::with_capacity(rows);
+        let mut previous = 0_usize;
+
+        for _ in 0..rows
+        {
+            previous = previous
+                .checked_add(read()?)
+                .ok_or(DecodeErrors::FormatStatic("Corrupt restart index"))?;
+            offsets.push(previous);
+        }
+        Ok(RestartIndex {
+            header_digest,
+            file_len,
+            restart_interval,
+            mcus_per_row,
+            offsets,
+        })
+    }
+}
+
+impl Decoder
+{
+    /// Number of MCUs in a row and number of rows of the first scan, as restart
+    /// intervals count them
+    ///
+    /// Headers should already be decoded.
+    pub(crate) fn restart_mcu_rows(&self) -> (usize, usize)
+    {
+        if self.components.len() == 1
+        {
+            // a single component scan is never interleaved, its MCUs are blocks
+            (
+                usize::from(self.width()).div_ceil(8),
+                usize::from(self.height()).div_ceil(8),
+            )
+        }
+        else
+        {
+            (self.mcu_x, self.mcu_y)
+        }
+    }
+
+    /// Decode a region of an image like [`decode_region`](Self::decode_region), starting
+    /// to entropy decode at the restart interval holding the region's first row
+    ///
+    /// `index` should be built from the same file, see
+    /// [`RestartIndex`](crate::restart_index::RestartIndex). Progressive images are
+    /// decoded like `decode_region` does.
+    ///
+    /// # Errors
+    /// If the index is for another file, the region is empty or doesn't fit in the
+    /// image, or the image is corrupt, see DecodeErrors enum for list of possible errors
+    pub fn decode_region_with_index(
+        &mut self, buf: &[u8], index: &RestartIndex, x: usize, y: usize, width: usize,
+        height: usize,
+    ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        self.restart_index = Some(index.clone());
+
+        let pixels = self.decode_region(buf, x, y, width, height);
+
+        self.restart_index = None;
+
+        pixels
+    }
+
+    /// Position `reader` at the restart interval holding MCU row `row`
+    ///
+    /// Returns the index of the first MCU of the interval, in raster order.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn seek_restart_interval(
+        &mut self, reader: &mut Cursor<Vec<u8>>, row: usize,
+    ) -> Result<usize, DecodeErrors>
+    {
+        let index = self.restart_index.as_ref().unwrap();
+
+        index.check(self, reader.get_ref(), reader.position() as usize)?;
+
+        let (offset, mcu) = index
+            .row_start(row)
+            .ok_or_else(|| DecodeErrors::Format(format!("No MCU row {row} in restart index")))?;
+
+        reader.set_position(offset as u64);
+
+        // the state of the decoder after a restart marker
+        self.todo = self.restart_interval;
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+
+        Ok(mcu)
+    }
+}
+
+/// Offsets of the restart intervals of the scan whose entropy coded data starts at
+/// `scan_start`, the first being `scan_start`, the others right after each RST marker
+pub(crate) fn restart_intervals(buf: &[u8], scan_start: usize) -> Vec<usize>
+{
+    let mut intervals = vec![scan_start];
+    let mut position = scan_start;
+
+    while let Some(found) = buf[position..].iter().position(|x| *x == 0xFF)
+    {
+        position += found + 1;
+
+        match buf.get(position)
+        {
+            // fill byte, the marker follows
+            Some(0xFF) => continue,
+            Some(0xD0..=0xD7) => intervals.push(position + 1),
+            // stuffed zero byte
+            Some(0x00) => (),
+            // the scan ends with any other marker
+            _ => break,
+        }
+        position += 1;
+    }
+    intervals
+}
+
+/// Append `value` to `output` as an unsigned LEB128 number
+#[allow(clippy::cast_possible_truncation)]
+fn write_leb128(output: &mut Vec<u8>, mut value: usize)
+{
+    while value >= 0x80
+    {
+        output.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+/// Read an unsigned LEB128 number at `position` of `buf`, advancing `position`
+fn read_leb128(buf: &[u8], position: &mut usize) -> Result<usize, DecodeErrors>
+{
+    let mut value = 0_usize;
+
+    for shift in (0..usize::BITS).step_by(7)
+    {
+        let byte = *buf
+            .get(*position)
+            .ok_or(DecodeErrors::FormatStatic("Truncated restart index"))?;
+
+        *position += 1;
+        value |= usize::from(byte & 0x7F) << shift;
+
+        if byte & 0x80 == 0
+        {
+            return Ok(value);
+        }
+    }
+    Err(DecodeErrors::FormatStatic("Corrupt restart index"))
+}
@@ -0,0 +1,504 @@
+//! Lossless metadata rewriting
+//!
+//! [`MetadataRewriter`] copies a JPEG file segment by segment, dropping, replacing
+//! or inserting APPn and COM segments while copying frame headers, tables and entropy
+//! coded data byte for byte, so pixels are untouched and no generation loss occurs.
+//!
+//! Metadata that doesn't fit in a single segment is split across several
+//!
+//! - ICC profiles are stored in APP2 `ICC_PROFILE\0` chunks numbered from 1, see
+//!   ICC.1:2010 Annex B.4.
+//! - XMP larger than a segment is split into a standard packet and an extended packet,
+//!   the extended packet is stored in APP1 `http://ns.adobe.com/xmp/extension/\0`
+//!   chunks identified by the MD5 digest of the packet, which the standard packet
+//!   references through `xmpNote:HasExtendedXMP`, see XMP Specification Part 3 1.1.3.1.
+//!
+//! Replacing or removing either drops all chunks of the old one.
+//!
+//! If the file is a Multi-Picture file the MP entries are updated, so secondary
+//! images following the primary image can still be found.
+//!
+//! # Examples
+//! Remove location and comments before sharing an image
+//! ```no_run
+//! use zune_jpeg::rewrite::MetadataRewriter;
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//!
+//! let stripped = MetadataRewriter::new()
+//!     .remove_gps()
+//!     .remove_xmp()
+//!     .remove_comments()
+//!     .rewrite(&img_data)
+//!     .unwrap();
+//! ```
+
+use crate::errors::DecodeErrors;
+use crate::exif;
+use crate::marker::Marker;
+use crate::md5::md5_hex;
+use crate::mpf::relocate_mp_entries;
+use crate::segments::{Segment, Segments};
+use crate::xmp::property_value;
+
+/// Identifier of the APP1 EXIF segment
+const EXIF_IDENTIFIER: &[u8] = b"Exif\0\0";
+
+/// Identifier of the APP1 standard XMP segment
+const XMP_IDENTIFIER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Identifier of the APP1 extended XMP segments
+const EXTENDED_XMP_IDENTIFIER: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+/// Identifier of the APP2 ICC profile segments
+const ICC_IDENTIFIER: &[u8] = b"ICC_PROFILE\0";
+
+/// Identifier of the APP2 MPF segment
+pub(crate) const MPF_IDENTIFIER: &[u8] = b"MPF\0";
+
+/// Largest segment payload, the length field counts itself
+const MAX_PAYLOAD: usize = 65533;
+
+/// Largest ICC profile chunk, leaving room for the identifier, sequence number
+/// and chunk count
+const ICC_CHUNK: usize = MAX_PAYLOAD - ICC_IDENTIFIER.len() - 2;
+
+/// Extended XMP chunk size, as written by the XMP toolkit
+const EXTENDED_XMP_CHUNK: usize = 65400;
+
+/// Namespace declaration and property added to the standard XMP packet to reference
+/// the extended packet, `{}` is the GUID
+const HAS_EXTENDED_XMP: &str =
+    " xmlns:xmpNote=\"http://ns.adobe.com/xmp/note/\" xmpNote:HasExtendedXMP=\"{}\"";
+
+/// What happens to a segment of the source file
+enum Action
+{
+    Keep,
+    Drop,
+    Replace(Vec<u8>),
+}
+
+/// Options for rewriting the metadata of a JPEG file without recompressing it
+///
+/// Only APPn and COM segments are ever dropped or inserted. Inserted segments are
+/// placed after the start of image and APP0 (JFIF) segments, before any other
+/// segment of the source file, in this order
+///
+/// 1. A new EXIF segment, if an orientation is set and the file has no EXIF
+/// 2. XMP, followed by extended XMP
+/// 3. ICC profile chunks
+/// 4. Segments added with [`insert_segment`](Self::insert_segment), in the order
+///    they were added
+#[derive(Clone, Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct MetadataRewriter
+{
+    removed:     Vec<(Marker, Vec<u8>)>,
+    inserted:    Vec<(Marker, Vec<u8>)>,
+    remove_exif: bool,
+    remove_gps:  bool,
+    orientation: Option<u16>,
+    remove_icc:  bool,
+    icc_profile: Option<Vec<u8>>,
+    remove_xmp:  bool,
+    xmp:         Option<(String, Option<String>)>,
+}
+
+impl MetadataRewriter
+{
+    /// Create a rewriter which copies files unchanged
+    #[must_use]
+    pub fn new() -> MetadataRewriter
+    {
+        Self::default()
+    }
+
+    /// Remove all segments with `marker`, which must be an APPn or COM marker
+    #[must_use]
+    pub fn remove_segments(self, marker: Marker) -> MetadataRewriter
+    {
+        self.remove_segments_with_identifier(marker, &[])
+    }
+
+    /// Remove segments with `marker` whose contents start with `identifier`,
+    /// e.g `APP(13)` and `Photoshop 3.0\0`
+    #[must_use]
+    pub fn remove_segments_with_identifier(
+        mut self, marker: Marker, identifier: &[u8],
+    ) -> MetadataRewriter
+    {
+        self.removed.push((marker, identifier.to_vec()));
+        self
+    }
+
+    /// Insert a segment with `marker`, which must be an APPn or COM marker
+    ///
+    /// `payload` is the segment contents after the length field, including any
+    /// identifier.
+    #[must_use]
+    pub fn insert_segment(mut self, marker: Marker, payload: Vec<u8>) -> MetadataRewriter
+    {
+        self.inserted.push((marker, payload));
+        self
+    }
+
+    /// Remove all comments
+    #[must_use]
+    pub fn remove_comments(self) -> MetadataRewriter
+    {
+        self.remove_segments(Marker::COM)
+    }
+
+    /// Remove EXIF, including the thumbnail and GPS information
+    #[must_use]
+    pub fn remove_exif(mut self) -> MetadataRewriter
+    {
+        self.remove_exif = true;
+        self
+    }
+
+    /// Remove GPS information from EXIF, keeping everything else
+    ///
+    /// The GPS IFD and its values are overwritten with zeroes, nothing else in
+    /// the EXIF data moves.
+    #[must_use]
+    pub fn remove_gps(mut self) -> MetadataRewriter
+    {
+        self.remove_gps = true;
+        self
+    }
+
+    /// Set the EXIF orientation, from 1 to 8
+    ///
+    /// An EXIF segment is created if the file has none. Other values make
+    /// [`rewrite`](Self::rewrite) fail.
+    #[must_use]
+    pub fn set_orientation(mut self, orientation: u16) -> MetadataRewriter
+    {
+        self.orientation = Some(orientation);
+        self
+    }
+
+    /// Remove the ICC profile, all of its chunks
+    #[must_use]
+    pub fn remove_icc_profile(mut self) -> MetadataRewriter
+    {
+        self.remove_icc = true;
+        self.icc_profile = None;
+        self
+    }
+
+    /// Replace the ICC profile, it is split into chunks if needed
+    #[must_use]
+    pub fn set_icc_profile(mut self, profile: Vec<u8>) -> MetadataRewriter
+    {
+        self.remove_icc = true;
+        self.icc_profile = Some(profile);
+        self
+    }
+
+    /// Remove standard and extended XMP
+    #[must_use]
+    pub fn remove_xmp(mut self) -> MetadataRewriter
+    {
+        self.remove_xmp = true;
+        self.xmp = None;
+        self
+    }
+
+    /// Replace XMP with `xmp` and optionally an extended packet
+    ///
+    /// If `extended` is given, `xmpNote:HasExtendedXMP` of the standard packet is set
+    /// to its GUID, or added to the first `rdf:Description` if it is missing.
+    ///
+    /// The standard packet must fit in a single segment, that is about 64KB, splitting
+    /// properties between both packets is up to the caller.
+    #[must_use]
+    pub fn set_xmp(mut self, xmp: String, extended: Option<String>) -> MetadataRewriter
+    {
+        self.remove_xmp = true;
+        self.xmp = Some((xmp, extended));
+        self
+    }
+
+    /// Rewrite the JPEG file in `buf`
+    ///
+    /// Data after the end of image, like MPF images or motion photo videos, is copied
+    /// unchanged.
+    ///
+    /// # Errors
+    /// - The file is corrupt or truncated before the end of image
+    /// - A marker which is not APPn or COM was given to remove or insert
+    /// - The orientation set is not between 1 and 8
+    /// - A new segment is too large
+    /// - EXIF needs to be edited but is corrupt
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn rewrite(&self, buf: &[u8]) -> Result<Vec<u8>, DecodeErrors>
+    {
+        if let Some((marker, _)) = self
+            .removed
+            .iter()
+            .chain(&self.inserted)
+            .find(|(marker, _)| !matches!(marker, Marker::APP(0..=15) | Marker::COM))
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Only APPn and COM segments can be removed or inserted, found {marker:?}"
+            )));
+        }
+        if let Some(orientation) = self.orientation.filter(|x| !(1..=8).contains(x))
+        {
+            return Err(DecodeErrors::Format(format!(
+                "EXIF orientation must be between 1 and 8, found {orientation}"
+            )));
+        }
+        let segments = Segments::new(buf).collect::<Vec<Segment>>();
+
+        let Some(eoi) = segments.last().filter(|x| x.marker() == Some(Marker::EOI))
+        else
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "End of image not found, corrupt or truncated JPEG",
+            ));
+        };
+        let has_exif = segments
+            .iter()
+            .any(|x| is_segment(x, Marker::APP(1), EXIF_IDENTIFIER));
+
+        let generated = self.generated_segments(!has_exif || self.remove_exif)?;
+
+        let mut output = Vec::with_capacity(buf.len() + generated.len());
+        let mut inserted = false;
+        // positions of the MP header in the source and output
+        let mut mp_header = None;
+
+        for segment in &segments
+        {
+            if !inserted && !matches!(segment.marker(), Some(Marker::SOI | Marker::APP(0)))
+            {
+                output.extend_from_slice(&generated);
+                inserted = true;
+            }
+            match self.segment_action(segment)?
+            {
+                Action::Keep =>
+                {
+                    if is_segment(segment, Marker::APP(2), MPF_IDENTIFIER)
+                    {
+                        let old = segment.payload_range().start + MPF_IDENTIFIER.len();
+                        let new = output.len() + 4 + MPF_IDENTIFIER.len();
+
+                        mp_header = Some((old, new));
+                    }
+                    output.extend_from_slice(&buf[segment.offset..segment.offset + segment.length]);
+                }
+                Action::Drop => (),
+                Action::Replace(payload) =>
+                {
+                    write_segment(&mut output, segment.marker().unwrap(), &payload)?;
+                }
+            }
+        }
+        let old_end = eoi.offset + eoi.length;
+        let new_end = output.len();
+
+        output.extend_from_slice(&buf[old_end..]);
+
+        if let Some((old, new)) = mp_header
+        {
+            let delta = (new_end - new) as i64 - (old_end - old) as i64;
+            let primary_size = u32::try_from(new_end).unwrap_or(u32::MAX);
+
+            if relocate_mp_entries(&mut output[new..], primary_size, delta).is_none()
+            {
+                warn!("Could not update MP entries, secondary images may not be found");
+            }
+        }
+        Ok(output)
+    }
+
+    /// Decide what to do with a segment of the source file
+    fn segment_action(&self, segment: &Segment) -> Result<Action, DecodeErrors>
+    {
+        let removed = self
+            .removed
+            .iter()
+            .any(|(m, identifier)| is_segment(segment, *m, identifier));
+
+        if removed
+            || (self.remove_xmp && is_segment(segment, Marker::APP(1), XMP_IDENTIFIER))
+            || (self.remove_xmp && is_segment(segment, Marker::APP(1), EXTENDED_XMP_IDENTIFIER))
+            || (self.remove_icc && is_segment(segment, Marker::APP(2), ICC_IDENTIFIER))
+            || (self.remove_exif && is_segment(segment, Marker::APP(1), EXIF_IDENTIFIER))
+        {
+            return Ok(Action::Drop);
+        }
+        if is_segment(segment, Marker::APP(1), EXIF_IDENTIFIER)
+            && (self.remove_gps || self.orientation.is_some())
+        {
+            let mut tiff = segment.payload[EXIF_IDENTIFIER.len()..].to_vec();
+
+            if self.remove_gps && exif::remove_gps(&mut tiff).is_none()
+            {
+                return Err(DecodeErrors::FormatStatic("Corrupt EXIF, cannot remove GPS"));
+            }
+            if let Some(orientation) = self.orientation
+            {
+                if exif::set_orientation(&mut tiff, orientation).is_none()
+                {
+                    return Err(DecodeErrors::FormatStatic(
+                        "Corrupt EXIF, cannot set orientation",
+                    ));
+                }
+            }
+            let mut payload = EXIF_IDENTIFIER.to_vec();
+
+            payload.extend_from_slice(&tiff);
+
+            return Ok(Action::Replace(payload));
+        }
+        Ok(Action::Keep)
+    }
+
+    /// Serialize all segments inserted into the file
+    fn generated_segments(&self, new_exif: bool) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let mut output = vec![];
+
+        if let (Some(orientation), true) = (self.orientation, new_exif)
+        {
+            let mut payload = EXIF_IDENTIFIER.to_vec();
+
+            payload.extend_from_slice(&exif::orientation_only(orientation));
+
+            write_segment(&mut output, Marker::APP(1), &payload)?;
+        }
+        if let Some((xmp, extended)) = &self.xmp
+        {
+            write_xmp(&mut output, xmp, extended.as_deref())?;
+        }
+        if let Some(profile) = &self.icc_profile
+        {
+            write_icc_profile(&mut output, profile)?;
+        }
+        for (marker, payload) in &self.inserted
+        {
+            write_segment(&mut output, *marker, payload)?;
+        }
+        Ok(output)
+    }
+}
+
+/// Whether `segment` has `marker` and its contents start with `identifier`
+fn is_segment(segment: &Segment, marker: Marker, identifier: &[u8]) -> bool
+{
+    segment.marker() == Some(marker) && segment.payload.starts_with(identifier)
+}
+
+/// Write a marker segment with its length
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn write_segment(
+    output: &mut Vec<u8>, marker: Marker, payload: &[u8],
+) -> Result<(), DecodeErrors>
+{
+    if payload.len() > MAX_PAYLOAD
+    {
+        return Err(DecodeErrors::Format(format!(
+            "{marker:?} segment of {} bytes is too large, the limit is {MAX_PAYLOAD}",
+            payload.len()
+        )));
+    }
+    let byte = marker.to_u8().ok_or_else(|| {
+        DecodeErrors::Format(format!("{marker:?} is not a marker that can be written"))
+    })?;
+
+    output.extend_from_slice(&[0xFF, byte]);
+    output.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    output.extend_from_slice(payload);
+
+    Ok(())
+}
+
+/// Write an ICC profile as APP2 chunks
+#[allow(clippy::cast_possible_truncation)]
+fn write_icc_profile(output: &mut Vec<u8>, profile: &[u8]) -> Result<(), DecodeErrors>
+{
+    let count = profile.len().div_ceil(ICC_CHUNK);
+
+    if profile.is_empty() || count > 255
+    {
+        return Err(DecodeErrors::Format(format!(
+            "ICC profile of {} bytes cannot be stored",
+            profile.len()
+        )));
+    }
+    for (i, chunk) in profile.chunks(ICC_CHUNK).enumerate()
+    {
+        let mut payload = ICC_IDENTIFIER.to_vec();
+
+        payload.extend_from_slice(&[(i + 1) as u8, count as u8]);
+        payload.extend_from_slice(chunk);
+
+        write_segment(output, Marker::APP(2), &payload)?;
+    }
+    Ok(())
+}
+
+/// Write a standard XMP packet followed by the chunks of an extended packet
+#[allow(clippy::cast_possible_truncation)]
+fn write_xmp(output: &mut Vec<u8>, xmp: &str, extended: Option<&str>) -> Result<(), DecodeErrors>
+{
+    let Some(extended) = extended
+    else
+    {
+        return write_segment(output, Marker::APP(1), &[XMP_IDENTIFIER, xmp.as_bytes()].concat());
+    };
+    // the GUID is the MD5 digest of the extended packet as uppercase hex
+    let guid = md5_hex(extended.as_bytes());
+
+    let xmp = with_extended_guid(xmp, &guid).ok_or(DecodeErrors::FormatStatic(
+        "XMP has no rdf:Description to reference extended XMP from",
+    ))?;
+
+    write_segment(output, Marker::APP(1), &[XMP_IDENTIFIER, xmp.as_bytes()].concat())?;
+
+    let length = u32::try_from(extended.len())
+        .map_err(|_| DecodeErrors::FormatStatic("Extended XMP is too large"))?;
+
+    for (i, chunk) in extended.as_bytes().chunks(EXTENDED_XMP_CHUNK).enumerate()
+    {
+        let offset = (i * EXTENDED_XMP_CHUNK) as u32;
+
+        let mut payload = EXTENDED_XMP_IDENTIFIER.to_vec();
+
+        payload.extend_from_slice(guid.as_bytes());
+        payload.extend_from_slice(&length.to_be_bytes());
+        payload.extend_from_slice(&offset.to_be_bytes());
+        payload.extend_from_slice(chunk);
+
+        write_segment(output, Marker::APP(1), &payload)?;
+    }
+    Ok(())
+}
+
+/// Set `xmpNote:HasExtendedXMP` of a standard XMP packet to `guid`
+///
+/// Returns `None` if the packet has neither the property nor an `rdf:Description`
+/// to add it to.
+fn with_extended_guid(xmp: &str, guid: &str) -> Option<String>
+{
+    if let Some(value) = property_value(xmp, "xmpNote:HasExtendedXMP")
+    {
+        // the value borrows from the packet, replace it in place
+        let start = value.as_ptr() as usize - xmp.as_ptr() as usize;
+
+        return Some(format!("{}{guid}{}", &xmp[..start], &xmp[start + value.len()..]));
+    }
+    let position = xmp.find("<rdf:Description")? + "<rdf:Description".len();
+
+    Some(format!(
+        "{}{}{}",
+        &xmp[..position],
+        HAS_EXTENDED_XMP.replace("{}", guid),
+        &xmp[position..]
+    ))
+}
@@ -0,0 +1,147 @@
+//! Reduced size decoding
+//!
+//! Decoding at N/8 of the size of an image, like libjpeg's `scale_num/scale_denom`
+//! with a denominator of 8, transforms each block with an N point IDCT of its lowest
+//! frequencies, see [`ScaledIdct`]. This happens per MCU row in the same loops as a full
+//! decode, up-sampling and color conversion then work on rows of reduced size, so the
+//! cost of a decode shrinks with the output.
+//!
+//! At 1/8 a block is just its DC coefficient, which is the average of the block
+//! (multiplied by 8), and the AC scans of progressive images aren't even decoded.
+//! Sequential images code AC coefficients between DC coefficients, so those are still
+//! Huffman decoded but never dequantized or transformed.
+//!
+//! [`ScaledIdct`]: crate::idct::scaled::ScaledIdct
+
+use std::io::Cursor;
+
+use crate::components::Components;
+use crate::errors::DecodeErrors;
+use crate::mcu::DCT_BLOCK;
+use crate::{ColorSpace, Decoder};
+
+impl Decoder
+{
+    /// Decode an image at `scale`/8 of its size
+    ///
+    /// The output is `ceil(width*scale/8)` by `ceil(height*scale/8)` pixels in the output
+    /// colorspace, see [`scaled_dimensions`](Self::scaled_dimensions). Unlike a full
+    /// decode, this also decodes arithmetic coded images.
+    ///
+    /// # Errors
+    /// If `scale` isn't between 1 and 8, the output colorspace can't be converted to, or
+    /// the image is corrupt, see DecodeErrors enum for list of possible errors
+    ///
+    /// # Examples
+    /// Decode an image at a quarter of its size
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// let mut decoder = Decoder::new();
+    ///
+    /// let pixels = decoder.decode_scaled(&img_data, 2).unwrap();
+    /// let (width, height) = decoder.scaled_dimensions(2);
+    /// ```
+    pub fn decode_scaled(&mut self, buf: &[u8], scale: u8) -> Result<Vec<u8>, DecodeErrors>
+    {
+        if !(1..=8).contains(&scale)
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Scale {scale} out of range, expected a value between 1 and 8"
+            )));
+        }
+        let mut reader = Cursor::new(buf.to_vec());
+
+        self.decode_headers_internal(&mut reader)?;
+
+        self.decode_scaled_internal(&mut reader, usize::from(scale))
+    }
+
+    /// Width and height of the image decoded at `scale`/8 of its size, as by
+    /// [`decode_scaled`](Self::decode_scaled)
+    ///
+    /// Headers should already be decoded.
+    #[must_use]
+    pub fn scaled_dimensions(&self, scale: u8) -> (usize, usize)
+    {
+        self.scaled_dimensions_internal(usize::from(scale))
+    }
+
+    /// [`scaled_dimensions`](Self::scaled_dimensions) for `scale` from 1 to 8
+    pub(crate) fn scaled_dimensions_internal(&self, scale: usize) -> (usize, usize)
+    {
+        (
+            (usize::from(self.info.width) * scale).div_ceil(8),
+            (usize::from(self.info.height) * scale).div_ceil(8),
+        )
+    }
+
+    /// Whether the input colorspace can be converted to the output colorspace when
+    /// decoding, other conversions leave the output zeroed
+    pub(crate) fn supports_conversion(&self) -> bool
+    {
+        matches!(
+            (self.input_colorspace, self.options.get_out_colorspace()),
+            (ColorSpace::YCbCr | ColorSpace::GRAYSCALE, ColorSpace::GRAYSCALE)
+                | (
+                    ColorSpace::YCbCr,
+                    ColorSpace::YCbCr | ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX
+                )
+        )
+    }
+
+    /// Decode an image at `scale`/8 of its size, `scale` from 1 to 8
+    ///
+    /// Headers should already be decoded, and `reader` positioned at the start of the
+    /// first scan.
+    pub(crate) fn decode_scaled_internal(
+        &mut self, reader: &mut Cursor<Vec<u8>>, scale: usize,
+    ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        if !self.supports_conversion()
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Conversion from {:?} to {:?} is not supported for reduced size decoding",
+                self.input_colorspace,
+                self.options.get_out_colorspace()
+            )));
+        }
+        let skip_ac = scale == 1;
+
+        if self.is_progressive && !self.is_arithmetic
+        {
+            self.check_huffman_coding()?;
+
+            let (block, mcu_width) = self.decode_progressive_coefficients(reader, skip_ac)?;
+
+            return self.finish_progressive_decoding(block, mcu_width, scale);
+        }
+        if self.is_arithmetic
+            || usize::from(self.num_scans) < self.input_colorspace.num_components()
+        {
+            // the main loop only decodes Huffman coded scans of all components, other
+            // images need all coefficients before any of them can be transformed
+            let planes = self.decode_coefficients_internal(reader, skip_ac)?;
+            let mut block = [vec![], vec![], vec![]];
+
+            for (block, plane) in block.iter_mut().zip(planes)
+            {
+                *block = plane;
+            }
+            return self.finish_progressive_decoding(block, self.mcu_x * DCT_BLOCK, scale);
+        }
+        self.decode_mcu_ycbcr_baseline(reader, scale)
+    }
+
+    /// Components with the widths of their rows of samples scaled to `scale`/8
+    pub(crate) fn scaled_components(&self, scale: usize) -> Vec<Components>
+    {
+        let mut components = self.components.clone();
+
+        for component in &mut components
+        {
+            component.width_stride = component.width_stride * scale / 8;
+        }
+        components
+    }
+}
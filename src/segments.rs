@@ -0,0 +1,233 @@
+//! Walking the segments of a JPEG file without decoding it
+//!
+//! A JPEG file is a sequence of marker segments, a `0xFF` byte followed by the marker
+//! and for most markers a big endian length and contents. Each start of scan segment
+//! is followed by entropy coded data, split into intervals by restart markers when
+//! the image has a restart interval.
+//!
+//! [`Segments`] iterates over all of them in file order without copying, so tools
+//! can inspect or rewrite files without decoding them. Any number of `0xFF` fill
+//! bytes may precede a marker, they belong to no segment and are counted in the
+//! [`fill`](Segment::fill) of the segment following them. Together with them the
+//! segments cover the file byte for byte, up to the end of image.
+//!
+//! # Examples
+//! Print the layout of a file
+//! ```no_run
+//! use zune_jpeg::segments::{SegmentKind, Segments};
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//!
+//! for segment in Segments::new(&img_data)
+//! {
+//!     match segment.kind
+//!     {
+//!         SegmentKind::Marker(marker) => println!("{} {:?}", segment.offset, marker),
+//!         SegmentKind::EntropyCoded => println!("{} scan data", segment.offset),
+//!     }
+//! }
+//! ```
+
+use std::ops::Range;
+
+use crate::marker::Marker;
+
+/// What a [`Segment`] holds
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SegmentKind
+{
+    /// A marker, with its contents if it has any
+    Marker(Marker),
+    /// Entropy coded data following a start of scan or restart marker
+    EntropyCoded,
+}
+
+/// A single segment of a JPEG file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Segment<'a>
+{
+    /// What the segment holds
+    pub kind:    SegmentKind,
+    /// Position of the segment in the file, for markers this is the position of
+    /// the `0xFF` byte preceding the marker
+    pub offset:  usize,
+    /// Length of the segment in the file, including the marker and length field
+    pub length:  usize,
+    /// Number of `0xFF` fill bytes right before `offset`, which are not part of the
+    /// segment, always zero for entropy coded data
+    pub fill:    usize,
+    /// Contents of the segment after the length field
+    ///
+    /// This is empty for markers without contents like SOI, EOI and RST(n), and for
+    /// entropy coded data it is the data itself, still containing stuffed bytes.
+    pub payload: &'a [u8],
+}
+
+impl Segment<'_>
+{
+    /// Returns the marker of this segment, or `None` for entropy coded data
+    #[must_use]
+    pub const fn marker(&self) -> Option<Marker>
+    {
+        match self.kind
+        {
+            SegmentKind::Marker(marker) => Some(marker),
+            SegmentKind::EntropyCoded => None,
+        }
+    }
+
+    /// Range of the payload in the file
+    #[must_use]
+    pub const fn payload_range(&self) -> Range<usize>
+    {
+        let end = self.offset + self.length;
+
+        end - self.payload.len()..end
+    }
+}
+
+/// An iterator over the segments of a JPEG file
+///
+/// Segments are returned in file order, including entropy coded data and restart
+/// markers inside scans. Fill bytes before markers are skipped, each segment starts
+/// [`fill`](Segment::fill) bytes after the end of the one before it.
+///
+/// The iterator ends after the end of image marker, or at the first segment that is
+/// truncated or corrupt, data appended after the image is not looked at.
+pub struct Segments<'a>
+{
+    buf:      &'a [u8],
+    position: usize,
+    in_scan:  bool,
+    finished: bool,
+}
+
+impl<'a> Segments<'a>
+{
+    /// Create an iterator over the segments of the JPEG file in `buf`
+    #[must_use]
+    pub fn new(buf: &'a [u8]) -> Segments<'a>
+    {
+        Segments {
+            buf,
+            position: 0,
+            in_scan: false,
+            finished: false,
+        }
+    }
+}
+
+impl<'a> Iterator for Segments<'a>
+{
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        if self.finished
+        {
+            return None;
+        }
+        if self.in_scan
+        {
+            let end = next_marker(self.buf, self.position);
+
+            if end > self.position
+            {
+                let segment = Segment {
+                    kind:    SegmentKind::EntropyCoded,
+                    offset:  self.position,
+                    length:  end - self.position,
+                    fill:    0,
+                    payload: &self.buf[self.position..end],
+                };
+
+                self.position = end;
+
+                return Some(segment);
+            }
+        }
+        // anything returning early below means the file is corrupt or truncated
+        self.finished = true;
+
+        let start = self.position;
+        let mut position = start;
+
+        if *self.buf.get(position)? != 0xFF
+        {
+            return None;
+        }
+        // skip fill bytes
+        while *self.buf.get(position + 1)? == 0xFF
+        {
+            position += 1;
+        }
+        let marker = Marker::from_u8(self.buf[position + 1])?;
+
+        let payload = if marker.is_standalone()
+        {
+            position + 2..position + 2
+        }
+        else
+        {
+            let length = usize::from(u16::from_be_bytes(
+                self.buf.get(position + 2..position + 4)?.try_into().unwrap(),
+            ));
+
+            if length < 2 || position + 2 + length > self.buf.len()
+            {
+                return None;
+            }
+            position + 4..position + 2 + length
+        };
+
+        self.position = payload.end;
+        self.in_scan = matches!(marker, Marker::SOS | Marker::RST(_));
+        self.finished = marker == Marker::EOI;
+
+        Some(Segment {
+            kind:    SegmentKind::Marker(marker),
+            offset:  position,
+            length:  payload.end - position,
+            fill:    position - start,
+            payload: &self.buf[payload],
+        })
+    }
+}
+
+/// Return the position of the first marker after entropy coded data starting at `start`
+///
+/// Stuffed zero bytes are part of entropy coded data, restart markers end it. If
+/// no marker is found the length of the buffer is returned.
+fn next_marker(buf: &[u8], start: usize) -> usize
+{
+    let mut position = start;
+
+    while let Some(offset) = buf.get(position..).and_then(|x| x.iter().position(|&b| b == 0xFF))
+    {
+        let marker = position + offset;
+        // fill bytes may precede the marker, they are not part of the data
+        let mut next = marker + 1;
+
+        while buf.get(next) == Some(&0xFF)
+        {
+            next += 1;
+        }
+        match buf.get(next)
+        {
+            // stuffed byte
+            Some(0x00) => position = next + 1,
+            Some(_) => return marker,
+            None => break,
+        }
+    }
+    buf.len()
+}
+
+/// Position just after the end of image marker of the first image in `buf`
+///
+/// Returns `None` if the file is corrupt or truncated before the end of image.
+pub(crate) fn end_of_image(buf: &[u8]) -> Option<usize>
+{
+    Segments::new(buf)
+        .find(|segment| segment.marker() == Some(Marker::EOI))
+        .map(|segment| segment.offset + segment.length)
+}
@@ -0,0 +1,250 @@
+//! Stereoscopic JPEG (JPS) support
+//!
+//! JPS files are ordinary JPEGs holding both views of a stereo pair in one image,
+//! their layout is described by an APP3 segment containing
+//!
+//! | Field                      | Size     |
+//! |----------------------------|----------|
+//! | Identifier `_JPSJPS_`      | 8        |
+//! | Descriptor length (4)      | 2        |
+//! | Descriptor                 | 4        |
+//! | Comment length             | 2        |
+//! | Comment                    | Variable |
+//!
+//! The descriptor is a big endian integer, from the lowest byte holding the media type
+//! (mono or stereo), the layout, flags and for stereo images the separation.
+//!
+//! When the left field first flag is not set (the default), the right view comes first,
+//! that is, a side-by-side image is meant for cross-eyed viewing.
+
+use crate::Decoder;
+
+/// Layout of the views in a stereo JPS image
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StereoLayout
+{
+    /// Rows of the views are interleaved
+    Interleaved,
+    /// Views are next to each other
+    SideBySide,
+    /// Views are on top of each other
+    OverUnder,
+    /// Views are combined in color channels, they can't be split
+    Anaglyph,
+}
+
+/// Which eye a mono JPS image is meant for
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MonoEye
+{
+    /// Both eyes
+    Both,
+    /// Left eye only
+    Left,
+    /// Right eye only
+    Right,
+}
+
+/// Contents of a JPS image, as described by its descriptor
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum JpsMedia
+{
+    /// A single view
+    Mono(MonoEye),
+    /// A stereo pair
+    Stereo
+    {
+        /// How the views are arranged
+        layout:      StereoLayout,
+        /// Views were squeezed to half their height
+        half_height: bool,
+        /// Views were squeezed to half their width
+        half_width:  bool,
+        /// The left view comes first, otherwise the right one does
+        left_first:  bool,
+        /// Separation between the views, in pixels
+        separation:  u8,
+    },
+}
+
+/// The APP3 `_JPSJPS_` descriptor of a stereoscopic JPEG
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JpsMetadata
+{
+    /// What the image contains
+    pub media:   JpsMedia,
+    /// Comment stored with the descriptor
+    pub comment: Option<String>,
+}
+
+/// The left and right views of a stereo image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StereoPair
+{
+    /// Pixels of the left view
+    pub left:   Vec<u8>,
+    /// Pixels of the right view
+    pub right:  Vec<u8>,
+    /// Width of each view
+    pub width:  usize,
+    /// Height of each view
+    pub height: usize,
+}
+
+impl JpsMetadata
+{
+    /// Parse the contents of an APP3 segment after the `_JPSJPS_` identifier
+    pub(crate) fn from_bytes(data: &[u8]) -> Option<JpsMetadata>
+    {
+        let descriptor_length =
+            usize::from(u16::from_be_bytes(data.get(0..2)?.try_into().unwrap()));
+
+        if descriptor_length < 4
+        {
+            warn!("JPS descriptor too short, {descriptor_length} bytes");
+            return None;
+        }
+        let descriptor = u32::from_be_bytes(data.get(2..6)?.try_into().unwrap());
+        let [separation, flags, layout, media_type] = descriptor.to_be_bytes();
+
+        let media = match media_type
+        {
+            0x00 => JpsMedia::Mono(match layout
+            {
+                0x00 => MonoEye::Both,
+                0x01 => MonoEye::Left,
+                0x02 => MonoEye::Right,
+                _ => return None,
+            }),
+            0x01 => JpsMedia::Stereo {
+                layout: match layout
+                {
+                    0x01 => StereoLayout::Interleaved,
+                    0x02 => StereoLayout::SideBySide,
+                    0x03 => StereoLayout::OverUnder,
+                    0x04 => StereoLayout::Anaglyph,
+                    _ => return None,
+                },
+                half_height: flags & 0x01 != 0,
+                half_width: flags & 0x02 != 0,
+                left_first: flags & 0x04 != 0,
+                separation,
+            },
+            _ => return None,
+        };
+
+        // the comment block is optional
+        let comment = data.get(2 + descriptor_length..).and_then(|rest| {
+            let length = usize::from(u16::from_be_bytes(rest.get(0..2)?.try_into().unwrap()));
+
+            Some(String::from_utf8_lossy(rest.get(2..2 + length)?).into_owned())
+        });
+
+        Some(JpsMetadata { media, comment })
+    }
+
+    /// Split decoded pixels of a stereo image into its left and right views
+    ///
+    /// `pixels` is the decoded image, `width` by `height` pixels with `components`
+    /// interleaved components each.
+    ///
+    /// Views are returned as stored, if the image has the half width or half height flags
+    /// set, views should be stretched back when displayed.
+    ///
+    /// Returns `None` for mono and anaglyph images, which can't be split, if any of the
+    /// dimensions is zero or if `pixels` is too small for them.
+    #[must_use]
+    pub fn split(
+        &self, pixels: &[u8], width: usize, height: usize, components: usize,
+    ) -> Option<StereoPair>
+    {
+        let JpsMedia::Stereo {
+            layout, left_first, ..
+        } = self.media
+        else
+        {
+            return None;
+        };
+        if width == 0 || height == 0 || components == 0
+        {
+            return None;
+        }
+        let stride = width.checked_mul(components)?;
+        let pixels = pixels.get(..stride.checked_mul(height)?)?;
+
+        let (first, second, view_width, view_height) = match layout
+        {
+            StereoLayout::SideBySide =>
+            {
+                let half = (width / 2) * components;
+                let (mut first, mut second) = (vec![], vec![]);
+
+                for row in pixels.chunks_exact(stride)
+                {
+                    first.extend_from_slice(&row[..half]);
+                    second.extend_from_slice(&row[half..2 * half]);
+                }
+                (first, second, width / 2, height)
+            }
+            StereoLayout::OverUnder =>
+            {
+                let half = (height / 2) * stride;
+
+                (
+                    pixels[..half].to_vec(),
+                    pixels[half..2 * half].to_vec(),
+                    width,
+                    height / 2,
+                )
+            }
+            StereoLayout::Interleaved =>
+            {
+                let rows = pixels.chunks_exact(stride).take(height / 2 * 2);
+
+                (
+                    rows.clone().step_by(2).flatten().copied().collect(),
+                    rows.skip(1).step_by(2).flatten().copied().collect(),
+                    width,
+                    height / 2,
+                )
+            }
+            StereoLayout::Anaglyph => return None,
+        };
+        let (left, right) = if left_first { (first, second) } else { (second, first) };
+
+        Some(StereoPair {
+            left,
+            right,
+            width: view_width,
+            height: view_height,
+        })
+    }
+}
+
+impl Decoder
+{
+    /// Returns the stereo layout described by an APP(3) `_JPSJPS_` segment, if present
+    ///
+    /// This **must** be called after `read_headers` or a decode call, otherwise
+    /// it will return None
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let mut decoder = Decoder::new();
+    /// let img_data = std::fs::read("a_stereo.jps").unwrap();
+    /// let pixels = decoder.decode_buffer(&img_data).unwrap();
+    ///
+    /// if let Some(jps) = decoder.jps()
+    /// {
+    ///     let (width, height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+    ///     let components = decoder.get_output_colorspace().num_components();
+    ///     let pair = jps.split(&pixels, width, height, components).unwrap();
+    /// }
+    /// ```
+    #[must_use]
+    pub fn jps(&self) -> Option<JpsMetadata>
+    {
+        JpsMetadata::from_bytes(self.jps_data.as_ref()?)
+    }
+}
@@ -0,0 +1,260 @@
+//! Fast thumbnail decoding
+//!
+//! File browsers and galleries want small previews of images without paying for
+//! a full decode, this module picks the cheapest way to get an image no larger than
+//! a requested size, in order of preference
+//!
+//! 1. The JPEG thumbnail embedded in the EXIF data, if it's big enough.
+//! 2. A DC only decode, which produces an image 1/8th the size of the original.
+//! 3. A decode at the smallest of 2/8 to 7/8 of the size that is big enough, see
+//!    [`Decoder::decode_scaled`].
+//! 4. A full decode, also used for output colorspaces reduced size decoding can't
+//!    convert to.
+//!
+//! Whatever the source, the result is then downscaled to fit.
+
+use std::cmp::max;
+use std::io::Cursor;
+
+use crate::errors::DecodeErrors;
+use crate::{ColorSpace, Decoder};
+
+/// Where the pixels of a [`Thumbnail`] came from
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ThumbnailSource
+{
+    /// The JPEG thumbnail embedded in the EXIF data
+    Exif,
+    /// A decode using only DC coefficients of the image (1/8th scale)
+    DcOnly,
+    /// A decode at the given eighths of the size of the image, from 2 to 7
+    Scaled(u8),
+    /// A full decode of the image
+    FullDecode,
+}
+
+/// A decoded thumbnail
+#[derive(Clone, Debug)]
+pub struct Thumbnail
+{
+    /// Pixels in `colorspace`
+    pub pixels:     Vec<u8>,
+    /// Width of the thumbnail
+    pub width:      usize,
+    /// Height of the thumbnail
+    pub height:     usize,
+    /// Colorspace of the pixels
+    pub colorspace: ColorSpace,
+    /// Where the pixels came from
+    pub source:     ThumbnailSource,
+}
+
+impl Decoder
+{
+    /// Decode an image no larger than `max_dim` in either dimension
+    ///
+    /// This picks the cheapest source that still produces an image of at least
+    /// `max_dim` pixels on the longest side (or the full image if it is smaller), see the
+    /// [module docs](crate::thumbnail) for the order, and then downscales it to fit,
+    /// keeping the aspect ratio.
+    ///
+    /// # Errors
+    /// If `max_dim` is zero or the image is corrupt, see DecodeErrors enum for list of possible errors
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zune_jpeg::Decoder;
+    /// let img_data = std::fs::read("a_valid.jpeg").unwrap();
+    /// let thumbnail = Decoder::new().decode_thumbnail(&img_data, 256).unwrap();
+    ///
+    /// assert!(thumbnail.width <= 256 && thumbnail.height <= 256);
+    /// ```
+    pub fn decode_thumbnail(&mut self, buf: &[u8], max_dim: usize)
+        -> Result<Thumbnail, DecodeErrors>
+    {
+        if max_dim == 0
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Cannot create a thumbnail with zero dimensions",
+            ));
+        }
+        let mut reader = Cursor::new(buf.to_vec());
+
+        self.decode_headers_internal(&mut reader)?;
+
+        let width = usize::from(self.info.width);
+        let height = usize::from(self.info.height);
+
+        if max(width, height) > max_dim
+        {
+            if let Some(thumbnail) = self.try_exif_thumbnail(max_dim)?
+            {
+                return Ok(thumbnail);
+            }
+
+            let scale = (1..8).find(|&scale| {
+                let (scaled_width, scaled_height) = self.scaled_dimensions(scale);
+
+                max(scaled_width, scaled_height) >= max_dim
+            });
+
+            if let Some(scale) = scale.filter(|_| self.supports_conversion())
+            {
+                let (scaled_width, scaled_height) = self.scaled_dimensions(scale);
+                let pixels = self.decode_scaled_internal(&mut reader, usize::from(scale))?;
+                let source = if scale == 1
+                {
+                    ThumbnailSource::DcOnly
+                }
+                else
+                {
+                    ThumbnailSource::Scaled(scale)
+                };
+
+                return Ok(self.fit(pixels, scaled_width, scaled_height, max_dim, source));
+            }
+        }
+
+        let pixels = if self.is_progressive
+        {
+            self.decode_mcu_ycbcr_progressive(&mut reader)?
+        }
+        else
+        {
+            self.decode_mcu_ycbcr_baseline(&mut reader, 8)?
+        };
+
+        Ok(self.fit(pixels, width, height, max_dim, ThumbnailSource::FullDecode))
+    }
+
+    /// Decode the EXIF thumbnail if it is at least `max_dim` pixels on its longest side and
+    /// has the same aspect ratio as the image
+    fn try_exif_thumbnail(&self, max_dim: usize) -> Result<Option<Thumbnail>, DecodeErrors>
+    {
+        let Some(data) = self.exif_thumbnail()
+        else
+        {
+            return Ok(None);
+        };
+        let mut decoder = Decoder::new_with_options(self.options);
+
+        if decoder.read_headers(data).is_err()
+        {
+            warn!("Corrupt EXIF thumbnail, ignoring it");
+            return Ok(None);
+        }
+        let (t_width, t_height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+
+        if max(t_width, t_height) < max_dim
+        {
+            return Ok(None);
+        }
+        // Some cameras letterbox thumbnails to 4:3, those would show black bars
+        // so we only accept thumbnails whose aspect ratio is within a pixel of the image.
+        let (width, height) = (usize::from(self.width()), usize::from(self.height()));
+
+        if (t_width * height).abs_diff(t_height * width) > max(width, height)
+        {
+            return Ok(None);
+        }
+        let pixels = decoder.decode_buffer(data)?;
+
+        let mut thumbnail = decoder.fit(pixels, t_width, t_height, max_dim, ThumbnailSource::Exif);
+        // The thumbnail may be grayscale even if the image isn't
+        thumbnail.colorspace = decoder.get_output_colorspace();
+
+        Ok(Some(thumbnail))
+    }
+
+    /// Downscale pixels in the output colorspace to fit in `max_dim`
+    fn fit(
+        &self, pixels: Vec<u8>, width: usize, height: usize, max_dim: usize,
+        source: ThumbnailSource,
+    ) -> Thumbnail
+    {
+        let colorspace = self.get_output_colorspace();
+
+        let (new_width, new_height) = if max(width, height) <= max_dim
+        {
+            (width, height)
+        }
+        else if width >= height
+        {
+            (max_dim, max((height * max_dim + width / 2) / width, 1))
+        }
+        else
+        {
+            (max((width * max_dim + height / 2) / height, 1), max_dim)
+        };
+
+        let pixels = if (new_width, new_height) == (width, height)
+        {
+            pixels
+        }
+        else
+        {
+            resize_box(
+                &pixels,
+                width,
+                height,
+                colorspace.num_components(),
+                new_width,
+                new_height,
+            )
+        };
+
+        Thumbnail {
+            pixels,
+            width: new_width,
+            height: new_height,
+            colorspace,
+            source,
+        }
+    }
+}
+
+/// Downscale an image with interleaved channels using a box filter
+///
+/// Each output pixel is the average of the input pixels it covers.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn resize_box(
+    pixels: &[u8], width: usize, height: usize, channels: usize, new_width: usize,
+    new_height: usize,
+) -> Vec<u8>
+{
+    let mut output = vec![0; new_width * new_height * channels];
+    let mut sum = vec![0_u32; channels];
+
+    for y in 0..new_height
+    {
+        let y_start = y * height / new_height;
+        let y_end = max((y + 1) * height / new_height, y_start + 1);
+
+        for x in 0..new_width
+        {
+            let x_start = x * width / new_width;
+            let x_end = max((x + 1) * width / new_width, x_start + 1);
+
+            sum.fill(0);
+
+            for row in pixels[y_start * width * channels..y_end * width * channels]
+                .chunks_exact(width * channels)
+            {
+                for pixel in row[x_start * channels..x_end * channels].chunks_exact(channels)
+                {
+                    for (s, p) in sum.iter_mut().zip(pixel)
+                    {
+                        *s += u32::from(*p);
+                    }
+                }
+            }
+            let count = ((y_end - y_start) * (x_end - x_start)) as u32;
+            let out = &mut output[(y * new_width + x) * channels..(y * new_width + x + 1) * channels];
+
+            out.iter_mut()
+                .zip(&sum)
+                .for_each(|(o, s)| *o = ((s + count / 2) / count) as u8);
+        }
+    }
+    output
+}
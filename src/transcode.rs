@@ -0,0 +1,923 @@
+//! Lossless transcoding
+//!
+//! [`Transcoder`] decodes the quantized DCT coefficients of an image, optionally
+//! transforms and crops them, see [`transform`](crate::transform), and codes them
+//! again. Quantization tables and coefficients are kept, so the result has exactly
+//! the quality of the source, unless [`Transcoder::set_quality`] or
+//! [`Transcoder::set_quantization_tables`] requantize them to a lower quality, see
+//! [`requantize`](crate::requantize), or [`Transcoder::set_downscale`] halves the
+//! image, see [`downscale`](crate::downscale).
+//!
+//! # Entropy coding
+//! By default the result is a sequential JPEG file. Huffman tables of the source are
+//! reused when they have codes for every symbol of the result, otherwise the tables
+//! of ITU-T T.81 Annex K are used. [`Transcoder::set_optimize`] builds optimal tables
+//! from the symbols of the image instead, like `jpegtran -optimize`, which usually
+//! makes files a few percent smaller.
+//!
+//! [`Transcoder::set_progressive`] writes a progressive file with the scans of
+//! `jpegtran -progressive`, and [`Transcoder::set_scan_script`] with any other
+//! [`Scan`]s. Progressive scans are always coded with optimal tables.
+//!
+//! [`Transcoder::set_arithmetic`] uses arithmetic coding instead of Huffman coding
+//! (SOF9 and SOF10 frames), which makes files another 8-12% smaller, though few
+//! decoders support it. Arithmetic coded images are read as well, so transcoding
+//! them without it converts them back to Huffman coding.
+//!
+//! The restart interval of the source is kept.
+//!
+//! APPn and COM segments of the source are copied, except the MPF segment since data
+//! following the image, like secondary images, is not. Metadata is otherwise not
+//! updated, after fixing the orientation of an image its EXIF orientation should
+//! be reset, e.g with [`MetadataRewriter::set_orientation`].
+//!
+//! # Examples
+//! Rotate an image upright
+//! ```no_run
+//! use zune_jpeg::rewrite::MetadataRewriter;
+//! use zune_jpeg::transcode::Transcoder;
+//! use zune_jpeg::transform::{EdgeHandling, Transform};
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//! // orientation 6, the camera was rotated clockwise
+//! let transform = Transform::from_orientation(6).unwrap();
+//!
+//! let rotated = Transcoder::new()
+//!     .set_transform(transform)
+//!     .set_edge_handling(EdgeHandling::Trim)
+//!     .transcode(&img_data)
+//!     .unwrap();
+//! let upright = MetadataRewriter::new()
+//!     .set_orientation(1)
+//!     .rewrite(&rotated)
+//!     .unwrap();
+//! ```
+//!
+//! Make an image smaller without touching its pixels
+//! ```no_run
+//! use zune_jpeg::transcode::Transcoder;
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//!
+//! let smaller = Transcoder::new()
+//!     .set_progressive(true)
+//!     .transcode(&img_data)
+//!     .unwrap();
+//! ```
+//!
+//! Recompress an image for storage, and restore it
+//! ```no_run
+//! use zune_jpeg::transcode::Transcoder;
+//! let img_data = std::fs::read("a_valid.jpeg").unwrap();
+//!
+//! let stored = Transcoder::new()
+//!     .set_arithmetic(true)
+//!     .transcode(&img_data)
+//!     .unwrap();
+//! let restored = Transcoder::new()
+//!     .set_optimize(true)
+//!     .transcode(&stored)
+//!     .unwrap();
+//! ```
+//!
+//! [`MetadataRewriter::set_orientation`]: crate::rewrite::MetadataRewriter::set_orientation
+
+use crate::arithmetic_encoder::ArithmeticEncoder;
+use crate::coefficients::Coefficients;
+use crate::errors::DecodeErrors;
+use crate::huffman_encoder::{encode_block, HuffmanEncoder, SymbolCounter, SymbolSink, SymbolWriter};
+use crate::huffman_encoder_prog::{encode_dc_first, encode_dc_refine, AcEncoder};
+use crate::marker::Marker;
+use crate::misc::UN_ZIGZAG;
+use crate::requantize::{quality_tables, TRELLIS_LAMBDA};
+use crate::rewrite::{write_segment, MPF_IDENTIFIER};
+use crate::segments::Segments;
+use crate::transform::{EdgeHandling, Transform};
+use crate::Decoder;
+
+/// A scan of a scan script
+///
+/// Fields are those of a SOS segment, see [`ScanDescription`], with components
+/// given by their position in the frame.
+///
+/// [`ScanDescription`]: crate::description::ScanDescription
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Scan
+{
+    /// Positions of the components in the frame, in increasing order
+    pub components: Vec<usize>,
+    /// Start of spectral selection
+    pub spec_start: u8,
+    /// End of spectral selection
+    pub spec_end:   u8,
+    /// Successive approximation bit position high
+    pub succ_high:  u8,
+    /// Successive approximation bit position low
+    pub succ_low:   u8,
+}
+
+impl Scan
+{
+    /// Create a scan of `components`
+    #[must_use]
+    pub fn new(
+        components: &[usize], spec_start: u8, spec_end: u8, succ_high: u8, succ_low: u8,
+    ) -> Scan
+    {
+        Scan {
+            components: components.to_vec(),
+            spec_start,
+            spec_end,
+            succ_high,
+            succ_low,
+        }
+    }
+
+    /// The single scan of a sequential image with `components` components
+    #[must_use]
+    pub fn sequential(components: usize) -> Vec<Scan>
+    {
+        let components = (0..components).collect::<Vec<_>>();
+
+        vec![Scan::new(&components, 0, 63, 0, 0)]
+    }
+
+    /// The progressive scan script of libjpeg's `jpeg_simple_progression`, which
+    /// `jpegtran -progressive` uses
+    #[must_use]
+    pub fn progressive(components: usize) -> Vec<Scan>
+    {
+        let all = (0..components).collect::<Vec<_>>();
+
+        if components == 3
+        {
+            // YCbCr, luminance gets its low frequencies first
+            return vec![
+                Scan::new(&all, 0, 0, 0, 1),
+                Scan::new(&[0], 1, 5, 0, 2),
+                Scan::new(&[2], 1, 63, 0, 1),
+                Scan::new(&[1], 1, 63, 0, 1),
+                Scan::new(&[0], 6, 63, 0, 2),
+                Scan::new(&[0], 1, 63, 2, 1),
+                Scan::new(&all, 0, 0, 1, 0),
+                Scan::new(&[2], 1, 63, 1, 0),
+                Scan::new(&[1], 1, 63, 1, 0),
+                Scan::new(&[0], 1, 63, 1, 0),
+            ];
+        }
+        let mut scans = vec![Scan::new(&all, 0, 0, 0, 1)];
+
+        for (ss, se, ah, al) in [(1, 5, 0, 2), (6, 63, 0, 2), (1, 63, 2, 1)]
+        {
+            scans.extend(all.iter().map(|x| Scan::new(&[*x], ss, se, ah, al)));
+        }
+        scans.push(Scan::new(&all, 0, 0, 1, 0));
+        scans.extend(all.iter().map(|x| Scan::new(&[*x], 1, 63, 1, 0)));
+
+        scans
+    }
+
+    /// Whether this is a scan of a progressive image
+    fn is_progressive(&self) -> bool
+    {
+        (self.spec_start, self.spec_end, self.succ_high, self.succ_low) != (0, 63, 0, 0)
+    }
+}
+
+/// Options for transcoding a JPEG file without generation loss
+#[derive(Clone, Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Transcoder
+{
+    transform:     Option<Transform>,
+    edge_handling: EdgeHandling,
+    grayscale:     bool,
+    crop:          Option<(u16, u16, u16, u16)>,
+    downscale:     bool,
+    optimize:      bool,
+    progressive:   bool,
+    scan_script:   Option<Vec<Scan>>,
+    arithmetic:    bool,
+    quantization:  Option<[[u16; 64]; 2]>,
+    trellis:       bool,
+}
+
+impl Transcoder
+{
+    /// Create a transcoder which keeps the image as is
+    #[must_use]
+    pub fn new() -> Transcoder
+    {
+        Self::default()
+    }
+
+    /// Apply `transform` to the image
+    #[must_use]
+    pub fn set_transform(mut self, transform: Transform) -> Transcoder
+    {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Select what happens to partial MCUs the transform moves, see [`EdgeHandling`]
+    #[must_use]
+    pub fn set_edge_handling(mut self, edge_handling: EdgeHandling) -> Transcoder
+    {
+        self.edge_handling = edge_handling;
+        self
+    }
+
+    /// Drop the chrominance components of a YCbCr image, keeping luminance as is
+    #[must_use]
+    pub fn set_grayscale(mut self, grayscale: bool) -> Transcoder
+    {
+        self.grayscale = grayscale;
+        self
+    }
+
+    /// Cut out the rectangle `width`x`height` at `x`, `y` after the transform
+    ///
+    /// The top left corner is aligned to MCU boundaries, see [`Coefficients::crop`].
+    #[must_use]
+    pub fn set_crop(mut self, x: u16, y: u16, width: u16, height: u16) -> Transcoder
+    {
+        self.crop = Some((x, y, width, height));
+        self
+    }
+
+    /// Halve the width and height of the image after the transform and crop, see
+    /// [`Coefficients::downscale`]
+    #[must_use]
+    pub fn set_downscale(mut self, downscale: bool) -> Transcoder
+    {
+        self.downscale = downscale;
+        self
+    }
+
+    /// Build optimal Huffman tables for the image instead of reusing tables
+    #[must_use]
+    pub fn set_optimize(mut self, optimize: bool) -> Transcoder
+    {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Write a progressive image with the scans of [`Scan::progressive`]
+    ///
+    /// Otherwise images are written sequential, whether the source is progressive
+    /// or not.
+    #[must_use]
+    pub fn set_progressive(mut self, progressive: bool) -> Transcoder
+    {
+        self.progressive = progressive;
+        self
+    }
+
+    /// Write the image with the scans of `scans`, this takes precedence over
+    /// [`set_progressive`](Self::set_progressive)
+    ///
+    /// The image is progressive if any scan codes less than all coefficients in
+    /// full, otherwise every component must be in exactly one scan.
+    #[must_use]
+    pub fn set_scan_script(mut self, scans: Vec<Scan>) -> Transcoder
+    {
+        self.scan_script = Some(scans);
+        self
+    }
+
+    /// Use arithmetic coding instead of Huffman coding
+    ///
+    /// Scans are coded with the default conditioning, [`set_optimize`](Self::set_optimize)
+    /// has no effect then.
+    #[must_use]
+    pub fn set_arithmetic(mut self, arithmetic: bool) -> Transcoder
+    {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// Requantize the image with the tables libjpeg uses for `quality`, from 1 to 100
+    ///
+    /// Steps finer than those of the image are kept as they are, so this never
+    /// raises the quality, see [`Coefficients::requantize`].
+    #[must_use]
+    pub fn set_quality(mut self, quality: u8) -> Transcoder
+    {
+        self.quantization = Some(quality_tables(quality));
+        self
+    }
+
+    /// Requantize the image with a luminance and a chrominance table in natural order,
+    /// this takes precedence over [`set_quality`](Self::set_quality)
+    #[must_use]
+    pub fn set_quantization_tables(mut self, tables: [[u16; 64]; 2]) -> Transcoder
+    {
+        self.quantization = Some(tables);
+        self
+    }
+
+    /// Round coefficients with trellis quantization, using [`TRELLIS_LAMBDA`]
+    ///
+    /// Without new tables the tables of the image are kept, and only coefficients
+    /// not worth their bits are rounded towards zero.
+    #[must_use]
+    pub fn set_trellis(mut self, trellis: bool) -> Transcoder
+    {
+        self.trellis = trellis;
+        self
+    }
+
+    /// Transcode the JPEG file in `buf`
+    ///
+    /// # Errors
+    /// If the image can't be decoded, the transform, crop, grayscale conversion or
+    /// requantization are not possible, see [`Coefficients::transform`],
+    /// [`Coefficients::crop`], [`Coefficients::to_grayscale`] and
+    /// [`Coefficients::requantize`], or the scan script is not valid for the image
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn transcode(&self, buf: &[u8]) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let mut decoder = Decoder::new();
+        let mut image = decoder.decode_coefficients(buf)?;
+
+        // tables of progressive images code different symbols, don't bother with them
+        let source_tables = if decoder.is_progressive || decoder.is_arithmetic
+        {
+            vec![]
+        }
+        else
+        {
+            decoder
+                .components
+                .iter()
+                .map(|component| {
+                    let dc = decoder.dc_huffman_tables[component.dc_huff_table & 3].as_ref();
+                    let ac = decoder.ac_huffman_tables[component.ac_huff_table & 3].as_ref();
+
+                    (
+                        dc.and_then(|x| HuffmanEncoder::from_table(x).ok()),
+                        ac.and_then(|x| HuffmanEncoder::from_table(x).ok()),
+                    )
+                })
+                .collect()
+        };
+
+        if self.grayscale
+        {
+            image = image.to_grayscale()?;
+        }
+        if let Some(transform) = self.transform
+        {
+            image = image.transform(transform, self.edge_handling)?;
+        }
+        if let Some((x, y, width, height)) = self.crop
+        {
+            image = image.crop(x, y, width, height)?;
+        }
+        if self.downscale
+        {
+            image = image.downscale();
+        }
+        if self.quantization.is_some() || self.trellis
+        {
+            // steps of one keep the tables of the image
+            let tables = self.quantization.unwrap_or([[1; 64]; 2]);
+
+            image = image.requantize(&tables, self.trellis.then_some(TRELLIS_LAMBDA))?;
+        }
+        let scans = match &self.scan_script
+        {
+            Some(scans) => scans.clone(),
+            None if self.progressive => Scan::progressive(image.components.len()),
+            None => Scan::sequential(image.components.len()),
+        };
+        let mut output = vec![0xFF, Marker::SOI.to_u8().unwrap()];
+
+        for segment in Segments::new(buf).take_while(|x| x.marker() != Some(Marker::SOS))
+        {
+            let copy = match segment.marker()
+            {
+                Some(Marker::APP(2)) => !segment.payload.starts_with(MPF_IDENTIFIER),
+                Some(Marker::APP(_) | Marker::COM) => true,
+                _ => false,
+            };
+            if copy
+            {
+                output.extend_from_slice(&buf[segment.offset..segment.offset + segment.length]);
+            }
+        }
+        let coding = Coding {
+            source_tables:    &source_tables,
+            optimize:         self.optimize,
+            arithmetic:       self.arithmetic,
+            // from a DRI segment, so it fits
+            restart_interval: decoder.restart_interval as u16,
+        };
+
+        write_image(&mut output, &image, &scans, &coding)?;
+
+        Ok(output)
+    }
+}
+
+/// How [`write_image`] codes the scans of an image
+pub(crate) struct Coding<'a>
+{
+    /// Huffman tables to reuse for sequential scans when they cover their symbols,
+    /// per component
+    pub source_tables:    &'a [(Option<HuffmanEncoder>, Option<HuffmanEncoder>)],
+    /// Build optimal Huffman tables for sequential scans too
+    pub optimize:         bool,
+    /// Use arithmetic coding
+    pub arithmetic:       bool,
+    /// Number of MCUs between restart markers, zero for none
+    pub restart_interval: u16,
+}
+
+/// Write `image` with `scans`, from the quantization tables to the end of image marker
+///
+/// # Errors
+/// If the scan script is not valid for the image, or a coefficient is too large for
+/// 8 bit samples
+pub(crate) fn write_image(
+    output: &mut Vec<u8>, image: &Coefficients, scans: &[Scan], coding: &Coding,
+) -> Result<(), DecodeErrors>
+{
+    let progressive = validate_script(image, scans)?;
+
+    write_frame(output, image, progressive, coding.arithmetic)?;
+
+    if coding.restart_interval > 0
+    {
+        write_segment(output, Marker::DRI, &coding.restart_interval.to_be_bytes())?;
+    }
+    let interval = usize::from(coding.restart_interval);
+
+    for scan in scans
+    {
+        let mut counter = SymbolCounter::new(table_slot(image.components.len() - 1) + 1);
+
+        // counted for arithmetic coding too, this checks coefficients are in range
+        encode_scan(&mut counter, image, scan, interval, |_, _| ());
+
+        let counter = counter.check()?;
+
+        if coding.arithmetic
+        {
+            write_arithmetic_scan(output, image, scan, interval)?;
+            continue;
+        }
+        let tables = if progressive || coding.optimize
+        {
+            optimal_tables(&counter)
+        }
+        else
+        {
+            choose_tables(&counter, coding.source_tables)
+        };
+
+        write_scan(output, image, scan, interval, &tables)?;
+    }
+    output.extend_from_slice(&[0xFF, Marker::EOI.to_u8().unwrap()]);
+
+    Ok(())
+}
+
+/// Huffman or conditioning table slot of a component, the first component gets its
+/// own tables and the others share the second, as libjpeg does
+fn table_slot(component: usize) -> usize
+{
+    component.min(1)
+}
+
+/// Check that `scans` can code `image`, as libjpeg's `validate_script` does
+///
+/// Returns whether the image is progressive.
+fn validate_script(image: &Coefficients, scans: &[Scan]) -> Result<bool, DecodeErrors>
+{
+    let progressive = scans.iter().any(Scan::is_progressive);
+    let count = image.components.len();
+
+    // bit position of each coefficient sent so far, None if not sent at all
+    let mut positions = vec![[None; 64]; count];
+
+    for scan in scans
+    {
+        let invalid = |reason: &str| {
+            Err(DecodeErrors::Format(format!(
+                "Invalid scan of components {:?} Ss={} Se={} Ah={} Al={}: {reason}",
+                scan.components, scan.spec_start, scan.spec_end, scan.succ_high, scan.succ_low
+            )))
+        };
+        if scan.components.is_empty() || scan.components.len() > 4
+        {
+            return invalid("scans must have 1 to 4 components");
+        }
+        if scan.components.iter().any(|x| *x >= count)
+        {
+            return invalid("no such component");
+        }
+        if scan.components.windows(2).any(|x| x[0] >= x[1])
+        {
+            return invalid("components must be in frame order");
+        }
+        if progressive
+        {
+            let (ss, se) = (scan.spec_start, scan.spec_end);
+
+            if se > 63 || ss > se || (ss == 0 && se != 0)
+            {
+                return invalid("bad spectral selection");
+            }
+            if ss > 0 && scan.components.len() != 1
+            {
+                return invalid("AC scans must have a single component");
+            }
+            // coefficients have at most 11 bits
+            if scan.succ_low > 10 || (scan.succ_high != 0 && scan.succ_high != scan.succ_low + 1)
+            {
+                return invalid("bad successive approximation");
+            }
+            for &component in &scan.components
+            {
+                if ss > 0 && positions[component][0].is_none()
+                {
+                    return invalid("AC scans must follow the first DC scan");
+                }
+                for position in &mut positions[component][usize::from(ss)..=usize::from(se)]
+                {
+                    let expected = if scan.succ_high == 0 { None } else { Some(scan.succ_high) };
+
+                    if *position != expected
+                    {
+                        return invalid("scan does not follow the previous scans of the band");
+                    }
+                    *position = Some(scan.succ_low);
+                }
+            }
+        }
+        else
+        {
+            for &component in &scan.components
+            {
+                if positions[component][0].is_some()
+                {
+                    return invalid("component appears in more than one scan");
+                }
+                positions[component] = [Some(0); 64];
+            }
+        }
+    }
+    if positions.iter().flatten().any(|x| *x != Some(0))
+    {
+        return Err(DecodeErrors::FormatStatic(
+            "Scan script does not code all coefficients of all components",
+        ));
+    }
+
+    Ok(progressive)
+}
+
+/// Call `f` with the MCU, position in the frame and coefficients of every block of the
+/// `components` of `image`, in the order a scan holding them codes them
+///
+/// MCUs are counted from the start of the scan, in a non-interleaved scan each block
+/// is an MCU.
+pub(crate) fn for_each_block(
+    image: &Coefficients, components: &[usize], mut f: impl FnMut(usize, usize, &[i16; 64]),
+)
+{
+    if let [pos] = components
+    {
+        let component = &image.components[*pos];
+
+        // non-interleaved, blocks in the padding of the plane are not coded
+        let blocks_x = component.width.div_ceil(8);
+
+        for y in 0..component.height.div_ceil(8)
+        {
+            for x in 0..blocks_x
+            {
+                f(y * blocks_x + x, *pos, component.block(x, y));
+            }
+        }
+        return;
+    }
+    let (mcu_width, mcu_height) = image.mcu_size();
+    let mcu_x = usize::from(image.width).div_ceil(mcu_width);
+    let mcu_y = usize::from(image.height).div_ceil(mcu_height);
+
+    for y in 0..mcu_y
+    {
+        for x in 0..mcu_x
+        {
+            for &pos in components
+            {
+                let component = &image.components[pos];
+                let h_samp = usize::from(component.horizontal_sample);
+                let v_samp = usize::from(component.vertical_sample);
+
+                for v in 0..v_samp
+                {
+                    for h in 0..h_samp
+                    {
+                        let block = component.block(x * h_samp + h, y * v_samp + v);
+
+                        f(y * mcu_x + x, pos, block);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Code the blocks of `scan` into `sink`, with a restart marker every `interval` MCUs
+/// unless it is zero, `restart` is called at each restart marker with its number
+#[allow(clippy::cast_possible_truncation)]
+fn encode_scan<S: SymbolSink>(
+    sink: &mut S, image: &Coefficients, scan: &Scan, interval: usize,
+    mut restart: impl FnMut(&mut S, u8),
+)
+{
+    let (ss, ah, al) = (scan.spec_start, scan.succ_high, scan.succ_low);
+    let mut predictions = vec![0; image.components.len()];
+    let slot = table_slot(scan.components[0]);
+    let mut encoder = AcEncoder::new(slot, ss, scan.spec_end, al);
+    let mut restarts = 0;
+
+    for_each_block(image, &scan.components, |mcu, pos, block| {
+        if interval > 0 && mcu == (restarts + 1) * interval
+        {
+            encoder.flush(sink);
+            restart(sink, (restarts % 8) as u8);
+            predictions.fill(0);
+            restarts += 1;
+        }
+        if !scan.is_progressive()
+        {
+            encode_block(sink, table_slot(pos), block, &mut predictions[pos]);
+        }
+        else if ss == 0 && ah == 0
+        {
+            encode_dc_first(sink, table_slot(pos), block, &mut predictions[pos], al);
+        }
+        else if ss == 0
+        {
+            encode_dc_refine(sink, block, al);
+        }
+        else if ah == 0
+        {
+            encoder.encode_first(sink, block);
+        }
+        else
+        {
+            encoder.encode_refine(sink, block);
+        }
+    });
+    encoder.flush(sink);
+}
+
+/// Code the blocks of `scan` with arithmetic coding, with a restart marker every
+/// `interval` MCUs unless it is zero, returning the coded data
+#[allow(clippy::cast_possible_truncation)]
+fn encode_arithmetic_scan(image: &Coefficients, scan: &Scan, interval: usize) -> Vec<u8>
+{
+    let mut encoder = ArithmeticEncoder::new();
+    let (ss, se, ah, al) = (scan.spec_start, scan.spec_end, scan.succ_high, scan.succ_low);
+    let slot = table_slot(scan.components[0]);
+    let mut restarts = 0;
+
+    for_each_block(image, &scan.components, |mcu, pos, block| {
+        if interval > 0 && mcu == (restarts + 1) * interval
+        {
+            encoder.restart((restarts % 8) as u8);
+            restarts += 1;
+        }
+        if !scan.is_progressive()
+        {
+            encoder.encode_sequential(pos, table_slot(pos), table_slot(pos), block);
+        }
+        else if ss == 0 && ah == 0
+        {
+            encoder.encode_dc_first(pos, table_slot(pos), block, al);
+        }
+        else if ss == 0
+        {
+            encoder.encode_dc_refine(block, al);
+        }
+        else if ah == 0
+        {
+            encoder.encode_ac_first(slot, block, ss, se, al);
+        }
+        else
+        {
+            encoder.encode_ac_refine(slot, block, ss, se, al);
+        }
+    });
+    encoder.finish()
+}
+
+/// Pick the DC and AC Huffman tables of each table slot from `source`, given per
+/// component, if they cover the counted symbols, otherwise from Annex K
+///
+/// Classes without symbols get no table.
+fn choose_tables(
+    counter: &SymbolCounter, source: &[(Option<HuffmanEncoder>, Option<HuffmanEncoder>)],
+) -> Vec<(Option<HuffmanEncoder>, Option<HuffmanEncoder>)>
+{
+    counter
+        .dc
+        .iter()
+        .zip(&counter.ac)
+        .enumerate()
+        .map(|(slot, (dc_frequencies, ac_frequencies))| {
+            let chrominance = slot == 1;
+            let (dc, ac) = source
+                .iter()
+                .enumerate()
+                .find(|(pos, _)| table_slot(*pos) == slot)
+                .map_or((None, None), |(_, tables)| tables.clone());
+
+            let used = |frequencies: &[u32; 256]| frequencies.iter().any(|x| *x > 0);
+
+            (
+                used(dc_frequencies).then(|| {
+                    dc.filter(|x| x.covers(dc_frequencies))
+                        .unwrap_or_else(|| HuffmanEncoder::std_dc(chrominance))
+                }),
+                used(ac_frequencies).then(|| {
+                    ac.filter(|x| x.covers(ac_frequencies))
+                        .unwrap_or_else(|| HuffmanEncoder::std_ac(chrominance))
+                }),
+            )
+        })
+        .collect()
+}
+
+/// Build optimal DC and AC Huffman tables of each table slot for the counted
+/// symbols, classes without symbols get no table
+fn optimal_tables(counter: &SymbolCounter) -> Vec<(Option<HuffmanEncoder>, Option<HuffmanEncoder>)>
+{
+    let optimal = |frequencies: &[u32; 256]| {
+        frequencies
+            .iter()
+            .any(|x| *x > 0)
+            .then(|| HuffmanEncoder::optimal(frequencies))
+    };
+
+    counter
+        .dc
+        .iter()
+        .zip(&counter.ac)
+        .map(|(dc, ac)| (optimal(dc), optimal(ac)))
+        .collect()
+}
+
+/// Write the quantization tables and frame header of `image`
+#[allow(clippy::cast_possible_truncation)]
+fn write_frame(
+    output: &mut Vec<u8>, image: &Coefficients, progressive: bool, arithmetic: bool,
+) -> Result<(), DecodeErrors>
+{
+    // quantization tables, shared by components with the same table
+    let mut quantization_tables: Vec<[u16; 64]> = vec![];
+    let mut table_numbers = vec![];
+
+    for component in &image.components
+    {
+        let number = quantization_tables
+            .iter()
+            .position(|x| *x == component.quantization_table)
+            .unwrap_or_else(|| {
+                quantization_tables.push(component.quantization_table);
+                quantization_tables.len() - 1
+            });
+
+        table_numbers.push(number as u8);
+    }
+    let mut dqt = vec![];
+    // like libjpeg, tables with values above 255 are written with 16 bits, which
+    // baseline frames don't allow
+    let mut extended = false;
+
+    for (number, table) in quantization_tables.iter().enumerate()
+    {
+        let wide = table.iter().any(|&x| x > 255);
+
+        dqt.push((u8::from(wide) << 4) | number as u8);
+
+        for &position in &UN_ZIGZAG[..64]
+        {
+            if wide
+            {
+                dqt.extend_from_slice(&table[position].to_be_bytes());
+            }
+            else
+            {
+                dqt.push(table[position] as u8);
+            }
+        }
+        extended |= wide;
+    }
+    write_segment(output, Marker::DQT, &dqt)?;
+
+    let mut sof = vec![8];
+
+    sof.extend_from_slice(&image.height.to_be_bytes());
+    sof.extend_from_slice(&image.width.to_be_bytes());
+    sof.push(image.components.len() as u8);
+
+    for (component, table) in image.components.iter().zip(&table_numbers)
+    {
+        sof.push(component.id);
+        sof.push((component.horizontal_sample << 4) | component.vertical_sample);
+        sof.push(*table);
+    }
+    let marker = match (progressive, arithmetic)
+    {
+        (false, false) if extended => Marker::SOF(1),
+        (false, false) => Marker::SOF(0),
+        (true, false) => Marker::SOF(2),
+        (false, true) => Marker::SOF(9),
+        (true, true) => Marker::SOF(10),
+    };
+
+    write_segment(output, marker, &sof)
+}
+
+/// Write the Huffman tables, header and coded blocks of `scan`, with a restart
+/// marker every `interval` MCUs unless it is zero
+///
+/// `tables` holds the DC and AC tables of each table slot, they must cover all
+/// symbols of the scan.
+#[allow(clippy::cast_possible_truncation)]
+fn write_scan(
+    output: &mut Vec<u8>, image: &Coefficients, scan: &Scan, interval: usize,
+    tables: &[(Option<HuffmanEncoder>, Option<HuffmanEncoder>)],
+) -> Result<(), DecodeErrors>
+{
+    let mut dht = vec![];
+
+    for (slot, (dc, ac)) in tables.iter().enumerate()
+    {
+        for (class, table) in [(0, dc), (1, ac)]
+        {
+            if let Some(table) = table
+            {
+                dht.push((class << 4) | slot as u8);
+                dht.extend_from_slice(&table.bits[1..]);
+                dht.extend_from_slice(&table.values);
+            }
+        }
+    }
+    // DC refinement scans code no symbols
+    if !dht.is_empty()
+    {
+        write_segment(output, Marker::DHT, &dht)?;
+    }
+    write_scan_header(output, image, scan)?;
+
+    let mut writer = SymbolWriter::new(tables);
+
+    encode_scan(&mut writer, image, scan, interval, |writer, number| {
+        writer.writer.flush();
+        writer.writer.data.extend_from_slice(&[0xFF, Marker::RST(number).to_u8().unwrap()]);
+    });
+
+    writer.writer.flush();
+    output.extend_from_slice(&writer.writer.data);
+
+    Ok(())
+}
+
+/// Write the header and arithmetic coded blocks of `scan`, with a restart marker
+/// every `interval` MCUs unless it is zero
+fn write_arithmetic_scan(
+    output: &mut Vec<u8>, image: &Coefficients, scan: &Scan, interval: usize,
+) -> Result<(), DecodeErrors>
+{
+    write_scan_header(output, image, scan)?;
+    output.extend_from_slice(&encode_arithmetic_scan(image, scan, interval));
+
+    Ok(())
+}
+
+/// Write the SOS segment of `scan`, components use the tables of their table slot
+#[allow(clippy::cast_possible_truncation)]
+fn write_scan_header(
+    output: &mut Vec<u8>, image: &Coefficients, scan: &Scan,
+) -> Result<(), DecodeErrors>
+{
+    let mut sos = vec![scan.components.len() as u8];
+
+    for &pos in &scan.components
+    {
+        let slot = table_slot(pos) as u8;
+
+        sos.extend_from_slice(&[image.components[pos].id, (slot << 4) | slot]);
+    }
+    sos.extend_from_slice(&[
+        scan.spec_start,
+        scan.spec_end,
+        (scan.succ_high << 4) | scan.succ_low,
+    ]);
+    write_segment(output, Marker::SOS, &sos)
+}
@@ -0,0 +1,456 @@
+//! Lossless transforms of quantized DCT coefficients
+//!
+//! Mirroring a block of DCT coefficients negates the coefficients of odd frequencies
+//! in the mirrored direction, and transposing it transposes the coefficients, so
+//! images can be flipped, rotated by multiples of 90 degrees and transposed by moving
+//! blocks around and applying these to each block, without decoding them to pixels
+//! and losing quality by encoding them again.
+//!
+//! # Partial MCUs
+//! Blocks are always aligned to the top left corner of the image, so when the width
+//! or height is not a multiple of the MCU size, the right or bottom edge holds a partial
+//! MCU that can't be moved to the opposite edge. [`EdgeHandling`] selects what happens
+//! to it, like the `-trim` and `-perfect` switches of jpegtran. For the same reason
+//! [`Coefficients::crop`] can only cut on MCU boundaries on the top and left.
+//!
+//! Transforms work on the coefficients returned by [`Decoder::decode_coefficients`],
+//! [`Transcoder`] writes the result back to a JPEG file.
+//!
+//! [`Decoder::decode_coefficients`]: crate::Decoder::decode_coefficients
+//! [`Transcoder`]: crate::transcode::Transcoder
+
+use crate::coefficients::{ComponentCoefficients, Coefficients};
+use crate::errors::DecodeErrors;
+use crate::mcu::DCT_BLOCK;
+use crate::ColorSpace;
+
+/// A lossless transform
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Transform
+{
+    /// Mirror left and right
+    FlipHorizontal,
+    /// Mirror top and bottom
+    FlipVertical,
+    /// Mirror along the top left to bottom right diagonal
+    Transpose,
+    /// Mirror along the top right to bottom left diagonal
+    Transverse,
+    /// Rotate by 90 degrees clockwise
+    Rotate90,
+    /// Rotate by 180 degrees
+    Rotate180,
+    /// Rotate by 270 degrees clockwise
+    Rotate270,
+}
+
+impl Transform
+{
+    /// The transform displaying an image with EXIF `orientation` upright
+    ///
+    /// Returns `None` for orientation 1, which needs no transform, and invalid
+    /// orientations.
+    #[must_use]
+    pub const fn from_orientation(orientation: u16) -> Option<Transform>
+    {
+        match orientation
+        {
+            2 => Some(Transform::FlipHorizontal),
+            3 => Some(Transform::Rotate180),
+            4 => Some(Transform::FlipVertical),
+            5 => Some(Transform::Transpose),
+            6 => Some(Transform::Rotate90),
+            7 => Some(Transform::Transverse),
+            8 => Some(Transform::Rotate270),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with partial MCUs on edges a transform moves
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum EdgeHandling
+{
+    /// Leave partial MCUs where they are, untransformed
+    ///
+    /// The image keeps its dimensions, but the right or bottom edge shows a strip
+    /// of the original image.
+    #[default]
+    Keep,
+    /// Drop partial MCUs, the image loses up to one MCU minus one pixel on edges that move
+    Trim,
+    /// Return an error if there are partial MCUs on edges that move
+    Perfect,
+}
+
+/// Whether a transform mirrors columns or rows of blocks
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Axis
+{
+    Horizontal,
+    Vertical,
+}
+
+impl Coefficients
+{
+    /// Apply a lossless transform
+    ///
+    /// # Errors
+    /// With [`EdgeHandling::Perfect`] if the transform would move a partial MCU, and
+    /// with [`EdgeHandling::Trim`] if nothing would be left of the image.
+    pub fn transform(
+        &self, transform: Transform, edges: EdgeHandling,
+    ) -> Result<Coefficients, DecodeErrors>
+    {
+        use Axis::{Horizontal, Vertical};
+
+        match transform
+        {
+            Transform::FlipHorizontal => self.flip(Horizontal, edges),
+            Transform::FlipVertical => self.flip(Vertical, edges),
+            Transform::Transpose => Ok(self.transpose()),
+            Transform::Transverse =>
+            {
+                self.transpose()
+                    .flip(Horizontal, edges)?
+                    .flip(Vertical, edges)
+            }
+            Transform::Rotate90 => self.transpose().flip(Horizontal, edges),
+            Transform::Rotate180 => self.flip(Horizontal, edges)?.flip(Vertical, edges),
+            Transform::Rotate270 => self.transpose().flip(Vertical, edges),
+        }
+    }
+
+    /// Drop the chrominance components of a YCbCr image
+    ///
+    /// Luminance is kept as is, so the result is the grayscale version of the image.
+    ///
+    /// # Errors
+    /// If the image is not YCbCr or grayscale, or its luminance is subsampled
+    pub fn to_grayscale(&self) -> Result<Coefficients, DecodeErrors>
+    {
+        match self.colorspace
+        {
+            ColorSpace::GRAYSCALE => return Ok(self.clone()),
+            ColorSpace::YCbCr => (),
+            _ =>
+            {
+                return Err(DecodeErrors::FormatStatic(
+                    "Only YCbCr images can be converted to grayscale losslessly",
+                ))
+            }
+        }
+        let luma = &self.components[0];
+
+        if (luma.width, luma.height) != (usize::from(self.width), usize::from(self.height))
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Luminance is subsampled, cannot drop chrominance losslessly",
+            ));
+        }
+        // a single component has 1x1 sampling, MCUs are single blocks
+        let (blocks_wide, blocks_high) = (luma.width.div_ceil(8), luma.height.div_ceil(8));
+        let mut coefficients = Vec::with_capacity(blocks_wide * blocks_high * DCT_BLOCK);
+
+        for y in 0..blocks_high
+        {
+            let start = DCT_BLOCK * y * luma.blocks_wide;
+            let end = start + DCT_BLOCK * blocks_wide;
+
+            coefficients.extend_from_slice(&luma.coefficients[start..end]);
+        }
+
+        Ok(Coefficients {
+            colorspace: ColorSpace::GRAYSCALE,
+            components: vec![ComponentCoefficients {
+                horizontal_sample: 1,
+                vertical_sample: 1,
+                blocks_wide,
+                blocks_high,
+                coefficients,
+                ..luma.clone()
+            }],
+            ..self.clone()
+        })
+    }
+
+    /// Cut out the rectangle `width`x`height` at `x`, `y`
+    ///
+    /// Like `jpegtran -crop WxH+X+Y`, the top left corner is moved up and left to the
+    /// closest MCU boundary, and the rectangle grows by the same amount so it still
+    /// covers the requested area. The rectangle is clipped to the image.
+    ///
+    /// # Errors
+    /// If the rectangle is empty or starts outside the image
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn crop(
+        &self, x: u16, y: u16, width: u16, height: u16,
+    ) -> Result<Coefficients, DecodeErrors>
+    {
+        if width == 0 || height == 0 || x >= self.width || y >= self.height
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Crop {width}x{height}+{x}+{y} is outside of the {}x{} image",
+                self.width, self.height
+            )));
+        }
+        let (mcu_width, mcu_height) = self.mcu_size();
+
+        let (x, y) = (usize::from(x), usize::from(y));
+        // MCUs to skip on the top and left
+        let (mcu_left, mcu_top) = (x / mcu_width, y / mcu_height);
+        let (left, top) = (mcu_left * mcu_width, mcu_top * mcu_height);
+
+        let right = (x + usize::from(width)).min(usize::from(self.width));
+        let bottom = (y + usize::from(height)).min(usize::from(self.height));
+        let (new_width, new_height) = (right - left, bottom - top);
+
+        let components = self
+            .components
+            .iter()
+            .map(|source| {
+                let (h_samp, v_samp) = self.sampling(source);
+
+                let blocks_wide = new_width.div_ceil(mcu_width) * h_samp;
+                let blocks_high = new_height.div_ceil(mcu_height) * v_samp;
+                let (offset_x, offset_y) = (mcu_left * h_samp, mcu_top * v_samp);
+
+                let mut coefficients = Vec::with_capacity(blocks_wide * blocks_high * DCT_BLOCK);
+
+                for block_y in 0..blocks_high
+                {
+                    for block_x in 0..blocks_wide
+                    {
+                        let block = source.block(offset_x + block_x, offset_y + block_y);
+
+                        coefficients.extend_from_slice(block);
+                    }
+                }
+                ComponentCoefficients {
+                    width: (new_width * h_samp).div_ceil(mcu_width / 8),
+                    height: (new_height * v_samp).div_ceil(mcu_height / 8),
+                    blocks_wide,
+                    blocks_high,
+                    coefficients,
+                    ..source.clone()
+                }
+            })
+            .collect();
+
+        Ok(Coefficients {
+            // the rectangle is within the image, so are its dimensions
+            width: new_width as u16,
+            height: new_height as u16,
+            colorspace: self.colorspace,
+            components,
+        })
+    }
+
+    /// Width and height of an MCU in pixels
+    pub(crate) fn mcu_size(&self) -> (usize, usize)
+    {
+        // a lone component is coded in single blocks whatever its sampling factors
+        if self.components.len() == 1
+        {
+            return (8, 8);
+        }
+        let h_max = self.components.iter().map(|x| x.horizontal_sample).max();
+        let v_max = self.components.iter().map(|x| x.vertical_sample).max();
+
+        (
+            8 * usize::from(h_max.unwrap_or(1)),
+            8 * usize::from(v_max.unwrap_or(1)),
+        )
+    }
+
+    /// Sampling factors of `component`, as used to lay out MCUs
+    pub(crate) fn sampling(&self, component: &ComponentCoefficients) -> (usize, usize)
+    {
+        if self.components.len() == 1
+        {
+            return (1, 1);
+        }
+        (
+            usize::from(component.horizontal_sample),
+            usize::from(component.vertical_sample),
+        )
+    }
+
+    /// Mirror the image along `axis`
+    #[allow(clippy::cast_possible_truncation)]
+    fn flip(&self, axis: Axis, edges: EdgeHandling) -> Result<Coefficients, DecodeErrors>
+    {
+        let (mcu_width, mcu_height) = self.mcu_size();
+
+        let (length, mcu_length) = match axis
+        {
+            Axis::Horizontal => (usize::from(self.width), mcu_width),
+            Axis::Vertical => (usize::from(self.height), mcu_height),
+        };
+        // MCUs that are complete along the axis
+        let full = length / mcu_length;
+
+        let new_length = match edges
+        {
+            EdgeHandling::Trim if full == 0 =>
+            {
+                return Err(DecodeErrors::FormatStatic(
+                    "Image is smaller than an MCU, nothing left after trimming",
+                ))
+            }
+            EdgeHandling::Trim => full * mcu_length,
+            EdgeHandling::Perfect if length % mcu_length != 0 =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Image size {length} is not a multiple of the MCU size {mcu_length}, \
+                     the transform is not perfect"
+                )))
+            }
+            EdgeHandling::Keep | EdgeHandling::Perfect => length,
+        };
+        let mut image = self.clone();
+
+        match axis
+        {
+            // dimensions are at most u16::MAX, trimming only shrinks them
+            Axis::Horizontal => image.width = new_length as u16,
+            Axis::Vertical => image.height = new_length as u16,
+        }
+        for (source, component) in self.components.iter().zip(&mut image.components)
+        {
+            let (h_samp, v_samp) = self.sampling(source);
+
+            let (samples, max_samples) = match axis
+            {
+                Axis::Horizontal => (h_samp, mcu_width / 8),
+                Axis::Vertical => (v_samp, mcu_height / 8),
+            };
+            // blocks of complete MCUs, these are mirrored, the rest stays in place
+            let mirrored = full * samples;
+            let new_blocks = new_length.div_ceil(mcu_length) * samples;
+            let new_size = (new_length * samples).div_ceil(max_samples);
+
+            match axis
+            {
+                Axis::Horizontal =>
+                {
+                    component.blocks_wide = new_blocks;
+                    component.width = new_size;
+                }
+                Axis::Vertical =>
+                {
+                    component.blocks_high = new_blocks;
+                    component.height = new_size;
+                }
+            }
+            component.coefficients = vec![0; component.blocks_wide * component.blocks_high * 64];
+
+            for y in 0..component.blocks_high
+            {
+                for x in 0..component.blocks_wide
+                {
+                    let position = match axis
+                    {
+                        Axis::Horizontal => x,
+                        Axis::Vertical => y,
+                    };
+                    let source_position =
+                        if position < mirrored { mirrored - 1 - position } else { position };
+
+                    let (source_x, source_y) = match axis
+                    {
+                        Axis::Horizontal => (source_position, y),
+                        Axis::Vertical => (x, source_position),
+                    };
+                    let block = source.block(source_x, source_y);
+                    let start = DCT_BLOCK * (y * component.blocks_wide + x);
+                    let output = &mut component.coefficients[start..start + DCT_BLOCK];
+
+                    output.copy_from_slice(block);
+
+                    if position < mirrored
+                    {
+                        mirror_block(output.try_into().unwrap(), axis);
+                    }
+                }
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Mirror the image along the top left to bottom right diagonal
+    fn transpose(&self) -> Coefficients
+    {
+        let components = self
+            .components
+            .iter()
+            .map(|source| {
+                let mut coefficients =
+                    vec![0; source.blocks_wide * source.blocks_high * DCT_BLOCK];
+
+                for y in 0..source.blocks_wide
+                {
+                    for x in 0..source.blocks_high
+                    {
+                        let start = DCT_BLOCK * (y * source.blocks_high + x);
+                        let output = &mut coefficients[start..start + DCT_BLOCK];
+
+                        transpose_block(source.block(y, x), output.try_into().unwrap());
+                    }
+                }
+                let mut quantization_table = [0; 64];
+
+                transpose_block(&source.quantization_table, &mut quantization_table);
+
+                ComponentCoefficients {
+                    id: source.id,
+                    horizontal_sample: source.vertical_sample,
+                    vertical_sample: source.horizontal_sample,
+                    width: source.height,
+                    height: source.width,
+                    blocks_wide: source.blocks_high,
+                    blocks_high: source.blocks_wide,
+                    quantization_table,
+                    coefficients,
+                }
+            })
+            .collect();
+
+        Coefficients {
+            width: self.height,
+            height: self.width,
+            colorspace: self.colorspace,
+            components,
+        }
+    }
+}
+
+/// Mirror a block in natural order along `axis`
+///
+/// Coefficients with odd horizontal (for `Axis::Horizontal`) or vertical frequencies
+/// change sign.
+fn mirror_block(block: &mut [i16; 64], axis: Axis)
+{
+    for (position, coefficient) in block.iter_mut().enumerate()
+    {
+        let frequency = match axis
+        {
+            Axis::Horizontal => position % 8,
+            Axis::Vertical => position / 8,
+        };
+        if frequency % 2 == 1
+        {
+            *coefficient = coefficient.wrapping_neg();
+        }
+    }
+}
+
+/// Transpose a block in natural order
+fn transpose_block<T: Copy>(block: &[T; 64], output: &mut [T; 64])
+{
+    for (position, value) in output.iter_mut().enumerate()
+    {
+        *value = block[(position % 8) * 8 + position / 8];
+    }
+}
@@ -72,6 +72,7 @@
 pub use sse::upsample_horizontal_sse;
 
 use crate::components::UpSampler;
+use crate::ZuneJpegOptions;
 pub use crate::upsampler::scalar::{upsample_horizontal, upsample_vertical};
 
 mod avx2;
@@ -79,31 +80,32 @@ mod scalar;
 mod sse;
 
 // choose best possible implementation for this platform
-pub fn choose_horizontal_samp_function(use_unsafe:bool) -> UpSampler
+pub fn choose_horizontal_samp_function(options: &ZuneJpegOptions) -> UpSampler
 {
-    if use_unsafe {
-        #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    {
+        if options.use_sse41()
         {
-            if is_x86_feature_detected!("sse4.1")
-            {
-                debug!("Using sse H up-sampler");
-                return sse::upsample_horizontal_sse;
-            }
+            debug!("Using sse H up-sampler");
+            return sse::upsample_horizontal_sse;
         }
     }
     debug!("Using scalar H up-sampler");
     return scalar::upsample_horizontal;
 }
-pub fn choose_hv_samp_function(use_unsafe:bool) -> UpSampler
+pub fn choose_hv_samp_function(options: &ZuneJpegOptions) -> UpSampler
 {
-    if use_unsafe {
-        #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    {
+        if options.use_avx2()
         {
-            if is_x86_feature_detected!("avx2")
-            {
-                debug!("Using avx HV up-sampler");
-                return avx2::upsample_hv_simd;
-            }
+            debug!("Using avx HV up-sampler");
+            return avx2::upsample_hv_simd;
+        }
+        if options.use_sse41()
+        {
+            debug!("Using sse HV up-sampler");
+            return sse::upsample_hv_sse;
         }
     }
     debug!("using scalar HV up-sampler");
@@ -112,7 +114,7 @@ pub fn choose_hv_samp_function(use_unsafe:bool) -> UpSampler
 
 /// Upsample nothing
 
-pub fn upsample_no_op(_: &[i16], _: usize) -> Vec<i16>
+pub fn upsample_no_op(_: &[i16], _: usize, _: usize) -> Vec<i16>
 {
     return Vec::new();
 }
@@ -128,8 +130,8 @@ fn upsample_sse_v1()
     let v: Vec<i16> = (0..128).collect();
 
     assert_eq!(
-        upsample_horizontal_sse(&v, v.len() * 2),
-        crate::upsampler::scalar::upsample_horizontal(&v, v.len() * 2),
+        upsample_horizontal_sse(&v, v.len(), v.len() * 2),
+        crate::upsampler::scalar::upsample_horizontal(&v, v.len(), v.len() * 2),
         "Algorithms do not match"
     );
 }
@@ -143,9 +145,51 @@ fn upsample_sse_v2()
 
     let v: Vec<i16> = (0..1280).rev().collect();
 
+    // rows of 8, 40 and 1280 samples
+    for stride in [8, 40, 1280]
+    {
+        assert_eq!(
+            upsample_horizontal_sse(&v, stride, v.len() * 2),
+            upsample_horizontal(&v, stride, v.len() * 2),
+            "Algorithms do not match"
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "x86")]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn upsample_hv_avx2()
+{
+    if !is_x86_feature_detected!("avx2")
+    {
+        return;
+    }
+    let v: Vec<i16> = (0..1280).map(|x| (x * 37 % 255) as i16).collect();
+
+    // rows shorter than the vector loop, with a scalar tail and of whole vectors
+    for stride in [8, 20, 40, 128]
+    {
+        assert_eq!(
+            avx2::upsample_hv_simd(&v, stride, v.len() * 4),
+            scalar::upsample_hv(&v, stride, v.len() * 4),
+            "Algorithms do not match"
+        );
+    }
+}
+
+#[test]
+fn upsample_rows()
+{
+    // two rows of 4 samples, each row is filtered on its own
+    let v: Vec<i16> = vec![0, 40, 80, 120, 200, 200, 200, 200];
+
     assert_eq!(
-        upsample_horizontal_sse(&v, v.len() * 2),
-        upsample_horizontal(&v, v.len() * 2),
-        "Algorithms do not match"
+        upsample_horizontal(&v, 4, v.len() * 2),
+        [0, 10, 30, 50, 70, 90, 110, 120, 200, 200, 200, 200, 200, 200, 200, 200]
+    );
+    assert_eq!(
+        upsample_vertical(&v, 4, v.len() * 2),
+        [0, 40, 80, 120, 50, 80, 110, 140, 150, 160, 170, 180, 200, 200, 200, 200]
     );
 }
@@ -8,29 +8,56 @@ use std::arch::x86::*;
 use std::arch::x86_64::*;
 use std::convert::TryInto;
 
+use crate::upsampler::scalar::{upsample_row, upsample_vertical};
+
 #[inline]
-pub fn upsample_horizontal_sse(input: &[i16], output_len: usize) -> Vec<i16>
+pub fn upsample_horizontal_sse(input: &[i16], stride: usize, output_len: usize) -> Vec<i16>
+{
+    let mut out = vec![0; output_len];
+
+    assert!(
+        stride > 0 && output_len == input.len() * 2,
+        "Too Short of a vector, cannot upsample"
+    );
+
+    for (input, out) in input.chunks_exact(stride).zip(out.chunks_exact_mut(stride * 2))
+    {
+        if stride < 8
+        {
+            // too short for the vector loop, fall back to scalar
+            upsample_row(input, out);
+        }
+        else
+        {
+            unsafe { upsample_row_sse(input, out) }
+        }
+    }
+    return out;
+}
+
+/// Upsample vertically then horizontally, the vertical pass is scalar
+/// because the compiler already vectorizes it well.
+pub fn upsample_hv_sse(input: &[i16], stride: usize, output_len: usize) -> Vec<i16>
 {
-    unsafe { upsample_horizontal_sse_u(input, output_len) }
+    let first_pass = upsample_vertical(input, stride, input.len() * 2);
+
+    return upsample_horizontal_sse(&first_pass, stride, output_len);
 }
 
-/// Upsample using SSE to improve speed
+/// Upsample a single row using SSE to improve speed
 ///
-/// The sampling filter is bi-linear or triangle filter
+/// The sampling filter is bi-linear or triangle filter, the row
+/// should have at least 8 samples
 #[target_feature(enable = "sse2")]
 //Some things are weird...
 #[target_feature(enable = "sse4.1")]
 #[inline]
-pub unsafe fn upsample_horizontal_sse_u(input: &[i16], output_len: usize) -> Vec<i16>
+unsafe fn upsample_row_sse(input: &[i16], out: &mut [i16])
 {
-    //let mut out = align_zero_alloc::<i16, 16>(output_len);
-    let mut out = vec![0; output_len];
-
-    // set first 8 pixels linearly
-    // Assert that out has more than 8 elements and input has more than 4
+    // Assert that out is twice input and input has at least 8 elements
     // Do this before otherwise Rust will bounds check all of these items like some
     // paranoid guy.
-    assert!(out.len() > 8 && input.len() > 5);
+    assert!(input.len() >= 8 && out.len() == input.len() * 2);
 
     //@ OPTIMIZE TIP: Borrow slices of a definite length and turn them into a reference
     // array to eliminate bounds checking.
@@ -107,28 +134,16 @@ pub unsafe fn upsample_horizontal_sse_u(input: &[i16], output_len: usize) -> Vec
         );
     }
 
-    // Do the last 8 manually because we can't  do it  with SSE because of out of bounds access
-    let ol = out.len() - 8;
-
-    let il = input.len() - 4;
-
-    let l_out: &mut [i16; 8] = out.get_mut(ol..ol + 8).unwrap().try_into().unwrap();
-
-    l_out[0] = (input[il] * 3 + input[il - 1] + 2) >> 2;
-
-    l_out[1] = (input[il] * 3 + input[il + 1] + 2) >> 2;
-
-    l_out[2] = (input[il + 1] * 3 + input[il] + 2) >> 2;
-
-    l_out[3] = (input[il + 1] * 3 + input[il + 1] + 2) >> 2;
-
-    l_out[4] = (input[il + 2] * 3 + input[il + 2] + 2) >> 2;
-
-    l_out[5] = (input[il + 2] * 3 + input[il + 1] + 2) >> 2;
+    // Do the rest manually because we can't do it with SSE because of out of bounds access
+    for i in ((inl >> 2) - 1) << 2..inl - 1
+    {
+        let sample = 3 * input[i] + 2;
 
-    l_out[6] = (input[il + 2] * 3 + input[il + 3] + 2) >> 2;
+        out[i * 2] = (sample + input[i - 1]) >> 2;
 
-    l_out[7] = input[il + 3];
+        out[i * 2 + 1] = (sample + input[i + 1]) >> 2;
+    }
+    out[inl * 2 - 2] = (input[inl - 1] * 3 + input[inl - 2] + 2) >> 2;
 
-    return out;
+    out[inl * 2 - 1] = input[inl - 1];
 }
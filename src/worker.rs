@@ -4,6 +4,8 @@ use std::convert::TryInto;
 use crate::color_convert::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
 use crate::components::Components;
 use crate::decoder::{ColorConvert16Ptr, IDCTPtr};
+use crate::idct::scaled::ScaledIdct;
+use crate::mcu::DCT_BLOCK;
 use crate::misc::ColorSpace;
 /// Handle everything else in jpeg processing that doesn't involve bitstream decoding
 ///
@@ -84,6 +86,52 @@ pub(crate) fn post_process(
                        input_colorspace, output_colorspace, output,  width);
 }
 
+/// Handle everything else in jpeg processing for an image decoded at `idct` size/8 of
+/// its size
+///
+/// Like [`post_process`] but every block is transformed to `size` by `size` samples,
+/// `width_stride` of each component in `component_data` should be scaled to match.
+#[allow(clippy::too_many_arguments)]
+#[rustfmt::skip]
+pub(crate) fn post_process_scaled(
+    coeff: &[&[i16]; 3],
+    component_data: &[Components],
+    idct: &ScaledIdct,
+    color_convert_16: ColorConvert16Ptr,
+    input_colorspace: ColorSpace,
+    output_colorspace: ColorSpace,
+    output: &mut [u8],
+    width: usize,
+)
+{
+    let x = min(
+        input_colorspace.num_components(),
+        output_colorspace.num_components(),
+    );
+    let size = idct.size();
+    let mut unprocessed = [vec![], vec![], vec![]];
+
+    for z in 0..x
+    {
+        // blocks are in raster order, rows of `stride` samples once transformed
+        let stride = component_data[z].width_stride;
+        let blocks_wide = stride / size;
+        let mut samples = vec![0; coeff[z].len() / DCT_BLOCK * size * size];
+
+        for (i, block) in coeff[z].chunks_exact(DCT_BLOCK).enumerate()
+        {
+            let start = (i / blocks_wide) * size * stride + (i % blocks_wide) * size;
+
+            idct.dequantize_and_idct(block, &component_data[z].quantization_table.0,
+                                     &mut samples[start..], stride);
+        }
+        unprocessed[z] = samples;
+    }
+
+    post_process_inner(&mut unprocessed, component_data, color_convert_16,
+                       input_colorspace, output_colorspace, output, width);
+}
+
 #[rustfmt::skip]
 pub(crate) fn post_process_inner(
     unprocessed: &mut [Vec<i16>; 3], component_data: &[Components],
@@ -99,13 +147,19 @@ pub(crate) fn post_process_inner(
     // maximum sampling factors are in Y-channel, no need to pass them.
     let h_samp = component_data[0].horizontal_sample;
     let v_samp = component_data[0].vertical_sample;
+    // Width of a row of samples which takes into account fill bytes
+    let stride = component_data[0].width_stride;
 
     if h_samp != 1 || v_samp != 1
     {
         // carry out upsampling , the return vector overwrites the original vector
         for i in 1..x
         {
-            unprocessed[i] = (component_data[i].up_sampler)(&unprocessed[i], unprocessed[0].len());
+            unprocessed[i] = (component_data[i].up_sampler)(
+                &unprocessed[i],
+                component_data[i].width_stride,
+                unprocessed[0].len(),
+            );
         }
     }
 
@@ -114,17 +168,17 @@ pub(crate) fn post_process_inner(
     {
         (ColorSpace::YCbCr | ColorSpace::GRAYSCALE, ColorSpace::GRAYSCALE) =>
         {
-            ycbcr_to_grayscale(&unprocessed[0], width, output);
+            ycbcr_to_grayscale(&unprocessed[0], width, stride, output);
         }
 
         (ColorSpace::YCbCr, ColorSpace::YCbCr) =>
         {
-            ycbcr_to_ycbcr(unprocessed, width, h_samp, v_samp, output);
+            ycbcr_to_ycbcr(unprocessed, width, stride, output);
         }
 
         (ColorSpace::YCbCr, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
         {
-            color_convert_ycbcr(unprocessed, width, h_samp, v_samp,
+            color_convert_ycbcr(unprocessed, width, stride,
                 output_colorspace, color_convert_16,  output);
         }
         // For the other components we do nothing(currently)
@@ -141,13 +195,12 @@ pub(crate) fn post_process_inner(
     clippy::unwrap_used
 )]
 fn color_convert_ycbcr(
-    mcu_block: &[Vec<i16>; 3], width: usize, h_samp: usize, v_samp: usize,
+    mcu_block: &[Vec<i16>; 3], width: usize, width_chunk: usize,
     output_colorspace: ColorSpace, color_convert_16: ColorConvert16Ptr, output: &mut [u8],
 )
 {
-    let mcu_chunks = mcu_block[0].len() / (h_samp * v_samp);
-    // Width of image which takes into account fill bytes(it may be larger than actual width).
-    let width_chunk = mcu_chunks >> 3;
+    // `width_chunk` is the width of image which takes into account fill bytes(it may be
+    // larger than actual width).
     let stride = width * output_colorspace.num_components();
 
     let mut start = 0;
@@ -168,7 +221,8 @@ fn color_convert_ycbcr(
         .zip(mcu_block[1].chunks_exact(width_chunk))
         .zip(mcu_block[2].chunks_exact(width_chunk))
     {
-        let elements = (y_width.len() / 16).saturating_sub(1);
+        // whole chunks of 16 pixels in the width, the rest is done by the last chunk
+        let elements = width / 16;
 
         let mut position = 0;
         let out = &mut output[start..end];
@@ -180,9 +234,9 @@ fn color_convert_ycbcr(
             let mut cb_out = [0; 16];
             let mut cr_out = [0; 16];
             // copy those small widths to that buffer
-            y_out[0..y_width.len()].copy_from_slice(y_width);
-            cb_out[0..cb_width.len()].copy_from_slice(cb_width);
-            cr_out[0..cr_width.len()].copy_from_slice(cr_width);
+            y_out[0..width].copy_from_slice(&y_width[0..width]);
+            cb_out[0..width].copy_from_slice(&cb_width[0..width]);
+            cr_out[0..width].copy_from_slice(&cr_width[0..width]);
             // we handle widths less than 16 a bit differently, allocating a temporary
             // buffer and writing to that and then flushing to the out buffer
             // because of the optimizations applied below,
@@ -214,33 +268,17 @@ fn color_convert_ycbcr(
         }
 
         // we have more pixels in the end that can't be handled by the main loop.
-        // move pointer back a little bit to get last 16 bytes,
-        // color convert, and overwrite
+        // color convert the last 16 pixels of the width, which may be followed by
+        // fill samples in the row, and overwrite
         // This means some values will be color converted twice.
+        let last = width - 16;
 
-        let diff = 64_usize.saturating_sub(stride.saturating_sub(position));
-
-        position = position.saturating_sub(diff);
+        position = last * output_colorspace.num_components();
 
         (color_convert_16)(
-            y_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-            cb_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-            cr_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
+            y_width[last..width].try_into().unwrap(),
+            cb_width[last..width].try_into().unwrap(),
+            cr_width[last..width].try_into().unwrap(),
             out,
             &mut position,
         );
@@ -0,0 +1,119 @@
+//! Minimal XMP property extraction
+//!
+//! XMP is RDF serialized as XML, a simple property can be written either as an
+//! attribute of an `rdf:Description` element
+//!
+//! ```xml
+//! <rdf:Description hdrgm:GainMapMax="2.5"/>
+//! ```
+//! or as an element
+//! ```xml
+//! <hdrgm:GainMapMax>2.5</hdrgm:GainMapMax>
+//! ```
+//! and array properties wrap their values in `rdf:li` elements
+//! ```xml
+//! <hdrgm:GainMapMax><rdf:Seq><rdf:li>2.5</rdf:li><rdf:li>2.4</rdf:li></rdf:Seq></hdrgm:GainMapMax>
+//! ```
+//!
+//! We don't carry an XML parser, so properties are looked up by their qualified name
+//! as it appears in the packet, namespace prefixes are not resolved.
+
+/// Find the values of the property `name` (e.g `hdrgm:GainMapMax`) in an XMP packet
+///
+/// Simple properties return a single value, array properties return one value per
+/// `rdf:li` item.
+///
+/// Returns `None` if the property is not present.
+pub(crate) fn property_values<'a>(xmp: &'a str, name: &str) -> Option<Vec<&'a str>>
+{
+    if let Some(value) = attribute_value(xmp, name)
+    {
+        return Some(vec![value]);
+    }
+    let content = element_content(xmp, name)?;
+
+    if content.contains("<rdf:li")
+    {
+        let mut values = vec![];
+
+        for item in content.split("<rdf:li").skip(1)
+        {
+            // skip attributes of rdf:li
+            let item = &item[item.find('>')? + 1..];
+
+            values.push(item[..item.find("</rdf:li>")?].trim());
+        }
+        return Some(values);
+    }
+    Some(vec![content.trim()])
+}
+
+/// Find the value of the property `name` in an XMP packet, taking the first value
+/// of array properties
+pub(crate) fn property_value<'a>(xmp: &'a str, name: &str) -> Option<&'a str>
+{
+    property_values(xmp, name)?.first().copied()
+}
+
+/// Value of an attribute `name="value"` or `name='value'`
+fn attribute_value<'a>(xmp: &'a str, name: &str) -> Option<&'a str>
+{
+    let mut search = xmp;
+
+    while let Some(pos) = search.find(name)
+    {
+        let before = search[..pos].chars().next_back();
+        let rest = search[pos + name.len()..].trim_start();
+
+        search = &search[pos + name.len()..];
+
+        if !before.is_some_and(char::is_whitespace)
+        {
+            continue;
+        }
+        if let Some(rest) = rest.strip_prefix('=')
+        {
+            let rest = rest.trim_start();
+            let quote = rest.chars().next()?;
+
+            if quote == '"' || quote == '\''
+            {
+                let rest = &rest[1..];
+
+                return Some(&rest[..rest.find(quote)?]);
+            }
+        }
+    }
+    None
+}
+
+/// Text between `<name>` and `</name>`
+fn element_content<'a>(xmp: &'a str, name: &str) -> Option<&'a str>
+{
+    let open = format!("<{name}>");
+    let close = format!("</{name}>");
+
+    let start = xmp.find(&open)? + open.len();
+    let end = start + xmp[start..].find(&close)?;
+
+    Some(&xmp[start..end])
+}
+
+//---------------------------------------------
+// TEST
+//----------------------------------------------
+#[test]
+fn xmp_attribute_and_element_properties()
+{
+    let xmp = r#"<rdf:Description hdrgm:Version="1.0"
+        hdrgm:GainMapMax = '2.5' xhdrgm:Gamma="3">
+        <hdrgm:Gamma>1.2</hdrgm:Gamma>
+        <hdrgm:OffsetSDR><rdf:Seq><rdf:li>0.1</rdf:li><rdf:li> 0.2 </rdf:li></rdf:Seq></hdrgm:OffsetSDR>
+        </rdf:Description>"#;
+
+    assert_eq!(property_value(xmp, "hdrgm:Version"), Some("1.0"));
+    assert_eq!(property_value(xmp, "hdrgm:GainMapMax"), Some("2.5"));
+    assert_eq!(property_value(xmp, "hdrgm:Gamma"), Some("1.2"));
+    assert_eq!(property_values(xmp, "hdrgm:OffsetSDR"), Some(vec!["0.1", "0.2"]));
+    assert_eq!(property_value(xmp, "hdrgm:OffsetHDR"), None);
+}
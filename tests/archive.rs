@@ -0,0 +1,119 @@
+use zune_jpeg::archive::{compress, decompress, MAGIC};
+
+fn open(path: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/" + path).unwrap()
+}
+
+fn round_trip(data: &[u8]) -> Vec<u8>
+{
+    let compressed = compress(data).unwrap();
+
+    assert!(compressed.starts_with(MAGIC));
+    assert_eq!(decompress(&compressed).unwrap(), data);
+
+    compressed
+}
+
+#[test]
+fn archive_baseline()
+{
+    let data = open("tests/inputs/medium_no_samp_2500x1786.jpg");
+
+    assert!(round_trip(&data).len() < data.len() * 9 / 10);
+}
+
+#[test]
+fn archive_progressive()
+{
+    let data = open("tests/inputs/huffman_third_index.jpg");
+
+    assert!(round_trip(&data).len() < data.len() * 9 / 10);
+}
+
+#[test]
+fn archive_subsampling()
+{
+    for path in [
+        "tests/inputs/medium_horiz_samp_2500x1786.jpg",
+        "tests/inputs/medium_vertical_samp_2500x1786.jpg",
+    ]
+    {
+        let data = open(path);
+
+        assert!(round_trip(&data).len() < data.len() * 9 / 10, "{path}");
+    }
+}
+
+#[test]
+fn archive_trailing_data()
+{
+    let mut data = open("tests/inputs/huffman_third_index.jpg");
+
+    data.extend_from_slice(b"data after the image");
+
+    round_trip(&data);
+}
+
+#[test]
+fn archive_truncated()
+{
+    let data = open("tests/inputs/huffman_third_index.jpg");
+
+    // the missing data is stored as is
+    round_trip(&data[..data.len() / 2]);
+}
+
+#[test]
+fn archive_not_jpeg()
+{
+    round_trip(b"");
+    round_trip(b"not a JPEG file");
+}
+
+#[test]
+fn archive_no_scan()
+{
+    let mut data = open("test-images/test-progressive.jpg");
+
+    // without the marker of the comment the segments run into the entropy coded data,
+    // while headers are still found by looking for markers
+    assert_eq!(data[20], 0xFF);
+    data[20] = 0x00;
+
+    round_trip(&data);
+}
+
+#[test]
+fn archive_corrupt()
+{
+    let data = open("tests/inputs/huffman_third_index.jpg");
+    let mut compressed = compress(&data).unwrap();
+
+    assert!(decompress(&data).is_err());
+    assert!(decompress(&compressed[..compressed.len() / 2]).is_err());
+
+    let middle = compressed.len() / 2;
+
+    compressed[middle] ^= 0x10;
+
+    assert!(decompress(&compressed).is_err());
+}
+
+#[test]
+fn archive_truncated_header()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let compressed = compress(&data).unwrap();
+
+    for length in 0..100
+    {
+        let mut truncated = compressed[..length].to_vec();
+
+        assert!(decompress(&truncated).is_err());
+
+        truncated.extend_from_slice(&[0xFF, 0xD9]);
+
+        assert!(decompress(&truncated).is_err());
+    }
+}
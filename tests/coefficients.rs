@@ -0,0 +1,90 @@
+use mozjpeg::ColorSpace as OutColorSpace;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::mozjpeg_encode;
+
+mod common;
+
+/// Quality 80 without trellis quantization, so both modes quantize the same way
+fn settings(progressive: bool) -> impl FnOnce(&mut mozjpeg::Compress)
+{
+    move |comp| {
+        comp.set_fastest_defaults();
+        comp.set_quality(80.0);
+
+        if progressive
+        {
+            comp.set_progressive_mode();
+        }
+    }
+}
+
+#[test]
+fn coefficients_baseline_progressive()
+{
+    let (width, height) = (33, 17);
+    let pixels = (0..width * height * 3)
+        .map(|x| ((x * 37) % 251) as u8)
+        .collect::<Vec<u8>>();
+
+    let rgb = OutColorSpace::JCS_RGB;
+    let baseline = mozjpeg_encode(rgb, &pixels, (width, height), settings(false));
+    let progressive = mozjpeg_encode(rgb, &pixels, (width, height), settings(true));
+
+    let coefficients = Decoder::new().decode_coefficients(&baseline).unwrap();
+
+    assert_eq!((coefficients.width, coefficients.height), (33, 17));
+    assert_eq!(coefficients.colorspace, ColorSpace::YCbCr);
+
+    // 4:2:0, the luminance plane covers two MCUs of 16x16 pixels vertically
+    let dimensions = coefficients
+        .components
+        .iter()
+        .map(|x| (x.width, x.height, x.blocks_wide, x.blocks_high))
+        .collect::<Vec<_>>();
+
+    assert_eq!(dimensions, [(33, 17, 6, 4), (17, 9, 3, 2), (17, 9, 3, 2)]);
+
+    for component in &coefficients.components
+    {
+        assert_eq!(
+            component.coefficients.len(),
+            component.blocks_wide * component.blocks_high * 64
+        );
+    }
+    assert_eq!(Decoder::new().decode_coefficients(&progressive).unwrap(), coefficients);
+}
+
+#[test]
+fn coefficients_dc()
+{
+    let (width, height) = (16, 16);
+    let pixels = (0..width * height)
+        .map(|x| ((x % width) * 8 + (x / width) * 4) as u8)
+        .collect::<Vec<u8>>();
+
+    let gray = OutColorSpace::JCS_GRAYSCALE;
+    let data = mozjpeg_encode(gray, &pixels, (width, height), settings(false));
+
+    let coefficients = Decoder::new().decode_coefficients(&data).unwrap();
+    let decoded = Decoder::new_with_options(
+        ZuneJpegOptions::default().set_out_colorspace(ColorSpace::GRAYSCALE),
+    )
+    .decode_buffer(&data)
+    .unwrap();
+
+    let component = &coefficients.components[0];
+
+    // a dequantized DC coefficient is eight times the level shifted block average
+    for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)]
+    {
+        let quant = i32::from(component.quantization_table[0]);
+        let dc = i32::from(component.block(x, y)[0]) * quant;
+
+        let sum = (0..64)
+            .map(|i| i32::from(decoded[(y * 8 + i / 8) * width + x * 8 + i % 8]))
+            .sum::<i32>();
+
+        assert!((dc - (sum / 8 - 1024)).abs() <= 8, "{dc} {sum}");
+    }
+}
@@ -0,0 +1,87 @@
+//! Helpers shared by the integration tests
+// each test crate uses only some of these
+#![allow(dead_code)]
+
+use mozjpeg::ColorSpace as OutColorSpace;
+use zune_jpeg::encoder::{Encoder, Subsampling};
+use zune_jpeg::ColorSpace;
+
+/// Encode `pixels` with mozjpeg, `configure` sets the compressor up before it
+/// starts, e.g its quality, sampling factors or progressive mode
+pub fn mozjpeg_encode(
+    colorspace: OutColorSpace, pixels: &[u8], (width, height): (usize, usize),
+    configure: impl FnOnce(&mut mozjpeg::Compress),
+) -> Vec<u8>
+{
+    let mut comp = mozjpeg::Compress::new(colorspace);
+
+    configure(&mut comp);
+    comp.set_size(width, height);
+    comp.set_mem_dest();
+    comp.start_compress();
+
+    assert!(comp.write_scanlines(pixels));
+
+    comp.finish_compress();
+    comp.data_to_vec().unwrap()
+}
+
+/// Smooth RGB gradients, `width` by `height` pixels, up to 64 by 64
+pub fn gradient(width: usize, height: usize) -> Vec<u8>
+{
+    (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+
+            [x * 3 + 20, y * 3 + 20, x + y + 60]
+        })
+        .map(|x| x as u8)
+        .collect()
+}
+
+/// Busy RGB pixels, `width` by `height`, so up-sampling differences show
+pub fn pattern(width: usize, height: usize) -> Vec<u8>
+{
+    (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+
+            [x * 7 + y, (x ^ y) * 5, x * y + 3 * y]
+        })
+        .map(|x| x as u8)
+        .collect()
+}
+
+/// Encode a `width` by `height` [`pattern`], keeping only its green channel for grayscale
+pub fn encode(
+    (width, height): (usize, usize), subsampling: Subsampling, colorspace: ColorSpace,
+    restart_interval: u16,
+) -> Vec<u8>
+{
+    let mut pixels = pattern(width, height);
+
+    if colorspace == ColorSpace::GRAYSCALE
+    {
+        pixels = pixels.chunks_exact(3).map(|x| x[1]).collect();
+    }
+    Encoder::new()
+        .set_subsampling(subsampling)
+        .set_restart_interval(restart_interval)
+        .encode(&pixels, width as u16, height as u16, colorspace)
+        .unwrap()
+}
+
+/// Mean and largest absolute difference of two images
+pub fn difference(a: &[u8], b: &[u8]) -> (f32, u8)
+{
+    assert_eq!(a.len(), b.len());
+
+    let sum = a
+        .iter()
+        .zip(b)
+        .map(|(&x, &y)| u32::from(x.abs_diff(y)))
+        .sum::<u32>();
+    let max = a.iter().zip(b).map(|(&x, &y)| x.abs_diff(y)).max().unwrap();
+
+    (sum as f32 / a.len() as f32, max)
+}
@@ -0,0 +1,137 @@
+use mozjpeg::ColorSpace as OutColorSpace;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::{difference, gradient, mozjpeg_encode};
+
+mod common;
+
+#[test]
+fn decode_large_ac_coefficients()
+{
+    // noise at the finest quantization with optimized tables, which give short codes
+    // to the symbols of large AC coefficients
+    let pixels = (0..64 * 64_u32)
+        .map(|x| (x.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect::<Vec<u8>>();
+
+    let data = mozjpeg_encode(OutColorSpace::JCS_GRAYSCALE, &pixels, (64, 64), |comp| {
+        comp.set_quality(100.0);
+        comp.set_optimize_coding(true);
+    });
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE);
+    let output = Decoder::new_with_options(options).decode_buffer(&data).unwrap();
+    let expected = jpeg_decoder::Decoder::new(data.as_slice()).decode().unwrap();
+
+    assert!(difference(&output, &expected).1 <= 2);
+}
+
+#[test]
+fn decode_color_conversion()
+{
+    // rows of whole chunks of 16 pixels, a partial one, and fewer than 16 pixels
+    for (width, height) in [(64, 32), (48, 16), (37, 24), (100, 24), (5, 3)]
+    {
+        let pixels = gradient(width, height);
+        let jpeg = mozjpeg_encode(OutColorSpace::JCS_RGB, &pixels, (width, height), |comp| {
+            comp.set_fastest_defaults();
+            comp.set_quality(95.0);
+            comp.set_chroma_sampling_pixel_sizes((1, 1), (1, 1));
+        });
+
+        let decoded = Decoder::new().decode_buffer(&jpeg).unwrap();
+        let expected = jpeg_decoder::Decoder::new(jpeg.as_slice()).decode().unwrap();
+
+        assert!(difference(&decoded, &expected).1 <= 4, "{width}x{height}");
+    }
+}
+
+/// Gray 8 by 8 blocks of pseudo random levels, `width` by `height` pixels
+fn blocks(width: usize, height: usize) -> Vec<u8>
+{
+    let mut state = 22_u32;
+    let levels: Vec<u8> = (0..width.div_ceil(8) * height.div_ceil(8))
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 24) as u8
+        })
+        .collect();
+
+    (0..width * height)
+        .map(|i| levels[(i % width) / 8 + (i / width) / 8 * width.div_ceil(8)])
+        .collect()
+}
+
+#[test]
+fn decode_end_of_scan()
+{
+    // the stream reads up to the end of the image with blocks of the last MCU left
+    let pixels = (0..33 * 17 * 3)
+        .map(|i: usize| ((i / 3) as u8).wrapping_mul(7))
+        .collect::<Vec<u8>>();
+    let sequential = mozjpeg_encode(OutColorSpace::JCS_RGB, &pixels, (33, 17), |comp| {
+        comp.set_fastest_defaults();
+        comp.set_quality(50.0);
+        comp.set_chroma_sampling_pixel_sizes((1, 1), (1, 1));
+    });
+
+    // a progressive DC scan decodes DC codes and their extra bits back to back
+    let pixels = blocks(32, 32);
+    let progressive = mozjpeg_encode(OutColorSpace::JCS_GRAYSCALE, &pixels, (32, 32), |comp| {
+        comp.set_fastest_defaults();
+        comp.set_quality(75.0);
+        comp.set_progressive_mode();
+    });
+
+    for jpeg in [sequential, progressive]
+    {
+        let decoded = Decoder::new().decode_buffer(&jpeg).unwrap();
+        let expected = jpeg_decoder::Decoder::new(jpeg.as_slice()).decode().unwrap();
+
+        assert!(difference(&decoded, &expected).1 <= 4);
+    }
+}
+
+/// Decode `data` to RGB with libjpeg, which up-samples chroma with its fancy filter
+fn libjpeg_decode(data: &[u8]) -> Vec<u8>
+{
+    let mut image = mozjpeg::Decompress::new_mem(data).unwrap().rgb().unwrap();
+    let pixels: Vec<[u8; 3]> = image.read_scanlines().unwrap();
+
+    assert!(image.finish_decompress());
+
+    pixels.concat()
+}
+
+#[test]
+fn decode_chroma_upsampling()
+{
+    // scalar, SSE and AVX2 up-samplers, those the CPU lacks fall back to the ones before
+    let paths = [
+        ("scalar", ZuneJpegOptions::new().set_use_unsafe(false)),
+        ("sse", ZuneJpegOptions::new().set_use_avx2(false)),
+        ("avx2", ZuneJpegOptions::new()),
+    ];
+    // rows of whole and partial MCUs, and an odd number of MCU rows
+    for (width, height) in [(64, 64), (48, 32), (37, 24), (64, 40), (9, 5)]
+    {
+        let pixels = gradient(width, height);
+
+        for sampling in [(2, 1), (2, 2)]
+        {
+            let jpeg = mozjpeg_encode(OutColorSpace::JCS_RGB, &pixels, (width, height), |comp| {
+                comp.set_fastest_defaults();
+                comp.set_quality(95.0);
+                comp.set_chroma_sampling_pixel_sizes(sampling, sampling);
+            });
+            let expected = libjpeg_decode(&jpeg);
+
+            for (path, options) in paths
+            {
+                let decoded = Decoder::new_with_options(options).decode_buffer(&jpeg).unwrap();
+
+                let max = difference(&decoded, &expected).1;
+                assert!(max <= 3, "{width}x{height} {sampling:?} {path}");
+            }
+        }
+    }
+}
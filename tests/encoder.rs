@@ -0,0 +1,259 @@
+use zune_jpeg::encoder::{Encoder, Subsampling};
+use zune_jpeg::rewrite::MetadataRewriter;
+use zune_jpeg::segments::Segments;
+use zune_jpeg::transcode::Scan;
+use zune_jpeg::{ColorSpace, Decoder, Marker};
+
+use crate::common::{difference, gradient};
+
+mod common;
+
+#[test]
+fn encode_round_trip()
+{
+    let subsamplings = [
+        (Subsampling::Chroma444, (1, 1)),
+        (Subsampling::Chroma422, (2, 1)),
+        (Subsampling::Chroma420, (2, 2)),
+    ];
+
+    for (width, height) in [(64, 48), (37, 21), (5, 3)]
+    {
+        let pixels = gradient(width, height);
+
+        for (subsampling, sampling) in subsamplings
+        {
+            let jpeg = Encoder::new()
+                .set_quality(95)
+                .set_subsampling(subsampling)
+                .encode(&pixels, width as u16, height as u16, ColorSpace::RGB)
+                .unwrap();
+
+            let image = Decoder::new().decode_coefficients(&jpeg).unwrap();
+            let luma = &image.components[0];
+
+            assert_eq!((luma.horizontal_sample, luma.vertical_sample), sampling);
+
+            let mut decoder = Decoder::new();
+            let decoded = decoder.decode_buffer(&jpeg).unwrap();
+
+            assert_eq!((decoder.width(), decoder.height()), (width as u16, height as u16));
+
+            let (mean, max) = difference(&decoded, &pixels);
+
+            assert!(mean < 1.5 && max <= 8, "{width}x{height} {subsampling:?} {mean} {max}");
+
+            let other = jpeg_decoder::Decoder::new(jpeg.as_slice()).decode().unwrap();
+            let (mean, max) = difference(&other, &pixels);
+
+            assert!(mean < 1.5 && max <= 8, "{width}x{height} {subsampling:?} {mean} {max}");
+        }
+    }
+}
+
+/// Round trip of subsampled images through this decoder, with an odd number of MCU
+/// rows and MCUs past the right edge
+#[test]
+fn encode_round_trip_subsampled()
+{
+    for (width, height) in [(64, 48), (37, 21), (5, 3)]
+    {
+        let pixels = gradient(width, height);
+
+        for subsampling in [Subsampling::Chroma422, Subsampling::Chroma420]
+        {
+            for progressive in [false, true]
+            {
+                let jpeg = Encoder::new()
+                    .set_quality(95)
+                    .set_subsampling(subsampling)
+                    .set_progressive(progressive)
+                    .encode(&pixels, width as u16, height as u16, ColorSpace::RGB)
+                    .unwrap();
+
+                let decoded = Decoder::new().decode_buffer(&jpeg).unwrap();
+                let (mean, max) = difference(&decoded, &pixels);
+
+                assert!(
+                    mean < 1.5 && max <= 8,
+                    "{width}x{height} {subsampling:?} {progressive} {mean} {max}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn encode_colorspaces()
+{
+    let (width, height) = (19, 11);
+    let rgb = gradient(width, height);
+    let expected = Decoder::new()
+        .decode_buffer(&Encoder::new().encode(&rgb, 19, 11, ColorSpace::RGB).unwrap())
+        .unwrap();
+
+    // alpha is dropped
+    let rgba = rgb
+        .chunks_exact(3)
+        .flat_map(|x| [x[0], x[1], x[2], 7])
+        .collect::<Vec<u8>>();
+
+    for colorspace in [ColorSpace::RGBA, ColorSpace::RGBX]
+    {
+        let jpeg = Encoder::new().encode(&rgba, 19, 11, colorspace).unwrap();
+
+        assert_eq!(Decoder::new().decode_buffer(&jpeg).unwrap(), expected);
+    }
+
+    let gray = rgb.iter().step_by(3).copied().collect::<Vec<u8>>();
+    let jpeg = Encoder::new()
+        .set_quality(95)
+        .encode(&gray, 19, 11, ColorSpace::GRAYSCALE)
+        .unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&jpeg).unwrap();
+
+    assert_eq!(decoder.info().unwrap().components, 1);
+
+    let decoded = jpeg_decoder::Decoder::new(jpeg.as_slice()).decode().unwrap();
+    let (mean, max) = difference(&decoded, &gray);
+
+    assert!(mean < 1.0 && max <= 4, "{mean} {max}");
+}
+
+#[test]
+fn encode_scans_and_restarts()
+{
+    let (width, height) = (45, 37);
+    let pixels = gradient(width, height);
+    let encode = |encoder: Encoder| encoder.encode(&pixels, 45, 37, ColorSpace::RGB).unwrap();
+
+    let baseline = encode(Encoder::new());
+    let expected = Decoder::new().decode_coefficients(&baseline).unwrap();
+    let expected_pixels = jpeg_decoder::Decoder::new(baseline.as_slice()).decode().unwrap();
+
+    let optimized = encode(Encoder::new().set_optimize(true));
+
+    assert!(optimized.len() < baseline.len());
+
+    let progressive = encode(Encoder::new().set_progressive(true));
+
+    assert!(Segments::new(&progressive).any(|x| x.marker() == Some(Marker::SOF(2))));
+
+    let script = vec![
+        Scan::new(&[0, 1, 2], 0, 0, 0, 0),
+        Scan::new(&[0], 1, 63, 0, 0),
+        Scan::new(&[1], 1, 63, 0, 0),
+        Scan::new(&[2], 1, 63, 0, 0),
+    ];
+    let outputs = [
+        optimized,
+        progressive,
+        encode(Encoder::new().set_scan_script(script)),
+        encode(Encoder::new().set_restart_interval(1)),
+        encode(Encoder::new().set_restart_interval(5).set_optimize(true)),
+        encode(Encoder::new().set_restart_interval(2).set_progressive(true)),
+    ];
+
+    for (i, output) in outputs.iter().enumerate()
+    {
+        let image = Decoder::new().decode_coefficients(output).unwrap();
+
+        // non-interleaved scans don't code the blocks padding components to whole MCUs
+        for (component, expected) in image.components.iter().zip(&expected.components)
+        {
+            for y in 0..component.height.div_ceil(8)
+            {
+                for x in 0..component.width.div_ceil(8)
+                {
+                    assert!(component.block(x, y) == expected.block(x, y), "output {i}");
+                }
+            }
+        }
+        assert_eq!(
+            jpeg_decoder::Decoder::new(output.as_slice()).decode().unwrap(),
+            expected_pixels,
+            "output {i}"
+        );
+    }
+    // 3x3 MCUs of 16x16 pixels, with a restart marker after the fifth
+    let restarts = Segments::new(&outputs[4])
+        .filter(|x| matches!(x.marker(), Some(Marker::RST(_))))
+        .count();
+
+    assert_eq!(restarts, 1);
+}
+
+#[test]
+fn encode_metadata()
+{
+    let pixels = gradient(16, 16);
+    let profile = vec![42; 1000];
+    let xmp = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"></x:xmpmeta>".to_string();
+
+    let metadata = MetadataRewriter::new()
+        .set_orientation(6)
+        .set_icc_profile(profile.clone())
+        .set_xmp(xmp.clone(), None)
+        .insert_segment(Marker::COM, b"encoded".to_vec());
+
+    let jpeg = Encoder::new()
+        .set_metadata(metadata)
+        .encode(&pixels, 16, 16, ColorSpace::RGB)
+        .unwrap();
+
+    let segments = Segments::new(&jpeg).collect::<Vec<_>>();
+
+    // JFIF stays first
+    assert_eq!(segments[1].marker(), Some(Marker::APP(0)));
+    assert!(segments[1].payload.starts_with(b"JFIF\0"));
+    assert!(segments
+        .iter()
+        .any(|x| x.marker() == Some(Marker::COM) && x.payload == b"encoded"));
+    let icc = [b"ICC_PROFILE\0\x01\x01", &profile[..]].concat();
+
+    assert!(segments
+        .iter()
+        .any(|x| x.marker() == Some(Marker::APP(2)) && x.payload == icc));
+
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&jpeg).unwrap();
+
+    assert_eq!(decoder.xmp(), Some(xmp.as_bytes()));
+    assert!(decoder.exif().is_some());
+}
+
+#[test]
+fn encode_errors()
+{
+    let pixels = gradient(8, 8);
+
+    for (pixels, width, height, colorspace) in [
+        (&pixels[..], 0, 8, ColorSpace::RGB),
+        (&pixels[..10], 8, 8, ColorSpace::RGB),
+        (&pixels[..], 4, 4, ColorSpace::CMYK),
+        (&pixels[..], 8, 8, ColorSpace::YCCK),
+    ]
+    {
+        assert!(Encoder::new().encode(pixels, width, height, colorspace).is_err());
+    }
+    let mut tables = [[1; 64]; 2];
+
+    tables[1][5] = 0;
+
+    assert!(Encoder::new()
+        .set_quantization_tables(tables)
+        .encode(&pixels, 8, 8, ColorSpace::RGB)
+        .is_err());
+
+    // every component must be in a scan
+    let script = vec![Scan::new(&[0], 0, 63, 0, 0)];
+
+    assert!(Encoder::new()
+        .set_scan_script(script)
+        .encode(&pixels, 8, 8, ColorSpace::RGB)
+        .is_err());
+}
@@ -0,0 +1,1057 @@
+use mozjpeg::ColorSpace as OutColorSpace;
+use zune_jpeg::mpf::MpImageType;
+use zune_jpeg::rewrite::MetadataRewriter;
+use zune_jpeg::segments::{SegmentKind, Segments};
+use zune_jpeg::stereo::{JpsMedia, MonoEye, StereoLayout};
+use zune_jpeg::{ColorSpace, Decoder, Marker, ZuneJpegOptions};
+
+use crate::common::mozjpeg_encode;
+
+mod common;
+
+fn read_input(name: &str) -> Vec<u8>
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+
+    std::fs::read(path).unwrap()
+}
+
+#[test]
+fn exif_thumbnail()
+{
+    let data = read_input("google_pixel.jpg");
+    let mut decoder = Decoder::new_with_options(
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA),
+    );
+
+    decoder.read_headers(&data).unwrap();
+
+    let (pixels, info) = decoder
+        .decode_exif_thumbnail()
+        .unwrap()
+        .expect("Image should have a thumbnail");
+
+    assert!(info.width < decoder.width() && info.height < decoder.height());
+    assert_eq!(pixels.len(), usize::from(info.width) * usize::from(info.height) * 4);
+}
+
+#[test]
+fn no_exif_thumbnail()
+{
+    let data = read_input("single_qt.jpeg");
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    assert!(decoder.decode_exif_thumbnail().unwrap().is_none());
+}
+
+/// Build a Multi-Picture file with `primary` as the first image and `secondary`
+/// stored after it as a disparity image
+fn build_mpf(primary: &[u8], secondary: &[u8]) -> Vec<u8>
+{
+    build_mpf_images(primary, &[secondary])
+}
+
+/// Build a Multi-Picture file with `primary` as the first image and each of
+/// `secondaries` stored after it in order as disparity images
+fn build_mpf_images(primary: &[u8], secondaries: &[&[u8]]) -> Vec<u8>
+{
+    let images = secondaries.len() + 1;
+    // MP header(8) + index IFD with 3 entries(42) + an MP entry(16) per image
+    let mp_header_len = 8 + 2 + 3 * 12 + 4 + 16 * images;
+    let segment_len = 2 + 4 + mp_header_len;
+    let primary_len = primary.len() + 2 + segment_len;
+    // SOI + APP2 marker + length + "MPF\0"
+    let mp_header_position = 2 + 4 + 4;
+
+    let mut mp = Vec::new();
+
+    mp.extend_from_slice(b"MM\x00\x2A\x00\x00\x00\x08");
+    mp.extend_from_slice(&3_u16.to_be_bytes());
+    // MPFVersion
+    mp.extend_from_slice(b"\xB0\x00\x00\x07\x00\x00\x00\x040100");
+    // NumberOfImages
+    mp.extend_from_slice(b"\xB0\x01\x00\x04\x00\x00\x00\x01");
+    mp.extend_from_slice(&(images as u32).to_be_bytes());
+    // MPEntry
+    mp.extend_from_slice(b"\xB0\x02\x00\x07");
+    mp.extend_from_slice(&(16 * images as u32).to_be_bytes());
+    mp.extend_from_slice(b"\x00\x00\x00\x32");
+    mp.extend_from_slice(&0_u32.to_be_bytes());
+
+    let mut entries = vec![(0x2003_0000_u32, primary_len, 0)];
+    let mut offset = primary_len - mp_header_position;
+
+    for secondary in secondaries
+    {
+        entries.push((0x0002_0002, secondary.len(), offset));
+        offset += secondary.len();
+    }
+    for (attribute, size, offset) in entries
+    {
+        mp.extend_from_slice(&attribute.to_be_bytes());
+        mp.extend_from_slice(&(size as u32).to_be_bytes());
+        mp.extend_from_slice(&(offset as u32).to_be_bytes());
+        mp.extend_from_slice(&[0; 4]);
+    }
+    assert_eq!(mp.len(), mp_header_len);
+
+    let mut file = vec![0xFF, 0xD8, 0xFF, 0xE2];
+
+    file.extend_from_slice(&(segment_len as u16).to_be_bytes());
+    file.extend_from_slice(b"MPF\x00");
+    file.extend_from_slice(&mp);
+    file.extend_from_slice(&primary[2..]);
+
+    for secondary in secondaries
+    {
+        file.extend_from_slice(secondary);
+    }
+    file
+}
+
+#[test]
+fn mpf_secondary_images()
+{
+    let primary = read_input("single_qt.jpeg");
+    let secondary = read_input("huffman_third_index.jpg");
+    let data = build_mpf(&primary, &secondary);
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let entries = decoder.mpf_entries().expect("Image should have MP entries");
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].image_type, MpImageType::BaselinePrimary);
+    assert!(entries[0].is_representative());
+    assert_eq!(entries[1].image_type, MpImageType::Disparity);
+
+    let images = decoder
+        .secondary_images(&data)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].data, &secondary[..]);
+
+    let expected = Decoder::new().decode_buffer(&secondary).unwrap();
+
+    assert_eq!(images[0].pixels, expected);
+}
+
+#[test]
+fn mpf_entry_out_of_bounds()
+{
+    let primary = read_input("single_qt.jpeg");
+    let secondary = read_input("huffman_third_index.jpg");
+
+    // the size and offset of the second MP entry, after the index IFD
+    let entry = 2 + 4 + 4 + 8 + 2 + 3 * 12 + 4 + 16;
+
+    for (size, offset) in [(u32::MAX, u32::MAX), (u32::MAX, 8), (16, u32::MAX)]
+    {
+        let mut data = build_mpf(&primary, &secondary);
+
+        data[entry + 4..entry + 8].copy_from_slice(&size.to_be_bytes());
+        data[entry + 8..entry + 12].copy_from_slice(&offset.to_be_bytes());
+
+        let mut decoder = Decoder::new();
+
+        decoder.read_headers(&data).unwrap();
+
+        let mut images = decoder.secondary_images(&data);
+
+        assert!(images.next().unwrap().is_err());
+    }
+}
+
+#[test]
+fn no_mpf()
+{
+    let data = read_input("google_pixel.jpg");
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    assert!(decoder.mpf_entries().is_none());
+    assert_eq!(decoder.secondary_images(&data).count(), 0);
+}
+
+/// Insert an application segment right after the SOI marker of `jpeg`
+fn insert_segment(jpeg: &[u8], marker: u8, payload: &[u8]) -> Vec<u8>
+{
+    let mut file = vec![0xFF, 0xD8, 0xFF, marker];
+
+    file.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    file.extend_from_slice(payload);
+    file.extend_from_slice(&jpeg[2..]);
+
+    file
+}
+
+/// Payload of an APP(1) segment holding `xmp`
+fn xmp_segment(xmp: &str) -> Vec<u8>
+{
+    let mut payload = b"http://ns.adobe.com/xap/1.0/\x00".to_vec();
+
+    payload.extend_from_slice(xmp.as_bytes());
+
+    payload
+}
+
+/// Build a gain map out of the EXIF thumbnail of an image and a segment with its metadata
+fn build_gain_map(gain_map_marker: u8, gain_map_payload: &[u8]) -> Vec<u8>
+{
+    let mut decoder = Decoder::new();
+    let pixel = read_input("google_pixel.jpg");
+
+    decoder.read_headers(&pixel).unwrap();
+
+    insert_segment(
+        decoder.exif_thumbnail().unwrap(),
+        gain_map_marker,
+        gain_map_payload,
+    )
+}
+
+/// Build an Ultra HDR image using the EXIF thumbnail of an image as the gain map
+fn build_ultra_hdr(gain_map_marker: u8, gain_map_payload: &[u8]) -> (Vec<u8>, Vec<u8>)
+{
+    let base = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+
+    let gain_map = build_gain_map(gain_map_marker, gain_map_payload);
+
+    (build_mpf(&base, &gain_map), base)
+}
+
+/// Gain map metadata in XMP, for gains up to 4
+const GAIN_MAP_XMP: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+        <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+        <rdf:Description xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/"
+            hdrgm:Version="1.0" hdrgm:GainMapMin="0" hdrgm:GainMapMax="2"
+            hdrgm:Gamma="1" hdrgm:OffsetSDR="0" hdrgm:OffsetHDR="0"
+            hdrgm:HDRCapacityMin="0" hdrgm:HDRCapacityMax="2"/>
+        </rdf:RDF></x:xmpmeta>"#;
+
+fn srgb_to_linear(x: u8) -> f32
+{
+    let x = f32::from(x) / 255.0;
+
+    if x <= 0.04045
+    {
+        x / 12.92
+    }
+    else
+    {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[test]
+fn ultra_hdr_xmp()
+{
+    let (data, base) = build_ultra_hdr(0xE1, &xmp_segment(GAIN_MAP_XMP));
+    let image = Decoder::new().decode_ultra_hdr(&data).unwrap();
+
+    assert_eq!(image.metadata.gain_map_max, [2.0; 3]);
+    assert_eq!(image.metadata.hdr_capacity_max, 2.0);
+    assert!(!image.metadata.base_rendition_is_hdr);
+
+    let expected = Decoder::new_with_options(
+        ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGB),
+    )
+    .decode_buffer(&base)
+    .unwrap();
+
+    assert_eq!(image.base, expected);
+
+    // no boost, the SDR rendition
+    let sdr = image.to_linear(1.0);
+
+    for (&a, &b) in sdr.iter().zip(&image.base)
+    {
+        assert!((a - srgb_to_linear(b)).abs() < 1e-6);
+    }
+    // full boost, at most 4 times brighter
+    let hdr = image.to_linear(4.0);
+
+    assert_eq!(hdr.len(), image.width * image.height * 3);
+
+    for (&a, &b) in hdr.iter().zip(&sdr)
+    {
+        assert!(a >= b - 1e-6 && a <= b * 4.0 + 1e-6);
+    }
+    assert!(hdr.iter().sum::<f32>() > sdr.iter().sum::<f32>());
+
+    let pq = image.to_pq(4.0);
+    let hlg = image.to_hlg(4.0);
+
+    assert_eq!(pq.len(), hdr.len());
+    assert_eq!(hlg.len(), hdr.len());
+}
+
+#[test]
+fn ultra_hdr_iso_metadata()
+{
+    let mut iso = b"urn:iso:std:iso:ts:21496:-1\x00".to_vec();
+
+    // versions
+    iso.extend_from_slice(&[0, 0, 0, 0]);
+    // flags, common denominator
+    iso.push(0x08);
+
+    for value in [1000_u32, 0, 2500, 500, 2000, 1000, 15, 30]
+    {
+        iso.extend_from_slice(&value.to_be_bytes());
+    }
+    let (data, _) = build_ultra_hdr(0xE2, &iso);
+    let image = Decoder::new().decode_ultra_hdr(&data).unwrap();
+
+    let metadata = image.metadata;
+
+    assert_eq!(metadata.hdr_capacity_min, 0.0);
+    assert_eq!(metadata.hdr_capacity_max, 2.5);
+    assert_eq!(metadata.gain_map_min, [0.5; 3]);
+    assert_eq!(metadata.gain_map_max, [2.0; 3]);
+    assert_eq!(metadata.gamma, [1.0; 3]);
+    assert_eq!(metadata.offset_sdr, [0.015; 3]);
+    assert_eq!(metadata.offset_hdr, [0.03; 3]);
+}
+
+#[test]
+fn ultra_hdr_entry_out_of_bounds()
+{
+    let base = read_input("single_qt.jpeg");
+    let gain_map = build_gain_map(0xE1, &xmp_segment(GAIN_MAP_XMP));
+    let mut data = build_mpf_images(&base, &[b"\xFF\xD8", &gain_map]);
+
+    // the offset of the first secondary MP entry, after the index IFD and primary entry
+    let entry = 2 + 4 + 4 + 8 + 2 + 3 * 12 + 4 + 16;
+
+    data[entry + 8..entry + 12].copy_from_slice(&u32::MAX.to_be_bytes());
+
+    // the entry out of bounds is skipped
+    let image = Decoder::new().decode_ultra_hdr(&data).unwrap();
+
+    assert_eq!(image.metadata.gain_map_max, [2.0; 3]);
+}
+
+#[test]
+fn ultra_hdr_grayscale_base()
+{
+    let gray = (0..16 * 16).map(|x| x as u8).collect::<Vec<u8>>();
+    let base = mozjpeg_encode(OutColorSpace::JCS_GRAYSCALE, &gray, (16, 16), |comp| {
+        comp.set_fastest_defaults();
+    });
+    let gain_map = build_gain_map(0xE1, &xmp_segment(GAIN_MAP_XMP));
+
+    let image = Decoder::new()
+        .decode_ultra_hdr(&build_mpf(&base, &gain_map))
+        .unwrap();
+    let expected = Decoder::new().decode_buffer(&base).unwrap();
+
+    // gray samples are repeated for red, green and blue
+    assert_eq!(image.base.len(), 16 * 16 * 3);
+    assert!(image.base.chunks_exact(3).zip(expected).all(|(x, y)| x == [y; 3]));
+}
+
+#[test]
+fn no_gain_map()
+{
+    let data = read_input("google_pixel.jpg");
+
+    assert!(Decoder::new().decode_ultra_hdr(&data).is_err());
+}
+
+#[test]
+fn trailing_data()
+{
+    let data = read_input("google_pixel.jpg");
+    let decoder = Decoder::new();
+
+    // the camera appends debug data after the image
+    assert_eq!(decoder.trailing_data(&data), Some(3_419_200..data.len()));
+
+    // this one is padded with zeroes
+    let data = read_input("single_qt.jpeg");
+
+    assert_eq!(decoder.trailing_data(&data), Some(176_377..data.len()));
+
+    let data = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+
+    assert!(decoder.trailing_data(&data).unwrap().is_empty());
+}
+
+/// Append a fake video of `length` bytes to `image` with `xmp` inserted
+fn build_motion_photo(xmp: &str, length: usize) -> (Vec<u8>, usize)
+{
+    let image = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+
+    let mut data = insert_segment(&image, 0xE1, &xmp_segment(xmp));
+    let image_length = data.len();
+
+    data.extend((0..length).map(|x| x as u8));
+
+    (data, image_length)
+}
+
+#[test]
+fn motion_photo_container()
+{
+    let xmp = r#"<rdf:Description Camera:MotionPhoto="1"
+        Camera:MotionPhotoPresentationTimestampUs="466016">
+        <Container:Directory><rdf:Seq>
+        <rdf:li rdf:parseType="Resource">
+            <Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary" Item:Length="0" Item:Padding="0"/>
+        </rdf:li>
+        <rdf:li rdf:parseType="Resource">
+            <Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="5000" Item:Padding="0"/>
+        </rdf:li>
+        </rdf:Seq></Container:Directory></rdf:Description>"#;
+
+    let (data, image_length) = build_motion_photo(xmp, 5000);
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let items = decoder.container_items(&data).unwrap();
+
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1].mime, "video/mp4");
+    assert_eq!(items[1].length, 5000);
+
+    let motion_photo = decoder.motion_photo(&data).unwrap();
+
+    assert_eq!(motion_photo.image, 0..image_length);
+    assert_eq!(motion_photo.video, image_length..data.len());
+    assert_eq!(motion_photo.presentation_timestamp_us, Some(466_016));
+    assert_eq!(decoder.trailing_data(&data), Some(motion_photo.video));
+}
+
+#[test]
+fn motion_photo_micro_video_offset()
+{
+    let xmp = r#"<rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoVersion="1"
+        GCamera:MicroVideoOffset="3000" GCamera:MicroVideoPresentationTimestampUs="-1"/>"#;
+
+    let (data, image_length) = build_motion_photo(xmp, 3000);
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    assert!(decoder.container_items(&data).is_none());
+
+    let motion_photo = decoder.motion_photo(&data).unwrap();
+
+    assert_eq!(motion_photo.image, 0..image_length);
+    assert_eq!(motion_photo.video, image_length..data.len());
+    assert_eq!(motion_photo.presentation_timestamp_us, None);
+}
+
+fn isobmff_box(box_type: &[u8; 4], contents: &[u8]) -> Vec<u8>
+{
+    let mut data = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+
+    data.extend_from_slice(box_type);
+    data.extend_from_slice(contents);
+
+    data
+}
+
+fn jumbf_superbox(label: &str, content_type: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8>
+{
+    // content type UUID, the first four bytes identify C2PA boxes
+    let mut description = content_type.to_vec();
+
+    description.extend_from_slice(&[0x00, 0x11, 0x00, 0x10, 0x80, 0x00, 0x00, 0xAA]);
+    description.extend_from_slice(&[0x00, 0x38, 0x9B, 0x71]);
+    // requestable, with a label
+    description.push(0x03);
+    description.extend_from_slice(label.as_bytes());
+    description.push(0);
+
+    let mut contents = isobmff_box(b"jumd", &description);
+
+    for child in children
+    {
+        contents.extend_from_slice(child);
+    }
+    isobmff_box(b"jumb", &contents)
+}
+
+/// Split a box into APP(11) payloads of at most `chunk` content bytes
+fn app11_packets(data: &[u8], instance: u16, chunk: usize) -> Vec<Vec<u8>>
+{
+    let (header, contents) = data.split_at(8);
+
+    contents
+        .chunks(chunk)
+        .enumerate()
+        .map(|(i, contents)| {
+            let mut packet = b"JP".to_vec();
+
+            packet.extend_from_slice(&instance.to_be_bytes());
+            packet.extend_from_slice(&(i as u32 + 1).to_be_bytes());
+            packet.extend_from_slice(header);
+            packet.extend_from_slice(contents);
+
+            packet
+        })
+        .collect()
+}
+
+#[test]
+fn c2pa_manifest_store()
+{
+    let actions = b"\xA1\x67actions\x80".to_vec();
+    let claim = br#"{"claim_generator":"test"}"#.to_vec();
+
+    let store = jumbf_superbox(
+        "c2pa",
+        b"c2pa",
+        &[jumbf_superbox(
+            "urn:uuid:1234",
+            b"c2ma",
+            &[
+                jumbf_superbox(
+                    "c2pa.assertions",
+                    b"c2as",
+                    &[jumbf_superbox("c2pa.actions", b"cbor", &[isobmff_box(b"cbor", &actions)])],
+                ),
+                jumbf_superbox("c2pa.claim", b"json", &[isobmff_box(b"json", &claim)]),
+            ],
+        )],
+    );
+    let other = isobmff_box(b"LCHK", &[1, 2, 3, 4]);
+
+    let mut image = read_input("single_qt.jpeg");
+
+    // insert in reverse so segments end up interleaved in file order
+    let mut packets = app11_packets(&store, 1, 40);
+
+    packets.insert(1, app11_packets(&other, 1, 40).remove(0));
+
+    for packet in packets.iter().rev()
+    {
+        image = insert_segment(&image, 0xEB, packet);
+    }
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&image).unwrap();
+
+    assert_eq!(decoder.app11_boxes().len(), 2);
+
+    let store = decoder.c2pa_manifest_store().expect("Image should have a C2PA store");
+    let payloads = store.payloads();
+
+    assert_eq!(payloads.len(), 2);
+
+    assert_eq!(payloads[0].label, "c2pa/urn:uuid:1234/c2pa.assertions/c2pa.actions");
+    assert_eq!(&payloads[0].box_type, b"cbor");
+    assert_eq!(payloads[0].data, &actions[..]);
+
+    assert_eq!(payloads[1].label, "c2pa/urn:uuid:1234/c2pa.claim");
+    assert_eq!(&payloads[1].box_type, b"json");
+    assert_eq!(payloads[1].data, &claim[..]);
+}
+
+/// Superboxes nested `depth` deep, built from the outside in
+fn nested_superboxes(depth: usize) -> Vec<u8>
+{
+    let description = isobmff_box(b"jumd", &[0; 17]);
+    let mut data = Vec::with_capacity(depth * (8 + description.len()));
+
+    for level in 0..depth
+    {
+        let size = (depth - level) * (8 + description.len());
+
+        data.extend_from_slice(&(size as u32).to_be_bytes());
+        data.extend_from_slice(b"jumb");
+        data.extend_from_slice(&description);
+    }
+    data
+}
+
+#[test]
+fn deeply_nested_superboxes()
+{
+    let image = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+
+    for (depth, parsed) in [(10, 1), (100_000, 0)]
+    {
+        let mut file = image.clone();
+
+        for packet in app11_packets(&nested_superboxes(depth), 1, 60_000).iter().rev()
+        {
+            file = insert_segment(&file, 0xEB, packet);
+        }
+        let mut decoder = Decoder::new();
+
+        decoder.read_headers(&file).unwrap();
+
+        // too deep to be a real box, it is skipped instead of overflowing the stack
+        assert_eq!(decoder.app11_boxes().len(), parsed, "{depth}");
+    }
+}
+
+fn jps_segment(descriptor: u32, comment: &str) -> Vec<u8>
+{
+    let mut payload = b"_JPSJPS_".to_vec();
+
+    payload.extend_from_slice(&4_u16.to_be_bytes());
+    payload.extend_from_slice(&descriptor.to_be_bytes());
+    payload.extend_from_slice(&(comment.len() as u16).to_be_bytes());
+    payload.extend_from_slice(comment.as_bytes());
+
+    payload
+}
+
+#[test]
+fn jps_side_by_side()
+{
+    let image = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+    // stereo, side by side, half width, right view first, 10 pixel separation
+    let data = insert_segment(&image, 0xE3, &jps_segment(0x0A02_0201, "cross-eyed"));
+
+    let mut decoder = Decoder::new();
+    let pixels = decoder.decode_buffer(&data).unwrap();
+    let jps = decoder.jps().expect("Image should have a JPS descriptor");
+
+    assert_eq!(
+        jps.media,
+        JpsMedia::Stereo {
+            layout:      StereoLayout::SideBySide,
+            half_height: false,
+            half_width:  true,
+            left_first:  false,
+            separation:  10,
+        }
+    );
+    assert_eq!(jps.comment.as_deref(), Some("cross-eyed"));
+
+    let (width, height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+    let pair = jps.split(&pixels, width, height, 3).unwrap();
+
+    assert_eq!((pair.width, pair.height), (width / 2, height));
+
+    // the right view is stored first
+    let row = &pixels[width * 3 * 5..width * 3 * 6];
+
+    assert_eq!(&pair.right[pair.width * 3 * 5..pair.width * 3 * 6], &row[..width / 2 * 3]);
+    assert_eq!(&pair.left[pair.width * 3 * 5..pair.width * 3 * 6], &row[width / 2 * 3..]);
+}
+
+#[test]
+fn jps_over_under_and_mono()
+{
+    let image = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+    // stereo, over under, left view first
+    let data = insert_segment(&image, 0xE3, &jps_segment(0x0004_0301, ""));
+
+    let mut decoder = Decoder::new();
+    let pixels = decoder.decode_buffer(&data).unwrap();
+    let (width, height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+
+    let pair = decoder.jps().unwrap().split(&pixels, width, height, 3).unwrap();
+
+    assert_eq!((pair.width, pair.height), (width, height / 2));
+    assert_eq!(&pair.left[..], &pixels[..pair.left.len()]);
+    assert_eq!(&pair.right[..], &pixels[pair.left.len()..pair.left.len() * 2]);
+
+    // mono, right eye
+    let data = insert_segment(&image, 0xE3, &jps_segment(0x0000_0200, ""));
+
+    decoder.read_headers(&data).unwrap();
+
+    let jps = decoder.jps().unwrap();
+
+    assert_eq!(jps.media, JpsMedia::Mono(MonoEye::Right));
+    assert!(jps.split(&pixels, width, height, 3).is_none());
+}
+
+#[test]
+fn jps_split_bad_dimensions()
+{
+    let image = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+    // stereo, interleaved rows
+    let data = insert_segment(&image, 0xE3, &jps_segment(0x0004_0101, ""));
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let jps = decoder.jps().unwrap();
+    let pixels = [0; 12];
+
+    assert!(jps.split(&pixels, 2, 2, 3).is_some());
+
+    // empty views, and sizes that overflow
+    assert!(jps.split(&pixels, 0, 2, 3).is_none());
+    assert!(jps.split(&pixels, 2, 0, 3).is_none());
+    assert!(jps.split(&pixels, 2, 2, 0).is_none());
+    assert!(jps.split(&pixels, usize::MAX, 2, 3).is_none());
+    assert!(jps.split(&pixels, 2, usize::MAX, 3).is_none());
+}
+
+/// Identifier of standard XMP segments
+const XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Identifier of extended XMP segments
+const EXTENDED_XMP: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+/// Tags and first values of the IFD0 entries of an EXIF structure
+fn ifd0_entries(exif: &[u8]) -> Vec<(u16, u16)>
+{
+    let u16_at = |offset: usize| {
+        let bytes = [exif[offset], exif[offset + 1]];
+
+        if &exif[0..2] == b"II"
+        {
+            u16::from_le_bytes(bytes)
+        }
+        else
+        {
+            u16::from_be_bytes(bytes)
+        }
+    };
+    let u32_at = |offset: usize| {
+        let bytes = exif[offset..offset + 4].try_into().unwrap();
+
+        if &exif[0..2] == b"II"
+        {
+            u32::from_le_bytes(bytes)
+        }
+        else
+        {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    let ifd0 = u32_at(4) as usize;
+
+    (0..usize::from(u16_at(ifd0)))
+        .map(|i| (u16_at(ifd0 + 2 + i * 12), u16_at(ifd0 + 2 + i * 12 + 8)))
+        .collect()
+}
+
+/// Payloads of the segments with `marker` starting with `identifier`, without it
+fn segment_payloads<'a>(data: &'a [u8], marker: Marker, identifier: &[u8]) -> Vec<&'a [u8]>
+{
+    Segments::new(data)
+        .filter(|x| x.marker() == Some(marker) && x.payload.starts_with(identifier))
+        .map(|x| &x.payload[identifier.len()..])
+        .collect()
+}
+
+/// Entropy coded data of a file, concatenated
+fn entropy_coded_data(data: &[u8]) -> Vec<u8>
+{
+    Segments::new(data)
+        .filter(|x| x.kind == SegmentKind::EntropyCoded)
+        .flat_map(|x| x.payload.iter().copied())
+        .collect()
+}
+
+#[test]
+fn rewrite_remove_private_metadata()
+{
+    let data = read_input("google_pixel.jpg");
+
+    let rewritten = MetadataRewriter::new()
+        .remove_gps()
+        .remove_xmp()
+        .remove_comments()
+        .rewrite(&data)
+        .unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let tags = ifd0_entries(decoder.exif().unwrap());
+
+    assert!(tags.iter().any(|&(tag, _)| tag == 0x8825));
+
+    let expected_thumbnail = decoder.exif_thumbnail().unwrap().to_vec();
+
+    decoder.read_headers(&rewritten).unwrap();
+
+    // the GPS IFD is gone, everything else survives
+    let new_tags = ifd0_entries(decoder.exif().unwrap());
+
+    assert_eq!(new_tags.len(), tags.len() - 1);
+    assert!(new_tags.iter().all(|&(tag, _)| tag != 0x8825));
+    assert_eq!(decoder.exif_thumbnail().unwrap(), &expected_thumbnail[..]);
+
+    assert!(decoder.xmp().is_none());
+    assert!(segment_payloads(&rewritten, Marker::APP(1), EXTENDED_XMP).is_empty());
+
+    // pixels and trailing data are untouched
+    assert_eq!(entropy_coded_data(&rewritten), entropy_coded_data(&data));
+    assert_eq!(
+        Decoder::new().decode_buffer(&rewritten).unwrap(),
+        Decoder::new().decode_buffer(&data).unwrap()
+    );
+    let trailing = Decoder::new().trailing_data(&data).unwrap();
+
+    assert!(rewritten.ends_with(&data[trailing]));
+}
+
+#[test]
+fn rewrite_icc_profile_and_orientation()
+{
+    let data = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+    // three chunks
+    let profile = (0..150_000).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+
+    let rewritten = MetadataRewriter::new()
+        .set_icc_profile(profile.clone())
+        .set_orientation(6)
+        .remove_segments(Marker::COM)
+        .insert_segment(Marker::APP(15), b"custom".to_vec())
+        .rewrite(&data)
+        .unwrap();
+
+    let chunks = segment_payloads(&rewritten, Marker::APP(2), b"ICC_PROFILE\0");
+
+    assert_eq!(chunks.len(), 3);
+
+    for (i, chunk) in chunks.iter().enumerate()
+    {
+        assert_eq!(chunk[..2], [i as u8 + 1, 3]);
+    }
+    let joined = chunks.iter().flat_map(|x| &x[2..]).copied().collect::<Vec<u8>>();
+
+    assert_eq!(joined, profile);
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&rewritten).unwrap();
+
+    assert_eq!(ifd0_entries(decoder.exif().unwrap()), [(0x0112, 6)]);
+    assert!(segment_payloads(&rewritten, Marker::COM, b"").is_empty());
+    assert_eq!(segment_payloads(&rewritten, Marker::APP(15), b""), [b"custom"]);
+
+    // JFIF stays first
+    let markers = Segments::new(&rewritten)
+        .take(3)
+        .map(|x| x.marker().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(markers, [Marker::SOI, Marker::APP(0), Marker::APP(1)]);
+    assert_eq!(entropy_coded_data(&rewritten), entropy_coded_data(&data));
+}
+
+#[test]
+fn rewrite_orientation_in_existing_exif()
+{
+    let data = read_input("google_pixel.jpg");
+    let rewritten = MetadataRewriter::new()
+        .set_orientation(3)
+        .rewrite(&data)
+        .unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&rewritten).unwrap();
+
+    let tags = ifd0_entries(decoder.exif().unwrap());
+
+    assert!(tags.contains(&(0x0112, 3)));
+
+    // an EXIF structure without an orientation gets a new IFD0
+    let exif = b"Exif\0\0MM\0\x2A\0\0\0\x08\0\x01\x01\x0F\0\x02\0\0\0\x04abc\0\0\0\0\0";
+    let image = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+    let data = insert_segment(&image, 0xE1, exif);
+
+    let rewritten = MetadataRewriter::new()
+        .set_orientation(8)
+        .rewrite(&data)
+        .unwrap();
+
+    decoder.read_headers(&rewritten).unwrap();
+
+    let tags = ifd0_entries(decoder.exif().unwrap());
+
+    assert_eq!(tags, [(0x010F, u16::from_be_bytes(*b"ab")), (0x0112, 8)]);
+}
+
+#[test]
+fn rewrite_extended_xmp()
+{
+    let data = read_input("google_pixel.jpg");
+
+    let standard = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF>
+        <rdf:Description rdf:about=""/>
+        </rdf:RDF></x:xmpmeta>"#;
+    let extended = "<x:xmpmeta>".to_string() + &"x".repeat(100_000) + "</x:xmpmeta>";
+
+    let rewritten = MetadataRewriter::new()
+        .set_xmp(standard.to_string(), Some(extended.clone()))
+        .rewrite(&data)
+        .unwrap();
+
+    let packets = segment_payloads(&rewritten, Marker::APP(1), XMP);
+
+    assert_eq!(packets.len(), 1);
+
+    let packet = std::str::from_utf8(packets[0]).unwrap();
+    let guid_start = packet.find("xmpNote:HasExtendedXMP=\"").unwrap() + 24;
+    let guid = &packet[guid_start..guid_start + 32];
+
+    assert!(guid.chars().all(|x| x.is_ascii_hexdigit() && !x.is_ascii_lowercase()));
+
+    let chunks = segment_payloads(&rewritten, Marker::APP(1), EXTENDED_XMP);
+
+    assert_eq!(chunks.len(), 2);
+
+    let mut joined = vec![0; extended.len()];
+
+    for chunk in chunks
+    {
+        assert_eq!(&chunk[..32], guid.as_bytes());
+
+        let length = u32::from_be_bytes(chunk[32..36].try_into().unwrap()) as usize;
+        let offset = u32::from_be_bytes(chunk[36..40].try_into().unwrap()) as usize;
+
+        assert_eq!(length, extended.len());
+
+        joined[offset..offset + chunk.len() - 40].copy_from_slice(&chunk[40..]);
+    }
+    assert_eq!(joined, extended.as_bytes());
+
+    // the old extended packet is gone, rewriting again replaces the GUID
+    let again = MetadataRewriter::new()
+        .set_xmp(packet.to_string(), Some("<x:xmpmeta/>".to_string()))
+        .rewrite(&rewritten)
+        .unwrap();
+
+    let packets = segment_payloads(&again, Marker::APP(1), XMP);
+    let packet = std::str::from_utf8(packets[0]).unwrap();
+
+    assert_eq!(packet.matches("HasExtendedXMP").count(), 1);
+    assert!(!packet.contains(guid));
+    assert_eq!(segment_payloads(&again, Marker::APP(1), EXTENDED_XMP).len(), 1);
+}
+
+#[test]
+fn rewrite_updates_mp_entries()
+{
+    let primary = std::fs::read(
+        env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-baseline.jpg",
+    )
+    .unwrap();
+    let secondary = read_input("huffman_third_index.jpg");
+    let data = build_mpf(&primary, &secondary);
+
+    // the ICC profile and comment follow the MPF segment, removing them moves the
+    // secondary image closer to the MP header
+    let rewritten = MetadataRewriter::new()
+        .remove_icc_profile()
+        .remove_comments()
+        .rewrite(&data)
+        .unwrap();
+
+    assert!(rewritten.len() < data.len());
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&rewritten).unwrap();
+
+    let images = decoder
+        .secondary_images(&rewritten)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].data, &secondary[..]);
+    assert_eq!(
+        decoder.mpf_entries().unwrap()[0].size as usize,
+        rewritten.len() - secondary.len()
+    );
+}
+
+#[test]
+fn rewrite_rejects_structural_markers()
+{
+    let data = read_input("google_pixel.jpg");
+
+    assert!(MetadataRewriter::new()
+        .remove_segments(Marker::DQT)
+        .rewrite(&data)
+        .is_err());
+    // APP(16) would be written as JPG0
+    assert!(MetadataRewriter::new()
+        .insert_segment(Marker::APP(16), vec![])
+        .rewrite(&data)
+        .is_err());
+    assert!(MetadataRewriter::new()
+        .rewrite(&data[..data.len() / 100])
+        .is_err());
+}
+
+#[test]
+fn rewrite_rejects_invalid_orientation()
+{
+    let data = read_input("google_pixel.jpg");
+
+    for orientation in [0, 9, u16::MAX]
+    {
+        assert!(MetadataRewriter::new()
+            .set_orientation(orientation)
+            .rewrite(&data)
+            .is_err());
+    }
+    assert!(MetadataRewriter::new().set_orientation(8).rewrite(&data).is_ok());
+}
+
+#[test]
+fn rewrite_truncated_ifd()
+{
+    let image = read_input("single_qt.jpeg");
+
+    // IFD0 ending in the middle of its only entry, an orientation or another tag
+    for tag in [0x0112_u16, 0x010F]
+    {
+        let mut exif = b"Exif\0\0MM\0\x2A\0\0\0\x08\0\x01".to_vec();
+
+        exif.extend_from_slice(&tag.to_be_bytes());
+        exif.extend_from_slice(&[0, 3, 0, 0, 0, 1]);
+
+        let data = insert_segment(&image, 0xE1, &exif);
+
+        assert!(MetadataRewriter::new()
+            .set_orientation(3)
+            .rewrite(&data)
+            .is_err());
+    }
+}
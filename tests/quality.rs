@@ -0,0 +1,143 @@
+use mozjpeg::ColorSpace as OutColorSpace;
+use zune_jpeg::quality::{EncoderSignature, QualityEstimate};
+use zune_jpeg::rewrite::MetadataRewriter;
+use zune_jpeg::{Decoder, Marker};
+
+fn open(path: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/" + path).unwrap()
+}
+
+fn estimate(data: &[u8]) -> QualityEstimate
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(data).unwrap();
+    decoder.estimate_quality().unwrap()
+}
+
+fn mozjpeg_encode(quality: f32) -> Vec<u8>
+{
+    let mut comp = mozjpeg::Compress::new(OutColorSpace::JCS_RGB);
+
+    comp.set_size(16, 16);
+    comp.set_quality(quality);
+    comp.set_mem_dest();
+    comp.start_compress();
+
+    assert!(comp.write_scanlines(&[127; 16 * 16 * 3]));
+
+    comp.finish_compress();
+    comp.data_to_vec().unwrap()
+}
+
+#[test]
+fn quality_ijg()
+{
+    // exported from GIMP at quality 90
+    for path in ["test-images/test-baseline.jpg", "test-images/test-progressive.jpg"]
+    {
+        let estimate = estimate(&open(path));
+
+        assert_eq!(estimate.encoder, EncoderSignature::Ijg);
+        assert_eq!(estimate.quality, 90);
+        assert!(estimate.is_exact());
+    }
+}
+
+#[test]
+fn quality_mozjpeg()
+{
+    for quality in [75, 90]
+    {
+        let estimate = estimate(&mozjpeg_encode(f32::from(quality)));
+
+        assert_eq!(estimate.encoder, EncoderSignature::Mozjpeg);
+        assert_eq!(estimate.quality, quality);
+        assert!(estimate.is_exact());
+    }
+}
+
+#[test]
+fn quality_camera()
+{
+    let data = open("tests/inputs/google_pixel.jpg");
+    let estimate = estimate(&data);
+
+    assert_eq!(estimate.encoder, EncoderSignature::Camera("Google".to_string()));
+    assert!(!estimate.is_exact());
+    assert!(estimate.quality > 90);
+
+    // recognised by its tables, not its EXIF data
+    let stripped = MetadataRewriter::new()
+        .remove_segments(Marker::APP(1))
+        .rewrite(&data)
+        .unwrap();
+
+    assert_eq!(self::estimate(&stripped).encoder, EncoderSignature::Camera("Google".to_string()));
+
+    // other tables, the EXIF data names the camera
+    let mut exif = b"Exif\0\0".to_vec();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+    exif.extend_from_slice(decoder.exif().unwrap());
+
+    let data = MetadataRewriter::new()
+        .insert_segment(Marker::APP(1), exif)
+        .rewrite(&open("tests/inputs/single_qt.jpeg"))
+        .unwrap();
+
+    assert_eq!(self::estimate(&data).encoder, EncoderSignature::Camera("Google".to_string()));
+
+    // libjpeg writes Adobe segments too, only Photoshop's own segment counts
+    let adobe = b"Adobe\0\x64\0\0\0\0\x01".to_vec();
+    let data = MetadataRewriter::new()
+        .insert_segment(Marker::APP(14), adobe)
+        .rewrite(&data)
+        .unwrap();
+
+    assert_eq!(self::estimate(&data).encoder, EncoderSignature::Camera("Google".to_string()));
+
+    // an XMP resource, ID 0x0424 named "a", but no JPEG quality resource
+    let photoshop = b"Photoshop 3.0\08BIM\x04\x24\x01a\0\0\0\x01x\0".to_vec();
+    let data = MetadataRewriter::new()
+        .insert_segment(Marker::APP(13), photoshop)
+        .rewrite(&data)
+        .unwrap();
+
+    assert_eq!(self::estimate(&data).encoder, EncoderSignature::Photoshop(None));
+}
+
+#[test]
+fn quality_photoshop()
+{
+    // saved by Photoshop at level 12
+    let data = open("tests/inputs/huffman_third_index.jpg");
+    let estimate = estimate(&data);
+
+    assert_eq!(estimate.encoder, EncoderSignature::Photoshop(Some(12)));
+
+    // recognised by its tables, not its Adobe segment
+    let data = MetadataRewriter::new()
+        .remove_segments(Marker::APP(14))
+        .rewrite(&data)
+        .unwrap();
+
+    assert_eq!(self::estimate(&data).encoder, EncoderSignature::Photoshop(Some(12)));
+
+    // other levels are read from the JPEG quality resource, level 6 is stored as 2
+    let resources = b"Photoshop 3.0\08BIM\x04\x06\0\0\0\0\0\x06\0\x02\0\0\0\x01".to_vec();
+    let data = MetadataRewriter::new()
+        .insert_segment(Marker::APP(13), resources)
+        .rewrite(&open("tests/inputs/single_qt.jpeg"))
+        .unwrap();
+
+    assert_eq!(self::estimate(&data).encoder, EncoderSignature::Photoshop(Some(6)));
+}
+
+#[test]
+fn quality_before_headers()
+{
+    assert!(Decoder::new().estimate_quality().is_none());
+}
@@ -0,0 +1,113 @@
+use zune_jpeg::encoder::{Encoder, Subsampling};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::pattern;
+
+mod common;
+
+fn read_input(name: &str) -> Vec<u8>
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+
+    std::fs::read(path).unwrap()
+}
+
+/// Copy `width` by `height` pixels at `x`,`y` out of an image `stride` pixels wide
+fn crop(
+    pixels: &[u8], stride: usize, components: usize,
+    (x, y, width, height): (usize, usize, usize, usize),
+) -> Vec<u8>
+{
+    pixels
+        .chunks_exact(stride * components)
+        .skip(y)
+        .take(height)
+        .flat_map(|row| &row[x * components..(x + width) * components])
+        .copied()
+        .collect()
+}
+
+/// Check regions at the corners, edges and middle of an image against a full decode
+fn check_regions(data: &[u8], options: ZuneJpegOptions)
+{
+    let mut decoder = Decoder::new_with_options(options);
+    let full = decoder.decode_buffer(data).unwrap();
+    let (width, height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+    let components = options.get_out_colorspace().num_components();
+
+    let regions = [
+        (0, 0, width, height),
+        (0, 0, 1, 1),
+        (width - 1, height - 1, 1, 1),
+        (0, 0, width / 3, height / 4),
+        (width / 2, 0, width - width / 2, 17),
+        (0, height / 2, 9, height - height / 2),
+        (width - 45, height / 3, 45, height / 3),
+        (width / 3 + 5, height / 3 + 3, width / 4, height / 5),
+        (17, 33, width - 40, 1),
+    ];
+
+    for region in regions
+    {
+        let (x, y, w, h) = region;
+        let pixels = Decoder::new_with_options(options)
+            .decode_region(data, x, y, w, h)
+            .unwrap();
+
+        assert!(
+            pixels == crop(&full, width, components, region),
+            "Region {region:?} of the {width}x{height} image differs from a full decode"
+        );
+    }
+}
+
+#[test]
+fn region_matches_cropped_decode()
+{
+    for name in [
+        "medium_no_samp_2500x1786.jpg",
+        "medium_horiz_samp_2500x1786.jpg",
+        "medium_vertical_samp_2500x1786.jpg",
+        "single_qt.jpeg",
+        "huffman_third_index.jpg",
+    ]
+    {
+        check_regions(&read_input(name), ZuneJpegOptions::new());
+    }
+}
+
+#[test]
+fn region_matches_cropped_decode_subsampled()
+{
+    let (width, height) = (523, 141);
+    let pixels = pattern(width, height);
+
+    for subsampling in [Subsampling::Chroma444, Subsampling::Chroma422, Subsampling::Chroma420]
+    {
+        for progressive in [false, true]
+        {
+            let jpeg = Encoder::new()
+                .set_subsampling(subsampling)
+                .set_progressive(progressive)
+                .set_restart_interval(if progressive { 0 } else { 3 })
+                .encode(&pixels, width as u16, height as u16, ColorSpace::RGB)
+                .unwrap();
+
+            for colorspace in [ColorSpace::RGB, ColorSpace::YCbCr, ColorSpace::GRAYSCALE]
+            {
+                check_regions(&jpeg, ZuneJpegOptions::new().set_out_colorspace(colorspace));
+            }
+        }
+    }
+}
+
+#[test]
+fn region_errors()
+{
+    let data = read_input("single_qt.jpeg");
+
+    for (x, y, width, height) in [(0, 0, 0, 10), (0, 0, 10, 0), (1, 0, 1920, 1), (0, 1070, 1, 11)]
+    {
+        assert!(Decoder::new().decode_region(&data, x, y, width, height).is_err());
+    }
+}
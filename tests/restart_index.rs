@@ -0,0 +1,219 @@
+use zune_jpeg::encoder::Subsampling;
+use zune_jpeg::restart_index::RestartIndex;
+use zune_jpeg::{ColorSpace, Decoder, Marker, ZuneJpegOptions};
+
+mod common;
+
+/// Encode a 301 by 213 pattern
+fn encode(subsampling: Subsampling, colorspace: ColorSpace, restart_interval: u16) -> Vec<u8>
+{
+    common::encode((301, 213), subsampling, colorspace, restart_interval)
+}
+
+#[test]
+fn indexed_region_matches_region()
+{
+    let images = [
+        (Subsampling::Chroma444, ColorSpace::RGB, 1),
+        (Subsampling::Chroma422, ColorSpace::RGB, 7),
+        (Subsampling::Chroma420, ColorSpace::RGB, 50),
+        (Subsampling::Chroma420, ColorSpace::GRAYSCALE, 3),
+    ];
+
+    for (subsampling, colorspace, restart_interval) in images
+    {
+        let jpeg = encode(subsampling, colorspace, restart_interval);
+        let index = RestartIndex::new(&jpeg).unwrap();
+
+        assert_eq!(index.restart_interval(), usize::from(restart_interval));
+
+        let options = ZuneJpegOptions::new().set_out_colorspace(colorspace);
+
+        for (x, y, width, height) in [
+            (0, 0, 301, 213),
+            (40, 100, 200, 13),
+            (300, 200, 1, 13),
+            (7, 57, 90, 80),
+        ]
+        {
+            let expected = Decoder::new_with_options(options)
+                .decode_region(&jpeg, x, y, width, height)
+                .unwrap();
+            let pixels = Decoder::new_with_options(options)
+                .decode_region_with_index(&jpeg, &index, x, y, width, height)
+                .unwrap();
+
+            assert!(
+                pixels == expected,
+                "{subsampling:?} {restart_interval} {x},{y} {width}x{height}"
+            );
+        }
+    }
+}
+
+#[test]
+fn indexed_region_skips_data_above()
+{
+    let jpeg = encode(Subsampling::Chroma420, ColorSpace::RGB, 4);
+    let index = RestartIndex::new(&jpeg).unwrap();
+    let expected = Decoder::new().decode_region(&jpeg, 10, 150, 200, 40).unwrap();
+
+    // damage all restart intervals before the one holding the region's first row of
+    // MCUs, the first of a chunk of two rows
+    let (start, _) = index.row_start(0).unwrap();
+    let (end, _) = index.row_start(150 / 32 * 2).unwrap();
+    let mut damaged = jpeg.clone();
+
+    damaged[start..end - 2]
+        .iter_mut()
+        .filter(|x| **x != 0xFF)
+        .for_each(|x| *x = 0);
+
+    let pixels = Decoder::new()
+        .decode_region_with_index(&damaged, &index, 10, 150, 200, 40)
+        .unwrap();
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn restart_intervals_decode_like_none()
+{
+    // restart intervals count MCUs, not blocks of each component
+    let (subsamplings, colorspaces) = (
+        [Subsampling::Chroma444, Subsampling::Chroma422, Subsampling::Chroma420],
+        [ColorSpace::RGB, ColorSpace::GRAYSCALE],
+    );
+
+    for subsampling in subsamplings
+    {
+        for colorspace in colorspaces
+        {
+            let options = ZuneJpegOptions::new().set_out_colorspace(colorspace);
+            let decode = |jpeg: &[u8]| Decoder::new_with_options(options).decode_buffer(jpeg).unwrap();
+            let expected = decode(&encode(subsampling, colorspace, 0));
+
+            for restart_interval in [1, 2, 7, 100]
+            {
+                let jpeg = encode(subsampling, colorspace, restart_interval);
+
+                assert!(
+                    decode(&jpeg) == expected,
+                    "{subsampling:?} {colorspace:?} {restart_interval}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn index_round_trip()
+{
+    let jpeg = encode(Subsampling::Chroma422, ColorSpace::RGB, 3);
+    let index = RestartIndex::new(&jpeg).unwrap();
+    let stored = index.to_bytes();
+
+    assert!(stored.starts_with(b"ZJRI"));
+    assert_eq!(RestartIndex::from_bytes(&stored).unwrap(), index);
+    // one row of MCUs per 16 pixels, and an offset of a few bytes each
+    assert_eq!(index.rows(), 27);
+    assert!(stored.len() < 21 + 8 + 27 * 2);
+
+    assert!(RestartIndex::from_bytes(&stored[..stored.len() - 1]).is_err());
+    assert!(RestartIndex::from_bytes(&jpeg).is_err());
+}
+
+#[test]
+fn index_errors()
+{
+    // no restart interval
+    let jpeg = encode(Subsampling::Chroma420, ColorSpace::RGB, 0);
+
+    assert!(RestartIndex::new(&jpeg).is_err());
+
+    // an index of another file
+    let jpeg = encode(Subsampling::Chroma420, ColorSpace::RGB, 2);
+    let other = encode(Subsampling::Chroma420, ColorSpace::RGB, 3);
+    let index = RestartIndex::new(&other).unwrap();
+
+    assert!(Decoder::new()
+        .decode_region_with_index(&jpeg, &index, 0, 0, 8, 8)
+        .is_err());
+
+    // a file cut in the middle of the entropy coded data
+    let mut truncated = jpeg[..jpeg.len() / 2].to_vec();
+
+    truncated.extend_from_slice(&[0xFF, Marker::EOI.to_u8().unwrap()]);
+
+    assert!(RestartIndex::new(&truncated).is_err());
+}
+
+/// A stored index with the header digest of `stored` and the given numbers
+fn forge(stored: &[u8], numbers: &[usize]) -> Vec<u8>
+{
+    let mut output = stored[..21].to_vec();
+
+    for &number in numbers
+    {
+        let mut value = number;
+
+        while value >= 0x80
+        {
+            output.push((value & 0x7F) as u8 | 0x80);
+            value >>= 7;
+        }
+        output.push(value as u8);
+    }
+    output
+}
+
+#[test]
+fn forged_index()
+{
+    let jpeg = encode(Subsampling::Chroma420, ColorSpace::RGB, 3);
+    let index = RestartIndex::new(&jpeg).unwrap();
+    let stored = index.to_bytes();
+
+    // file length, restart interval, MCUs in a row and rows, then offset deltas
+    let deltas = (0..index.rows())
+        .map(|row| index.row_start(row).unwrap().0)
+        .scan(0, |previous, offset| Some(offset - std::mem::replace(previous, offset)))
+        .collect::<Vec<usize>>();
+    let numbers = |interval: usize, mcus_per_row: usize, deltas: &[usize]| {
+        [jpeg.len(), interval, mcus_per_row, deltas.len()]
+            .into_iter()
+            .chain(deltas.iter().copied())
+            .collect::<Vec<usize>>()
+    };
+
+    let genuine = forge(&stored, &numbers(3, 19, &deltas));
+
+    assert_eq!(genuine, stored);
+
+    let mut shifted = deltas.clone();
+
+    shifted[0] += 1;
+
+    let mut past_end = deltas.clone();
+
+    *past_end.last_mut().unwrap() += jpeg.len();
+
+    for forged in [
+        numbers(1, 19, &deltas),
+        numbers(3, 20, &deltas),
+        numbers(3, 19, &deltas[..10]),
+        numbers(3, 19, &shifted),
+        numbers(3, 19, &past_end),
+    ]
+    {
+        let index = RestartIndex::from_bytes(&forge(&stored, &forged)).unwrap();
+
+        assert!(Decoder::new()
+            .decode_region_with_index(&jpeg, &index, 0, 100, 50, 50)
+            .is_err());
+    }
+    // row positions don't overflow
+    let index = RestartIndex::from_bytes(&forge(&stored, &numbers(3, usize::MAX, &deltas))).unwrap();
+
+    assert_eq!(index.row_start(5), None);
+}
@@ -0,0 +1,182 @@
+use std::num::NonZeroU32;
+
+use zune_jpeg::encoder::{Encoder, Subsampling};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+mod common;
+
+/// Encode a 347 by 229 pattern
+fn encode(subsampling: Subsampling, colorspace: ColorSpace, restart_interval: u16) -> Vec<u8>
+{
+    common::encode((347, 229), subsampling, colorspace, restart_interval)
+}
+
+/// Decode on `threads` threads
+fn decode(jpeg: &[u8], colorspace: ColorSpace, threads: u32) -> Vec<u8>
+{
+    let options = ZuneJpegOptions::new()
+        .set_out_colorspace(colorspace)
+        .set_num_threads(NonZeroU32::new(threads).unwrap());
+
+    Decoder::new_with_options(options).decode_buffer(jpeg).unwrap()
+}
+
+#[test]
+fn parallel_intervals_match_serial()
+{
+    for subsampling in [Subsampling::Chroma444, Subsampling::Chroma422, Subsampling::Chroma420]
+    {
+        for restart_interval in [1, 5, 22, 1000]
+        {
+            let jpeg = encode(subsampling, ColorSpace::RGB, restart_interval);
+
+            for colorspace in [ColorSpace::RGB, ColorSpace::YCbCr, ColorSpace::GRAYSCALE]
+            {
+                assert!(
+                    decode(&jpeg, colorspace, 4) == decode(&jpeg, colorspace, 1),
+                    "{subsampling:?} {restart_interval} {colorspace:?}"
+                );
+            }
+        }
+    }
+    let jpeg = encode(Subsampling::Chroma444, ColorSpace::GRAYSCALE, 9);
+
+    assert!(
+        decode(&jpeg, ColorSpace::GRAYSCALE, 3) == decode(&jpeg, ColorSpace::GRAYSCALE, 1)
+    );
+}
+
+#[test]
+fn missing_restart_marker_decodes_serially()
+{
+    let jpeg = encode(Subsampling::Chroma420, ColorSpace::RGB, 4);
+
+    // drop the last restart marker, the intervals can't be found anymore
+    let marker = jpeg
+        .windows(2)
+        .rposition(|x| x[0] == 0xFF && (0xD0..=0xD7).contains(&x[1]))
+        .unwrap();
+    let mut damaged = jpeg.clone();
+
+    damaged.drain(marker..marker + 2);
+
+    assert!(decode(&damaged, ColorSpace::RGB, 4) == decode(&damaged, ColorSpace::RGB, 1));
+}
+
+#[test]
+fn progressive_restart_intervals()
+{
+    // flat areas give EOB runs over whole restart intervals
+    let (width, height) = (347, 229);
+    let pixels = common::pattern(width, height)
+        .chunks_exact(3)
+        .enumerate()
+        .flat_map(|(i, x)| if (i % width) < 200 { [128; 3] } else { [x[0], x[1], x[2]] })
+        .collect::<Vec<u8>>();
+
+    for subsampling in [Subsampling::Chroma444, Subsampling::Chroma420]
+    {
+        for restart_interval in [1, 3, 7, 50]
+        {
+            let encode = |progressive| {
+                Encoder::new()
+                    .set_subsampling(subsampling)
+                    .set_restart_interval(restart_interval)
+                    .set_progressive(progressive)
+                    .encode(&pixels, width as u16, height as u16, ColorSpace::RGB)
+                    .unwrap()
+            };
+            let (progressive, sequential) = (encode(true), encode(false));
+
+            assert!(
+                decode(&progressive, ColorSpace::RGB, 1) == decode(&sequential, ColorSpace::RGB, 1),
+                "{subsampling:?} {restart_interval}"
+            );
+        }
+    }
+}
+
+#[test]
+fn segment_after_last_restart_interval()
+{
+    // 44 by 29 MCUs, the last interval ends with the last MCU
+    for restart_interval in [1, 4]
+    {
+        let jpeg = encode(Subsampling::Chroma444, ColorSpace::RGB, restart_interval);
+        let mut commented = jpeg[..jpeg.len() - 2].to_vec();
+
+        // the decoder finds the comment where it expects a restart marker
+        commented.extend_from_slice(&[0xFF, 0xFE, 0x00, 0x06, b'n', b'o', b't', b'e']);
+        commented.extend_from_slice(&[0xFF, 0xD9]);
+
+        assert!(decode(&commented, ColorSpace::RGB, 1) == decode(&jpeg, ColorSpace::RGB, 1));
+    }
+}
+
+/// Decode on 4 threads, speculatively
+fn decode_speculatively(jpeg: &[u8], colorspace: ColorSpace) -> Vec<u8>
+{
+    let options = ZuneJpegOptions::new()
+        .set_out_colorspace(colorspace)
+        .set_speculative_decoding(true);
+
+    Decoder::new_with_options(options).decode_buffer(jpeg).unwrap()
+}
+
+#[test]
+fn speculative_decoding_matches_serial()
+{
+    for subsampling in [Subsampling::Chroma444, Subsampling::Chroma422, Subsampling::Chroma420]
+    {
+        let jpeg = encode(subsampling, ColorSpace::RGB, 0);
+
+        for colorspace in [ColorSpace::RGB, ColorSpace::YCbCr, ColorSpace::GRAYSCALE]
+        {
+            assert!(
+                decode_speculatively(&jpeg, colorspace) == decode(&jpeg, colorspace, 1),
+                "{subsampling:?} {colorspace:?}"
+            );
+        }
+    }
+    let jpeg = encode(Subsampling::Chroma444, ColorSpace::GRAYSCALE, 0);
+
+    assert!(
+        decode_speculatively(&jpeg, ColorSpace::GRAYSCALE)
+            == decode(&jpeg, ColorSpace::GRAYSCALE, 1)
+    );
+}
+
+#[test]
+fn speculative_decoding_matches_serial_on_files()
+{
+    for name in ["medium_horiz_samp_2500x1786.jpg", "huffman_third_index.jpg"]
+    {
+        let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+        let jpeg = std::fs::read(path).unwrap();
+
+        assert!(
+            decode_speculatively(&jpeg, ColorSpace::RGB) == decode(&jpeg, ColorSpace::RGB, 1),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn speculative_decoding_matches_serial_on_damaged_data()
+{
+    let jpeg = encode(Subsampling::Chroma444, ColorSpace::RGB, 0);
+
+    for (position, value) in [(jpeg.len() / 3, 0x00), (jpeg.len() / 2, 0x5A), (jpeg.len() - 40, 0xC3)]
+    {
+        let mut damaged = jpeg.clone();
+
+        damaged[position] = value;
+
+        let options = ZuneJpegOptions::new().set_num_threads(NonZeroU32::new(1).unwrap());
+        let expected = Decoder::new_with_options(options).decode_buffer(&damaged);
+        let pixels = Decoder::new_with_options(ZuneJpegOptions::new().set_speculative_decoding(true))
+            .decode_buffer(&damaged);
+
+        assert!(pixels.ok() == expected.ok(), "{position}");
+    }
+}
@@ -0,0 +1,178 @@
+use mozjpeg::ColorSpace as OutColorSpace;
+use zune_jpeg::transcode::Transcoder;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+use crate::common::{gradient, mozjpeg_encode};
+
+mod common;
+
+fn read_input(name: &str) -> Vec<u8>
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+
+    std::fs::read(path).unwrap()
+}
+
+/// Mean absolute difference of `scaled` and the box filtered `full` image it should
+/// be a reduced copy of, both RGB
+fn difference(full: &[u8], width: usize, height: usize, scaled: &[u8], scale: usize) -> f64
+{
+    let (scaled_width, scaled_height) = ((width * scale).div_ceil(8), (height * scale).div_ceil(8));
+    let mut total = 0;
+
+    assert_eq!(scaled.len(), scaled_width * scaled_height * 3);
+
+    for y in 0..scaled_height
+    {
+        for x in 0..scaled_width
+        {
+            // the full size pixels the scaled pixel covers
+            let (x_start, x_end) = (x * 8 / scale, ((x + 1) * 8 / scale).min(width));
+            let (y_start, y_end) = (y * 8 / scale, ((y + 1) * 8 / scale).min(height));
+            let count = ((x_end - x_start) * (y_end - y_start)) as u32;
+
+            for c in 0..3
+            {
+                let sum = (y_start..y_end)
+                    .flat_map(|j| (x_start..x_end).map(move |i| (j * width + i) * 3 + c))
+                    .map(|i| u32::from(full[i]))
+                    .sum::<u32>();
+                let average = ((sum + count / 2) / count) as u8;
+
+                total += u64::from(average.abs_diff(scaled[(y * scaled_width + x) * 3 + c]));
+            }
+        }
+    }
+    total as f64 / scaled.len() as f64
+}
+
+#[test]
+fn scaled_matches_full_decode()
+{
+    for name in ["single_qt.jpeg", "huffman_third_index.jpg"]
+    {
+        let data = read_input(name);
+        let mut decoder = Decoder::new();
+        let full = decoder.decode_buffer(&data).unwrap();
+        let (width, height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+
+        for scale in 1..=8
+        {
+            let mut decoder = Decoder::new();
+            let scaled = decoder.decode_scaled(&data, scale).unwrap();
+            let (scaled_width, scaled_height) = decoder.scaled_dimensions(scale);
+
+            assert_eq!(scaled.len(), scaled_width * scaled_height * 3);
+
+            let mean = difference(&full, width, height, &scaled, usize::from(scale));
+
+            assert!(mean < 4.0, "Mean difference {mean} too large for {name} at {scale}/8");
+        }
+    }
+}
+
+#[test]
+fn scaled_full_size_matches_decode()
+{
+    // scalar, SSE and AVX2 up-samplers and color converters
+    let paths = [
+        ("scalar", ZuneJpegOptions::new().set_use_unsafe(false)),
+        ("sse", ZuneJpegOptions::new().set_use_avx2(false)),
+        ("avx2", ZuneJpegOptions::new()),
+    ];
+    let colorspaces = [ColorSpace::RGB, ColorSpace::RGBA, ColorSpace::YCbCr, ColorSpace::GRAYSCALE];
+    let samplings = [None, Some((1, 1)), Some((2, 1)), Some((2, 2))];
+
+    // rows of whole and partial MCUs, fewer than 16 pixels, and an odd number of MCU rows
+    for (width, height) in [(64, 48), (37, 24), (21, 13), (9, 5)]
+    {
+        let pixels = gradient(width, height);
+        let gray = pixels.iter().step_by(3).copied().collect::<Vec<u8>>();
+
+        for (sampling, progressive) in samplings.into_iter().flat_map(|s| [(s, false), (s, true)])
+        {
+            // no sampling factors for a grayscale image
+            let (colorspace, pixels) = match sampling
+            {
+                Some(_) => (OutColorSpace::JCS_RGB, &pixels),
+                None => (OutColorSpace::JCS_GRAYSCALE, &gray),
+            };
+            let jpeg = mozjpeg_encode(colorspace, pixels, (width, height), |comp| {
+                comp.set_quality(90.0);
+
+                if let Some(sampling) = sampling
+                {
+                    comp.set_chroma_sampling_pixel_sizes(sampling, sampling);
+                }
+                if progressive
+                {
+                    comp.set_progressive_mode();
+                }
+            });
+
+            for ((path, options), colorspace) in paths
+                .into_iter()
+                .flat_map(|path| colorspaces.map(|colorspace| (path, colorspace)))
+            {
+                let options = options.set_out_colorspace(colorspace);
+
+                if sampling.is_none() && colorspace != ColorSpace::GRAYSCALE
+                {
+                    continue;
+                }
+                let full = Decoder::new_with_options(options).decode_buffer(&jpeg).unwrap();
+                let scaled = Decoder::new_with_options(options).decode_scaled(&jpeg, 8).unwrap();
+
+                assert!(
+                    full == scaled,
+                    "{width}x{height} {sampling:?} {progressive} {path} {colorspace:?}"
+                );
+
+                for scale in 1..8
+                {
+                    let mut decoder = Decoder::new_with_options(options);
+                    let scaled = decoder.decode_scaled(&jpeg, scale).unwrap();
+                    let (scaled_width, scaled_height) = decoder.scaled_dimensions(scale);
+
+                    assert_eq!(scaled.len(), scaled_width * scaled_height * colorspace.num_components());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn scaled_skips_progressive_ac()
+{
+    // the same coefficients, where AC coefficients can't be skipped
+    let sequential = read_input("huffman_third_index.jpg");
+    let progressive = Transcoder::new().set_progressive(true).transcode(&sequential).unwrap();
+    let arithmetic = Transcoder::new()
+        .set_progressive(true)
+        .set_arithmetic(true)
+        .transcode(&sequential)
+        .unwrap();
+
+    for scale in [1, 3, 8]
+    {
+        let expected = Decoder::new().decode_scaled(&sequential, scale).unwrap();
+
+        assert!(Decoder::new().decode_scaled(&progressive, scale).unwrap() == expected);
+        assert!(Decoder::new().decode_scaled(&arithmetic, scale).unwrap() == expected);
+    }
+}
+
+#[test]
+fn scaled_errors()
+{
+    let data = read_input("single_qt.jpeg");
+
+    for scale in [0, 9]
+    {
+        assert!(Decoder::new().decode_scaled(&data, scale).is_err());
+    }
+    // there is no conversion to CMYK
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::CMYK);
+
+    assert!(Decoder::new_with_options(options).decode_scaled(&data, 4).is_err());
+}
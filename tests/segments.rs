@@ -0,0 +1,269 @@
+use zune_jpeg::description::{HuffmanClass, ScanComponent};
+use zune_jpeg::errors::{DecodeErrors, UnsupportedSchemes};
+use zune_jpeg::segments::{SegmentKind, Segments};
+use zune_jpeg::{Decoder, Marker};
+
+fn open(path: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/" + path).unwrap()
+}
+
+#[test]
+fn marker_round_trip()
+{
+    for byte in 0x01..=0xFE
+    {
+        let marker = Marker::from_u8(byte).unwrap();
+
+        assert_eq!(marker.to_u8(), Some(byte));
+    }
+    // numbers out of range, or of other markers
+    for marker in [
+        Marker::SOF(4),
+        Marker::SOF(16),
+        Marker::SOF(200),
+        Marker::RST(8),
+        Marker::APP(16),
+        Marker::APP(255),
+        Marker::JPGn(14),
+        Marker::RES(0x01),
+        Marker::RES(0xD8),
+    ]
+    {
+        assert_eq!(marker.to_u8(), None, "{marker:?}");
+    }
+    assert_eq!(Marker::from_u8(0x00), None);
+    assert_eq!(Marker::from_u8(0xFF), None);
+
+    assert_eq!(Marker::from_u8(0xC1), Some(Marker::SOF(1)));
+    assert_eq!(Marker::from_u8(0xC9), Some(Marker::SOF(9)));
+    assert_eq!(Marker::from_u8(0xC8), Some(Marker::JPG));
+    assert_eq!(Marker::from_u8(0xE7), Some(Marker::APP(7)));
+    assert_eq!(Marker::from_u8(0xF3), Some(Marker::JPGn(3)));
+}
+
+#[test]
+fn segments_cover_file()
+{
+    let data = open("test-images/test-baseline.jpg");
+
+    let segments = Segments::new(&data).collect::<Vec<_>>();
+
+    assert_eq!(segments.first().unwrap().marker(), Some(Marker::SOI));
+    assert_eq!(segments.last().unwrap().marker(), Some(Marker::EOI));
+
+    // no fill bytes in this file, segments follow each other
+    let mut end = 0;
+
+    for segment in &segments
+    {
+        assert_eq!(segment.fill, 0);
+        assert_eq!(segment.offset, end);
+        assert_eq!(&data[segment.payload_range()], segment.payload);
+
+        end = segment.offset + segment.length;
+    }
+    assert_eq!(end, data.len());
+
+    // a baseline image has a single scan
+    let scan = segments
+        .iter()
+        .position(|x| x.marker() == Some(Marker::SOS))
+        .unwrap();
+
+    assert_eq!(segments[scan + 1].kind, SegmentKind::EntropyCoded);
+    assert_eq!(segments.len(), scan + 3);
+}
+
+#[test]
+fn segments_restart_intervals()
+{
+    let data = [
+        0xFF, 0xD8, // SOI
+        0xFF, 0xE5, 0x00, 0x04, 0xAB, 0xCD, // APP(5)
+        0xFF, 0xF3, 0x00, 0x02, // JPG(3)
+        0xFF, 0xDD, 0x00, 0x04, 0x00, 0x01, // DRI
+        0xFF, 0xDA, 0x00, 0x02, // SOS, contents don't matter here
+        0x12, 0xFF, 0x00, 0x34, // entropy coded data with a stuffed byte
+        0xFF, 0xD0, // RST(0)
+        0x56, // entropy coded data
+        0xFF, 0xFF, 0xD1, // RST(1) after a fill byte
+        0x78, 0x9A, // entropy coded data
+        0xFF, 0xD9, // EOI
+        0x00, 0x01, // trailing data
+    ];
+    let segments = Segments::new(&data)
+        .map(|x| (x.kind, x.offset - x.fill, x.offset, x.length, x.payload))
+        .collect::<Vec<_>>();
+
+    let ecs = SegmentKind::EntropyCoded;
+    let marker = SegmentKind::Marker;
+
+    assert_eq!(
+        segments,
+        [
+            (marker(Marker::SOI), 0, 0, 2, &[][..]),
+            (marker(Marker::APP(5)), 2, 2, 6, &[0xAB, 0xCD][..]),
+            (marker(Marker::JPGn(3)), 8, 8, 4, &[][..]),
+            (marker(Marker::DRI), 12, 12, 6, &[0x00, 0x01][..]),
+            (marker(Marker::SOS), 18, 18, 4, &[][..]),
+            (ecs, 22, 22, 4, &[0x12, 0xFF, 0x00, 0x34][..]),
+            (marker(Marker::RST(0)), 26, 26, 2, &[][..]),
+            (ecs, 28, 28, 1, &[0x56][..]),
+            (marker(Marker::RST(1)), 29, 30, 2, &[][..]),
+            (ecs, 32, 32, 2, &[0x78, 0x9A][..]),
+            (marker(Marker::EOI), 34, 34, 2, &[][..]),
+        ]
+    );
+
+    // with their fill bytes, segments follow each other up to the end of image
+    for pair in segments.windows(2)
+    {
+        assert_eq!(pair[1].1, pair[0].2 + pair[0].3);
+    }
+}
+
+#[test]
+fn segments_truncated()
+{
+    // APP(1) claims more bytes than there are
+    let data = [0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x10, 0x00];
+
+    let markers = Segments::new(&data)
+        .map(|x| x.marker())
+        .collect::<Vec<_>>();
+
+    assert_eq!(markers, [Some(Marker::SOI)]);
+}
+
+#[test]
+fn unsupported_frame()
+{
+    let data = open("test-images/test-arithmetic-coding.jpg");
+
+    let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+    assert!(matches!(
+        err,
+        DecodeErrors::Unsupported(UnsupportedSchemes::ProgressiveDctArithmetic)
+    ));
+}
+
+/// Position of the n-th coefficient of a block in zig-zag order, ITU-T T.81 Figure A.6
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27,
+    20, 13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+#[test]
+fn frame_description_baseline()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let frame = decoder.frame_description().unwrap();
+
+    assert_eq!((frame.width, frame.height), (1920, 1080));
+    assert_eq!(frame.precision, 8);
+    assert!(!frame.progressive);
+    assert_eq!(frame.restart_interval, 0);
+
+    let ids = frame.components.iter().map(|x| x.id).collect::<Vec<u8>>();
+    let tables = frame
+        .components
+        .iter()
+        .map(|x| x.quantization_table_number)
+        .collect::<Vec<u8>>();
+
+    assert_eq!(ids, [1, 2, 3]);
+    assert_eq!(tables, [0, 1, 1]);
+    assert!(frame
+        .components
+        .iter()
+        .all(|x| (x.horizontal_sample, x.vertical_sample) == (1, 1)));
+
+    // tables are in natural order, DQT stores them in zig-zag order
+    let dqt = Segments::new(&data)
+        .filter(|x| x.marker() == Some(Marker::DQT))
+        .map(|x| x.payload)
+        .collect::<Vec<_>>();
+
+    for (component, table) in [(0, dqt[0]), (1, dqt[1])]
+    {
+        let natural = &frame.components[component].quantization_table;
+
+        for (k, &value) in table[1..65].iter().enumerate()
+        {
+            assert_eq!(natural[ZIGZAG[k]], u16::from(value));
+        }
+    }
+    // each DHT segment of this file holds one table
+    let dht = Segments::new(&data)
+        .filter(|x| x.marker() == Some(Marker::DHT))
+        .map(|x| x.payload)
+        .collect::<Vec<_>>();
+
+    assert_eq!(frame.huffman_tables.len(), dht.len());
+
+    for (table, payload) in frame.huffman_tables.iter().zip(dht)
+    {
+        let class = if payload[0] >> 4 == 0 { HuffmanClass::Dc } else { HuffmanClass::Ac };
+
+        assert_eq!((table.class, table.index), (class, payload[0] & 15));
+        assert_eq!(table.bits, payload[1..17]);
+        assert_eq!(table.values, payload[17..]);
+    }
+    assert_eq!(frame.scans.len(), 1);
+
+    let scan = &frame.scans[0];
+
+    assert_eq!((scan.spec_start, scan.spec_end, scan.succ_high, scan.succ_low), (0, 63, 0, 0));
+    assert_eq!(
+        scan.components,
+        [
+            ScanComponent { id: 1, dc_table: 0, ac_table: 0 },
+            ScanComponent { id: 2, dc_table: 1, ac_table: 1 },
+            ScanComponent { id: 3, dc_table: 1, ac_table: 1 },
+        ]
+    );
+}
+
+#[test]
+fn frame_description_progressive()
+{
+    let data = open("test-images/test-progressive.jpg");
+    let mut decoder = Decoder::new();
+
+    assert!(decoder.frame_description().is_none());
+
+    decoder.read_headers(&data).unwrap();
+
+    let frame = decoder.frame_description().unwrap();
+
+    assert!(frame.progressive);
+    assert_eq!(frame.scans.len(), 1);
+
+    // DC first scan, interleaved
+    let scan = &frame.scans[0];
+
+    assert_eq!((scan.spec_start, scan.spec_end, scan.succ_high, scan.succ_low), (0, 0, 0, 1));
+    assert_eq!(scan.components.len(), 3);
+
+    decoder.decode_buffer(&data).unwrap();
+
+    // the whole scan script is known after decoding
+    let frame = decoder.frame_description().unwrap();
+    let sos = Segments::new(&data)
+        .filter(|x| x.marker() == Some(Marker::SOS))
+        .count();
+    let dht = Segments::new(&data)
+        .filter(|x| x.marker() == Some(Marker::DHT))
+        .count();
+
+    assert_eq!(frame.scans.len(), sos);
+    assert!(frame.huffman_tables.len() >= dht);
+    assert!(frame.scans[1..].iter().all(|x| x.components.len() == 1 || x.spec_start == 0));
+}
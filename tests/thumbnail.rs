@@ -0,0 +1,85 @@
+use zune_jpeg::thumbnail::ThumbnailSource;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn read_input(name: &str) -> Vec<u8>
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+
+    std::fs::read(path).unwrap()
+}
+
+#[test]
+fn thumbnail_sources()
+{
+    let data = read_input("medium_horiz_samp_2500x1786.jpg");
+
+    let cases = [
+        (64, ThumbnailSource::Exif, (64, 46)),
+        (300, ThumbnailSource::DcOnly, (300, 215)),
+        (600, ThumbnailSource::Scaled(2), (600, 429)),
+        (2000, ThumbnailSource::Scaled(7), (2000, 1429)),
+        (4000, ThumbnailSource::FullDecode, (2500, 1786)),
+    ];
+
+    for (max_dim, source, dimensions) in cases
+    {
+        let thumbnail = Decoder::new().decode_thumbnail(&data, max_dim).unwrap();
+
+        assert_eq!(thumbnail.source, source, "Wrong source for {}", max_dim);
+        assert_eq!((thumbnail.width, thumbnail.height), dimensions);
+        assert_eq!(thumbnail.pixels.len(), thumbnail.width * thumbnail.height * 3);
+    }
+}
+
+#[test]
+fn dc_only_thumbnail_matches_full_decode()
+{
+    for name in ["single_qt.jpeg", "huffman_third_index.jpg"]
+    {
+        let data = read_input(name);
+        let thumbnail = Decoder::new().decode_thumbnail(&data, 200).unwrap();
+
+        assert_eq!(thumbnail.source, ThumbnailSource::DcOnly);
+
+        let mut decoder = Decoder::new();
+        let full = decoder.decode_buffer(&data).unwrap();
+        let (width, height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+        let channels = thumbnail.colorspace.num_components();
+
+        // compare against the center pixel of the area each thumbnail pixel covers
+        let mut difference = 0;
+
+        for y in 0..thumbnail.height
+        {
+            for x in 0..thumbnail.width
+            {
+                let full_x = (2 * x + 1) * width / (2 * thumbnail.width);
+                let full_y = (2 * y + 1) * height / (2 * thumbnail.height);
+
+                for c in 0..channels
+                {
+                    let a = full[(full_y * width + full_x) * channels + c];
+                    let b = thumbnail.pixels[(y * thumbnail.width + x) * channels + c];
+
+                    difference += u64::from(a.abs_diff(b));
+                }
+            }
+        }
+        let mean = difference / (thumbnail.pixels.len() as u64);
+
+        assert!(mean < 12, "Mean difference {} too large for {}", mean, name);
+    }
+}
+
+#[test]
+fn thumbnail_falls_back_to_full_decode()
+{
+    // reduced size decoding can't convert to CMYK
+    let data = read_input("medium_horiz_samp_2500x1786.jpg");
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::CMYK);
+    let thumbnail = Decoder::new_with_options(options).decode_thumbnail(&data, 300).unwrap();
+
+    assert_eq!(thumbnail.source, ThumbnailSource::FullDecode);
+    assert_eq!((thumbnail.width, thumbnail.height), (300, 214));
+    assert_eq!(thumbnail.pixels.len(), thumbnail.width * thumbnail.height * 4);
+}
@@ -0,0 +1,829 @@
+use mozjpeg::ColorSpace as OutColorSpace;
+use zune_jpeg::encoder::Encoder;
+use zune_jpeg::requantize::quality_tables;
+use zune_jpeg::rewrite::MetadataRewriter;
+use zune_jpeg::segments::Segments;
+use zune_jpeg::transcode::{Scan, Transcoder};
+use zune_jpeg::transform::{EdgeHandling, Transform};
+use zune_jpeg::{ColorSpace, Decoder, Marker};
+
+const TRANSFORMS: [Transform; 7] = [
+    Transform::FlipHorizontal,
+    Transform::FlipVertical,
+    Transform::Transpose,
+    Transform::Transverse,
+    Transform::Rotate90,
+    Transform::Rotate180,
+    Transform::Rotate270,
+];
+
+fn open(path: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/" + path).unwrap()
+}
+
+/// Encode RGB pixels with chroma `subsampling` in pixels, e.g (2, 2) for 4:2:0
+fn encode(width: usize, height: usize, subsampling: (u8, u8)) -> Vec<u8>
+{
+    // smooth gradients, sharp edges make decoders disagree by more than rounding
+    let pixels = (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+
+            [x * 200 / width + 20, y * 200 / height + 20, (x * 3 + y * 5) % 60 + 100]
+        })
+        .map(|x| x as u8)
+        .collect::<Vec<u8>>();
+
+    let mut comp = mozjpeg::Compress::new(OutColorSpace::JCS_RGB);
+
+    comp.set_size(width, height);
+    comp.set_quality(90.0);
+    comp.set_chroma_sampling_pixel_sizes(subsampling, subsampling);
+    comp.set_mem_dest();
+    comp.start_compress();
+
+    assert!(comp.write_scanlines(&pixels));
+
+    comp.finish_compress();
+    comp.data_to_vec().unwrap()
+}
+
+fn transcode(data: &[u8], transform: Transform, edges: EdgeHandling) -> Vec<u8>
+{
+    Transcoder::new()
+        .set_transform(transform)
+        .set_edge_handling(edges)
+        .transcode(data)
+        .unwrap()
+}
+
+/// Decode with an independent decoder, so the encoder is checked against
+/// another implementation
+fn decode(data: &[u8]) -> (Vec<u8>, usize, usize)
+{
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let pixels = decoder.decode().unwrap();
+    let info = decoder.info().unwrap();
+
+    (pixels, usize::from(info.width), usize::from(info.height))
+}
+
+/// Decode with libjpeg, the reference for arithmetic coded images
+fn decode_libjpeg(data: &[u8]) -> (Vec<u8>, usize, usize)
+{
+    let decompress = mozjpeg::Decompress::new_mem(data).unwrap();
+    let (width, height) = decompress.size();
+    let mut decompress = decompress.rgb().unwrap();
+    let pixels = decompress.read_scanlines::<[u8; 3]>().unwrap();
+
+    (pixels.concat(), width, height)
+}
+
+#[test]
+fn transcode_identity()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let output = Transcoder::new().transcode(&data).unwrap();
+
+    assert_eq!(decode(&output), decode(&data));
+    assert_eq!(
+        Decoder::new().decode_coefficients(&output).unwrap(),
+        Decoder::new().decode_coefficients(&data).unwrap()
+    );
+
+    // tables of a sequential image are reused
+    let tables = |data: &[u8]| {
+        let mut decoder = Decoder::new();
+
+        decoder.read_headers(data).unwrap();
+
+        let mut tables = decoder.frame_description().unwrap().huffman_tables;
+
+        tables.sort_by_key(|x| (x.index, x.class == zune_jpeg::description::HuffmanClass::Ac));
+        tables
+    };
+    assert_eq!(tables(&output), tables(&data));
+}
+
+#[test]
+fn transcode_progressive()
+{
+    let data = open("test-images/test-progressive.jpg");
+    let output = Transcoder::new().transcode(&data).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&output).unwrap();
+
+    assert!(!decoder.frame_description().unwrap().progressive);
+    assert_eq!(
+        Decoder::new().decode_coefficients(&output).unwrap(),
+        Decoder::new().decode_coefficients(&data).unwrap()
+    );
+}
+
+#[test]
+fn transcode_optimize()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let output = Transcoder::new().set_optimize(true).transcode(&data).unwrap();
+
+    assert!(output.len() < data.len());
+    assert_eq!(decode(&output), decode(&data));
+}
+
+#[test]
+fn transcode_to_progressive()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let coefficients = Decoder::new().decode_coefficients(&data).unwrap();
+
+    let progressive = Transcoder::new().set_progressive(true).transcode(&data).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&progressive).unwrap();
+
+    assert!(decoder.frame_description().unwrap().progressive);
+    assert!(progressive.len() < data.len());
+    assert_eq!(
+        Decoder::new().decode_coefficients(&progressive).unwrap(),
+        coefficients
+    );
+    assert_eq!(decode(&progressive), decode(&data));
+
+    // and back
+    let baseline = Transcoder::new().transcode(&progressive).unwrap();
+
+    assert_eq!(Decoder::new().decode_coefficients(&baseline).unwrap(), coefficients);
+}
+
+#[test]
+fn transcode_scan_script()
+{
+    // whole MCUs, non-interleaved scans don't code blocks in the padding
+    let data = encode(48, 32, (2, 2));
+    let coefficients = Decoder::new().decode_coefficients(&data).unwrap();
+
+    let scripts = [
+        // sequential, non-interleaved
+        vec![Scan::new(&[0], 0, 63, 0, 0), Scan::new(&[1, 2], 0, 63, 0, 0)],
+        // progressive, with refinement of DC and AC bands over several bits
+        vec![
+            Scan::new(&[0, 1, 2], 0, 0, 0, 2),
+            Scan::new(&[0, 1, 2], 0, 0, 2, 1),
+            Scan::new(&[0, 1, 2], 0, 0, 1, 0),
+            Scan::new(&[0], 1, 63, 0, 3),
+            Scan::new(&[0], 1, 63, 3, 2),
+            Scan::new(&[0], 1, 63, 2, 1),
+            Scan::new(&[0], 1, 63, 1, 0),
+            Scan::new(&[1], 1, 9, 0, 0),
+            Scan::new(&[1], 10, 63, 0, 0),
+            Scan::new(&[2], 1, 63, 0, 0),
+        ],
+        // the generic script of libjpeg, without the fourth component
+        Scan::progressive(4)
+            .into_iter()
+            .map(|mut x| {
+                x.components.retain(|x| *x < 3);
+                x
+            })
+            .filter(|x| !x.components.is_empty())
+            .collect(),
+    ];
+
+    for script in scripts
+    {
+        let output = Transcoder::new().set_scan_script(script).transcode(&data).unwrap();
+
+        assert_eq!(Decoder::new().decode_coefficients(&output).unwrap(), coefficients);
+        assert_eq!(decode(&output), decode(&data));
+    }
+
+    // grayscale images use the generic script
+    let gray = Transcoder::new()
+        .set_grayscale(true)
+        .set_progressive(true)
+        .transcode(&data)
+        .unwrap();
+
+    assert_eq!(decode(&gray).0.len(), 48 * 32);
+
+    let invalid = [
+        // AC before DC
+        vec![Scan::new(&[0], 1, 63, 0, 0), Scan::new(&[0, 1, 2], 0, 0, 0, 0)],
+        // missing coefficients
+        vec![Scan::new(&[0, 1, 2], 0, 0, 0, 0), Scan::new(&[0], 1, 63, 0, 0)],
+        // two components in an AC scan
+        vec![Scan::new(&[0, 1, 2], 0, 0, 0, 0), Scan::new(&[0, 1], 1, 63, 0, 0)],
+        // refinement without a first scan
+        vec![Scan::new(&[0, 1, 2], 0, 0, 1, 0)],
+        // sequential, component twice
+        vec![Scan::new(&[0, 1, 2], 0, 63, 0, 0), Scan::new(&[0], 0, 63, 0, 0)],
+        vec![Scan::new(&[0, 1, 3], 0, 63, 0, 0)],
+        // out of frame order
+        vec![Scan::new(&[0], 0, 63, 0, 0), Scan::new(&[2, 1], 0, 63, 0, 0)],
+    ];
+
+    for script in invalid
+    {
+        assert!(Transcoder::new().set_scan_script(script).transcode(&data).is_err());
+    }
+}
+
+#[test]
+fn transcode_arithmetic()
+{
+    for path in ["test-images/test-baseline.jpg", "test-images/test-progressive.jpg"]
+    {
+        let data = open(path);
+
+        for progressive in [false, true]
+        {
+            let arithmetic = Transcoder::new()
+                .set_arithmetic(true)
+                .set_progressive(progressive)
+                .transcode(&data)
+                .unwrap();
+            let huffman = Transcoder::new()
+                .set_optimize(true)
+                .set_progressive(progressive)
+                .transcode(&data)
+                .unwrap();
+
+            let sof = if progressive { Marker::SOF(10) } else { Marker::SOF(9) };
+            let markers = Segments::new(&arithmetic)
+                .filter_map(|x| x.marker())
+                .collect::<Vec<_>>();
+
+            assert!(markers.contains(&sof));
+            assert!(!markers.contains(&Marker::DHT));
+
+            let mut decoder = Decoder::new();
+
+            decoder.read_headers(&arithmetic).unwrap();
+
+            let frame = decoder.frame_description().unwrap();
+
+            assert!(frame.arithmetic);
+            assert_eq!(frame.progressive, progressive);
+            assert!(arithmetic.len() < huffman.len());
+
+            // libjpeg decodes what zune decodes
+            assert_eq!(decode_libjpeg(&arithmetic), decode_libjpeg(&huffman));
+            assert_eq!(
+                Decoder::new().decode_coefficients(&arithmetic).unwrap(),
+                Decoder::new().decode_coefficients(&huffman).unwrap()
+            );
+
+            // and back to Huffman coding
+            let restored = Transcoder::new().transcode(&arithmetic).unwrap();
+
+            assert_eq!(decode(&restored), decode(&huffman));
+        }
+    }
+}
+
+#[test]
+fn transcode_arithmetic_scan_script()
+{
+    let data = encode(48, 32, (2, 2));
+    let coefficients = Decoder::new().decode_coefficients(&data).unwrap();
+
+    let scripts = [
+        vec![Scan::new(&[0], 0, 63, 0, 0), Scan::new(&[1, 2], 0, 63, 0, 0)],
+        vec![
+            Scan::new(&[0, 1, 2], 0, 0, 0, 2),
+            Scan::new(&[0, 1, 2], 0, 0, 2, 1),
+            Scan::new(&[0, 1, 2], 0, 0, 1, 0),
+            Scan::new(&[0], 1, 63, 0, 3),
+            Scan::new(&[0], 1, 63, 3, 2),
+            Scan::new(&[0], 1, 63, 2, 1),
+            Scan::new(&[0], 1, 63, 1, 0),
+            Scan::new(&[1], 1, 9, 0, 0),
+            Scan::new(&[1], 10, 63, 0, 0),
+            Scan::new(&[2], 1, 63, 0, 0),
+        ],
+    ];
+
+    for script in scripts
+    {
+        let output = Transcoder::new()
+            .set_arithmetic(true)
+            .set_scan_script(script)
+            .transcode(&data)
+            .unwrap();
+
+        assert_eq!(Decoder::new().decode_coefficients(&output).unwrap(), coefficients);
+        assert_eq!(decode_libjpeg(&output), decode_libjpeg(&data));
+    }
+
+    // grayscale
+    let gray = Transcoder::new()
+        .set_grayscale(true)
+        .set_arithmetic(true)
+        .transcode(&data)
+        .unwrap();
+
+    assert_eq!(decode_libjpeg(&gray).0.len(), 48 * 32 * 3);
+}
+
+#[test]
+fn transcode_from_arithmetic()
+{
+    // progressive, with DAC segments, made by libjpeg
+    let data = open("test-images/test-arithmetic-coding.jpg");
+    let coefficients = Decoder::new().decode_coefficients(&data).unwrap();
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+
+    let huffman = Transcoder::new().transcode(&data).unwrap();
+
+    assert_eq!(Decoder::new().decode_coefficients(&huffman).unwrap(), coefficients);
+    assert_eq!(decode_libjpeg(&huffman), decode_libjpeg(&data));
+
+    let arithmetic = Transcoder::new()
+        .set_arithmetic(true)
+        .set_progressive(true)
+        .transcode(&data)
+        .unwrap();
+
+    assert_eq!(decode_libjpeg(&arithmetic), decode_libjpeg(&data));
+}
+
+#[test]
+fn transform_pixels()
+{
+    // 4:4:4, no upsampling, so pixels move exactly like coefficients
+    let data = encode(48, 24, (1, 1));
+    let (pixels, width, height) = decode(&data);
+
+    for transform in TRANSFORMS
+    {
+        let (output, new_width, new_height) =
+            decode(&transcode(&data, transform, EdgeHandling::Perfect));
+
+        for y in 0..new_height
+        {
+            for x in 0..new_width
+            {
+                let (source_x, source_y) = match transform
+                {
+                    Transform::FlipHorizontal => (width - 1 - x, y),
+                    Transform::FlipVertical => (x, height - 1 - y),
+                    Transform::Transpose => (y, x),
+                    Transform::Transverse => (width - 1 - y, height - 1 - x),
+                    Transform::Rotate90 => (y, height - 1 - x),
+                    Transform::Rotate180 => (width - 1 - x, height - 1 - y),
+                    Transform::Rotate270 => (width - 1 - y, x),
+                };
+                let a = &output[(y * new_width + x) * 3..][..3];
+                let b = &pixels[(source_y * width + source_x) * 3..][..3];
+
+                assert!(
+                    a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= 2),
+                    "{transform:?} ({x}, {y}) {a:?} {b:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn transform_round_trip()
+{
+    // 4:2:2 becomes 4:4:0 when transposed
+    let data = encode(64, 32, (2, 1));
+    let coefficients = Decoder::new().decode_coefficients(&data).unwrap();
+
+    let rotate = |data: &[u8]| transcode(data, Transform::Rotate90, EdgeHandling::Perfect);
+
+    let rotated = rotate(&data);
+    let sampling = Decoder::new()
+        .decode_coefficients(&rotated)
+        .unwrap()
+        .components
+        .iter()
+        .map(|x| (x.horizontal_sample, x.vertical_sample))
+        .collect::<Vec<_>>();
+
+    assert_eq!(sampling, [(1, 2), (1, 1), (1, 1)]);
+
+    let upright = rotate(&rotate(&rotate(&rotated)));
+
+    assert_eq!(Decoder::new().decode_coefficients(&upright).unwrap(), coefficients);
+
+    for (transform, inverse) in [
+        (Transform::FlipHorizontal, Transform::FlipHorizontal),
+        (Transform::Transverse, Transform::Transverse),
+        (Transform::Rotate90, Transform::Rotate270),
+    ]
+    {
+        let output = transcode(&data, transform, EdgeHandling::Perfect);
+        let output = transcode(&output, inverse, EdgeHandling::Perfect);
+
+        assert_eq!(Decoder::new().decode_coefficients(&output).unwrap(), coefficients);
+    }
+}
+
+#[test]
+fn transform_partial_mcus()
+{
+    // 4:2:0, MCUs are 16x16 so there is a partial MCU on both edges
+    let data = encode(33, 17, (2, 2));
+    let size = |data: &[u8]| {
+        let mut decoder = Decoder::new();
+
+        decoder.read_headers(data).unwrap();
+        (decoder.width(), decoder.height())
+    };
+
+    for transform in TRANSFORMS
+    {
+        let perfect = Transcoder::new()
+            .set_transform(transform)
+            .set_edge_handling(EdgeHandling::Perfect)
+            .transcode(&data);
+
+        assert_eq!(perfect.is_ok(), transform == Transform::Transpose);
+
+        let trimmed = transcode(&data, transform, EdgeHandling::Trim);
+        let kept = transcode(&data, transform, EdgeHandling::Keep);
+
+        let expected = match transform
+        {
+            Transform::FlipHorizontal => (32, 17),
+            Transform::FlipVertical => (33, 16),
+            Transform::Transpose => (17, 33),
+            Transform::Rotate90 => (16, 33),
+            Transform::Rotate270 => (17, 32),
+            Transform::Transverse => (16, 32),
+            Transform::Rotate180 => (32, 16),
+        };
+        assert_eq!(size(&trimmed), expected);
+
+        let (width, height) = size(&kept);
+
+        assert_eq!((width.max(height), width.min(height)), (33, 17));
+
+        decode(&trimmed);
+        decode(&kept);
+    }
+}
+
+#[test]
+fn transcode_crop()
+{
+    // 4:2:0, MCUs are 16x16
+    let data = encode(48, 40, (2, 2));
+    let coefficients = Decoder::new().decode_coefficients(&data).unwrap();
+
+    // the corner moves to (16, 0), the right and bottom edges stay at (36, 26)
+    let output = Transcoder::new().set_crop(20, 10, 16, 16).transcode(&data).unwrap();
+    let cropped = Decoder::new().decode_coefficients(&output).unwrap();
+
+    assert_eq!((cropped.width, cropped.height), (20, 26));
+
+    for (source, component) in coefficients.components.iter().zip(&cropped.components)
+    {
+        let offset = usize::from(source.horizontal_sample);
+
+        assert_eq!(component.blocks_wide, 2 * offset);
+
+        for y in 0..component.blocks_high
+        {
+            for x in 0..component.blocks_wide
+            {
+                assert_eq!(component.block(x, y), source.block(x + offset, y));
+            }
+        }
+    }
+    decode(&output);
+
+    // the rectangle is clipped to the image
+    let output = Transcoder::new().set_crop(40, 32, 100, 100).transcode(&data).unwrap();
+    let (_, width, height) = decode(&output);
+
+    assert_eq!((width, height), (16, 8));
+
+    assert!(Transcoder::new().set_crop(48, 0, 8, 8).transcode(&data).is_err());
+    assert!(Transcoder::new().set_crop(0, 0, 0, 8).transcode(&data).is_err());
+}
+
+#[test]
+fn transcode_crop_progressive()
+{
+    let baseline = open("test-images/test-baseline.jpg");
+    let progressive = open("test-images/test-progressive.jpg");
+
+    let crop = |data: &[u8]| {
+        let output = Transcoder::new()
+            .set_crop(100, 50, 300, 200)
+            .transcode(data)
+            .unwrap();
+
+        Decoder::new().decode_coefficients(&output).unwrap()
+    };
+    let cropped = crop(&progressive);
+
+    // 4:4:4, MCUs are 8x8
+    assert_eq!((cropped.width, cropped.height), (304, 202));
+    assert_eq!(cropped, crop(&baseline));
+}
+
+#[test]
+fn transcode_grayscale()
+{
+    let data = encode(42, 20, (2, 2));
+    let (rgb, _, _) = decode(&data);
+
+    let gray = Transcoder::new().set_grayscale(true).transcode(&data).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&gray).unwrap();
+
+    assert_eq!(decoder.info().unwrap().components, 1);
+
+    let (gray, _, _) = decode(&gray);
+
+    for (pixel, luma) in rgb.chunks_exact(3).zip(&gray)
+    {
+        let [r, g, b] = [0, 1, 2].map(|x| f32::from(pixel[x]));
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+
+        assert!((y - f32::from(*luma)).abs() <= 2.0);
+    }
+
+    // MCUs of a grayscale image are single blocks, only the right and bottom
+    // two pixels are trimmed
+    let rotated = Transcoder::new()
+        .set_grayscale(true)
+        .set_transform(Transform::Rotate180)
+        .set_edge_handling(EdgeHandling::Trim)
+        .transcode(&data)
+        .unwrap();
+    let (rotated, width, height) = decode(&rotated);
+
+    assert_eq!((width, height), (40, 16));
+
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            let source = gray[(height - 1 - y) * 42 + width - 1 - x];
+
+            assert!(rotated[y * width + x].abs_diff(source) <= 2);
+        }
+    }
+}
+
+#[test]
+fn transcode_quality()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let source = Decoder::new().decode_coefficients(&data).unwrap();
+    let tables = quality_tables(50);
+
+    let output = Transcoder::new().set_quality(50).transcode(&data).unwrap();
+
+    assert!(output.len() < data.len());
+
+    // tables are never finer than those of the source
+    let image = Decoder::new().decode_coefficients(&output).unwrap();
+
+    for (pos, (component, original)) in image.components.iter().zip(&source.components).enumerate()
+    {
+        let table = &tables[usize::from(pos > 0)];
+
+        let steps = original.quantization_table.iter().zip(table).map(|(a, b)| *a.max(b));
+
+        assert!(component.quantization_table.iter().copied().eq(steps));
+    }
+    assert_eq!(image, source.requantize(&tables, None).unwrap());
+
+    let (pixels, _, _) = decode(&output);
+    let (expected, _, _) = decode(&data);
+    let error = pixels
+        .iter()
+        .zip(&expected)
+        .map(|(&x, &y)| u64::from(x.abs_diff(y)))
+        .sum::<u64>();
+
+    assert!(error < 4 * pixels.len() as u64);
+}
+
+#[test]
+fn transcode_quality_finer()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let output = Transcoder::new().set_quality(100).transcode(&data).unwrap();
+
+    assert_eq!(
+        Decoder::new().decode_coefficients(&output).unwrap(),
+        Decoder::new().decode_coefficients(&data).unwrap()
+    );
+}
+
+#[test]
+fn transcode_trellis()
+{
+    let data = open("test-images/test-baseline.jpg");
+    let nearest = Transcoder::new()
+        .set_quality(75)
+        .set_optimize(true)
+        .transcode(&data)
+        .unwrap();
+    let trellis = Transcoder::new()
+        .set_quality(75)
+        .set_optimize(true)
+        .set_trellis(true)
+        .transcode(&data)
+        .unwrap();
+
+    assert!(trellis.len() < nearest.len());
+
+    // trellis only rounds towards zero, and keeps DC coefficients
+    let nearest = Decoder::new().decode_coefficients(&nearest).unwrap();
+    let trellis = Decoder::new().decode_coefficients(&trellis).unwrap();
+
+    for (a, b) in nearest.components.iter().zip(&trellis.components)
+    {
+        assert_eq!(a.quantization_table, b.quantization_table);
+
+        for (x, y) in a.coefficients.chunks_exact(64).zip(b.coefficients.chunks_exact(64))
+        {
+            assert_eq!(x[0], y[0]);
+            assert!(x.iter().zip(y).all(|(x, y)| y.abs() <= x.abs() && x * y >= 0));
+        }
+    }
+    // without new tables the tables of the source are kept
+    let kept = Transcoder::new().set_trellis(true).transcode(&data).unwrap();
+    let kept = Decoder::new().decode_coefficients(&kept).unwrap();
+
+    assert_eq!(
+        kept.components[0].quantization_table,
+        Decoder::new().decode_coefficients(&data).unwrap().components[0].quantization_table
+    );
+}
+
+#[test]
+fn transcode_downscale()
+{
+    for (width, height, subsampling) in [(64, 48, (2, 2)), (42, 20, (2, 2)), (27, 13, (1, 1))]
+    {
+        let data = encode(width, height, subsampling);
+        let (rgb, _, _) = decode(&data);
+
+        let output = Transcoder::new().set_downscale(true).transcode(&data).unwrap();
+        let (half, new_width, new_height) = decode(&output);
+
+        assert_eq!((new_width, new_height), (width.div_ceil(2), height.div_ceil(2)));
+
+        let mut error = 0;
+
+        for y in 0..new_height
+        {
+            for x in 0..new_width
+            {
+                for c in 0..3
+                {
+                    // average of the 2x2 source pixels, repeating the edge
+                    let sum = [(0, 0), (0, 1), (1, 0), (1, 1)]
+                        .iter()
+                        .map(|&(dy, dx)| {
+                            let sx = (2 * x + dx).min(width - 1);
+                            let sy = (2 * y + dy).min(height - 1);
+
+                            usize::from(rgb[3 * (sy * width + sx) + c])
+                        })
+                        .sum::<usize>();
+                    let average = ((sum + 2) / 4) as u8;
+                    let difference = half[3 * (y * new_width + x) + c].abs_diff(average);
+
+                    // the blue sawtooth has sharp edges, which are quantized
+                    // harder in the smaller image
+                    assert!(difference <= 24, "{width}x{height} {x} {y} {difference}");
+                    error += usize::from(difference);
+                }
+            }
+        }
+        assert!(error <= 4 * half.len(), "{width}x{height} {error}");
+    }
+}
+
+#[test]
+fn transcode_metadata()
+{
+    let data = MetadataRewriter::new()
+        .insert_segment(Marker::COM, b"a comment".to_vec())
+        .insert_segment(Marker::APP(2), b"MPF\0II*\0".to_vec())
+        .rewrite(&encode(16, 16, (2, 2)))
+        .unwrap();
+
+    let output = Transcoder::new().transcode(&data).unwrap();
+    let markers = Segments::new(&output)
+        .filter_map(|x| x.marker())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        markers,
+        [
+            Marker::SOI,
+            Marker::APP(0),
+            Marker::COM,
+            Marker::DQT,
+            Marker::SOF(0),
+            Marker::DHT,
+            Marker::SOS,
+            Marker::EOI
+        ]
+    );
+}
+
+#[test]
+fn transcode_16_bit_tables()
+{
+    // libjpeg writes the tables of very low qualities with 16 bits
+    let pixels = (0..32 * 24 * 3).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+    let mut comp = mozjpeg::Compress::new(OutColorSpace::JCS_RGB);
+
+    comp.set_size(32, 24);
+    comp.set_quality(1.0);
+    comp.set_mem_dest();
+    comp.start_compress();
+
+    assert!(comp.write_scanlines(&pixels));
+
+    comp.finish_compress();
+
+    let data = comp.data_to_vec().unwrap();
+    let source = Decoder::new().decode_coefficients(&data).unwrap();
+
+    assert!(source.components[0].quantization_table.iter().any(|&x| x > 255));
+
+    let output = Transcoder::new().transcode(&data).unwrap();
+
+    // an extended frame, with the luminance table in 16 bits
+    let segments = Segments::new(&output).collect::<Vec<_>>();
+    let dqt = segments.iter().find(|x| x.marker() == Some(Marker::DQT)).unwrap();
+
+    assert_eq!(dqt.payload[0], 0x10);
+    assert!(segments.iter().any(|x| x.marker() == Some(Marker::SOF(1))));
+    assert_eq!(Decoder::new().decode_coefficients(&output).unwrap(), source);
+
+    let (expected, width, height) = decode(&output);
+
+    assert_eq!((width, height), (32, 24));
+    assert_eq!(Decoder::new().decode_buffer(&output).unwrap().len(), expected.len());
+}
+
+#[test]
+fn transcode_restart_interval()
+{
+    let pixels = (0..64 * 48 * 3).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+    let data = Encoder::new()
+        .set_restart_interval(3)
+        .encode(&pixels, 64, 48, ColorSpace::RGB)
+        .unwrap();
+
+    let restart_interval = |data: &[u8]| {
+        Segments::new(data)
+            .find(|x| x.marker() == Some(Marker::DRI))
+            .map(|x| x.payload.to_vec())
+    };
+
+    let source = Decoder::new().decode_coefficients(&data).unwrap();
+    let rotated = source.transform(Transform::Rotate90, EdgeHandling::default()).unwrap();
+
+    for (transcoder, expected) in [
+        (Transcoder::new(), &source),
+        (Transcoder::new().set_progressive(true), &source),
+        (Transcoder::new().set_transform(Transform::Rotate90), &rotated),
+    ]
+    {
+        let output = transcoder.transcode(&data).unwrap();
+
+        assert_eq!(restart_interval(&output), Some(vec![0, 3]));
+        assert_eq!(&Decoder::new().decode_coefficients(&output).unwrap(), expected);
+    }
+    // and with arithmetic coding
+    for progressive in [false, true]
+    {
+        let output = Transcoder::new()
+            .set_arithmetic(true)
+            .set_progressive(progressive)
+            .transcode(&data)
+            .unwrap();
+        let markers = Segments::new(&output)
+            .filter(|x| matches!(x.marker(), Some(Marker::RST(_))))
+            .count();
+
+        assert_eq!(restart_interval(&output), Some(vec![0, 3]));
+        assert!(markers > 0);
+        assert_eq!(&Decoder::new().decode_coefficients(&output).unwrap(), &source);
+        // libjpeg reads the restart markers too
+        assert_eq!(decode_libjpeg(&output), decode_libjpeg(&data));
+    }
+}